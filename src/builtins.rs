@@ -0,0 +1,1070 @@
+use crate::disassembler::disassemble;
+use crate::format::format_value;
+use crate::methods::{get_attribute, int_arg, set_attribute};
+use crate::operators::eval_binop;
+use crate::stdio::{self, Stream};
+use crate::types::Op;
+use crate::unicode;
+use crate::value::{
+  char_count, next_item, range_len, repr_str, send_value, stop_iteration, Builtin, Caller, Dict, Exception, FunctionBody, Iter, Keywords, Resumed, Value,
+};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// Functions available everywhere unless shadowed by a variable of the same name
+const BUILTINS: &[Builtin] = &[
+  Builtin { name: "__import__", func: builtin_import },
+  Builtin { name: "abs", func: builtin_abs },
+  Builtin { name: "all", func: builtin_all },
+  Builtin { name: "any", func: builtin_any },
+  Builtin { name: "bin", func: builtin_bin },
+  Builtin { name: "chr", func: builtin_chr },
+  Builtin { name: "dis", func: builtin_dis },
+  Builtin { name: "enumerate", func: builtin_enumerate },
+  Builtin { name: "filter", func: builtin_filter },
+  Builtin { name: "format", func: builtin_format },
+  Builtin { name: "getattr", func: builtin_getattr },
+  Builtin { name: "hasattr", func: builtin_hasattr },
+  Builtin { name: "hash", func: builtin_hash },
+  Builtin { name: "hex", func: builtin_hex },
+  Builtin { name: "id", func: builtin_id },
+  Builtin { name: "input", func: builtin_input },
+  Builtin { name: "isinstance", func: builtin_isinstance },
+  Builtin { name: "iter", func: builtin_iter },
+  Builtin { name: "len", func: builtin_len },
+  Builtin { name: "map", func: builtin_map },
+  Builtin { name: "max", func: builtin_max },
+  Builtin { name: "min", func: builtin_min },
+  Builtin { name: "next", func: builtin_next },
+  Builtin { name: "oct", func: builtin_oct },
+  Builtin { name: "ord", func: builtin_ord },
+  Builtin { name: "print", func: builtin_print },
+  Builtin { name: "repr", func: builtin_repr },
+  Builtin { name: "reversed", func: builtin_reversed },
+  Builtin { name: "round", func: builtin_round },
+  Builtin { name: "setattr", func: builtin_setattr },
+  Builtin { name: "sorted", func: builtin_sorted },
+  Builtin { name: "sum", func: builtin_sum },
+  Builtin { name: "zip", func: builtin_zip },
+];
+
+// Built-in types, each with the function that runs when the type is called
+const TYPES: &[Builtin] = &[
+  Builtin { name: "bool", func: type_bool },
+  Builtin { name: "bytes", func: type_bytes },
+  Builtin { name: "dict", func: type_dict },
+  Builtin { name: "float", func: type_float },
+  Builtin { name: "int", func: type_int },
+  Builtin { name: "list", func: type_list },
+  Builtin { name: "range", func: type_range },
+  Builtin { name: "set", func: type_set },
+  Builtin { name: "str", func: type_str },
+  Builtin { name: "tuple", func: type_tuple },
+  Builtin { name: "type", func: type_type },
+];
+
+// Built-in exception types. Calling one creates an exception, which generator.throw() can raise
+pub const EXCEPTIONS: &[&str] = &[
+  "ArithmeticError", "AssertionError", "AttributeError", "BaseException", "Exception", "GeneratorExit", "IndexError", "KeyError", "LookupError",
+  "NameError", "OverflowError", "RecursionError", "RuntimeError", "StopIteration", "TypeError", "UnboundLocalError",
+  "ValueError", "ZeroDivisionError",
+];
+
+pub fn lookup(name: &str) -> Option<Value> {
+  match BUILTINS.iter().find(|builtin| builtin.name == name) {
+    Some(builtin) => Some(Value::Builtin(*builtin)),
+    None => TYPES
+      .iter()
+      .map(|builtin| builtin.name)
+      .chain(EXCEPTIONS.iter().copied())
+      .find(|type_name| *type_name == name)
+      .map(Value::Type),
+  }
+}
+
+// Calls a builtin function or type, or a built-in method with the object it is bound to as the first argument
+pub fn call(function: &Value, args: Vec<Value>, kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  match function {
+    Value::Builtin(builtin) => (builtin.func)(&args, kwargs, caller),
+    Value::Method(method) => {
+      let mut full_args = Vec::with_capacity(args.len() + 1);
+      full_args.push(method.receiver.clone());
+      full_args.extend(args);
+      (method.builtin.func)(&full_args, kwargs, caller)
+    }
+    Value::Type(name) => match TYPES.iter().find(|builtin| builtin.name == *name) {
+      Some(builtin) => (builtin.func)(&args, kwargs, caller),
+      None if EXCEPTIONS.contains(name) => {
+        no_keywords(name, kwargs)?;
+        Ok(Value::Exception(Rc::new(Exception { kind: name, args })))
+      }
+      None => Err(format!("TypeError: cannot create '{}' instances", name)),
+    },
+    _ => Err(format!("TypeError: '{}' object is not callable", function.type_name())),
+  }
+}
+
+fn is_callable(value: &Value) -> bool {
+  matches!(value, Value::Function(_) | Value::Builtin(_) | Value::Method(_) | Value::Type(_))
+}
+
+fn no_keywords(name: &str, kwargs: &Keywords) -> Result<(), String> {
+  if kwargs.is_empty() {
+    Ok(())
+  } else {
+    Err(format!("TypeError: {}() takes no keyword arguments", name))
+  }
+}
+
+// Checks the number of positional arguments of a function taking between min and max of them
+fn expect_args(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), String> {
+  let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+  if min == max && args.len() != min {
+    Err(format!("TypeError: {} expected {} {}, got {}", name, min, plural(min), args.len()))
+  } else if args.len() < min {
+    Err(format!("TypeError: {} expected at least {} {}, got {}", name, min, plural(min), args.len()))
+  } else if args.len() > max {
+    Err(format!("TypeError: {} expected at most {} {}, got {}", name, max, plural(max), args.len()))
+  } else {
+    Ok(())
+  }
+}
+
+// The argument of a function taking exactly one
+fn one_argument<'a>(name: &str, args: &'a [Value], kwargs: &Keywords) -> Result<&'a Value, String> {
+  no_keywords(name, kwargs)?;
+  match args {
+    [value] => Ok(value),
+    _ => Err(format!("TypeError: {}() takes exactly one argument ({} given)", name, args.len())),
+  }
+}
+
+// Matches the arguments of a function that also takes them by keyword to its parameters, of which
+// the first `required` must be given. Parameters named "" can only be passed by position
+fn arguments(name: &str, args: &[Value], kwargs: &Keywords, params: &[&str], required: usize) -> Result<Vec<Option<Value>>, String> {
+  if args.len() > params.len() {
+    return Err(format!("TypeError: {}() takes at most {} arguments ({} given)", name, params.len(), args.len()));
+  }
+  let mut bound: Vec<Option<Value>> = params.iter().enumerate().map(|(i, _)| args.get(i).cloned()).collect();
+  for (key, value) in kwargs {
+    match params.iter().position(|param| !param.is_empty() && param == key) {
+      Some(i) if i < args.len() => return Err(format!("TypeError: argument for {}() given by name ('{}') and position ({})", name, key, i + 1)),
+      Some(i) => bound[i] = Some(value.clone()),
+      None => return Err(format!("TypeError: '{}' is an invalid keyword argument for {}()", key, name)),
+    }
+  }
+  if let Some(i) = bound[..required].iter().position(Option::is_none) {
+    return Err(format!("TypeError: {}() missing required argument '{}' (pos {})", name, params[i], i + 1));
+  }
+  Ok(bound)
+}
+
+// __import__(name), which the import statement calls. sys is the only module there is
+fn builtin_import(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  match one_argument("__import__", args, kwargs)? {
+    Value::Str(name) if &**name == "sys" => Ok(Value::Module("sys")),
+    Value::Str(name) => Err(format!("ModuleNotFoundError: No module named '{}'", name)),
+    value => Err(format!("TypeError: __import__() argument 1 must be str, not {}", value.type_name())),
+  }
+}
+
+// abs(x)
+fn builtin_abs(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  match one_argument("abs", args, kwargs)? {
+    Value::Int(n) => n.checked_abs().map(Value::Int).ok_or_else(|| "OverflowError: integer overflow".to_string()),
+    Value::Bool(b) => Ok(Value::Int(*b as i64)),
+    Value::Float(d) => Ok(Value::Float(d.abs())),
+    value => Err(format!("TypeError: bad operand type for abs(): '{}'", value.type_name())),
+  }
+}
+
+// all(iterable): whether every item is truthy, stopping at the first that is not
+fn builtin_all(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  let iterator = one_argument("all", args, kwargs)?.to_iterator()?;
+  while let Some(item) = next_item(&iterator, caller)? {
+    if !item.is_truthy() {
+      return Ok(Value::Bool(false));
+    }
+  }
+  Ok(Value::Bool(true))
+}
+
+// any(iterable): whether some item is truthy, stopping at the first that is
+fn builtin_any(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  let iterator = one_argument("any", args, kwargs)?.to_iterator()?;
+  while let Some(item) = next_item(&iterator, caller)? {
+    if item.is_truthy() {
+      return Ok(Value::Bool(true));
+    }
+  }
+  Ok(Value::Bool(false))
+}
+
+// bin(), oct() and hex(): an int in base 2, 8 or 16 with the prefix Python literals use
+fn int_literal(name: &str, args: &[Value], kwargs: &Keywords, radix: u32) -> Result<Value, String> {
+  let n = int_arg(one_argument(name, args, kwargs)?)?;
+  let digits = match radix {
+    2 => format!("0b{:b}", n.unsigned_abs()),
+    8 => format!("0o{:o}", n.unsigned_abs()),
+    _ => format!("0x{:x}", n.unsigned_abs()),
+  };
+  Ok(Value::new_str(&format!("{}{}", if n < 0 { "-" } else { "" }, digits)))
+}
+
+fn builtin_bin(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  int_literal("bin", args, kwargs, 2)
+}
+
+fn builtin_oct(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  int_literal("oct", args, kwargs, 8)
+}
+
+fn builtin_hex(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  int_literal("hex", args, kwargs, 16)
+}
+
+// chr(i): the string of one code point. Surrogates can't be stored in a Rust string, so they are
+// rejected along with values out of range
+fn builtin_chr(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let n = int_arg(one_argument("chr", args, kwargs)?)?;
+  match u32::try_from(n).ok().and_then(char::from_u32) {
+    Some(c) => Ok(Value::new_str(&c.to_string())),
+    None => Err("ValueError: chr() arg not in range(0x110000)".to_string()),
+  }
+}
+
+// dis(f): prints the bytecode of a function, headed line by line by the source of a script like --dis
+fn builtin_dis(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("dis", kwargs)?;
+  match args {
+    [Value::Function(func)] => match &func.body {
+      FunctionBody::Code(code, _) => {
+        stdio::write(Stream::Stdout, &disassemble(code, code.source_lines().as_deref()))?;
+        Ok(Value::None)
+      }
+      FunctionBody::Ast(..) | FunctionBody::Steps(..) => Err("TypeError: dis() needs the bytecode VM, functions run by --tree-walk are not compiled".to_string()),
+    },
+    [value] => Err(format!("TypeError: don't know how to disassemble {} objects", value.type_name())),
+    _ => Err(format!("TypeError: dis() takes exactly one argument ({} given)", args.len())),
+  }
+}
+
+// enumerate(iterable, start=0): pairs each item with a count
+fn builtin_enumerate(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let bound = arguments("enumerate", args, kwargs, &["iterable", "start"], 1)?;
+  let iterator = bound[0].as_ref().unwrap().to_iterator()?;
+  let start = match &bound[1] {
+    Some(start) => int_arg(start)?,
+    None => 0,
+  };
+  Ok(new_iterator(Iter::Enumerate(iterator, start)))
+}
+
+fn new_iterator(iter: Iter) -> Value {
+  Value::Iterator(Rc::new(RefCell::new(iter)))
+}
+
+// filter(function, iterable): the items for which the function returns a truthy value, or the
+// truthy items when the function is None
+fn builtin_filter(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("filter", kwargs)?;
+  expect_args("filter", args, 2, 2)?;
+  Ok(new_iterator(Iter::Filter(args[0].clone(), args[1].to_iterator()?)))
+}
+
+// format(value, spec=''): formats a value as in an f-string replacement field
+fn builtin_format(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("format", kwargs)?;
+  match args {
+    [value] => Ok(Value::new_str(&format_value(value, "")?)),
+    [value, Value::Str(spec)] => Ok(Value::new_str(&format_value(value, spec)?)),
+    [_, spec] => Err(format!("TypeError: format() argument 2 must be str, not {}", spec.type_name())),
+    [] => Err("TypeError: format expected at least 1 argument, got 0".to_string()),
+    _ => Err(format!("TypeError: format expected at most 2 arguments, got {}", args.len())),
+  }
+}
+
+fn attribute_name(value: &Value) -> Result<&str, String> {
+  match value {
+    Value::Str(name) => Ok(name),
+    _ => Err(format!("TypeError: attribute name must be string, not '{}'", value.type_name())),
+  }
+}
+
+// getattr(object, name[, default]): the default is returned when the attribute does not exist
+fn builtin_getattr(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("getattr", kwargs)?;
+  expect_args("getattr", args, 2, 3)?;
+  match (get_attribute(&args[0], attribute_name(&args[1])?), args.get(2)) {
+    (Err(e), Some(default)) if e.starts_with("AttributeError") => Ok(default.clone()),
+    (result, _) => result,
+  }
+}
+
+// hasattr(object, name)
+fn builtin_hasattr(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("hasattr", kwargs)?;
+  expect_args("hasattr", args, 2, 2)?;
+  match get_attribute(&args[0], attribute_name(&args[1])?) {
+    Ok(_) => Ok(Value::Bool(true)),
+    Err(e) if e.starts_with("AttributeError") => Ok(Value::Bool(false)),
+    Err(e) => Err(e),
+  }
+}
+
+// setattr(object, name, value)
+fn builtin_setattr(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("setattr", kwargs)?;
+  expect_args("setattr", args, 3, 3)?;
+  set_attribute(&args[0], attribute_name(&args[1])?, Some(args[2].clone())).map(|_| Value::None)
+}
+
+// hash(object)
+fn builtin_hash(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  hash_value(one_argument("hash", args, kwargs)?).map(Value::Int)
+}
+
+// Modulus of CPython's numeric hash, chosen so that hash(n) == n for small ints
+const HASH_MODULUS: u64 = (1 << 61) - 1;
+
+// CPython's hash() for numbers and tuples, so that equal numbers of any type hash alike and
+// hash(n) == n for small ints. Other values hash their dict key, which differs from CPython's
+// randomized string hash
+fn hash_value(value: &Value) -> Result<i64, String> {
+  let hash = match value {
+    Value::Bool(b) => *b as i64,
+    Value::Int(n) => {
+      let hash = (n.unsigned_abs() % HASH_MODULUS) as i64;
+      if *n < 0 { -hash } else { hash }
+    }
+    Value::Float(d) if d.is_infinite() => if *d > 0.0 { 314159 } else { -314159 },
+    Value::Float(d) if d.is_nan() => 0,
+    // d is mantissa * 2^exponent, and 2^61 is 1 modulo the modulus, so the exponent can be reduced modulo 61
+    Value::Float(d) => {
+      let bits = d.to_bits();
+      let (mantissa, exponent) = match (bits >> 52) & 0x7ff {
+        0 => (bits & ((1 << 52) - 1), -1074),
+        biased => (bits & ((1 << 52) - 1) | 1 << 52, biased as i64 - 1075),
+      };
+      let hash = ((mantissa as u128) << exponent.rem_euclid(61)) % HASH_MODULUS as u128;
+      if *d < 0.0 { -(hash as i64) } else { hash as i64 }
+    }
+    // xxHash over the hashes of the items
+    Value::Tuple(items) => {
+      const PRIME_1: u64 = 11400714785074694791;
+      const PRIME_2: u64 = 14029467366897019727;
+      const PRIME_5: u64 = 2870177450012600261;
+      let mut acc = PRIME_5;
+      for item in items.iter() {
+        acc = acc.wrapping_add((hash_value(item)? as u64).wrapping_mul(PRIME_2)).rotate_left(31).wrapping_mul(PRIME_1);
+      }
+      acc = acc.wrapping_add(items.len() as u64 ^ (PRIME_5 ^ 3527539));
+      if acc == u64::MAX { 1546275796 } else { acc as i64 }
+    }
+    _ => {
+      let mut hasher = DefaultHasher::new();
+      value.hash_key()?.hash(&mut hasher);
+      hasher.finish() as i64
+    }
+  };
+  // -1 is reserved for errors in CPython
+  Ok(if hash == -1 { -2 } else { hash })
+}
+
+// id(object): the address of a heap object. Scalars have no address, so equal ones of the same
+// type share an id, as they are identical to `is`
+fn builtin_id(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let value = one_argument("id", args, kwargs)?;
+  let id = match value {
+    Value::Str(s) => s.as_ptr() as usize,
+    Value::Bytes(b) => b.as_ptr() as usize,
+    Value::List(items) => Rc::as_ptr(items) as usize,
+    Value::Tuple(items) => Rc::as_ptr(items) as usize,
+    Value::Dict(table) | Value::Set(table) => Rc::as_ptr(table) as usize,
+    Value::Function(func) => Rc::as_ptr(func) as usize,
+    Value::Builtin(builtin) => builtin.name.as_ptr() as usize,
+    Value::Method(method) => Rc::as_ptr(method) as usize,
+    Value::Iterator(it) => Rc::as_ptr(it) as usize,
+    Value::Slice(bounds) => Rc::as_ptr(bounds) as usize,
+    _ => {
+      let mut hasher = DefaultHasher::new();
+      value.type_name().hash(&mut hasher);
+      match value {
+        Value::Float(d) => d.to_bits().hash(&mut hasher),
+        _ => value.hash_key()?.hash(&mut hasher),
+      }
+      (hasher.finish() >> 1) as usize
+    }
+  };
+  Ok(Value::Int(id as i64))
+}
+
+// input(prompt=''): writes the prompt and reads a line of input without its line ending
+fn builtin_input(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("input", kwargs)?;
+  match args {
+    [] => {}
+    [prompt] => stdio::write(Stream::Stdout, &prompt.str()?)?,
+    _ => return Err(format!("TypeError: input expected at most 1 argument, got {}", args.len())),
+  }
+  stdio::flush(Stream::Stdout)?;
+  match stdio::read_line()? {
+    Some(line) => Ok(Value::new_str(&line)),
+    None => Err("EOFError: EOF when reading a line".to_string()),
+  }
+}
+
+// isinstance(object, classinfo): classinfo is a type or a tuple of them
+fn builtin_isinstance(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("isinstance", kwargs)?;
+  expect_args("isinstance", args, 2, 2)?;
+  is_instance(&args[0], &args[1]).map(Value::Bool)
+}
+
+// bool is the only subclass among the built-in types, of int
+fn is_instance(value: &Value, classinfo: &Value) -> Result<bool, String> {
+  match classinfo {
+    Value::Type(name) => Ok(value.type_name() == *name || (*name == "int" && matches!(value, Value::Bool(_)))),
+    Value::Tuple(types) => {
+      for classinfo in types.iter() {
+        if is_instance(value, classinfo)? {
+          return Ok(true);
+        }
+      }
+      Ok(false)
+    }
+    _ => Err("TypeError: isinstance() arg 2 must be a type, a tuple of types, or a union".to_string()),
+  }
+}
+
+// iter(object) or iter(function, sentinel), which calls the function until it returns the sentinel
+fn builtin_iter(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("iter", kwargs)?;
+  expect_args("iter", args, 1, 2)?;
+  match args {
+    [iterable] => iterable.to_iterator(),
+    [function, _] if !is_callable(function) => Err("TypeError: iter(v, w): v must be callable".to_string()),
+    _ => Ok(new_iterator(Iter::Callable(args[0].clone(), Some(args[1].clone())))),
+  }
+}
+
+// len(obj): the number of items in a container, or of code points in a string
+fn builtin_len(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("len", kwargs)?;
+  let len = match args {
+    [Value::Str(s)] => char_count(s),
+    [Value::Bytes(b)] => b.len(),
+    [Value::List(items)] => items.borrow().len(),
+    [Value::Tuple(items)] => items.len(),
+    [Value::Dict(table)] | [Value::Set(table)] => table.borrow().len(),
+    [Value::Range(start, stop, step)] => match i64::try_from(range_len(*start, *stop, *step)) {
+      Ok(len) => len as usize,
+      Err(_) => return Err("OverflowError: Python int too large to convert to C ssize_t".to_string()),
+    },
+    [value] => return Err(format!("TypeError: object of type '{}' has no len()", value.type_name())),
+    _ => return Err(format!("TypeError: len() takes exactly one argument ({} given)", args.len())),
+  };
+  Ok(Value::Int(len as i64))
+}
+
+// map(function, iterable, *iterables): the function applied to the items, taking one argument
+// from each iterable and stopping at the shortest
+fn builtin_map(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("map", kwargs)?;
+  if args.len() < 2 {
+    return Err("TypeError: map() must have at least two arguments.".to_string());
+  }
+  let iterators = args[1..].iter().map(Value::to_iterator).collect::<Result<Vec<_>, _>>()?;
+  Ok(new_iterator(Iter::Map(args[0].clone(), iterators)))
+}
+
+// max() and min(), of an iterable or of two or more arguments. The first of equal items wins
+fn extreme(name: &str, args: &[Value], kwargs: &Keywords, op: Op, caller: &mut dyn Caller) -> Result<Value, String> {
+  let mut key = Value::None;
+  let mut default = None;
+  for (keyword, value) in kwargs {
+    match keyword.as_str() {
+      "key" => key = value.clone(),
+      "default" => default = Some(value.clone()),
+      _ => return Err(format!("TypeError: '{}' is an invalid keyword argument for {}()", keyword, name)),
+    }
+  }
+  let items = match args {
+    [] => return Err(format!("TypeError: {} expected at least 1 argument, got 0", name)),
+    [iterable] => iterable.items(caller)?,
+    _ if default.is_some() => return Err(format!("TypeError: Cannot specify a default for {}() with multiple positional arguments", name)),
+    _ => args.to_vec(),
+  };
+
+  let mut best: Option<(Value, Value)> = None; // item, key
+  for item in items {
+    let item_key = match key {
+      Value::None => item.clone(),
+      _ => caller.call(&key, vec![item.clone()])?,
+    };
+    let better = match &best {
+      Some((_, best_key)) => eval_binop(&op, &item_key, best_key)?.is_truthy(),
+      None => true,
+    };
+    if better {
+      best = Some((item, item_key));
+    }
+  }
+  match (best, default) {
+    (Some((item, _)), _) => Ok(item),
+    (None, Some(default)) => Ok(default),
+    (None, None) => Err(format!("ValueError: {}() arg is an empty sequence", name)),
+  }
+}
+
+fn builtin_max(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  extreme("max", args, kwargs, Op::Greater, caller)
+}
+
+fn builtin_min(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  extreme("min", args, kwargs, Op::Less, caller)
+}
+
+// next(iterator[, default]): the default is returned instead of raising StopIteration
+fn builtin_next(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("next", kwargs)?;
+  expect_args("next", args, 1, 2)?;
+  // The value a generator returns is given with the StopIteration
+  match (send_value(&args[0], Value::None, caller)?, args.get(1)) {
+    (Resumed::Yield(item), _) => Ok(item),
+    (Resumed::Return(_), Some(default)) => Ok(default.clone()),
+    (Resumed::Return(value), None) => Err(stop_iteration(&value)),
+  }
+}
+
+// ord(c): the code point of a one-character string, or the value of a one-byte bytes
+fn builtin_ord(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let len = match one_argument("ord", args, kwargs)? {
+    Value::Str(s) => {
+      let mut chars = s.chars();
+      if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Value::Int(c as i64));
+      }
+      char_count(s)
+    }
+    Value::Bytes(b) if b.len() == 1 => return Ok(Value::Int(b[0] as i64)),
+    Value::Bytes(b) => b.len(),
+    value => return Err(format!("TypeError: ord() expected string of length 1, but {} found", value.type_name())),
+  };
+  Err(format!("TypeError: ord() expected a character, but string of length {} found", len))
+}
+
+// print(*args, sep=' ', end='\n', file=None, flush=False): writes the str() of each argument to
+// file, which is sys.stdout when None. The only objects with a write method are sys.stdout and
+// sys.stderr, so any other file is rejected the way CPython rejects an object without one
+fn builtin_print(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let mut sep = " ".to_string();
+  let mut end = "\n".to_string();
+  let mut flush = false;
+  let mut file = Stream::Stdout;
+  for (name, value) in kwargs {
+    match (name.as_str(), value) {
+      ("sep" | "end", Value::None) => {}
+      ("sep", Value::Str(s)) => sep = s.to_string(),
+      ("end", Value::Str(s)) => end = s.to_string(),
+      ("sep" | "end", _) => return Err(format!("TypeError: {} must be None or a string, not {}", name, value.type_name())),
+      ("file", Value::None) => {}
+      ("file", Value::Stream(stream)) => file = *stream,
+      ("file", _) => return Err(format!("AttributeError: '{}' object has no attribute 'write'", value.type_name())),
+      ("flush", _) => flush = value.is_truthy(),
+      _ => return Err(format!("TypeError: '{}' is an invalid keyword argument for print()", name)),
+    }
+  }
+
+  let text = args.iter().map(|arg| arg.str()).collect::<Result<Vec<_>, _>>()?;
+  stdio::write(file, &(text.join(&sep) + &end))?;
+  if flush {
+    stdio::flush(file)?;
+  }
+  Ok(Value::None)
+}
+
+// repr(object)
+fn builtin_repr(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  Ok(Value::new_str(&one_argument("repr", args, kwargs)?.repr()?))
+}
+
+// reversed(sequence): the items of a sequence from last to first. A range gives a range iterator
+// counting down, the other sequences the items they held when reversed() was called
+fn builtin_reversed(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("reversed", kwargs)?;
+  expect_args("reversed", args, 1, 1)?;
+  let iter = match &args[0] {
+    Value::Range(start, stop, step) => {
+      let len = range_len(*start, *stop, *step);
+      let last = (*start as i128 + (len as i128 - 1) * *step as i128) as i64;
+      Iter::Range { next: last, step: step.wrapping_neg(), remaining: len }
+    }
+    Value::List(items) => Iter::Reversed("list_reverseiterator", items.borrow().clone()),
+    Value::Tuple(items) => Iter::Reversed("reversed", items.to_vec()),
+    Value::Str(s) => Iter::Reversed("reversed", s.chars().map(|c| Value::new_str(&c.to_string())).collect()),
+    Value::Bytes(b) => Iter::Reversed("reversed", b.iter().map(|byte| Value::Int(*byte as i64)).collect()),
+    Value::Dict(table) => Iter::Reversed("dict_reversekeyiterator", table.borrow().entries().iter().map(|(key, _)| key.clone()).collect()),
+    value => return Err(format!("TypeError: '{}' object is not reversible", value.type_name())),
+  };
+  Ok(new_iterator(iter))
+}
+
+// round(number, ndigits=None): rounds half to even. Without ndigits the result is an int
+fn builtin_round(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let bound = arguments("round", args, kwargs, &["number", "ndigits"], 1)?;
+  let ndigits = match &bound[1] {
+    None | Some(Value::None) => None,
+    Some(ndigits) => Some(int_arg(ndigits)?),
+  };
+  match (bound[0].as_ref().unwrap(), ndigits) {
+    (Value::Bool(b), _) => Ok(Value::Int(*b as i64)),
+    (Value::Int(n), Some(ndigits)) if ndigits < 0 => {
+      let unit = match 10i64.checked_pow(ndigits.unsigned_abs().min(u32::MAX as u64) as u32) {
+        Some(unit) => unit,
+        None => return Ok(Value::Int(0)),
+      };
+      let (quotient, remainder) = (n.div_euclid(unit), n.rem_euclid(unit) as i128);
+      let round_up = 2 * remainder > unit as i128 || (2 * remainder == unit as i128 && quotient % 2 != 0);
+      let quotient = if round_up { quotient + 1 } else { quotient };
+      quotient.checked_mul(unit).map(Value::Int).ok_or_else(|| "OverflowError: integer overflow".to_string())
+    }
+    (Value::Int(n), _) => Ok(Value::Int(*n)),
+    (Value::Float(d), None) => float_to_int(d.round_ties_even()),
+    (Value::Float(d), Some(_)) if !d.is_finite() => Ok(Value::Float(*d)),
+    // Formatting rounds the exact binary value, as CPython does
+    (Value::Float(d), Some(ndigits)) if ndigits >= 0 => Ok(Value::Float(format!("{:.*}", ndigits.min(400) as usize, d).parse().unwrap())),
+    (Value::Float(d), Some(ndigits)) => {
+      let unit = 10f64.powi(ndigits.unsigned_abs().min(400) as i32);
+      Ok(Value::Float((d / unit).round_ties_even() * unit))
+    }
+    (value, _) => Err(format!("TypeError: type {} doesn't define __round__ method", value.type_name())),
+  }
+}
+
+fn float_to_int(d: f64) -> Result<Value, String> {
+  if d.is_nan() {
+    Err("ValueError: cannot convert float NaN to integer".to_string())
+  } else if d.is_infinite() {
+    Err("OverflowError: cannot convert float infinity to integer".to_string())
+  } else if !(-9.223372036854776e18..9.223372036854776e18).contains(&d) {
+    Err("OverflowError: integer overflow".to_string())
+  } else {
+    Ok(Value::Int(d as i64))
+  }
+}
+
+// sorted(iterable, *, key=None, reverse=False)
+fn builtin_sorted(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  expect_args("sorted", args, 1, 1)?;
+  let mut key = Value::None;
+  let mut reverse = false;
+  for (keyword, value) in kwargs {
+    match keyword.as_str() {
+      "key" => key = value.clone(),
+      "reverse" => reverse = int_arg(value)? != 0,
+      _ => return Err(format!("TypeError: '{}' is an invalid keyword argument for sort()", keyword)),
+    }
+  }
+  let mut items = args[0].items(caller)?;
+  sort_values(&mut items, &key, reverse, caller)?;
+  Ok(Value::new_list(items))
+}
+
+// Stable sort comparing items (or their keys) with < only, like list.sort(). Sorting in reverse
+// reverses the items before and after sorting, so that equal items keep their order
+pub fn sort_values(items: &mut Vec<Value>, key: &Value, reverse: bool, caller: &mut dyn Caller) -> Result<(), String> {
+  let mut keyed = Vec::with_capacity(items.len());
+  for item in items.drain(..) {
+    let item_key = match key {
+      Value::None => item.clone(),
+      _ => caller.call(key, vec![item.clone()])?,
+    };
+    keyed.push((item_key, item));
+  }
+  if reverse {
+    keyed.reverse();
+  }
+  let mut sorted = merge_sort(keyed)?;
+  if reverse {
+    sorted.reverse();
+  }
+  items.extend(sorted.into_iter().map(|(_, item)| item));
+  Ok(())
+}
+
+// Merge sort, which unlike the standard library's sorts copes with an ordering that is not total
+// (NaN) or that fails partway through
+fn merge_sort(mut keyed: Vec<(Value, Value)>) -> Result<Vec<(Value, Value)>, String> {
+  if keyed.len() <= 1 {
+    return Ok(keyed);
+  }
+  let right = merge_sort(keyed.split_off(keyed.len() / 2))?;
+  let left = merge_sort(keyed)?;
+
+  let mut merged = Vec::with_capacity(left.len() + right.len());
+  let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+  while let (Some((left_key, _)), Some((right_key, _))) = (left.peek(), right.peek()) {
+    // Taking from the right only when it is strictly less keeps the sort stable
+    if eval_binop(&Op::Less, right_key, left_key)?.is_truthy() {
+      merged.extend(right.next());
+    } else {
+      merged.extend(left.next());
+    }
+  }
+  merged.extend(left);
+  merged.extend(right);
+  Ok(merged)
+}
+
+// sum(iterable, /, start=0): adds the items to start. Strings and bytes are refused in favour of join()
+fn builtin_sum(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  if args.is_empty() {
+    return Err("TypeError: sum() takes at least 1 positional argument (0 given)".to_string());
+  }
+  let bound = arguments("sum", args, kwargs, &["", "start"], 1)?;
+  let mut total = bound[1].clone().unwrap_or(Value::Int(0));
+  match total {
+    Value::Str(_) => return Err("TypeError: sum() can't sum strings [use ''.join(seq) instead]".to_string()),
+    Value::Bytes(_) => return Err("TypeError: sum() can't sum bytes [use b''.join(seq) instead]".to_string()),
+    _ => {}
+  }
+  let iterator = args[0].to_iterator()?;
+  while let Some(item) = next_item(&iterator, caller)? {
+    total = eval_binop(&Op::Add, &total, &item)?;
+  }
+  Ok(total)
+}
+
+// zip(*iterables, strict=False): tuples of the items at the same position, stopping at the
+// shortest iterable, or raising ValueError when strict and the lengths differ
+fn builtin_zip(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let mut strict = false;
+  for (keyword, value) in kwargs {
+    match keyword.as_str() {
+      "strict" => strict = value.is_truthy(),
+      _ => return Err(format!("TypeError: '{}' is an invalid keyword argument for zip()", keyword)),
+    }
+  }
+  let iterators = args.iter().map(Value::to_iterator).collect::<Result<Vec<_>, _>>()?;
+  Ok(new_iterator(Iter::Zip(iterators, strict)))
+}
+
+// bool(x=False)
+fn type_bool(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("bool", kwargs)?;
+  expect_args("bool", args, 0, 1)?;
+  Ok(Value::Bool(args.first().is_some_and(Value::is_truthy)))
+}
+
+// bytes(source=b'', encoding, errors): from a string and its encoding, a count of zero bytes or
+// an iterable of ints
+fn type_bytes(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  let bound = arguments("bytes", args, kwargs, &["source", "encoding", "errors"], 0)?;
+  match (&bound[0], &bound[1], &bound[2]) {
+    (Some(source @ Value::Str(_)), Some(_), _) | (Some(source @ Value::Str(_)), _, Some(_)) => {
+      let options = ["encoding", "errors"].iter().zip(&bound[1..]).filter_map(|(name, value)| Some((name.to_string(), value.clone()?)));
+      call(&get_attribute(source, "encode")?, Vec::new(), &options.collect::<Vec<_>>(), caller)
+    }
+    (Some(Value::Str(_)), None, None) => Err("TypeError: string argument without an encoding".to_string()),
+    (_, Some(_), _) => Err("TypeError: encoding without a string argument".to_string()),
+    (_, _, Some(_)) => Err("TypeError: errors without a string argument".to_string()),
+    (None, _, _) => Ok(Value::Bytes(Rc::from(Vec::new()))),
+    (Some(Value::Bytes(b)), _, _) => Ok(Value::Bytes(b.clone())),
+    (Some(Value::Int(n)), _, _) if *n < 0 => Err("ValueError: negative count".to_string()),
+    (Some(Value::Int(n)), _, _) => Ok(Value::Bytes(Rc::from(vec![0; *n as usize]))),
+    (Some(source), _, _) => {
+      let mut bytes = Vec::new();
+      for item in source.items(caller).map_err(|_| format!("TypeError: cannot convert '{}' object to bytes", source.type_name()))? {
+        match int_arg(&item)? {
+          n @ 0..=255 => bytes.push(n as u8),
+          _ => return Err("ValueError: bytes must be in range(0, 256)".to_string()),
+        }
+      }
+      Ok(Value::Bytes(Rc::from(bytes)))
+    }
+  }
+}
+
+// dict(mapping_or_iterable=(), **kwargs): from another dict or from key-value pairs, followed by
+// the keyword arguments
+fn type_dict(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  expect_args("dict", args, 0, 1)?;
+  let mut table = Dict::new();
+  match args.first() {
+    Some(Value::Dict(other)) => {
+      for (key, value) in other.borrow().entries() {
+        table.insert(key.clone(), value.clone())?;
+      }
+    }
+    Some(source) => {
+      for (i, item) in source.items(caller)?.iter().enumerate() {
+        let pair = match item {
+          Value::Iterator(_) | Value::List(_) | Value::Tuple(_) | Value::Str(_) | Value::Bytes(_) | Value::Dict(_) | Value::Set(_) | Value::Range(..) => item.items(caller)?,
+          _ => return Err(format!("TypeError: cannot convert dictionary update sequence element #{} to a sequence", i)),
+        };
+        match <[Value; 2]>::try_from(pair) {
+          Ok([key, value]) => table.insert(key, value)?,
+          Err(pair) => return Err(format!("ValueError: dictionary update sequence element #{} has length {}; 2 is required", i, pair.len())),
+        }
+      }
+    }
+    None => {}
+  }
+  for (name, value) in kwargs {
+    table.insert(Value::new_str(name), value.clone())?;
+  }
+  Ok(Value::Dict(Rc::new(RefCell::new(table))))
+}
+
+// float(x=0.0): from a number or a string
+fn type_float(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("float", kwargs)?;
+  expect_args("float", args, 0, 1)?;
+  match args.first() {
+    None => Ok(Value::Float(0.0)),
+    Some(Value::Float(d)) => Ok(Value::Float(*d)),
+    Some(Value::Int(n)) => Ok(Value::Float(*n as f64)),
+    Some(Value::Bool(b)) => Ok(Value::Float(*b as i64 as f64)),
+    Some(Value::Str(s)) => parse_float(s),
+    Some(value) => Err(format!("TypeError: float() argument must be a string or a real number, not '{}'", value.type_name())),
+  }
+}
+
+// Parses a float literal as float() does: optional whitespace and sign, digits from any script
+// with underscores between them, and the names inf, infinity and nan in any case
+fn parse_float(text: &str) -> Result<Value, String> {
+  let invalid = || format!("ValueError: could not convert string to float: {}", repr_str(text));
+  let chars: Vec<char> = text.trim_matches(unicode::is_space).chars().map(|c| unicode::decimal_value(c).map_or(c, |d| (b'0' + d as u8) as char)).collect();
+  let mut literal = String::with_capacity(chars.len());
+  for (i, c) in chars.iter().enumerate() {
+    if *c != '_' {
+      literal.push(*c);
+    } else if !(i > 0 && chars[i - 1].is_ascii_digit() && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+      return Err(invalid());
+    }
+  }
+  literal.parse::<f64>().map(Value::Float).map_err(|_| invalid())
+}
+
+// int(x=0, base=10): from a number, truncating floats, or from a string in the given base
+fn type_int(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let bound = arguments("int", args, kwargs, &["", "base"], 0)?;
+  match (&bound[0], &bound[1]) {
+    (None, None) => Ok(Value::Int(0)),
+    (None, Some(_)) => Err("TypeError: int() missing string argument".to_string()),
+    (Some(Value::Str(s)), base) => {
+      let base = match base {
+        Some(base) => int_arg(base)?,
+        None => 10,
+      };
+      if base != 0 && !(2..=36).contains(&base) {
+        return Err("ValueError: int() base must be >= 2 and <= 36, or 0".to_string());
+      }
+      parse_int(s, base as u32).map(Value::Int)
+    }
+    (Some(_), Some(_)) => Err("TypeError: int() can't convert non-string with explicit base".to_string()),
+    (Some(Value::Int(n)), None) => Ok(Value::Int(*n)),
+    (Some(Value::Bool(b)), None) => Ok(Value::Int(*b as i64)),
+    (Some(Value::Float(d)), None) => float_to_int(d.trunc()),
+    (Some(value), None) => Err(format!("TypeError: int() argument must be a string, a bytes-like object or a real number, not '{}'", value.type_name())),
+  }
+}
+
+// Parses an int literal as int() does: optional whitespace and sign, a 0x/0o/0b prefix when it
+// matches the base, and digits from any script with underscores between them. Base 0 takes the
+// base from the prefix, defaulting to 10
+fn parse_int(text: &str, base: u32) -> Result<i64, String> {
+  let invalid = || format!("ValueError: invalid literal for int() with base {}: {}", base, repr_str(text));
+  let s = text.trim_matches(unicode::is_space);
+  let (negative, s) = match s.strip_prefix('-') {
+    Some(rest) => (true, rest),
+    None => (false, s.strip_prefix('+').unwrap_or(s)),
+  };
+  let prefix_base = match s.get(..2).map(|prefix| prefix.to_ascii_lowercase()).as_deref() {
+    Some("0x") => 16,
+    Some("0o") => 8,
+    Some("0b") => 2,
+    _ => 0,
+  };
+  let (radix, digits) = match base {
+    _ if prefix_base != 0 && (base == 0 || base == prefix_base) => (prefix_base, s[2..].strip_prefix('_').unwrap_or(&s[2..])),
+    0 => (10, s),
+    _ => (base, s),
+  };
+  if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+    return Err(invalid());
+  }
+
+  let mut value: i128 = 0;
+  for c in digits.chars().filter(|c| *c != '_') {
+    match unicode::decimal_value(c).or_else(|| c.to_digit(36)) {
+      Some(digit) if digit < radix => value = (value * radix as i128 + digit as i128).min(i64::MAX as i128 + 2),
+      _ => return Err(invalid()),
+    }
+  }
+  // Leading zeros are only allowed in base 0 when the value is zero, as in Python source
+  if base == 0 && prefix_base == 0 && value != 0 && digits.starts_with('0') {
+    return Err(invalid());
+  }
+  i64::try_from(if negative { -value } else { value }).map_err(|_| "OverflowError: integer overflow".to_string())
+}
+
+// list(iterable=())
+fn type_list(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("list", kwargs)?;
+  expect_args("list", args, 0, 1)?;
+  match args.first() {
+    Some(iterable) => Ok(Value::new_list(iterable.items(caller)?)),
+    None => Ok(Value::new_list(Vec::new())),
+  }
+}
+
+// range(stop) or range(start, stop, step=1)
+fn type_range(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("range", kwargs)?;
+  expect_args("range", args, 1, 3)?;
+  let bounds = args.iter().map(int_arg).collect::<Result<Vec<_>, _>>()?;
+  match bounds[..] {
+    [stop] => Ok(Value::Range(0, stop, 1)),
+    [start, stop] => Ok(Value::Range(start, stop, 1)),
+    [_, _, 0] => Err("ValueError: range() arg 3 must not be zero".to_string()),
+    [start, stop, step] => Ok(Value::Range(start, stop, step)),
+    _ => unreachable!("range() takes 1 to 3 arguments"),
+  }
+}
+
+// set(iterable=())
+fn type_set(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("set", kwargs)?;
+  expect_args("set", args, 0, 1)?;
+  let mut table = Dict::new();
+  if let Some(iterable) = args.first() {
+    for item in iterable.items(caller)? {
+      table.insert(item, Value::None)?;
+    }
+  }
+  Ok(Value::Set(Rc::new(RefCell::new(table))))
+}
+
+// str(object=''). Decoding bytes with an encoding is not supported
+fn type_str(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let bound = arguments("str", args, kwargs, &["object"], 0)?;
+  match &bound[0] {
+    Some(Value::Str(s)) => Ok(Value::Str(s.clone())),
+    Some(value) => Ok(Value::new_str(&value.str()?)),
+    None => Ok(Value::new_str("")),
+  }
+}
+
+// tuple(iterable=())
+fn type_tuple(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("tuple", kwargs)?;
+  expect_args("tuple", args, 0, 1)?;
+  match args.first() {
+    Some(Value::Tuple(items)) => Ok(Value::Tuple(items.clone())),
+    Some(iterable) => Ok(Value::Tuple(Rc::new(iterable.items(caller)?))),
+    None => Ok(Value::Tuple(Rc::new(Vec::new()))),
+  }
+}
+
+// type(object): the type of a value. There are no classes, so the three-argument form that
+// creates one is not supported
+fn type_type(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  no_keywords("type", kwargs)?;
+  match args {
+    [value] => Ok(Value::Type(value.type_name())),
+    [_, _, _] => Err("TypeError: type() can't create new classes".to_string()),
+    _ => Err("TypeError: type() takes 1 or 3 arguments".to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::compile_script;
+  use crate::vm::Vm;
+  use std::io::{self, Write};
+
+  // An output sink whose bytes stay readable after it is handed to stdio
+  #[derive(Clone, Default)]
+  struct Capture(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  // Runs f with the output going to a fresh sink, returning its result and what was written
+  fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let capture = Capture::default();
+    let previous = stdio::set_output(Stream::Stdout, Box::new(capture.clone()));
+    let result = f();
+    stdio::set_output(Stream::Stdout, previous);
+    let written = String::from_utf8(capture.0.borrow().clone()).unwrap();
+    (result, written)
+  }
+
+  // Calls a builtin, returning the repr of its result and what it wrote
+  fn captured(name: &str, args: Vec<Value>, kwargs: Vec<(String, Value)>) -> (Result<String, String>, String) {
+    capture_output(|| call(&lookup(name).unwrap(), args, &kwargs, &mut Vm::new(false)).and_then(|value| value.repr()))
+  }
+
+  // Compiles and runs a script on the VM, returning what it printed
+  fn run_script(source: &str) -> String {
+    let code = compile_script(source, false).unwrap();
+    let (result, written) = capture_output(|| Vm::new(false).run_module(Rc::new(code)));
+    result.unwrap();
+    written
+  }
+
+  #[test]
+  fn print_writes_to_the_output_sink() {
+    let args = vec![Value::Int(1), Value::new_str("two"), Value::Float(3.0)];
+    let (result, written) = captured("print", args.clone(), Vec::new());
+    assert_eq!((result, written.as_str()), (Ok("None".to_string()), "1 two 3.0\n"));
+
+    let kwargs = vec![("sep".to_string(), Value::new_str(", ")), ("end".to_string(), Value::new_str("!"))];
+    let (_, written) = captured("print", args.clone(), kwargs);
+    assert_eq!(written, "1, two, 3.0!");
+
+    let kwargs = vec![("sep".to_string(), Value::None), ("end".to_string(), Value::None), ("file".to_string(), Value::None)];
+    let (_, written) = captured("print", args, kwargs);
+    assert_eq!(written, "1 two 3.0\n");
+  }
+
+  #[test]
+  fn print_rejects_a_file_object() {
+    let (result, written) = captured("print", vec![Value::Int(1)], vec![("file".to_string(), Value::Int(2))]);
+    assert_eq!(result, Err("AttributeError: 'int' object has no attribute 'write'".to_string()));
+    assert_eq!(written, "");
+  }
+
+  #[test]
+  fn input_reads_from_the_input_source() {
+    let previous = stdio::set_input(Some(Box::new(io::Cursor::new(b"first line\r\nsecond".to_vec()))));
+    let (first, prompt) = captured("input", vec![Value::new_str("> ")], Vec::new());
+    let (second, _) = captured("input", Vec::new(), Vec::new());
+    let (end, _) = captured("input", Vec::new(), Vec::new());
+    stdio::set_input(previous);
+    assert_eq!(prompt, "> ");
+    assert_eq!((first, second), (Ok("'first line'".to_string()), Ok("'second'".to_string())));
+    assert_eq!(end, Err("EOFError: EOF when reading a line".to_string()));
+  }
+
+  #[test]
+  fn dis_shows_the_source_lines_of_a_script() {
+    let written = run_script("def add(a, b):\n    # the sum\n    total = a + b\n    return total\n\ndis(add)\n");
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines[0].trim(), "# total = a + b");
+    assert!(lines[1].starts_with("   3         0 LOAD_FAST"));
+    assert!(lines.contains(&"            # return total"));
+  }
+}
@@ -0,0 +1,355 @@
+use crate::types::Op;
+use crate::value::{Signature, Value};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+// Version of the instruction set and of the cache file layout. Bump it whenever either changes so
+// that stale cache files are recompiled instead of misread
+pub const BYTECODE_VERSION: u32 = 1;
+
+// Operators in the order they are numbered in BinaryOp, InplaceOp and CompareOp arguments
+const OPERATORS: [Op; 24] = [
+  Op::Add, Op::Sub, Op::Mult, Op::Div, Op::Or, Op::And, Op::Equal, Op::NotEqual, Op::Less, Op::Greater,
+  Op::LessEqual, Op::GreaterEqual, Op::In, Op::NotIn, Op::Is, Op::IsNot, Op::Mod, Op::FloorDiv, Op::Pow,
+  Op::BitAnd, Op::BitOr, Op::BitXor, Op::LShift, Op::RShift,
+];
+
+// Opcode names, indexed by opcode number
+const OPCODE_NAMES: [&str; 51] = [
+  "LOAD_CONST", "LOAD_FAST", "STORE_FAST", "LOAD_DEREF", "STORE_DEREF", "LOAD_GLOBAL", "STORE_GLOBAL", "POP_TOP",
+  "DUP_TOP", "ROT_TWO", "ROT_THREE", "UNARY_NOT", "BINARY_OP", "COMPARE_OP", "JUMP", "POP_JUMP_IF_FALSE",
+  "JUMP_IF_FALSE_OR_POP", "JUMP_IF_TRUE_OR_POP", "GET_ITER", "FOR_ITER", "BUILD_LIST", "BUILD_TUPLE", "BUILD_MAP",
+  "BUILD_SET", "MAKE_FUNCTION", "CALL_FUNCTION", "RETURN_VALUE", "FORMAT_VALUE", "BUILD_STRING",
+  "LOAD_ATTR", "CALL_FUNCTION_KW", "BUILD_SLICE", "BINARY_SUBSCR", "LIST_APPEND", "LIST_EXTEND",
+  "DICT_MERGE", "CALL_FUNCTION_EX", "YIELD_VALUE", "YIELD_FROM",
+  "SET_ADD", "MAP_ADD", "INPLACE_OP", "DUP_TOP_TWO", "STORE_SUBSCR", "STORE_ATTR",
+  "DELETE_FAST", "DELETE_DEREF", "DELETE_GLOBAL", "DELETE_SUBSCR", "DELETE_ATTR",
+  "RAISE_ASSERTION",
+];
+
+// FormatValue flags: the low two bits select the conversion applied before formatting
+pub const FORMAT_STR: u32 = 1;   // !s
+pub const FORMAT_REPR: u32 = 2;  // !r
+pub const FORMAT_ASCII: u32 = 3; // !a
+pub const FORMAT_WITH_SPEC: u32 = 4; // the format spec is on the stack above the value
+
+// CodeObject flags
+pub const CODE_VARARGS: u32 = 4;        // has a *args parameter
+pub const CODE_VARKEYWORDS: u32 = 8;    // has a **kwargs parameter
+pub const CODE_GENERATOR: u32 = 0x20;   // calling the function creates a generator instead of running the body
+pub const CODE_DEFAULTS: u32 = 0x100;   // MakeFunction pops a tuple of positional parameter defaults
+pub const CODE_KWDEFAULTS: u32 = 0x200; // MakeFunction pops a dict of keyword-only parameter defaults, pushed after the tuple
+
+// CallFunctionEx flag: a dict of keyword arguments is on the stack above the list of positional ones
+pub const CALL_WITH_KEYWORDS: u32 = 1;
+
+// A single VM instruction. Arguments index into the tables of the CodeObject being run,
+// except for jump targets, which are instruction offsets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instr {
+  LoadConst(u32),   // constants[i]
+  LoadFast(u32),    // local slot i
+  StoreFast(u32),
+  LoadDeref(u32),   // cell i, counting cellvars then freevars
+  StoreDeref(u32),
+  LoadGlobal(u32),  // names[i]
+  StoreGlobal(u32),
+  PopTop,
+  DupTop,
+  RotTwo,
+  RotThree,
+  UnaryNot,
+  BinaryOp(Op),
+  CompareOp(Op),
+  Jump(u32),
+  PopJumpIfFalse(u32),
+  JumpIfFalseOrPop(u32),
+  JumpIfTrueOrPop(u32),
+  GetIter,
+  ForIter(u32),     // pushes the next item, or pops the iterator and jumps when it is exhausted
+  BuildList(u32),
+  BuildTuple(u32),
+  BuildDict(u32),   // number of key-value pairs
+  BuildSet(u32),
+  MakeFunction(u32), // constants[i] is the function's code, after any defaults (see the CODE_ flags)
+  CallFunction(u32), // number of arguments
+  ReturnValue,
+  FormatValue(u32), // formats the value on the stack (see the FORMAT_ flags)
+  BuildString(u32), // joins this many strings
+  LoadAttr(u32),    // replaces the object on the stack with its attribute names[i]
+  CallFunctionKw(u32), // number of arguments, the last of which are named by the tuple of names on top of the stack
+  BuildSlice(u32),  // slice from 2 or 3 values: start, stop and maybe step
+  BinarySubscr,     // replaces the container and key on the stack with container[key]
+  ListAppend(u32),  // adds the value on the stack to the list i deep once it is popped, which for calls is the argument list below it
+  ListExtend,       // adds the items of *iterable to the argument list below it, with the function being called below that
+  DictMerge,        // adds the entries of **mapping to the keyword argument dict below it, with the function 3 below that
+  CallFunctionEx(u32), // calls with an argument list and maybe a keyword argument dict (see CALL_WITH_KEYWORDS)
+  YieldValue,       // suspends the generator with the value on the stack, which is replaced by the value sent on resuming
+  YieldFrom,        // sends the value on the stack to the iterator below it, suspending until the iterator returns its value
+  SetAdd(u32),      // adds the value on the stack to the set i deep once it is popped
+  MapAdd(u32),      // adds the key and value on the stack to the dict i deep once they are popped
+  InplaceOp(Op),    // like BinaryOp, but updates a list, set or dict on the left in place
+  DupTopTwo,
+  StoreSubscr,      // container[key] = value, with the key on top of the stack, then the container, then the value
+  StoreAttr(u32),   // object.names[i] = value, with the object on top of the stack and the value below it
+  DeleteFast(u32),
+  DeleteDeref(u32),
+  DeleteGlobal(u32),
+  DeleteSubscr,     // del container[key], with the key on top of the stack and the container below it
+  DeleteAttr(u32),  // del object.names[i]
+  RaiseAssertion(u32), // raises AssertionError, with the message on the stack if the argument is 1
+}
+
+impl Instr {
+  pub fn opcode(&self) -> u8 {
+    match self {
+      Instr::LoadConst(_) => 0,
+      Instr::LoadFast(_) => 1,
+      Instr::StoreFast(_) => 2,
+      Instr::LoadDeref(_) => 3,
+      Instr::StoreDeref(_) => 4,
+      Instr::LoadGlobal(_) => 5,
+      Instr::StoreGlobal(_) => 6,
+      Instr::PopTop => 7,
+      Instr::DupTop => 8,
+      Instr::RotTwo => 9,
+      Instr::RotThree => 10,
+      Instr::UnaryNot => 11,
+      Instr::BinaryOp(_) => 12,
+      Instr::CompareOp(_) => 13,
+      Instr::Jump(_) => 14,
+      Instr::PopJumpIfFalse(_) => 15,
+      Instr::JumpIfFalseOrPop(_) => 16,
+      Instr::JumpIfTrueOrPop(_) => 17,
+      Instr::GetIter => 18,
+      Instr::ForIter(_) => 19,
+      Instr::BuildList(_) => 20,
+      Instr::BuildTuple(_) => 21,
+      Instr::BuildDict(_) => 22,
+      Instr::BuildSet(_) => 23,
+      Instr::MakeFunction(_) => 24,
+      Instr::CallFunction(_) => 25,
+      Instr::ReturnValue => 26,
+      Instr::FormatValue(_) => 27,
+      Instr::BuildString(_) => 28,
+      Instr::LoadAttr(_) => 29,
+      Instr::CallFunctionKw(_) => 30,
+      Instr::BuildSlice(_) => 31,
+      Instr::BinarySubscr => 32,
+      Instr::ListAppend(_) => 33,
+      Instr::ListExtend => 34,
+      Instr::DictMerge => 35,
+      Instr::CallFunctionEx(_) => 36,
+      Instr::YieldValue => 37,
+      Instr::YieldFrom => 38,
+      Instr::SetAdd(_) => 39,
+      Instr::MapAdd(_) => 40,
+      Instr::InplaceOp(_) => 41,
+      Instr::DupTopTwo => 42,
+      Instr::StoreSubscr => 43,
+      Instr::StoreAttr(_) => 44,
+      Instr::DeleteFast(_) => 45,
+      Instr::DeleteDeref(_) => 46,
+      Instr::DeleteGlobal(_) => 47,
+      Instr::DeleteSubscr => 48,
+      Instr::DeleteAttr(_) => 49,
+      Instr::RaiseAssertion(_) => 50,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    OPCODE_NAMES[self.opcode() as usize]
+  }
+
+  // Numeric argument of the instruction, if it takes one
+  pub fn arg(&self) -> Option<u32> {
+    match *self {
+      Instr::LoadConst(i) | Instr::LoadFast(i) | Instr::StoreFast(i) | Instr::LoadDeref(i) | Instr::StoreDeref(i)
+      | Instr::LoadGlobal(i) | Instr::StoreGlobal(i) | Instr::Jump(i) | Instr::PopJumpIfFalse(i)
+      | Instr::JumpIfFalseOrPop(i) | Instr::JumpIfTrueOrPop(i) | Instr::ForIter(i) | Instr::BuildList(i)
+      | Instr::BuildTuple(i) | Instr::BuildDict(i) | Instr::BuildSet(i) | Instr::MakeFunction(i)
+      | Instr::CallFunction(i) | Instr::FormatValue(i) | Instr::BuildString(i) | Instr::LoadAttr(i)
+      | Instr::CallFunctionKw(i) | Instr::BuildSlice(i) | Instr::CallFunctionEx(i) | Instr::ListAppend(i)
+      | Instr::SetAdd(i) | Instr::MapAdd(i) | Instr::StoreAttr(i) | Instr::DeleteFast(i) | Instr::DeleteDeref(i)
+      | Instr::DeleteGlobal(i) | Instr::DeleteAttr(i) | Instr::RaiseAssertion(i) => Some(i),
+      Instr::BinaryOp(op) | Instr::InplaceOp(op) | Instr::CompareOp(op) => Some(OPERATORS.iter().position(|o| *o == op).unwrap() as u32),
+      _ => None,
+    }
+  }
+
+  // Rebuilds an instruction from its opcode and argument, as stored in cache files
+  pub fn decode(opcode: u8, arg: u32) -> Option<Instr> {
+    let op = OPERATORS.get(arg as usize).copied();
+    match opcode {
+      0 => Some(Instr::LoadConst(arg)),
+      1 => Some(Instr::LoadFast(arg)),
+      2 => Some(Instr::StoreFast(arg)),
+      3 => Some(Instr::LoadDeref(arg)),
+      4 => Some(Instr::StoreDeref(arg)),
+      5 => Some(Instr::LoadGlobal(arg)),
+      6 => Some(Instr::StoreGlobal(arg)),
+      7 => Some(Instr::PopTop),
+      8 => Some(Instr::DupTop),
+      9 => Some(Instr::RotTwo),
+      10 => Some(Instr::RotThree),
+      11 => Some(Instr::UnaryNot),
+      12 => op.map(Instr::BinaryOp),
+      13 => op.map(Instr::CompareOp),
+      14 => Some(Instr::Jump(arg)),
+      15 => Some(Instr::PopJumpIfFalse(arg)),
+      16 => Some(Instr::JumpIfFalseOrPop(arg)),
+      17 => Some(Instr::JumpIfTrueOrPop(arg)),
+      18 => Some(Instr::GetIter),
+      19 => Some(Instr::ForIter(arg)),
+      20 => Some(Instr::BuildList(arg)),
+      21 => Some(Instr::BuildTuple(arg)),
+      22 => Some(Instr::BuildDict(arg)),
+      23 => Some(Instr::BuildSet(arg)),
+      24 => Some(Instr::MakeFunction(arg)),
+      25 => Some(Instr::CallFunction(arg)),
+      26 => Some(Instr::ReturnValue),
+      27 => Some(Instr::FormatValue(arg)),
+      28 => Some(Instr::BuildString(arg)),
+      29 => Some(Instr::LoadAttr(arg)),
+      30 => Some(Instr::CallFunctionKw(arg)),
+      31 => Some(Instr::BuildSlice(arg)),
+      32 => Some(Instr::BinarySubscr),
+      33 => Some(Instr::ListAppend(arg)),
+      34 => Some(Instr::ListExtend),
+      35 => Some(Instr::DictMerge),
+      36 => Some(Instr::CallFunctionEx(arg)),
+      37 => Some(Instr::YieldValue),
+      38 => Some(Instr::YieldFrom),
+      39 => Some(Instr::SetAdd(arg)),
+      40 => Some(Instr::MapAdd(arg)),
+      41 => op.map(Instr::InplaceOp),
+      42 => Some(Instr::DupTopTwo),
+      43 => Some(Instr::StoreSubscr),
+      44 => Some(Instr::StoreAttr(arg)),
+      45 => Some(Instr::DeleteFast(arg)),
+      46 => Some(Instr::DeleteDeref(arg)),
+      47 => Some(Instr::DeleteGlobal(arg)),
+      48 => Some(Instr::DeleteSubscr),
+      49 => Some(Instr::DeleteAttr(arg)),
+      50 => Some(Instr::RaiseAssertion(arg)),
+      _ => None,
+    }
+  }
+
+  // Offset this instruction may jump to
+  pub fn jump_target(&self) -> Option<u32> {
+    match *self {
+      Instr::Jump(target) | Instr::PopJumpIfFalse(target) | Instr::JumpIfFalseOrPop(target)
+      | Instr::JumpIfTrueOrPop(target) | Instr::ForIter(target) => Some(target),
+      _ => None,
+    }
+  }
+}
+
+// Literal values embedded in compiled code
+#[derive(Clone, Debug)]
+pub enum Constant {
+  None,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Str(Rc<str>),
+  Bytes(Rc<[u8]>),
+  Tuple(Vec<Constant>),
+  Code(Rc<CodeObject>),
+}
+
+impl Constant {
+  pub fn to_value(&self) -> Value {
+    match self {
+      Constant::None => Value::None,
+      Constant::Bool(b) => Value::Bool(*b),
+      Constant::Int(n) => Value::Int(*n),
+      Constant::Float(d) => Value::Float(*d),
+      Constant::Str(s) => Value::Str(s.clone()),
+      Constant::Bytes(b) => Value::Bytes(b.clone()),
+      Constant::Tuple(items) => Value::Tuple(Rc::new(items.iter().map(Constant::to_value).collect())),
+      Constant::Code(_) => unreachable!("code constants are only used by MakeFunction"),
+    }
+  }
+
+}
+
+// Constants are deduplicated by value and type, so 1, 1.0 and True stay separate entries
+impl PartialEq for Constant {
+  fn eq(&self, other: &Constant) -> bool {
+    match (self, other) {
+      (Constant::None, Constant::None) => true,
+      (Constant::Bool(b1), Constant::Bool(b2)) => b1 == b2,
+      (Constant::Int(n1), Constant::Int(n2)) => n1 == n2,
+      (Constant::Float(d1), Constant::Float(d2)) => d1.to_bits() == d2.to_bits(),
+      (Constant::Str(s1), Constant::Str(s2)) => s1 == s2,
+      (Constant::Bytes(b1), Constant::Bytes(b2)) => b1 == b2,
+      (Constant::Tuple(t1), Constant::Tuple(t2)) => t1 == t2,
+      (Constant::Code(c1), Constant::Code(c2)) => Rc::ptr_eq(c1, c2),
+      _ => false,
+    }
+  }
+}
+
+impl Eq for Constant {}
+
+impl Hash for Constant {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    mem::discriminant(self).hash(state);
+    match self {
+      Constant::None => {}
+      Constant::Bool(b) => b.hash(state),
+      Constant::Int(n) => n.hash(state),
+      Constant::Float(d) => d.to_bits().hash(state),
+      Constant::Str(s) => s.hash(state),
+      Constant::Bytes(b) => b.hash(state),
+      Constant::Tuple(items) => items.hash(state),
+      Constant::Code(code) => Rc::as_ptr(code).hash(state),
+    }
+  }
+}
+
+// Compiled body of a module or function
+#[derive(Debug)]
+pub struct CodeObject {
+  pub name: String,
+  pub first_line: u32,        // line of the def statement, or 1 for a module
+  pub argcount: u32,          // positional parameters, which with the other parameters occupy the first local slots
+  pub posonlyargcount: u32,
+  pub kwonlyargcount: u32,
+  pub flags: u32,             // see the CODE_ flags
+  pub instructions: Vec<Instr>,
+  pub lines: Vec<u32>,        // source line of each instruction
+  pub constants: Vec<Constant>,
+  pub names: Vec<String>,     // global names
+  pub varnames: Vec<String>,  // local slot names
+  pub cellvars: Vec<String>,  // locals captured by nested functions
+  pub freevars: Vec<String>,  // variables captured from enclosing functions
+  pub source: Option<Rc<str>>, // text of the script the code was compiled from, which dis() shows, None in the REPL
+}
+
+impl CodeObject {
+  pub fn source_lines(&self) -> Option<Vec<&str>> {
+    self.source.as_ref().map(|source| source.lines().collect())
+  }
+
+  // Number of local slots taken by parameters: positional, keyword-only, *args then **kwargs
+  pub fn param_count(&self) -> usize {
+    let varargs = (self.flags & CODE_VARARGS != 0) as u32;
+    let varkw = (self.flags & CODE_VARKEYWORDS != 0) as u32;
+    (self.argcount + self.kwonlyargcount + varargs + varkw) as usize
+  }
+
+  pub fn signature(&self) -> Signature {
+    Signature {
+      names: self.varnames[..self.param_count()].to_vec(),
+      argcount: self.argcount as usize,
+      posonly: self.posonlyargcount as usize,
+      kwonly: self.kwonlyargcount as usize,
+      varargs: self.flags & CODE_VARARGS != 0,
+      varkw: self.flags & CODE_VARKEYWORDS != 0,
+    }
+  }
+}
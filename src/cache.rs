@@ -0,0 +1,319 @@
+use crate::bytecode::{CodeObject, Constant, Instr, BYTECODE_VERSION};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Compiled scripts are cached next to their source (script.py -> script.tpyc, or script.opt-1.tpyc
+// when compiled with -O) so that unchanged scripts skip parsing and compiling. A cache file holds:
+//   magic "TPYC", bytecode version (u32), hash of the source (u64), module code
+// and is only used when the version and the hash both match. Integers are little-endian
+const MAGIC: &[u8; 4] = b"TPYC";
+
+pub fn cache_path(source_path: &str, optimize: bool) -> PathBuf {
+  Path::new(source_path).with_extension(if optimize { "opt-1.tpyc" } else { "tpyc" })
+}
+
+// 64-bit FNV-1a, which unlike std's hasher is stable between Rust versions
+pub fn source_hash(source: &str) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in source.bytes() {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+// Returns the cached code for source, or None if there is no usable cache file
+pub fn load(source_path: &str, source: &str, optimize: bool) -> Option<CodeObject> {
+  let bytes = fs::read(cache_path(source_path, optimize)).ok()?;
+  let mut reader = Reader { bytes: &bytes, pos: 0, source: Rc::from(source) };
+
+  if reader.take(4)? != MAGIC || reader.u32()? != BYTECODE_VERSION || reader.u64()? != source_hash(source) {
+    return None;
+  }
+  let code = reader.code()?;
+  if reader.pos != bytes.len() {
+    return None;
+  }
+  Some(code)
+}
+
+// Writes the cache file for source. Failing to write it (such as in a read-only directory) only
+// means the script is compiled again next time, so callers can ignore the error
+pub fn store(source_path: &str, source: &str, optimize: bool, code: &CodeObject) -> std::io::Result<()> {
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(MAGIC);
+  bytes.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+  bytes.extend_from_slice(&source_hash(source).to_le_bytes());
+  write_code(&mut bytes, code);
+  fs::write(cache_path(source_path, optimize), bytes)
+}
+
+fn write_u32(bytes: &mut Vec<u8>, n: u32) {
+  bytes.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+  write_u32(bytes, s.len() as u32);
+  bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_strs(bytes: &mut Vec<u8>, strs: &[String]) {
+  write_u32(bytes, strs.len() as u32);
+  for s in strs {
+    write_str(bytes, s);
+  }
+}
+
+fn write_code(bytes: &mut Vec<u8>, code: &CodeObject) {
+  write_str(bytes, &code.name);
+  write_u32(bytes, code.first_line);
+  write_u32(bytes, code.argcount);
+  write_u32(bytes, code.posonlyargcount);
+  write_u32(bytes, code.kwonlyargcount);
+  write_u32(bytes, code.flags);
+
+  // Each instruction is its opcode followed by its argument (0 when it takes none) and source line
+  write_u32(bytes, code.instructions.len() as u32);
+  for (instr, line) in code.instructions.iter().zip(&code.lines) {
+    bytes.push(instr.opcode());
+    write_u32(bytes, instr.arg().unwrap_or(0));
+    write_u32(bytes, *line);
+  }
+
+  write_u32(bytes, code.constants.len() as u32);
+  for constant in &code.constants {
+    write_constant(bytes, constant);
+  }
+
+  write_strs(bytes, &code.names);
+  write_strs(bytes, &code.varnames);
+  write_strs(bytes, &code.cellvars);
+  write_strs(bytes, &code.freevars);
+}
+
+// Each constant is a tag byte followed by its value
+fn write_constant(bytes: &mut Vec<u8>, constant: &Constant) {
+  match constant {
+    Constant::None => bytes.push(0),
+    Constant::Bool(b) => {
+      bytes.push(1);
+      bytes.push(*b as u8);
+    }
+    Constant::Int(n) => {
+      bytes.push(2);
+      bytes.extend_from_slice(&n.to_le_bytes());
+    }
+    Constant::Float(d) => {
+      bytes.push(3);
+      bytes.extend_from_slice(&d.to_bits().to_le_bytes());
+    }
+    Constant::Str(s) => {
+      bytes.push(4);
+      write_str(bytes, s);
+    }
+    Constant::Bytes(b) => {
+      bytes.push(6);
+      write_u32(bytes, b.len() as u32);
+      bytes.extend_from_slice(b);
+    }
+    Constant::Tuple(items) => {
+      bytes.push(7);
+      write_u32(bytes, items.len() as u32);
+      for item in items {
+        write_constant(bytes, item);
+      }
+    }
+    Constant::Code(inner) => {
+      bytes.push(5);
+      write_code(bytes, inner);
+    }
+  }
+}
+
+// Reads back what write_code wrote. Any malformed input gives None
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  source: Rc<str>, // the script the cache is for, which the cache file doesn't hold
+}
+
+impl Reader<'_> {
+  fn take(&mut self, n: usize) -> Option<&[u8]> {
+    let slice = self.bytes.get(self.pos..self.pos.checked_add(n)?)?;
+    self.pos += n;
+    Some(slice)
+  }
+
+  fn u8(&mut self) -> Option<u8> {
+    Some(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Option<u32> {
+    Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+  }
+
+  fn u64(&mut self) -> Option<u64> {
+    Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+  }
+
+  fn str(&mut self) -> Option<String> {
+    let len = self.u32()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).ok()
+  }
+
+  fn strs(&mut self) -> Option<Vec<String>> {
+    let count = self.u32()?;
+    (0..count).map(|_| self.str()).collect()
+  }
+
+  fn code(&mut self) -> Option<CodeObject> {
+    let name = self.str()?;
+    let first_line = self.u32()?;
+    let argcount = self.u32()?;
+    let posonlyargcount = self.u32()?;
+    let kwonlyargcount = self.u32()?;
+    let flags = self.u32()?;
+
+    let count = self.u32()?;
+    let mut instructions = Vec::new();
+    let mut lines = Vec::new();
+    for _ in 0..count {
+      let opcode = self.u8()?;
+      let arg = self.u32()?;
+      instructions.push(Instr::decode(opcode, arg)?);
+      lines.push(self.u32()?);
+    }
+
+    let count = self.u32()?;
+    let constants = (0..count).map(|_| self.constant()).collect::<Option<Vec<_>>>()?;
+
+    let names = self.strs()?;
+    let varnames = self.strs()?;
+    let cellvars = self.strs()?;
+    let freevars = self.strs()?;
+
+    Some(CodeObject {
+      name,
+      first_line,
+      argcount,
+      posonlyargcount,
+      kwonlyargcount,
+      flags,
+      instructions,
+      lines,
+      constants,
+      names,
+      varnames,
+      cellvars,
+      freevars,
+      source: Some(self.source.clone()),
+    })
+  }
+
+  fn constant(&mut self) -> Option<Constant> {
+    let constant = match self.u8()? {
+      0 => Constant::None,
+      1 => Constant::Bool(self.u8()? != 0),
+      2 => Constant::Int(self.u64()? as i64),
+      3 => Constant::Float(f64::from_bits(self.u64()?)),
+      4 => Constant::Str(Rc::from(self.str()?)),
+      5 => Constant::Code(Rc::new(self.code()?)),
+      6 => {
+        let len = self.u32()? as usize;
+        Constant::Bytes(Rc::from(self.take(len)?))
+      }
+      7 => {
+        let count = self.u32()?;
+        Constant::Tuple((0..count).map(|_| self.constant()).collect::<Option<Vec<_>>>()?)
+      }
+      _ => return None,
+    };
+    Some(constant)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::compile_script;
+  use crate::disassembler::disassemble;
+
+  const SOURCE: &str = "\
+def scale(x, *rest, factor=2.5, **options):
+    total = 0
+    def inner():
+        return total
+    for item in (1, 'two', b'three', None, True):
+        total = total + 1
+    return ([x * factor for x in rest], inner)
+
+print(scale(1, 2, 3), -1e300, 'caf\\u00e9')
+";
+
+  // A script path in a directory of its own, so tests running at the same time don't share cache files
+  fn script_path(test: &str) -> (PathBuf, String) {
+    let dir = std::env::temp_dir().join(format!("tompython-cache-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("script.py").to_str().unwrap().to_string();
+    (dir, path)
+  }
+
+  #[test]
+  fn cached_code_round_trips() {
+    let (dir, path) = script_path("round-trip");
+    let code = compile_script(SOURCE, false).unwrap();
+    store(&path, SOURCE, false, &code).unwrap();
+    let loaded = load(&path, SOURCE, false).expect("the cache file was rejected");
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The listing shows every instruction, line, constant and name of the module and its functions
+    let lines = code.source_lines();
+    assert_eq!(disassemble(&loaded, lines.as_deref()), disassemble(&code, lines.as_deref()));
+    assert_eq!(loaded.source.as_deref(), Some(SOURCE));
+
+    let function = |code: &CodeObject| {
+      let mut functions = code.constants.iter().filter_map(|constant| match constant {
+        Constant::Code(function) => Some(function.clone()),
+        _ => None,
+      });
+      functions.next().expect("the module defines a function")
+    };
+    let (original, loaded) = (function(&code), function(&loaded));
+    assert_eq!((loaded.argcount, loaded.kwonlyargcount, loaded.flags), (original.argcount, original.kwonlyargcount, original.flags));
+    assert_eq!((&loaded.varnames, &loaded.cellvars), (&original.varnames, &original.cellvars));
+  }
+
+  #[test]
+  fn cache_is_rejected_when_stale_or_damaged() {
+    let (dir, path) = script_path("rejection");
+    let code = compile_script(SOURCE, false).unwrap();
+    store(&path, SOURCE, false, &code).unwrap();
+    let cache = cache_path(&path, false);
+    let bytes = fs::read(&cache).unwrap();
+
+    // A changed source, or a cache written with the other optimization level
+    assert!(load(&path, &SOURCE.replace("2.5", "3.5"), false).is_none());
+    assert!(load(&path, SOURCE, true).is_none());
+
+    let damaged = |change: &dyn Fn(&mut Vec<u8>)| {
+      let mut changed = bytes.clone();
+      change(&mut changed);
+      fs::write(&cache, &changed).unwrap();
+      load(&path, SOURCE, false).is_none()
+    };
+    assert!(damaged(&|bytes| bytes[0] = b'X'), "bad magic");
+    assert!(damaged(&|bytes| bytes[4..8].copy_from_slice(&(BYTECODE_VERSION - 1).to_le_bytes())), "old version");
+    assert!(damaged(&|bytes| bytes.truncate(bytes.len() - 1)), "truncated");
+    assert!(damaged(&|bytes| bytes.push(0)), "trailing bytes");
+    assert!(damaged(&|bytes| bytes.truncate(16)), "header only");
+    assert!(!damaged(&|_| {}), "the undamaged file loads");
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn optimized_code_has_its_own_cache_file() {
+    assert_eq!(cache_path("dir/script.py", false), PathBuf::from("dir/script.tpyc"));
+    assert_eq!(cache_path("dir/script.py", true), PathBuf::from("dir/script.opt-1.tpyc"));
+  }
+}
@@ -0,0 +1,1002 @@
+use crate::bytecode::{
+  CodeObject, Constant, Instr, CALL_WITH_KEYWORDS, CODE_DEFAULTS, CODE_GENERATOR, CODE_KWDEFAULTS, CODE_VARARGS, CODE_VARKEYWORDS,
+  FORMAT_ASCII, FORMAT_REPR, FORMAT_STR, FORMAT_WITH_SPEC,
+};
+use crate::types::{
+  body_contains_yield, comprehension_scope, local_names, push_unique, Clause, Comprehension, Declarations, Expr, FStringPart, Op, Parameters,
+  PyType, Statement, Stmt,
+};
+use crate::parser::parse_script;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Parses and compiles the text of a script
+pub fn compile_script(source: &str, optimize: bool) -> Result<CodeObject, String> {
+  compile_module(&parse_script(source)?, Rc::from(source), optimize)
+}
+
+// Compiles a whole script, whose text is kept with the code for dis(). With optimize set (-O),
+// assert statements are left out
+pub fn compile_module(body: &[Statement], source: Rc<str>, optimize: bool) -> Result<CodeObject, String> {
+  let mut compiler = Compiler::new("<module>", 0, Vec::new(), Vec::new(), Vec::new(), false);
+  compiler.code.source = Some(source);
+  compiler.optimize = optimize;
+  compiler.compile_body(body)?;
+  compiler.load_const(Constant::None);
+  compiler.emit(Instr::ReturnValue);
+  Ok(compiler.code)
+}
+
+// Compiles one statement entered in the REPL. The code returns the value of an expression so it
+// can be echoed, and None for other statements
+pub fn compile_interactive(statement: &Statement, optimize: bool) -> Result<CodeObject, String> {
+  let mut compiler = Compiler::new("<module>", 0, Vec::new(), Vec::new(), Vec::new(), false);
+  compiler.optimize = optimize;
+  compiler.line = statement.line;
+
+  match &statement.node {
+    PyType::Expr(_) => compiler.compile_expr(&statement.node)?,
+    PyType::Stmt(_) => {
+      compiler.compile_stmt(&statement.node)?;
+      compiler.load_const(Constant::None);
+    }
+  }
+  compiler.emit(Instr::ReturnValue);
+  Ok(compiler.code)
+}
+
+// Jumps waiting on the position of a loop's start and end
+struct Loop {
+  start: u32,
+  breaks: Vec<usize>,
+  is_for: bool, // for loops keep their iterator on the stack, which break has to pop
+}
+
+struct Compiler {
+  code: CodeObject,
+  in_function: bool,
+  outer: Vec<String>,   // locals of the enclosing functions, which this code can capture
+  globals: Vec<String>, // names this function declares global, which hide those locals from the code nested in it
+  loops: Vec<Loop>,
+  line: u32,            // source line of the statement being compiled
+  optimize: bool,       // leave out assert statements
+  // Where each constant, global name, local and cell is in the code's tables, so that looking one
+  // up doesn't scan them
+  constant_slots: HashMap<Constant, u32>,
+  name_slots: HashMap<String, u32>,
+  local_slots: HashMap<String, u32>,
+  cell_slots: HashMap<String, u32>, // cellvars then freevars
+}
+
+// The position of each name in a table, keeping the first if one appears twice
+fn slots<'a>(names: impl Iterator<Item = &'a String>) -> HashMap<String, u32> {
+  let mut slots = HashMap::new();
+  for (i, name) in names.enumerate() {
+    slots.entry(name.clone()).or_insert(i as u32);
+  }
+  slots
+}
+
+impl Compiler {
+  fn new(name: &str, argcount: u32, varnames: Vec<String>, cellvars: Vec<String>, freevars: Vec<String>, in_function: bool) -> Compiler {
+    let local_slots = slots(varnames.iter());
+    let cell_slots = slots(cellvars.iter().chain(&freevars));
+    Compiler {
+      code: CodeObject {
+        name: name.to_string(),
+        first_line: 1,
+        argcount,
+        posonlyargcount: 0,
+        kwonlyargcount: 0,
+        flags: 0,
+        instructions: Vec::new(),
+        lines: Vec::new(),
+        constants: Vec::new(),
+        names: Vec::new(),
+        varnames,
+        cellvars,
+        freevars,
+        source: None,
+      },
+      in_function,
+      outer: Vec::new(),
+      globals: Vec::new(),
+      loops: Vec::new(),
+      line: 0,
+      optimize: false,
+      constant_slots: HashMap::new(),
+      name_slots: HashMap::new(),
+      local_slots,
+      cell_slots,
+    }
+  }
+
+  fn emit(&mut self, instr: Instr) -> usize {
+    self.code.instructions.push(instr);
+    self.code.lines.push(self.line);
+    self.code.instructions.len() - 1
+  }
+
+  fn here(&self) -> u32 {
+    self.code.instructions.len() as u32
+  }
+
+  // Points the jump at `at` to the next instruction to be emitted
+  fn patch(&mut self, at: usize) {
+    let target = self.here();
+    self.code.instructions[at] = match self.code.instructions[at] {
+      Instr::Jump(_) => Instr::Jump(target),
+      Instr::PopJumpIfFalse(_) => Instr::PopJumpIfFalse(target),
+      Instr::JumpIfFalseOrPop(_) => Instr::JumpIfFalseOrPop(target),
+      Instr::JumpIfTrueOrPop(_) => Instr::JumpIfTrueOrPop(target),
+      Instr::ForIter(_) => Instr::ForIter(target),
+      instr => unreachable!("{:?} is not a jump", instr),
+    };
+  }
+
+  fn add_const(&mut self, constant: Constant) -> u32 {
+    if let Some(&i) = self.constant_slots.get(&constant) {
+      return i;
+    }
+    let i = self.code.constants.len() as u32;
+    self.constant_slots.insert(constant.clone(), i);
+    self.code.constants.push(constant);
+    i
+  }
+
+  fn add_name(&mut self, name: &str) -> u32 {
+    if let Some(&i) = self.name_slots.get(name) {
+      return i;
+    }
+    let i = self.code.names.len() as u32;
+    self.name_slots.insert(name.to_string(), i);
+    self.code.names.push(name.to_string());
+    i
+  }
+
+  // Index of a variable among the cells of this code (cellvars then freevars)
+  fn cell_index(&self, name: &str) -> Option<u32> {
+    self.cell_slots.get(name).copied()
+  }
+
+  fn local_index(&self, name: &str) -> Option<u32> {
+    self.local_slots.get(name).copied()
+  }
+
+  fn load(&mut self, name: &str) {
+    let instr = match (self.cell_index(name), self.local_index(name)) {
+      (Some(i), _) => Instr::LoadDeref(i),
+      (None, Some(i)) => Instr::LoadFast(i),
+      (None, None) => Instr::LoadGlobal(self.add_name(name)),
+    };
+    self.emit(instr);
+  }
+
+  fn store(&mut self, name: &str) {
+    let instr = match (self.cell_index(name), self.local_index(name)) {
+      (Some(i), _) => Instr::StoreDeref(i),
+      (None, Some(i)) => Instr::StoreFast(i),
+      (None, None) => Instr::StoreGlobal(self.add_name(name)),
+    };
+    self.emit(instr);
+  }
+
+  fn delete(&mut self, name: &str) {
+    let instr = match (self.cell_index(name), self.local_index(name)) {
+      (Some(i), _) => Instr::DeleteDeref(i),
+      (None, Some(i)) => Instr::DeleteFast(i),
+      (None, None) => Instr::DeleteGlobal(self.add_name(name)),
+    };
+    self.emit(instr);
+  }
+
+  // Stores the value on top of the stack into an assignment target
+  fn store_target(&mut self, target: &PyType) -> Result<(), String> {
+    match target {
+      PyType::Expr(Expr::Var(v)) => self.store(v),
+      PyType::Expr(Expr::Subscript(container, key)) => {
+        self.compile_expr(container)?;
+        self.compile_expr(key)?;
+        self.emit(Instr::StoreSubscr);
+      }
+      PyType::Expr(Expr::Attribute(object, name)) => {
+        self.compile_expr(object)?;
+        let index = self.add_name(name);
+        self.emit(Instr::StoreAttr(index));
+      }
+      _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+    }
+    Ok(())
+  }
+
+  fn compile_body(&mut self, body: &[Statement]) -> Result<(), String> {
+    for statement in body {
+      self.line = statement.line;
+      self.compile_stmt(&statement.node)?;
+    }
+    Ok(())
+  }
+
+  fn compile_stmt(&mut self, stmt: &PyType) -> Result<(), String> {
+    match stmt {
+      // Expression statement, value is discarded
+      PyType::Expr(_) => {
+        self.compile_expr(stmt)?;
+        self.emit(Instr::PopTop);
+      }
+
+      // Assign, with the value duplicated for every target but the last
+      PyType::Stmt(Stmt::Assign(targets, e)) => {
+        self.compile_expr(e)?;
+        for (i, target) in targets.iter().enumerate() {
+          if i + 1 < targets.len() {
+            self.emit(Instr::DupTop);
+          }
+          self.store_target(target)?;
+        }
+      }
+
+      // Del
+      PyType::Stmt(Stmt::Del(targets)) => {
+        for target in targets {
+          match target {
+            PyType::Expr(Expr::Var(v)) => self.delete(v),
+            PyType::Expr(Expr::Subscript(container, key)) => {
+              self.compile_expr(container)?;
+              self.compile_expr(key)?;
+              self.emit(Instr::DeleteSubscr);
+            }
+            PyType::Expr(Expr::Attribute(object, name)) => {
+              self.compile_expr(object)?;
+              let index = self.add_name(name);
+              self.emit(Instr::DeleteAttr(index));
+            }
+            _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+          }
+        }
+      }
+
+      // AugAssign, which keeps the parts of the target on the stack to store the result back into
+      PyType::Stmt(Stmt::AugAssign(target, op, e)) => {
+        match &**target {
+          PyType::Expr(Expr::Var(v)) => {
+            self.load(v);
+            self.compile_expr(e)?;
+            self.emit(Instr::InplaceOp(*op));
+            self.store(v);
+          }
+          PyType::Expr(Expr::Subscript(container, key)) => {
+            self.compile_expr(container)?;
+            self.compile_expr(key)?;
+            self.emit(Instr::DupTopTwo);
+            self.emit(Instr::BinarySubscr);
+            self.compile_expr(e)?;
+            self.emit(Instr::InplaceOp(*op));
+            self.emit(Instr::RotThree);
+            self.emit(Instr::StoreSubscr);
+          }
+          PyType::Expr(Expr::Attribute(object, name)) => {
+            self.compile_expr(object)?;
+            self.emit(Instr::DupTop);
+            let index = self.add_name(name);
+            self.emit(Instr::LoadAttr(index));
+            self.compile_expr(e)?;
+            self.emit(Instr::InplaceOp(*op));
+            self.emit(Instr::RotTwo);
+            self.emit(Instr::StoreAttr(index));
+          }
+          _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+        }
+      }
+
+      // If-Else Statement
+      PyType::Stmt(Stmt::If(condition, body, else_body)) => {
+        self.compile_expr(condition)?;
+        let to_else = self.emit(Instr::PopJumpIfFalse(0));
+        self.compile_body(body)?;
+
+        match else_body {
+          Some(else_body_list) => {
+            let to_end = self.emit(Instr::Jump(0));
+            self.patch(to_else);
+            self.compile_body(else_body_list)?;
+            self.patch(to_end);
+          }
+          None => self.patch(to_else),
+        }
+      }
+
+      // While Loop
+      PyType::Stmt(Stmt::While(condition, body)) => {
+        let start = self.here();
+        self.compile_expr(condition)?;
+        let to_end = self.emit(Instr::PopJumpIfFalse(0));
+        self.compile_loop_body(body, start, false)?;
+        self.patch(to_end);
+        self.patch_breaks();
+      }
+
+      // For Loop
+      PyType::Stmt(Stmt::For(var, iterable, body)) => {
+        self.compile_expr(iterable)?;
+        self.emit(Instr::GetIter);
+        let start = self.here();
+        let to_end = self.emit(Instr::ForIter(0));
+        self.store(var);
+        self.compile_loop_body(body, start, true)?;
+        self.patch(to_end);
+        self.patch_breaks();
+      }
+
+      // Function Definition
+      PyType::Stmt(Stmt::Function(func_name, parameters, body)) => {
+        let mut code = self.compile_function(func_name, parameters, body)?;
+
+        // Defaults are evaluated once, when the function is defined
+        let defaults: Vec<&PyType> = parameters.positional.iter().filter_map(|(_, default)| default.as_ref()).collect();
+        if !defaults.is_empty() {
+          for default in &defaults {
+            self.compile_expr(default)?;
+          }
+          self.emit(Instr::BuildTuple(defaults.len() as u32));
+          code.flags |= CODE_DEFAULTS;
+        }
+        let kwdefaults: Vec<(&String, &PyType)> =
+          parameters.kwonly.iter().filter_map(|(name, default)| default.as_ref().map(|default| (name, default))).collect();
+        if !kwdefaults.is_empty() {
+          for (name, default) in &kwdefaults {
+            self.load_const(Constant::Str(Rc::from(name.as_str())));
+            self.compile_expr(default)?;
+          }
+          self.emit(Instr::BuildDict(kwdefaults.len() as u32));
+          code.flags |= CODE_KWDEFAULTS;
+        }
+
+        let index = self.add_const(Constant::Code(Rc::new(code)));
+        self.emit(Instr::MakeFunction(index));
+        self.store(func_name);
+      }
+
+      // Return
+      PyType::Stmt(Stmt::Return(e)) => {
+        if !self.in_function {
+          return Err("SyntaxError: 'return' outside function".to_string());
+        }
+        self.compile_expr(e)?;
+        self.emit(Instr::ReturnValue);
+      }
+
+      // Break
+      PyType::Stmt(Stmt::Break) => {
+        match self.loops.last() {
+          Some(current) => {
+            if current.is_for {
+              self.emit(Instr::PopTop);
+            }
+            let jump = self.emit(Instr::Jump(0));
+            self.loops.last_mut().unwrap().breaks.push(jump);
+          }
+          None => return Err("SyntaxError: 'break' outside loop".to_string()),
+        }
+      }
+
+      // Continue
+      PyType::Stmt(Stmt::Continue) => {
+        match self.loops.last() {
+          Some(current) => {
+            let start = current.start;
+            self.emit(Instr::Jump(start));
+          }
+          None => return Err("SyntaxError: 'continue' not properly in loop".to_string()),
+        }
+      }
+
+      // Assert, which raises AssertionError when the condition is false. -O leaves it out
+      PyType::Stmt(Stmt::Assert(condition, message)) => {
+        if !self.optimize {
+          self.compile_expr(condition)?;
+          self.emit(Instr::UnaryNot);
+          let to_end = self.emit(Instr::PopJumpIfFalse(0));
+          if let Some(message) = message {
+            self.compile_expr(message)?;
+          }
+          self.emit(Instr::RaiseAssertion(message.is_some() as u32));
+          self.patch(to_end);
+        }
+      }
+
+      // Pass, and declarations, which only change how the compiler resolves names
+      PyType::Stmt(Stmt::Pass) | PyType::Stmt(Stmt::Global(_)) | PyType::Stmt(Stmt::Nonlocal(_)) => {}
+    }
+    Ok(())
+  }
+
+  // Compiles a loop body followed by the jump back to the top of the loop
+  fn compile_loop_body(&mut self, body: &[Statement], start: u32, is_for: bool) -> Result<(), String> {
+    self.loops.push(Loop { start, breaks: Vec::new(), is_for });
+    self.compile_body(body)?;
+    self.emit(Instr::Jump(start));
+    Ok(())
+  }
+
+  // Points the breaks of the loop that was just compiled past its end
+  fn patch_breaks(&mut self) {
+    let finished = self.loops.pop().unwrap();
+    for jump in finished.breaks {
+      self.patch(jump);
+    }
+  }
+
+  // Decides where each variable of the function lives: parameters and assigned names are locals,
+  // locals used by nested functions become cells, and names bound by an enclosing function are free.
+  // Names declared global are globals, and names declared nonlocal are free
+  fn compile_function(&self, name: &str, parameters: &Parameters, body: &[Statement]) -> Result<CodeObject, String> {
+    let varnames = local_names(parameters, body);
+
+    let mut captured = Vec::new();
+    for statement in body {
+      nested_free_names(&statement.node, &mut captured);
+    }
+    let cellvars = varnames.iter().filter(|v| captured.contains(v)).cloned().collect();
+
+    let outer = self.enclosing_locals();
+    let freevars = free_names(parameters, body).into_iter().filter(|v| outer.contains(v)).collect();
+
+    let mut compiler = Compiler::new(name, parameters.positional.len() as u32, varnames, cellvars, freevars, true);
+    compiler.code.posonlyargcount = parameters.posonly as u32;
+    compiler.code.kwonlyargcount = parameters.kwonly.len() as u32;
+    if parameters.varargs.is_some() {
+      compiler.code.flags |= CODE_VARARGS;
+    }
+    if parameters.varkw.is_some() {
+      compiler.code.flags |= CODE_VARKEYWORDS;
+    }
+    if body_contains_yield(body) {
+      compiler.code.flags |= CODE_GENERATOR;
+    }
+    compiler.outer = outer;
+    compiler.globals = Declarations::of(body).globals;
+    compiler.code.source = self.code.source.clone();
+    compiler.line = self.line;
+    compiler.optimize = self.optimize;
+    compiler.code.first_line = self.line;
+    compiler.compile_body(body)?;
+
+    // Reaching the end of the body returns None
+    compiler.load_const(Constant::None);
+    compiler.emit(Instr::ReturnValue);
+    Ok(compiler.code)
+  }
+
+  // Locals of this code and of the functions around it, which code nested in it can capture
+  fn enclosing_locals(&self) -> Vec<String> {
+    let mut outer = self.outer.clone();
+    outer.retain(|name| !self.globals.contains(name));
+    if self.in_function {
+      outer.extend(self.code.varnames.iter().cloned());
+    }
+    outer
+  }
+
+  // A comprehension runs as a function of its own, so that its loop variables are locals of that
+  // function. The function is called with the iterator of the first iterable, which is evaluated here
+  fn compile_comprehension(&mut self, kind: &Comprehension, element: &PyType, clauses: &[Clause]) -> Result<(), String> {
+    let name = match kind {
+      Comprehension::List => "<listcomp>",
+      Comprehension::Set => "<setcomp>",
+      Comprehension::Dict(_) => "<dictcomp>",
+      Comprehension::Generator => "<genexpr>",
+    };
+    let mut varnames = vec![".0".to_string()];
+    for clause in clauses {
+      if let Clause::For(var, _) = clause {
+        push_unique(&mut varnames, var);
+      }
+    }
+
+    let mut captured = Vec::new();
+    for node in comprehension_scope(kind, element, clauses) {
+      nested_free_names(node, &mut captured);
+    }
+    let cellvars = varnames.iter().filter(|v| captured.contains(v)).cloned().collect();
+
+    let outer = self.enclosing_locals();
+    let freevars = comprehension_free_names(kind, element, clauses).into_iter().filter(|v| outer.contains(v)).collect();
+
+    let mut compiler = Compiler::new(name, 1, varnames, cellvars, freevars, true);
+    if *kind == Comprehension::Generator {
+      compiler.code.flags |= CODE_GENERATOR;
+    }
+    compiler.outer = outer;
+    compiler.code.source = self.code.source.clone();
+    compiler.line = self.line;
+    compiler.code.first_line = self.line;
+    let empty = match kind {
+      Comprehension::List => Some(Instr::BuildList(0)),
+      Comprehension::Set => Some(Instr::BuildSet(0)),
+      Comprehension::Dict(_) => Some(Instr::BuildDict(0)),
+      Comprehension::Generator => None,
+    };
+    if let Some(instr) = empty {
+      compiler.emit(instr);
+    }
+    compiler.compile_clauses(kind, element, clauses, 0)?;
+    // A generator expression returns None once it is exhausted, the others return what they built
+    if *kind == Comprehension::Generator {
+      compiler.load_const(Constant::None);
+    }
+    compiler.emit(Instr::ReturnValue);
+
+    let index = self.add_const(Constant::Code(Rc::new(compiler.code)));
+    self.emit(Instr::MakeFunction(index));
+    self.compile_expr(clauses[0].node())?;
+    self.emit(Instr::GetIter);
+    self.emit(Instr::CallFunction(1));
+    Ok(())
+  }
+
+  // Compiles the clauses of a comprehension as nested loops and ifs, with the element added to the
+  // result innermost. The result is below the iterators of the depth loops around the element
+  fn compile_clauses(&mut self, kind: &Comprehension, element: &PyType, clauses: &[Clause], depth: u32) -> Result<(), String> {
+    match clauses.split_first() {
+      None => match kind {
+        Comprehension::List => {
+          self.compile_expr(element)?;
+          self.emit(Instr::ListAppend(depth + 1));
+        }
+        Comprehension::Set => {
+          self.compile_expr(element)?;
+          self.emit(Instr::SetAdd(depth + 1));
+        }
+        Comprehension::Dict(value) => {
+          self.compile_expr(element)?;
+          self.compile_expr(value)?;
+          self.emit(Instr::MapAdd(depth + 1));
+        }
+        Comprehension::Generator => {
+          self.compile_expr(element)?;
+          self.emit(Instr::YieldValue);
+          self.emit(Instr::PopTop);
+        }
+      },
+      Some((Clause::If(condition), rest)) => {
+        self.compile_expr(condition)?;
+        let to_end = self.emit(Instr::PopJumpIfFalse(0));
+        self.compile_clauses(kind, element, rest, depth)?;
+        self.patch(to_end);
+      }
+      Some((Clause::For(var, iterable), rest)) => {
+        // The iterator of the first iterable is the argument
+        if depth == 0 {
+          self.load(".0");
+        } else {
+          self.compile_expr(iterable)?;
+          self.emit(Instr::GetIter);
+        }
+        let start = self.here();
+        let exit = self.emit(Instr::ForIter(0));
+        self.store(var);
+        self.compile_clauses(kind, element, rest, depth + 1)?;
+        self.emit(Instr::Jump(start));
+        self.patch(exit);
+      }
+    }
+    Ok(())
+  }
+
+  // A call using *iterable or **mapping collects its positional arguments into a list and its keyword
+  // arguments into a dict, which CallFunctionEx passes to the function
+  fn compile_unpacking_call(&mut self, args: &[PyType], kwargs: &[(Option<String>, PyType)]) -> Result<(), String> {
+    let leading = args.iter().take_while(|arg| !matches!(arg, PyType::Expr(Expr::Starred(_)))).count();
+    let count = self.compile_all(&args[..leading])?;
+    self.emit(Instr::BuildList(count));
+    for arg in &args[leading..] {
+      match arg {
+        PyType::Expr(Expr::Starred(iterable)) => {
+          self.compile_expr(iterable)?;
+          self.emit(Instr::ListExtend);
+        }
+        _ => {
+          self.compile_expr(arg)?;
+          self.emit(Instr::ListAppend(1));
+        }
+      }
+    }
+    if kwargs.is_empty() {
+      self.emit(Instr::CallFunctionEx(0));
+      return Ok(());
+    }
+
+    // Keywords after the first **mapping are merged one at a time, so that repeats are reported
+    let leading = kwargs.iter().take_while(|(name, _)| name.is_some()).count();
+    for (name, arg) in &kwargs[..leading] {
+      self.load_const(Constant::Str(Rc::from(name.as_deref().unwrap())));
+      self.compile_expr(arg)?;
+    }
+    self.emit(Instr::BuildDict(leading as u32));
+    for (name, arg) in &kwargs[leading..] {
+      match name {
+        Some(name) => {
+          self.load_const(Constant::Str(Rc::from(name.as_str())));
+          self.compile_expr(arg)?;
+          self.emit(Instr::BuildDict(1));
+        }
+        None => self.compile_expr(arg)?,
+      }
+      self.emit(Instr::DictMerge);
+    }
+    self.emit(Instr::CallFunctionEx(CALL_WITH_KEYWORDS));
+    Ok(())
+  }
+
+  fn compile_all(&mut self, exprs: &[PyType]) -> Result<u32, String> {
+    for expr in exprs {
+      self.compile_expr(expr)?;
+    }
+    Ok(exprs.len() as u32)
+  }
+
+  fn compile_expr(&mut self, expr: &PyType) -> Result<(), String> {
+    match expr {
+      // Literals
+      PyType::Expr(Expr::Int(n)) => self.load_const(Constant::Int(*n)),
+      PyType::Expr(Expr::Float(d)) => self.load_const(Constant::Float(*d)),
+      PyType::Expr(Expr::Bool(b)) => self.load_const(Constant::Bool(*b)),
+      PyType::Expr(Expr::String(s)) => self.load_const(Constant::Str(Rc::from(s.as_str()))),
+      PyType::Expr(Expr::Bytes(b)) => self.load_const(Constant::Bytes(Rc::from(b.as_slice()))),
+      PyType::Expr(Expr::None) => self.load_const(Constant::None),
+
+      // F-string
+      PyType::Expr(Expr::FString(parts)) => self.compile_fstring(parts)?,
+
+      // Var
+      PyType::Expr(Expr::Var(v)) => self.load(v),
+
+      // Containers
+      PyType::Expr(Expr::List(items)) => {
+        let count = self.compile_all(items)?;
+        self.emit(Instr::BuildList(count));
+      }
+      PyType::Expr(Expr::Tuple(items)) => {
+        let count = self.compile_all(items)?;
+        self.emit(Instr::BuildTuple(count));
+      }
+      PyType::Expr(Expr::Set(items)) => {
+        let count = self.compile_all(items)?;
+        self.emit(Instr::BuildSet(count));
+      }
+      PyType::Expr(Expr::Dict(pairs)) => {
+        for (key, value) in pairs {
+          self.compile_expr(key)?;
+          self.compile_expr(value)?;
+        }
+        self.emit(Instr::BuildDict(pairs.len() as u32));
+      }
+
+      // Not
+      PyType::Expr(Expr::Not(e)) => {
+        self.compile_expr(e)?;
+        self.emit(Instr::UnaryNot);
+      }
+
+      // Or/And (the left value is kept as the result when it decides the outcome)
+      PyType::Expr(Expr::Binop(op @ (Op::Or | Op::And), left, right)) => {
+        self.compile_expr(left)?;
+        let to_end = match op {
+          Op::Or => self.emit(Instr::JumpIfTrueOrPop(0)),
+          _ => self.emit(Instr::JumpIfFalseOrPop(0)),
+        };
+        self.compile_expr(right)?;
+        self.patch(to_end);
+      }
+
+      // Binop
+      PyType::Expr(Expr::Binop(op, left, right)) => {
+        self.compile_expr(left)?;
+        self.compile_expr(right)?;
+        self.emit(Instr::BinaryOp(*op));
+      }
+
+      // Ternary
+      PyType::Expr(Expr::Ternary(condition, if_true, if_false)) => {
+        self.compile_expr(condition)?;
+        let to_else = self.emit(Instr::PopJumpIfFalse(0));
+        self.compile_expr(if_true)?;
+        let to_end = self.emit(Instr::Jump(0));
+        self.patch(to_else);
+        self.compile_expr(if_false)?;
+        self.patch(to_end);
+      }
+
+      // Comparison chain. Each middle operand is duplicated so it can be the left side of the
+      // next link, and a false link jumps out with the duplicate still under its result
+      PyType::Expr(Expr::Compare(first, comparisons)) => {
+        self.compile_expr(first)?;
+        let mut to_cleanup = Vec::new();
+
+        for (i, (op, operand)) in comparisons.iter().enumerate() {
+          self.compile_expr(operand)?;
+          if i + 1 < comparisons.len() {
+            self.emit(Instr::DupTop);
+            self.emit(Instr::RotThree);
+            self.emit(Instr::CompareOp(*op));
+            to_cleanup.push(self.emit(Instr::JumpIfFalseOrPop(0)));
+          } else {
+            self.emit(Instr::CompareOp(*op));
+          }
+        }
+
+        if !to_cleanup.is_empty() {
+          let to_end = self.emit(Instr::Jump(0));
+          for jump in to_cleanup {
+            self.patch(jump);
+          }
+          self.emit(Instr::RotTwo);
+          self.emit(Instr::PopTop);
+          self.patch(to_end);
+        }
+      }
+
+      // Function Call
+      PyType::Expr(Expr::FunctionCall(func, args, kwargs)) => {
+        self.compile_expr(func)?;
+        let unpacking =
+          args.iter().any(|arg| matches!(arg, PyType::Expr(Expr::Starred(_)))) || kwargs.iter().any(|(name, _)| name.is_none());
+        if unpacking {
+          self.compile_unpacking_call(args, kwargs)?;
+        } else if kwargs.is_empty() {
+          let count = self.compile_all(args)?;
+          self.emit(Instr::CallFunction(count));
+        } else {
+          let count = self.compile_all(args)?;
+          for (_, arg) in kwargs {
+            self.compile_expr(arg)?;
+          }
+          let names = kwargs.iter().flat_map(|(name, _)| name).map(|name| Constant::Str(Rc::from(name.as_str()))).collect();
+          self.load_const(Constant::Tuple(names));
+          self.emit(Instr::CallFunctionKw(count + kwargs.len() as u32));
+        }
+      }
+
+      // *iterable is only allowed among the arguments of a call
+      PyType::Expr(Expr::Starred(_)) => return Err("SyntaxError: can't use starred expression here".to_string()),
+
+      // Yield
+      PyType::Expr(Expr::Yield(value)) => {
+        if !self.in_function {
+          return Err("SyntaxError: 'yield' outside function".to_string());
+        }
+        match value {
+          Some(value) => self.compile_expr(value)?,
+          None => self.load_const(Constant::None),
+        }
+        self.emit(Instr::YieldValue);
+      }
+
+      // Yield from, which starts the delegation by sending None
+      PyType::Expr(Expr::YieldFrom(iterable)) => {
+        if !self.in_function {
+          return Err("SyntaxError: 'yield' outside function".to_string());
+        }
+        self.compile_expr(iterable)?;
+        self.emit(Instr::GetIter);
+        self.load_const(Constant::None);
+        self.emit(Instr::YieldFrom);
+      }
+
+      // Comprehension
+      PyType::Expr(Expr::Comprehension(kind, element, clauses)) => self.compile_comprehension(kind, element, clauses)?,
+
+      // Attribute
+      PyType::Expr(Expr::Attribute(e, name)) => {
+        self.compile_expr(e)?;
+        let index = self.add_name(name);
+        self.emit(Instr::LoadAttr(index));
+      }
+
+      // Subscript
+      PyType::Expr(Expr::Subscript(e, index)) => {
+        self.compile_expr(e)?;
+        self.compile_expr(index)?;
+        self.emit(Instr::BinarySubscr);
+      }
+
+      // Slice, with None for a left out start or stop
+      PyType::Expr(Expr::Slice(start, stop, step)) => {
+        for bound in [start, stop] {
+          match bound {
+            Some(bound) => self.compile_expr(bound)?,
+            None => self.load_const(Constant::None),
+          }
+        }
+        match step {
+          Some(step) => {
+            self.compile_expr(step)?;
+            self.emit(Instr::BuildSlice(3));
+          }
+          None => {
+            self.emit(Instr::BuildSlice(2));
+          }
+        }
+      }
+
+      PyType::Stmt(_) => return Err("SyntaxError: invalid syntax".to_string()),
+    }
+    Ok(())
+  }
+
+  fn load_const(&mut self, constant: Constant) {
+    let index = self.add_const(constant);
+    self.emit(Instr::LoadConst(index));
+  }
+
+  // Pushes each literal part and formatted field, then joins them. A format spec is itself an
+  // f-string, built just before the field is formatted
+  fn compile_fstring(&mut self, parts: &[FStringPart]) -> Result<(), String> {
+    for part in parts {
+      match part {
+        FStringPart::Literal(s) => self.load_const(Constant::Str(Rc::from(s.as_str()))),
+        FStringPart::Field(expr, conversion, spec) => {
+          self.compile_expr(expr)?;
+          let mut flags = match conversion {
+            Some('s') => FORMAT_STR,
+            Some('r') => FORMAT_REPR,
+            Some(_) => FORMAT_ASCII,
+            None => 0,
+          };
+          if !spec.is_empty() {
+            self.compile_fstring(spec)?;
+            flags |= FORMAT_WITH_SPEC;
+          }
+          self.emit(Instr::FormatValue(flags));
+        }
+      }
+    }
+    if parts.len() != 1 {
+      self.emit(Instr::BuildString(parts.len() as u32));
+    }
+    Ok(())
+  }
+}
+
+// Names a function reads without binding them itself, including those its nested functions read
+// from it or from further out. Names declared nonlocal are free even if they are only assigned,
+// while names declared global are never free
+fn free_names(parameters: &Parameters, body: &[Statement]) -> Vec<String> {
+  let locals = local_names(parameters, body);
+  let declarations = Declarations::of(body);
+
+  let mut used = declarations.nonlocals.clone();
+  for statement in body {
+    used_names(&statement.node, &mut used);
+  }
+  used.into_iter().filter(|name| !locals.contains(name) && !declarations.globals.contains(name)).collect()
+}
+
+// Names a comprehension reads from outside its own scope, not counting its first iterable
+fn comprehension_free_names(kind: &Comprehension, element: &PyType, clauses: &[Clause]) -> Vec<String> {
+  let mut used = Vec::new();
+  for node in comprehension_scope(kind, element, clauses) {
+    used_names(node, &mut used);
+  }
+  used.into_iter().filter(|name| !clauses.iter().any(|clause| matches!(clause, Clause::For(var, _) if var == name))).collect()
+}
+
+// Free names of the functions and comprehensions defined directly or indirectly inside a node
+fn nested_free_names(node: &PyType, names: &mut Vec<String>) {
+  match node {
+    PyType::Stmt(Stmt::Function(_, parameters, body)) => {
+      parameters.defaults().for_each(|default| nested_free_names(default, names));
+      for name in free_names(parameters, body) {
+        push_unique(names, &name);
+      }
+    }
+    PyType::Expr(Expr::Comprehension(kind, element, clauses)) => {
+      nested_free_names(clauses[0].node(), names);
+      for name in comprehension_free_names(kind, element, clauses) {
+        push_unique(names, &name);
+      }
+    }
+    _ => node.children().into_iter().for_each(|child| nested_free_names(child, names)),
+  }
+}
+
+// Names read by a node, with nested functions contributing their free names
+fn used_names(node: &PyType, names: &mut Vec<String>) {
+  match node {
+    PyType::Expr(expr) => match expr {
+      Expr::Var(v) => push_unique(names, v),
+      Expr::FunctionCall(func, args, kwargs) => {
+        used_names(func, names);
+        args.iter().for_each(|arg| used_names(arg, names));
+        kwargs.iter().for_each(|(_, arg)| used_names(arg, names));
+      }
+      Expr::Attribute(e, _) | Expr::Starred(e) | Expr::YieldFrom(e) => used_names(e, names),
+      Expr::Yield(value) => value.iter().for_each(|value| used_names(value, names)),
+      Expr::Subscript(e, index) => {
+        used_names(e, names);
+        used_names(index, names);
+      }
+      Expr::Slice(start, stop, step) => [start, stop, step].into_iter().flatten().for_each(|bound| used_names(bound, names)),
+      Expr::List(items) | Expr::Tuple(items) | Expr::Set(items) => items.iter().for_each(|item| used_names(item, names)),
+      Expr::Dict(pairs) => {
+        for (key, value) in pairs {
+          used_names(key, names);
+          used_names(value, names);
+        }
+      }
+      Expr::Binop(_, left, right) => {
+        used_names(left, names);
+        used_names(right, names);
+      }
+      Expr::Compare(first, comparisons) => {
+        used_names(first, names);
+        comparisons.iter().for_each(|(_, operand)| used_names(operand, names));
+      }
+      Expr::Not(e) => used_names(e, names),
+      Expr::Ternary(condition, if_true, if_false) => {
+        used_names(condition, names);
+        used_names(if_true, names);
+        used_names(if_false, names);
+      }
+      Expr::FString(parts) => fstring_used_names(parts, names),
+      // The first iterable is evaluated where the comprehension is
+      Expr::Comprehension(kind, element, clauses) => {
+        used_names(clauses[0].node(), names);
+        for name in comprehension_free_names(kind, element, clauses) {
+          push_unique(names, &name);
+        }
+      }
+      Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::String(_) | Expr::Bytes(_) | Expr::None => {}
+    },
+    PyType::Stmt(stmt) => match stmt {
+      Stmt::Return(e) => used_names(e, names),
+      // A name target is bound rather than used, but the parts of other targets are evaluated
+      Stmt::Assign(targets, e) => {
+        used_names(e, names);
+        targets.iter().filter(|target| !matches!(target, PyType::Expr(Expr::Var(_)))).for_each(|target| used_names(target, names));
+      }
+      Stmt::Del(targets) => {
+        targets.iter().filter(|target| !matches!(target, PyType::Expr(Expr::Var(_)))).for_each(|target| used_names(target, names));
+      }
+      Stmt::AugAssign(target, _, e) => {
+        used_names(target, names);
+        used_names(e, names);
+      }
+      Stmt::If(condition, body, else_body) => {
+        used_names(condition, names);
+        body.iter().for_each(|statement| used_names(&statement.node, names));
+        else_body.iter().flatten().for_each(|statement| used_names(&statement.node, names));
+      }
+      Stmt::While(condition, body) => {
+        used_names(condition, names);
+        body.iter().for_each(|statement| used_names(&statement.node, names));
+      }
+      Stmt::For(_, iterable, body) => {
+        used_names(iterable, names);
+        body.iter().for_each(|statement| used_names(&statement.node, names));
+      }
+      // Defaults are evaluated where the function is defined
+      Stmt::Function(_, parameters, body) => {
+        parameters.defaults().for_each(|default| used_names(default, names));
+        for name in free_names(parameters, body) {
+          push_unique(names, &name);
+        }
+      }
+      Stmt::Assert(condition, message) => {
+        used_names(condition, names);
+        message.iter().for_each(|message| used_names(message, names));
+      }
+      Stmt::Break | Stmt::Continue | Stmt::Pass | Stmt::Global(_) | Stmt::Nonlocal(_) => {}
+    },
+  }
+}
+
+fn fstring_used_names(parts: &[FStringPart], names: &mut Vec<String>) {
+  for part in parts {
+    if let FStringPart::Field(expr, _, spec) = part {
+      used_names(expr, names);
+      fstring_used_names(spec, names);
+    }
+  }
+}
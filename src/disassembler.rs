@@ -0,0 +1,98 @@
+use crate::bytecode::{CodeObject, Constant, Instr, FORMAT_ASCII, FORMAT_REPR, FORMAT_STR, FORMAT_WITH_SPEC};
+use crate::types::float_repr;
+use crate::value::{repr_bytes, repr_str};
+
+// Lists compiled code in the style of Python's dis module: the source line where a new line begins,
+// the instruction offset (>> marks jump targets), the opcode, its argument and what the argument
+// refers to. When the source text is given, each line's code is headed by that line.
+// Functions defined in the code are listed after it
+pub fn disassemble(code: &CodeObject, source: Option<&[&str]>) -> String {
+  let mut result = String::new();
+  disassemble_into(code, source, &mut result);
+  result
+}
+
+fn disassemble_into(code: &CodeObject, source: Option<&[&str]>, result: &mut String) {
+  let targets: Vec<u32> = code.instructions.iter().filter_map(Instr::jump_target).collect();
+  let mut prev_line = None;
+
+  for (offset, instr) in code.instructions.iter().enumerate() {
+    let line = code.lines[offset];
+
+    let line_column = if prev_line != Some(line) {
+      if prev_line.is_some() {
+        result.push('\n');
+      }
+      if let Some(text) = source.and_then(|lines| lines.get((line as usize).wrapping_sub(1))) {
+        result.push_str(&format!("{:>12}# {}\n", "", text.trim()));
+      }
+      prev_line = Some(line);
+      line.to_string()
+    } else {
+      String::new()
+    };
+
+    let marker = if targets.contains(&(offset as u32)) { ">>" } else { "" };
+    let arg = instr.arg().map(|a| a.to_string()).unwrap_or_default();
+    let row = format!("{:>4} {:>4} {:>4} {:<24} {:>4} {}", line_column, marker, offset, instr.name(), arg, arg_repr(code, instr));
+    result.push_str(row.trim_end());
+    result.push('\n');
+  }
+
+  for constant in &code.constants {
+    if let Constant::Code(inner) = constant {
+      result.push_str(&format!("\nDisassembly of {}:\n", code_repr(inner)));
+      disassemble_into(inner, source, result);
+    }
+  }
+}
+
+// Human-readable meaning of an instruction's argument
+fn arg_repr(code: &CodeObject, instr: &Instr) -> String {
+  match *instr {
+    Instr::LoadConst(i) | Instr::MakeFunction(i) => format!("({})", constant_repr(&code.constants[i as usize])),
+    Instr::LoadFast(i) | Instr::StoreFast(i) | Instr::DeleteFast(i) => format!("({})", code.varnames[i as usize]),
+    Instr::LoadDeref(i) | Instr::StoreDeref(i) | Instr::DeleteDeref(i) => {
+      let i = i as usize;
+      let name = if i < code.cellvars.len() { &code.cellvars[i] } else { &code.freevars[i - code.cellvars.len()] };
+      format!("({})", name)
+    }
+    Instr::LoadGlobal(i) | Instr::StoreGlobal(i) | Instr::DeleteGlobal(i) | Instr::LoadAttr(i) | Instr::StoreAttr(i)
+    | Instr::DeleteAttr(i) => format!("({})", code.names[i as usize]),
+    Instr::BinaryOp(op) | Instr::CompareOp(op) => format!("({})", op),
+    Instr::InplaceOp(op) => format!("({}=)", op),
+    Instr::FormatValue(flags) => {
+      let conversion = match flags & 3 {
+        FORMAT_STR => Some("str"),
+        FORMAT_REPR => Some("repr"),
+        FORMAT_ASCII => Some("ascii"),
+        _ => None,
+      };
+      let with_spec = if flags & FORMAT_WITH_SPEC != 0 { Some("with format") } else { None };
+      let notes: Vec<&str> = conversion.into_iter().chain(with_spec).collect();
+      if notes.is_empty() { String::new() } else { format!("({})", notes.join(", ")) }
+    }
+    _ => match instr.jump_target() {
+      Some(target) => format!("(to {})", target),
+      None => String::new(),
+    },
+  }
+}
+
+fn constant_repr(constant: &Constant) -> String {
+  match constant {
+    Constant::None => "None".to_string(),
+    Constant::Bool(b) => if *b { "True".to_string() } else { "False".to_string() },
+    Constant::Int(n) => n.to_string(),
+    Constant::Float(d) => float_repr(*d),
+    Constant::Str(s) => repr_str(s),
+    Constant::Bytes(b) => repr_bytes(b),
+    Constant::Tuple(items) if items.len() == 1 => format!("({},)", constant_repr(&items[0])),
+    Constant::Tuple(items) => format!("({})", items.iter().map(constant_repr).collect::<Vec<_>>().join(", ")),
+    Constant::Code(code) => code_repr(code),
+  }
+}
+
+fn code_repr(code: &CodeObject) -> String {
+  format!("<code object {}, line {}>", code.name, code.first_line)
+}
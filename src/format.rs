@@ -0,0 +1,846 @@
+use crate::methods::get_attribute;
+use crate::operators::get_item;
+use crate::types::float_repr;
+use crate::value::{Keywords, Value};
+use std::iter::{Enumerate, Peekable};
+use std::str::Chars;
+
+// Value formatting shared by f-strings, str.format(), format() and printf-style % formatting
+
+// Applies an f-string conversion: !s gives str(), !r gives repr() and !a gives ascii()
+pub fn convert(value: &Value, conversion: char) -> Result<String, String> {
+  match conversion {
+    's' => value.str(),
+    'r' => value.repr(),
+    _ => Ok(ascii(&value.repr()?)),
+  }
+}
+
+// Python's ascii(): a repr with every non-ASCII character escaped
+fn ascii(repr: &str) -> String {
+  let mut result = String::with_capacity(repr.len());
+  for c in repr.chars() {
+    match c as u32 {
+      0..=0x7f => result.push(c),
+      n @ 0x80..=0xff => result.push_str(&format!("\\x{:02x}", n)),
+      n @ 0x100..=0xffff => result.push_str(&format!("\\u{:04x}", n)),
+      n => result.push_str(&format!("\\U{:08x}", n)),
+    }
+  }
+  result
+}
+
+// Python's str.format(): replaces each {field!conversion:spec} in the template. Fields are numbered
+// automatically when their name is left out
+pub fn str_format(template: &str, args: &[Value], kwargs: &Keywords) -> Result<String, String> {
+  let mut next_index = Some(0);
+  expand_template(template, args, kwargs, &mut next_index, 2)
+}
+
+// next_index is the next automatically numbered field, or None once a field was numbered manually.
+// Format specs may themselves hold fields, up to a nesting depth of 2
+fn expand_template(template: &str, args: &[Value], kwargs: &Keywords, next_index: &mut Option<usize>, depth: u32) -> Result<String, String> {
+  if depth == 0 {
+    return Err("ValueError: Max string recursion exceeded".to_string());
+  }
+  let mut result = String::new();
+  let mut chars = template.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        result.push('{');
+      }
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        result.push('}');
+      }
+      '}' => return Err("ValueError: Single '}' encountered in format string".to_string()),
+      '{' if chars.peek().is_none() => return Err("ValueError: Single '{' encountered in format string".to_string()),
+      '{' => {
+        // The field runs to the matching '}', which may follow nested fields in the spec. Braces
+        // inside an [index] of the field name do not count
+        let mut field = String::new();
+        let mut nesting = 0;
+        let mut in_name = true;
+        let mut in_index = false;
+        loop {
+          match chars.next() {
+            Some('}') if nesting == 0 && !in_index => break,
+            Some(c) => {
+              match c {
+                '[' if in_name => in_index = true,
+                ']' if in_index => in_index = false,
+                '!' | ':' if !in_index => in_name = false,
+                '{' if !in_index => nesting += 1,
+                '}' if !in_index => nesting -= 1,
+                _ => {}
+              }
+              field.push(c);
+            }
+            None => return Err("ValueError: expected '}' before end of string".to_string()),
+          }
+        }
+        result.push_str(&expand_field(&field, args, kwargs, next_index, depth)?);
+      }
+      c => result.push(c),
+    }
+  }
+  Ok(result)
+}
+
+fn expand_field(field: &str, args: &[Value], kwargs: &Keywords, next_index: &mut Option<usize>, depth: u32) -> Result<String, String> {
+  // The field name ends at the first '!' or ':' outside an index
+  let mut in_index = false;
+  let name_end = field
+    .find(|c: char| {
+      match c {
+        '[' => in_index = true,
+        ']' => in_index = false,
+        _ => {}
+      }
+      !in_index && (c == '!' || c == ':')
+    })
+    .unwrap_or(field.len());
+  let (name, mut rest) = field.split_at(name_end);
+
+  let mut value = lookup_field(name, args, kwargs, next_index)?;
+
+  if let Some(after) = rest.strip_prefix('!') {
+    let mut after_chars = after.chars();
+    let conversion = match after_chars.next() {
+      Some(c @ ('s' | 'r' | 'a')) => c,
+      Some(c) => return Err(format!("ValueError: Unknown conversion specifier {}", c)),
+      None => return Err("ValueError: end of string while looking for conversion specifier".to_string()),
+    };
+    rest = after_chars.as_str();
+    if !rest.is_empty() && !rest.starts_with(':') {
+      return Err("ValueError: expected ':' after conversion specifier".to_string());
+    }
+    value = Value::new_str(&convert(&value, conversion)?);
+  }
+
+  let spec = match rest.strip_prefix(':') {
+    Some(spec) => expand_template(spec, args, kwargs, next_index, depth - 1)?,
+    None => String::new(),
+  };
+  format_value(&value, &spec)
+}
+
+// Finds the value a field name refers to: an argument given by position or keyword, followed by
+// any number of .attribute and [index] lookups
+fn lookup_field(name: &str, args: &[Value], kwargs: &Keywords, next_index: &mut Option<usize>) -> Result<Value, String> {
+  let first_end = name.find(['.', '[']).unwrap_or(name.len());
+  let (first, mut rest) = name.split_at(first_end);
+
+  let mut value = if first.is_empty() {
+    let index = match next_index {
+      Some(index) => *index,
+      None => return Err("ValueError: cannot switch from manual field specification to automatic field numbering".to_string()),
+    };
+    *next_index = Some(index + 1);
+    positional(args, index)?
+  } else if let Ok(index) = first.parse::<usize>() {
+    if matches!(next_index, Some(n) if *n > 0) {
+      return Err("ValueError: cannot switch from automatic field numbering to manual field specification".to_string());
+    }
+    *next_index = None;
+    positional(args, index)?
+  } else {
+    match kwargs.iter().find(|(key, _)| key == first) {
+      Some((_, value)) => value.clone(),
+      None => return Err(format!("KeyError: '{}'", first)),
+    }
+  };
+
+  while !rest.is_empty() {
+    if let Some(after) = rest.strip_prefix('.') {
+      let end = after.find(['.', '[']).unwrap_or(after.len());
+      if end == 0 {
+        return Err("ValueError: Empty attribute in format string".to_string());
+      }
+      value = get_attribute(&value, &after[..end])?;
+      rest = &after[end..];
+    } else if let Some(after) = rest.strip_prefix('[') {
+      let end = match after.find(']') {
+        Some(end) => end,
+        None => return Err("ValueError: Missing ']' in format string".to_string()),
+      };
+      let key = &after[..end];
+      let key = match key.parse::<i64>() {
+        Ok(n) if key.chars().all(|c| c.is_ascii_digit()) => Value::Int(n),
+        _ => Value::new_str(key),
+      };
+      value = get_item(&value, &key)?;
+      rest = &after[end + 1..];
+      if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+        return Err("ValueError: Only '.' or '[' may follow ']' in format field specifier".to_string());
+      }
+    }
+  }
+  Ok(value)
+}
+
+fn positional(args: &[Value], index: usize) -> Result<Value, String> {
+  match args.get(index) {
+    Some(value) => Ok(value.clone()),
+    None => Err(format!("IndexError: Replacement index {} out of range for positional args tuple", index)),
+  }
+}
+
+// printf-style formatting, template % args. args is a tuple of values, a dict for %(key)s
+// conversions, or else a single value
+pub fn printf_format(template: &str, args: &Value) -> Result<String, String> {
+  let (items, mapping) = match args {
+    Value::Tuple(items) => (items.to_vec(), None),
+    Value::Dict(_) => (vec![args.clone()], Some(args)),
+    _ => (vec![args.clone()], None),
+  };
+  let mut next = 0;
+  let mut result = String::new();
+  let mut chars = template.chars().enumerate().peekable();
+
+  while let Some((_, c)) = chars.next() {
+    if c != '%' {
+      result.push(c);
+      continue;
+    }
+
+    // %(key) takes the value from the mapping
+    let mut value = None;
+    if let Some((_, '(')) = chars.peek() {
+      chars.next();
+      let mapping = match mapping {
+        Some(mapping) => mapping,
+        None => return Err("TypeError: format requires a mapping".to_string()),
+      };
+      let mut key = String::new();
+      let mut depth = 1;
+      loop {
+        match chars.next() {
+          Some((_, ')')) if depth == 1 => break,
+          Some((_, c)) => {
+            match c {
+              '(' => depth += 1,
+              ')' => depth -= 1,
+              _ => {}
+            }
+            key.push(c);
+          }
+          None => return Err("ValueError: incomplete format key".to_string()),
+        }
+      }
+      value = Some(get_item(mapping, &Value::new_str(&key))?);
+    }
+
+    let mut spec = Spec { fill: None, align: None, sign: '-', alternate: false, zero: false, width: 0, grouping: None, precision: None, kind: None };
+    while let Some(&(_, flag)) = chars.peek() {
+      match flag {
+        '-' => spec.align = Some('<'),
+        '0' => spec.zero = true,
+        '+' => spec.sign = '+',
+        ' ' if spec.sign != '+' => spec.sign = ' ',
+        ' ' => {}
+        '#' => spec.alternate = true,
+        _ => break,
+      }
+      chars.next();
+    }
+
+    if let Some(width) = printf_number(&mut chars, &items, &mut next)? {
+      if width < 0 {
+        spec.align = Some('<');
+      }
+      spec.width = width.unsigned_abs() as usize;
+    }
+    if let Some((_, '.')) = chars.peek() {
+      chars.next();
+      spec.precision = Some(printf_number(&mut chars, &items, &mut next)?.unwrap_or(0).max(0) as usize);
+    }
+    if spec.align.is_some() {
+      spec.zero = false;
+    }
+
+    // Length modifiers are accepted and ignored, as in Python
+    while let Some((_, 'h' | 'l' | 'L')) = chars.peek() {
+      chars.next();
+    }
+
+    let (index, conversion) = match chars.next() {
+      Some(next_char) => next_char,
+      None => return Err("ValueError: incomplete format".to_string()),
+    };
+    if conversion == '%' {
+      result.push('%');
+      continue;
+    }
+    let value = match value {
+      Some(value) => value,
+      None => next_arg(&items, &mut next)?,
+    };
+    result.push_str(&printf_conversion(&value, conversion, index, &mut spec)?);
+  }
+
+  if mapping.is_none() && next < items.len() {
+    return Err("TypeError: not all arguments converted during string formatting".to_string());
+  }
+  Ok(result)
+}
+
+// Reads a width or precision, which is digits or * to take it from the arguments
+fn printf_number(chars: &mut Peekable<Enumerate<Chars>>, items: &[Value], next: &mut usize) -> Result<Option<i64>, String> {
+  if let Some((_, '*')) = chars.peek() {
+    chars.next();
+    return match next_arg(items, next)? {
+      Value::Int(n) => Ok(Some(n)),
+      _ => Err("TypeError: * wants int".to_string()),
+    };
+  }
+  let mut digits = String::new();
+  while let Some(&(_, d @ '0'..='9')) = chars.peek() {
+    digits.push(d);
+    chars.next();
+  }
+  Ok(digits.parse::<i64>().ok())
+}
+
+fn next_arg(items: &[Value], next: &mut usize) -> Result<Value, String> {
+  match items.get(*next) {
+    Some(value) => {
+      *next += 1;
+      Ok(value.clone())
+    }
+    None => Err("TypeError: not enough arguments for format string".to_string()),
+  }
+}
+
+// Formats one printf-style conversion. Strings are right-aligned and never zero padded, and the
+// precision of an integer conversion is its minimum number of digits
+fn printf_conversion(value: &Value, conversion: char, index: usize, spec: &mut Spec) -> Result<String, String> {
+  match conversion {
+    's' | 'r' | 'a' => {
+      let text = convert(value, conversion)?;
+      let text: String = match spec.precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text,
+      };
+      spec.zero = false;
+      Ok(pad("", &text, spec, '>'))
+    }
+    'c' => {
+      let c = match value {
+        Value::Int(n) => match u32::try_from(*n).ok().and_then(char::from_u32) {
+          Some(c) => c,
+          None => return Err("OverflowError: %c arg not in range(0x110000)".to_string()),
+        },
+        Value::Str(s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+        _ => return Err("TypeError: %c requires int or char".to_string()),
+      };
+      spec.zero = false;
+      Ok(pad("", &c.to_string(), spec, '>'))
+    }
+    'd' | 'i' | 'u' | 'o' | 'x' | 'X' => {
+      let n = match value {
+        Value::Int(n) => *n,
+        Value::Bool(b) => *b as i64,
+        Value::Float(d) if matches!(conversion, 'd' | 'i' | 'u') => {
+          if d.is_nan() {
+            return Err("ValueError: cannot convert float NaN to integer".to_string());
+          } else if d.is_infinite() {
+            return Err("OverflowError: cannot convert float infinity to integer".to_string());
+          }
+          d.trunc() as i64
+        }
+        _ if matches!(conversion, 'd' | 'i' | 'u') => {
+          return Err(format!("TypeError: %{} format: a real number is required, not {}", conversion, value.type_name()))
+        }
+        _ => return Err(format!("TypeError: %{} format: an integer is required, not {}", conversion, value.type_name())),
+      };
+      let magnitude = n.unsigned_abs();
+      let (mut digits, prefix) = match conversion {
+        'o' => (format!("{:o}", magnitude), "0o"),
+        'x' => (format!("{:x}", magnitude), "0x"),
+        'X' => (format!("{:X}", magnitude), "0X"),
+        _ => (magnitude.to_string(), ""),
+      };
+      if let Some(precision) = spec.precision {
+        while digits.len() < precision {
+          digits.insert(0, '0');
+        }
+      }
+      let prefix = if spec.alternate { prefix } else { "" };
+      Ok(pad(&format!("{}{}", sign_of(n < 0, spec), prefix), &digits, spec, '>'))
+    }
+    'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+      let d = match value.as_number() {
+        Some(n) => n.as_f64(),
+        None => return Err(format!("TypeError: must be real number, not {}", value.type_name())),
+      };
+      spec.precision = Some(spec.precision.unwrap_or(6));
+      spec.kind = Some(conversion);
+      format_float(d, spec, value)
+    }
+    _ => Err(format!("ValueError: unsupported format character '{}' ({:#x}) at index {}", conversion, conversion as u32, index)),
+  }
+}
+
+// A parsed format spec: [[fill]align][sign][#][0][width][grouping][.precision][type]
+struct Spec {
+  fill: Option<char>,
+  align: Option<char>,
+  sign: char,
+  alternate: bool,
+  zero: bool,
+  width: usize,
+  grouping: Option<char>,
+  precision: Option<usize>,
+  kind: Option<char>,
+}
+
+fn parse_spec(spec: &str, value: &Value) -> Result<Spec, String> {
+  let chars: Vec<char> = spec.chars().collect();
+  let mut parsed = Spec {
+    fill: None,
+    align: None,
+    sign: '-',
+    alternate: false,
+    zero: false,
+    width: 0,
+    grouping: None,
+    precision: None,
+    kind: None,
+  };
+  let mut i = 0;
+
+  let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '=' | '^'));
+  if is_align(chars.get(1)) {
+    parsed.fill = Some(chars[0]);
+    parsed.align = Some(chars[1]);
+    i = 2;
+  } else if is_align(chars.first()) {
+    parsed.align = Some(chars[0]);
+    i = 1;
+  }
+
+  if let Some(&sign @ ('+' | '-' | ' ')) = chars.get(i) {
+    parsed.sign = sign;
+    i += 1;
+  }
+  if chars.get(i) == Some(&'#') {
+    parsed.alternate = true;
+    i += 1;
+  }
+  if chars.get(i) == Some(&'0') {
+    parsed.zero = true;
+    i += 1;
+  }
+
+  let digits = |i: &mut usize| {
+    let start = *i;
+    while matches!(chars.get(*i), Some(c) if c.is_ascii_digit()) {
+      *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse::<usize>().ok()
+  };
+  parsed.width = digits(&mut i).unwrap_or(0);
+
+  if let Some(&grouping @ (',' | '_')) = chars.get(i) {
+    parsed.grouping = Some(grouping);
+    i += 1;
+    if matches!(chars.get(i), Some(',' | '_')) {
+      return Err("ValueError: Cannot specify both ',' and '_'.".to_string());
+    }
+  }
+
+  if chars.get(i) == Some(&'.') {
+    i += 1;
+    match digits(&mut i) {
+      Some(precision) => parsed.precision = Some(precision),
+      None => return Err("ValueError: Format specifier missing precision".to_string()),
+    }
+  }
+
+  if i + 1 == chars.len() {
+    parsed.kind = Some(chars[i]);
+  } else if i < chars.len() {
+    return Err(format!("ValueError: Invalid format specifier '{}' for object of type '{}'", spec, value.type_name()));
+  }
+
+  // Like CPython, the presentation type is checked against the grouping before the type is known
+  // to suit the value. A string with no type is formatted as 's'
+  let default_kind = if matches!(value, Value::Str(_)) { Some('s') } else { None };
+  if let (Some(grouping), Some(kind)) = (parsed.grouping, parsed.kind.or(default_kind)) {
+    let allowed = if grouping == ',' { "deEfFgG%" } else { "deEfFgG%boxX" };
+    if !allowed.contains(kind) {
+      return Err(grouping_error(grouping, kind));
+    }
+  }
+  Ok(parsed)
+}
+
+// Python's format(value, spec)
+pub fn format_value(value: &Value, spec: &str) -> Result<String, String> {
+  if spec.is_empty() {
+    return value.str();
+  }
+  let parsed = parse_spec(spec, value)?;
+
+  match value {
+    Value::Int(n) => format_int(*n, &parsed, value),
+    Value::Bool(b) => format_int(*b as i64, &parsed, value),
+    Value::Float(d) => format_float(*d, &parsed, value),
+    Value::Str(s) => format_str(s, &parsed, value),
+    _ => Err(format!("TypeError: unsupported format string passed to {}.__format__", value.type_name())),
+  }
+}
+
+fn unknown_code(kind: char, value: &Value) -> String {
+  format!("ValueError: Unknown format code '{}' for object of type '{}'", kind, value.type_name())
+}
+
+fn grouping_error(grouping: char, kind: char) -> String {
+  format!("ValueError: Cannot specify '{}' with '{}'.", grouping, kind)
+}
+
+fn format_str(s: &str, spec: &Spec, value: &Value) -> Result<String, String> {
+  match spec.kind {
+    None | Some('s') => {
+      if spec.sign != '-' {
+        return Err("ValueError: Sign not allowed in string format specifier".to_string());
+      }
+      if spec.alternate {
+        return Err("ValueError: Alternate form (#) not allowed in string format specifier".to_string());
+      }
+      if spec.align == Some('=') {
+        return Err("ValueError: '=' alignment not allowed in string format specifier".to_string());
+      }
+      let text: String = match spec.precision {
+        Some(precision) => s.chars().take(precision).collect(),
+        None => s.to_string(),
+      };
+      Ok(pad("", &text, spec, '<'))
+    }
+    Some(kind) => Err(unknown_code(kind, value)),
+  }
+}
+
+fn format_int(n: i64, spec: &Spec, value: &Value) -> Result<String, String> {
+  let kind = match spec.kind {
+    Some('e' | 'E' | 'f' | 'F' | 'g' | 'G' | '%') => return format_float(n as f64, spec, value),
+    Some(kind @ ('d' | 'n' | 'b' | 'o' | 'x' | 'X' | 'c')) => kind,
+    None => 'd',
+    Some(kind) => return Err(unknown_code(kind, value)),
+  };
+  if spec.precision.is_some() {
+    return Err("ValueError: Precision not allowed in integer format specifier".to_string());
+  }
+
+  // A single character with the given code point
+  if kind == 'c' {
+    if spec.sign != '-' {
+      return Err("ValueError: Sign not allowed with integer format specifier 'c'".to_string());
+    }
+    if spec.alternate {
+      return Err("ValueError: Alternate form (#) not allowed with integer format specifier 'c'".to_string());
+    }
+    return match u32::try_from(n).ok().and_then(char::from_u32) {
+      Some(c) => Ok(pad("", &c.to_string(), spec, '>')),
+      None => Err("OverflowError: %c arg not in range(0x110000)".to_string()),
+    };
+  }
+
+  let magnitude = n.unsigned_abs();
+  let (digits, prefix, interval) = match kind {
+    'b' => (format!("{:b}", magnitude), "0b", 4),
+    'o' => (format!("{:o}", magnitude), "0o", 4),
+    'x' => (format!("{:x}", magnitude), "0x", 4),
+    'X' => (format!("{:X}", magnitude), "0X", 4),
+    _ => (magnitude.to_string(), "", 3),
+  };
+  let prefix = if spec.alternate { prefix } else { "" };
+  Ok(finish(sign_of(n < 0, spec), prefix, &digits, interval, spec))
+}
+
+fn format_float(d: f64, spec: &Spec, value: &Value) -> Result<String, String> {
+  let kind = match spec.kind {
+    Some(kind @ ('e' | 'E' | 'f' | 'F' | 'g' | 'G' | 'n' | '%')) => Some(kind),
+    None => None,
+    Some(kind) => return Err(unknown_code(kind, value)),
+  };
+
+  let magnitude = d.abs();
+  let precision = spec.precision.unwrap_or(6);
+  let digits = match kind {
+    _ if d.is_nan() => "nan".to_string(),
+    _ if d.is_infinite() => "inf".to_string(),
+    Some('f' | 'F') => fixed(magnitude, precision, spec.alternate),
+    Some('e' | 'E') => scientific(magnitude, precision, spec.alternate),
+    Some('%') => fixed(magnitude * 100.0, precision, spec.alternate),
+    Some(_) => general(magnitude, precision, spec.alternate, false),
+    None => match spec.precision {
+      Some(precision) => general(magnitude, precision, spec.alternate, true),
+      None => float_repr(magnitude),
+    },
+  };
+  let digits = if kind == Some('%') { digits + "%" } else { digits };
+  let digits = if matches!(kind, Some('E' | 'F' | 'G')) { digits.to_uppercase() } else { digits };
+  Ok(finish(sign_of(d.is_sign_negative() && !d.is_nan(), spec), "", &digits, 3, spec))
+}
+
+// Fixed-point notation with the given number of decimals. The alternate form always has a point
+fn fixed(d: f64, precision: usize, alternate: bool) -> String {
+  let result = format!("{:.*}", precision, d);
+  if alternate && precision == 0 {
+    result + "."
+  } else {
+    result
+  }
+}
+
+// Scientific notation with the given number of decimals and an exponent of at least two digits
+fn scientific(d: f64, precision: usize, alternate: bool) -> String {
+  let result = format!("{:.*e}", precision, d);
+  let (mantissa, exponent) = result.split_once('e').unwrap();
+  let exponent = exponent.parse::<i32>().unwrap();
+  let point = if alternate && precision == 0 { "." } else { "" };
+  format!("{}{}e{}{:02}", mantissa, point, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+// The 'g' format: precision significant digits, in scientific notation when the exponent is below
+// -4 or at least the precision, with trailing zeros removed unless in alternate form. A float with
+// no format type but a precision (repr_style) switches once the exponent reaches precision - 1 and
+// keeps a digit after the point
+fn general(d: f64, precision: usize, alternate: bool, repr_style: bool) -> String {
+  let precision = precision.max(1);
+  let exponent = format!("{:.*e}", precision - 1, d).split_once('e').unwrap().1.parse::<i32>().unwrap();
+  let threshold = if repr_style { precision as i32 - 1 } else { precision as i32 };
+  let use_fixed = exponent >= -4 && exponent < threshold;
+
+  let result = if use_fixed {
+    fixed(d, (precision as i32 - 1 - exponent) as usize, alternate)
+  } else {
+    scientific(d, precision - 1, alternate)
+  };
+  if alternate {
+    return result;
+  }
+
+  let (mantissa, exponent_part) = match result.find('e') {
+    Some(i) => result.split_at(i),
+    None => (result.as_str(), ""),
+  };
+  let mut mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa }.to_string();
+  if repr_style && use_fixed && !mantissa.contains('.') {
+    mantissa.push_str(".0");
+  }
+  mantissa + exponent_part
+}
+
+fn sign_of(negative: bool, spec: &Spec) -> &'static str {
+  match (negative, spec.sign) {
+    (true, _) => "-",
+    (false, '+') => "+",
+    (false, ' ') => " ",
+    _ => "",
+  }
+}
+
+// Groups the integer digits of a formatted number, then pads it. Zero padding is grouped along
+// with the digits, as in format(1234, '09,') == '0,001,234'
+fn finish(sign: &str, prefix: &str, digits: &str, interval: usize, spec: &Spec) -> String {
+  let grouping = match spec.grouping {
+    Some(grouping) => grouping,
+    None => return pad(&format!("{}{}", sign, prefix), digits, spec, '>'),
+  };
+
+  // Only the digits before any point or exponent are grouped, and nan and inf not at all
+  let int_len = if interval == 4 { digits.len() } else { digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len()) };
+  let (int_part, rest) = digits.split_at(int_len);
+  if int_part.is_empty() {
+    return pad(&format!("{}{}", sign, prefix), digits, spec, '>');
+  }
+
+  let zero_padded = spec.zero && spec.fill.is_none() && matches!(spec.align, None | Some('='));
+  let target = if zero_padded { spec.width.saturating_sub(sign.len() + prefix.len() + rest.chars().count()) } else { 0 };
+  let mut int_part = int_part.to_string();
+  let mut grouped = group(&int_part, grouping, interval);
+  while grouped.len() < target {
+    int_part.insert(0, '0');
+    grouped = group(&int_part, grouping, interval);
+  }
+  pad(&format!("{}{}", sign, prefix), &(grouped + rest), spec, '>')
+}
+
+// Inserts a separator between every interval digits, counting from the right
+fn group(digits: &str, separator: char, interval: usize) -> String {
+  let mut result = String::with_capacity(digits.len() * 2);
+  for (i, c) in digits.chars().enumerate() {
+    if i > 0 && (digits.len() - i).is_multiple_of(interval) {
+      result.push(separator);
+    }
+    result.push(c);
+  }
+  result
+}
+
+// Pads a formatted value out to the spec's width. With '=' alignment, or the 0 flag on a number, the
+// padding goes between the sign and the digits
+fn pad(sign: &str, digits: &str, spec: &Spec, default_align: char) -> String {
+  let fill = spec.fill.unwrap_or(if spec.zero { '0' } else { ' ' });
+  let align = spec.align.unwrap_or(if spec.zero && default_align == '>' { '=' } else { default_align });
+  let len = sign.chars().count() + digits.chars().count();
+  let padding = spec.width.saturating_sub(len);
+  let fill_str = |n: usize| fill.to_string().repeat(n);
+
+  match align {
+    '<' => format!("{}{}{}", sign, digits, fill_str(padding)),
+    '^' => format!("{}{}{}{}", fill_str(padding / 2), sign, digits, fill_str(padding - padding / 2)),
+    '=' => format!("{}{}{}", sign, fill_str(padding), digits),
+    _ => format!("{}{}{}", fill_str(padding), sign, digits),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::value::Dict;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  fn error(message: &str) -> Result<String, String> {
+    Err(message.to_string())
+  }
+
+  #[test]
+  fn format_spec_for_ints() {
+    let cases = [
+      (1234567, ",", "1,234,567"),
+      (1234567, "_x", "12_d687"),
+      (255, "#010b", "0b11111111"),
+      (-42, "+d", "-42"),
+      (42, " d", " 42"),
+      (42, "*^9", "***42****"),
+      (12, "X", "C"),
+    ];
+    for (n, spec, expected) in cases {
+      assert_eq!(format_value(&Value::Int(n), spec), Ok(expected.to_string()), "format({}, {:?})", n, spec);
+    }
+    assert_eq!(format_value(&Value::Bool(true), "d"), Ok("1".to_string()));
+  }
+
+  #[test]
+  fn format_spec_for_floats() {
+    let cases = [
+      (1.23556, ".2f", "1.24"),
+      (0.000123, "e", "1.230000e-04"),
+      (1e16, "", "1e+16"),
+      (1e-5, "", "1e-05"),
+      (1.0, "", "1.0"),
+      (-0.0, "", "-0.0"),
+      (1234.5, "g", "1234.5"),
+      (0.5, ".1%", "50.0%"),
+      (f64::INFINITY, "f", "inf"),
+    ];
+    for (d, spec, expected) in cases {
+      assert_eq!(format_value(&Value::Float(d), spec), Ok(expected.to_string()), "format({}, {:?})", d, spec);
+    }
+  }
+
+  #[test]
+  fn format_spec_for_strings() {
+    assert_eq!(format_value(&Value::new_str("abc"), ">6"), Ok("   abc".to_string()));
+    assert_eq!(format_value(&Value::new_str("abc"), ".2"), Ok("ab".to_string()));
+  }
+
+  #[test]
+  fn format_spec_errors() {
+    assert_eq!(format_value(&Value::Float(1.5), "d"), error("ValueError: Unknown format code 'd' for object of type 'float'"));
+    assert_eq!(format_value(&Value::new_str("s"), "d"), error("ValueError: Unknown format code 'd' for object of type 'str'"));
+    assert_eq!(format_value(&Value::Int(1), ",s"), error("ValueError: Cannot specify ',' with 's'."));
+    assert_eq!(format_value(&Value::Int(1), ",x"), error("ValueError: Cannot specify ',' with 'x'."));
+    assert_eq!(format_value(&Value::Float(1.5), "_n"), error("ValueError: Cannot specify '_' with 'n'."));
+    assert_eq!(format_value(&Value::new_str("a"), "+,"), error("ValueError: Cannot specify ',' with 's'."));
+    assert_eq!(format_value(&Value::new_str("a"), ",d"), error("ValueError: Unknown format code 'd' for object of type 'str'"));
+    assert_eq!(format_value(&Value::Int(5), "_b"), Ok("101".to_string()));
+  }
+
+  #[test]
+  fn str_format_fields() {
+    let args = [Value::Int(1), Value::new_list(vec![Value::Int(7)])];
+    let kwargs = [("name".to_string(), Value::new_str("n"))];
+    assert_eq!(str_format("{0} {name} {0} {1[0]}", &args, &kwargs), Ok("1 n 1 7".to_string()));
+    assert_eq!(str_format("{} {name} {}", &args, &kwargs), Ok("1 n [7]".to_string()));
+
+    let args = [Value::new_str("a"), Value::Int(3), Value::new_str("b")];
+    assert_eq!(str_format("{:{}}|{!r:>5}", &args, &[]), Ok("a  |  'b'".to_string()));
+    assert_eq!(str_format("{{}}", &[], &[]), Ok("{}".to_string()));
+  }
+
+  #[test]
+  fn str_format_errors() {
+    let args = [Value::new_str("x")];
+    assert_eq!(str_format("{", &args, &[]), error("ValueError: Single '{' encountered in format string"));
+    assert_eq!(str_format("}", &args, &[]), error("ValueError: Single '}' encountered in format string"));
+    assert_eq!(
+      str_format("{0}{}", &args, &[]),
+      error("ValueError: cannot switch from manual field specification to automatic field numbering")
+    );
+    assert_eq!(str_format("{:d}", &args, &[]), error("ValueError: Unknown format code 'd' for object of type 'str'"));
+  }
+
+  fn tuple(items: Vec<Value>) -> Value {
+    Value::Tuple(Rc::new(items))
+  }
+
+  #[test]
+  fn printf_conversions() {
+    let args = tuple(vec![Value::new_str("box"), Value::Int(3), Value::Float(12.345)]);
+    assert_eq!(printf_format("%s has %d items (%.2f%%)", &args), Ok("box has 3 items (12.35%)".to_string()));
+    let args = tuple(vec![Value::new_str("ab"), Value::Float(2.25), Value::Int(255), Value::Int(8), Value::Int(65), Value::new_str("q")]);
+    assert_eq!(printf_format("%-5s|%5.1f|%x|%#o|%c|%r", &args), Ok("ab   |  2.2|ff|0o10|A|'q'".to_string()));
+    assert_eq!(printf_format("%*d", &tuple(vec![Value::Int(4), Value::Int(7)])), Ok("   7".to_string()));
+    assert_eq!(printf_format("%+.3e", &tuple(vec![Value::Float(12345.678)])), Ok("+1.235e+04".to_string()));
+    assert_eq!(printf_format("%5.3s|", &tuple(vec![Value::new_str("abcdef")])), Ok("  abc|".to_string()));
+    assert_eq!(printf_format("%i %u", &tuple(vec![Value::Int(3), Value::Int(4)])), Ok("3 4".to_string()));
+    assert_eq!(printf_format("%%", &tuple(Vec::new())), Ok("%".to_string()));
+  }
+
+  #[test]
+  fn printf_single_argument() {
+    // Anything but a tuple is the only argument, and a tuple inside a tuple is one argument
+    assert_eq!(printf_format("%s", &Value::Int(5)), Ok("5".to_string()));
+    assert_eq!(printf_format("%s", &Value::new_list(vec![Value::Int(1), Value::Int(2)])), Ok("[1, 2]".to_string()));
+    let pair = tuple(vec![Value::Int(1), Value::Int(2)]);
+    assert_eq!(printf_format("%s", &tuple(vec![pair])), Ok("(1, 2)".to_string()));
+  }
+
+  #[test]
+  fn printf_mapping_keys() {
+    let mut table = Dict::new();
+    table.insert(Value::new_str("k"), Value::new_str("x")).unwrap();
+    table.insert(Value::new_str("v"), Value::Int(42)).unwrap();
+    let mapping = Value::Dict(Rc::new(RefCell::new(table)));
+    assert_eq!(printf_format("%(k)s=%(v)05d", &mapping), Ok("x=00042".to_string()));
+    assert_eq!(printf_format("%(b)s", &mapping), error("KeyError: 'b'"));
+    assert_eq!(printf_format("%(a)s", &tuple(vec![Value::Int(1)])), error("TypeError: format requires a mapping"));
+  }
+
+  #[test]
+  fn printf_errors() {
+    let one = tuple(vec![Value::Int(1)]);
+    assert_eq!(printf_format("%d %d", &one), error("TypeError: not enough arguments for format string"));
+    assert_eq!(printf_format("%s %s", &Value::Int(5)), error("TypeError: not enough arguments for format string"));
+    assert_eq!(
+      printf_format("%s", &tuple(vec![Value::Int(1), Value::Int(2)])),
+      error("TypeError: not all arguments converted during string formatting")
+    );
+    assert_eq!(
+      printf_format("%d", &tuple(vec![Value::new_str("x")])),
+      error("TypeError: %d format: a real number is required, not str")
+    );
+    assert_eq!(printf_format("%c", &tuple(vec![Value::Float(1.5)])), error("TypeError: %c requires int or char"));
+    assert_eq!(printf_format("%q", &one), error("ValueError: unsupported format character 'q' (0x71) at index 1"));
+    assert_eq!(printf_format("%", &one), error("ValueError: incomplete format"));
+  }
+}
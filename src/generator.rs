@@ -0,0 +1,359 @@
+use crate::types::{Clause, Expr, FStringPart, Op, PyType, Statement, Stmt};
+
+// The tree-walker runs a function by recursing over its statements, which can't stop halfway and
+// carry on later. So the body of a generator function is lowered to a flat list of steps instead:
+// statements containing a yield become jumps between steps, and expressions containing a yield are
+// split up so that each yield is a step of its own, with the values worked out before it kept in
+// temporary variables. Everything else runs as it is, so a generator is only suspended between steps
+pub enum Step {
+  Run(PyType, Option<Targets>), // node without yields, with the loop its break and continue apply to
+  Yield(PyType, String),        // value to yield, temporary variable the value sent back is stored in
+  YieldFrom(String, String),    // temporary variable holding the iterator delegated to, and the one its return value is stored in
+  GetIter(PyType, String),      // stores iter(value) in a temporary variable
+  ForIter(String, String, usize), // iterator variable, loop variable, step to jump to once the iterator is exhausted
+  JumpIfFalse(PyType, usize),
+  Jump(usize),
+  Return(PyType),
+}
+
+// Steps that break and continue in a lowered loop jump to
+#[derive(Clone, Copy)]
+pub struct Targets {
+  pub start: usize,
+  pub end: usize,
+}
+
+// With optimize set (-O), assert statements are left out
+pub fn lower(body: &[Statement], optimize: bool) -> Vec<Step> {
+  let mut lowerer = Lowerer { steps: Vec::new(), temps: 0, loops: Vec::new(), optimize };
+  lowerer.lower_body(body);
+  lowerer.steps
+}
+
+// Jumps waiting on the end of a lowered loop
+struct Loop {
+  start: usize,
+  exits: Vec<usize>, // steps that jump to the end of the loop, or break out of it
+}
+
+struct Lowerer {
+  steps: Vec<Step>,
+  temps: usize,
+  loops: Vec<Loop>,
+  optimize: bool,
+}
+
+impl Lowerer {
+  fn here(&self) -> usize {
+    self.steps.len()
+  }
+
+  // Names that can't clash with a Python variable
+  fn temp(&mut self) -> String {
+    self.temps += 1;
+    format!(".{}", self.temps - 1)
+  }
+
+  fn run(&mut self, node: PyType) {
+    let targets = self.loops.last().map(|current| Targets { start: current.start, end: 0 });
+    if targets.is_some() {
+      let here = self.here();
+      self.loops.last_mut().unwrap().exits.push(here);
+    }
+    self.steps.push(Step::Run(node, targets));
+  }
+
+  fn assign(&mut self, name: &str, value: PyType) {
+    self.run(PyType::Stmt(Stmt::Assign(vec![var(name)], Box::new(value))));
+  }
+
+  // Points the jump at `at` to the next step
+  fn patch(&mut self, at: usize) {
+    let target = self.here();
+    match &mut self.steps[at] {
+      Step::ForIter(_, _, end) | Step::JumpIfFalse(_, end) | Step::Jump(end) => *end = target,
+      Step::Run(_, Some(targets)) => targets.end = target,
+      _ => unreachable!("only jumps are patched"),
+    }
+  }
+
+  fn lower_body(&mut self, body: &[Statement]) {
+    for statement in body {
+      self.lower_stmt(&statement.node);
+    }
+  }
+
+  fn lower_stmt(&mut self, node: &PyType) {
+    if !node.contains_yield() {
+      self.run(node.clone());
+      return;
+    }
+
+    match node {
+      PyType::Expr(_) => {
+        // The value of an expression statement is thrown away, and a temporary variable has no effect
+        let value = self.lower_expr(node);
+        if !matches!(value, PyType::Expr(Expr::Var(_))) {
+          self.run(value);
+        }
+      }
+      PyType::Stmt(stmt) => match stmt {
+        // The value is evaluated before the parts of the targets
+        Stmt::Assign(targets, e) => {
+          let (mut targets, mut value) = (targets.clone(), e.clone());
+          let mut parts = vec![&mut *value];
+          parts.extend(targets.iter_mut().flat_map(target_operands));
+          self.lower_in_order(&mut parts);
+          self.run(PyType::Stmt(Stmt::Assign(targets, value)));
+        }
+        // The parts of the target are evaluated before the value
+        Stmt::AugAssign(target, op, e) => {
+          let (mut target, mut value) = (target.clone(), e.clone());
+          let mut parts = target_operands(&mut target);
+          parts.push(&mut value);
+          self.lower_in_order(&mut parts);
+          self.run(PyType::Stmt(Stmt::AugAssign(target, *op, value)));
+        }
+        Stmt::Del(targets) => {
+          let mut targets = targets.clone();
+          let mut parts: Vec<&mut PyType> = targets.iter_mut().flat_map(target_operands).collect();
+          self.lower_in_order(&mut parts);
+          self.run(PyType::Stmt(Stmt::Del(targets)));
+        }
+        Stmt::Return(e) => {
+          let value = self.lower_expr(e);
+          self.steps.push(Step::Return(value));
+        }
+        Stmt::If(condition, body, else_body) => {
+          let condition = self.lower_expr(condition);
+          let to_else = self.here();
+          self.steps.push(Step::JumpIfFalse(condition, 0));
+          self.lower_body(body);
+          match else_body {
+            Some(else_body) => {
+              let to_end = self.here();
+              self.steps.push(Step::Jump(0));
+              self.patch(to_else);
+              self.lower_body(else_body);
+              self.patch(to_end);
+            }
+            None => self.patch(to_else),
+          }
+        }
+        Stmt::While(condition, body) => {
+          let start = self.here();
+          let condition = self.lower_expr(condition);
+          let exit = self.here();
+          self.steps.push(Step::JumpIfFalse(condition, 0));
+          self.lower_loop(start, exit, body);
+        }
+        Stmt::For(var, iterable, body) => {
+          let iterable = self.lower_expr(iterable);
+          let iterator = self.temp();
+          self.steps.push(Step::GetIter(iterable, iterator.clone()));
+          let start = self.here();
+          self.steps.push(Step::ForIter(iterator, var.clone(), 0));
+          self.lower_loop(start, start, body);
+        }
+        // Only the defaults of a nested function run here
+        Stmt::Function(name, parameters, body) => {
+          let mut parameters = parameters.clone();
+          let mut defaults: Vec<&mut PyType> =
+            parameters.positional.iter_mut().chain(parameters.kwonly.iter_mut()).filter_map(|(_, default)| default.as_mut()).collect();
+          self.lower_in_order(&mut defaults);
+          self.run(PyType::Stmt(Stmt::Function(name.clone(), parameters, body.clone())));
+        }
+        // The message is only evaluated once the condition turns out false
+        Stmt::Assert(condition, message) => {
+          if self.optimize {
+            return;
+          }
+          let condition = self.lower_expr(condition);
+          let to_message = self.here();
+          self.steps.push(Step::JumpIfFalse(condition, 0));
+          let to_end = self.here();
+          self.steps.push(Step::Jump(0));
+          self.patch(to_message);
+          let message = message.as_ref().map(|message| Box::new(self.lower_expr(message)));
+          self.run(PyType::Stmt(Stmt::Assert(Box::new(PyType::Expr(Expr::Bool(false))), message)));
+          self.patch(to_end);
+        }
+        Stmt::Break | Stmt::Continue | Stmt::Pass | Stmt::Global(_) | Stmt::Nonlocal(_) => {
+          unreachable!("break, continue, pass and declarations don't contain a yield")
+        }
+      },
+    }
+  }
+
+  // Lowers the body of a loop whose first step, at `exit`, jumps past the end of the loop
+  fn lower_loop(&mut self, start: usize, exit: usize, body: &[Statement]) {
+    self.loops.push(Loop { start, exits: vec![exit] });
+    self.lower_body(body);
+    self.steps.push(Step::Jump(start));
+    for at in self.loops.pop().unwrap().exits {
+      self.patch(at);
+    }
+  }
+
+  // Gives an expression without yields that has the value of e once the steps added for it have run
+  fn lower_expr(&mut self, e: &PyType) -> PyType {
+    if !e.contains_yield() {
+      return e.clone();
+    }
+
+    let result = self.temp();
+    match e {
+      PyType::Expr(Expr::Yield(value)) => {
+        let value = match value {
+          Some(value) => self.lower_expr(value),
+          None => PyType::Expr(Expr::None),
+        };
+        self.steps.push(Step::Yield(value, result.clone()));
+      }
+      PyType::Expr(Expr::YieldFrom(iterable)) => {
+        let iterable = self.lower_expr(iterable);
+        let iterator = self.temp();
+        self.steps.push(Step::GetIter(iterable, iterator.clone()));
+        self.steps.push(Step::YieldFrom(iterator, result.clone()));
+      }
+
+      // and/or only evaluate their right operand when the left one doesn't decide the result
+      PyType::Expr(Expr::Binop(op @ (Op::And | Op::Or), left, right)) => {
+        let left = self.lower_expr(left);
+        self.assign(&result, left);
+        let decided = match op {
+          Op::And => var(&result),
+          _ => PyType::Expr(Expr::Not(Box::new(var(&result)))),
+        };
+        let to_end = self.here();
+        self.steps.push(Step::JumpIfFalse(decided, 0));
+        let right = self.lower_expr(right);
+        self.assign(&result, right);
+        self.patch(to_end);
+      }
+      PyType::Expr(Expr::Ternary(condition, if_true, if_false)) => {
+        let condition = self.lower_expr(condition);
+        let to_else = self.here();
+        self.steps.push(Step::JumpIfFalse(condition, 0));
+        let if_true = self.lower_expr(if_true);
+        self.assign(&result, if_true);
+        let to_end = self.here();
+        self.steps.push(Step::Jump(0));
+        self.patch(to_else);
+        let if_false = self.lower_expr(if_false);
+        self.assign(&result, if_false);
+        self.patch(to_end);
+      }
+      // Each link of a comparison chain is compared in turn, stopping at the first false one
+      PyType::Expr(Expr::Compare(first, comparisons)) => {
+        let mut left = self.hoist(first);
+        let mut to_end = Vec::new();
+        for (i, (op, operand)) in comparisons.iter().enumerate() {
+          let right = self.hoist(operand);
+          self.assign(&result, PyType::Expr(Expr::Compare(Box::new(left), vec![(*op, right.clone())])));
+          if i + 1 < comparisons.len() {
+            to_end.push(self.here());
+            self.steps.push(Step::JumpIfFalse(var(&result), 0));
+          }
+          left = right;
+        }
+        for at in to_end {
+          self.patch(at);
+        }
+      }
+
+      _ => {
+        let mut e = e.clone();
+        self.lower_in_order(&mut operands(&mut e));
+        self.assign(&result, e);
+      }
+    }
+    var(&result)
+  }
+
+  // Lowers expressions that are evaluated one after another. Those evaluated before the last yield
+  // are kept in temporary variables, as the yield could change what they would evaluate to
+  fn lower_in_order(&mut self, exprs: &mut [&mut PyType]) {
+    if let Some(last) = exprs.iter().rposition(|e| e.contains_yield()) {
+      for e in &mut exprs[..=last] {
+        **e = self.hoist(e);
+      }
+    }
+  }
+
+  // Evaluates e into a temporary variable, unless it is a constant
+  fn hoist(&mut self, e: &PyType) -> PyType {
+    let value = self.lower_expr(e);
+    match value {
+      PyType::Expr(Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::String(_) | Expr::Bytes(_) | Expr::None) => value,
+      _ => {
+        let temp = self.temp();
+        self.assign(&temp, value);
+        var(&temp)
+      }
+    }
+  }
+}
+
+fn var(name: &str) -> PyType {
+  PyType::Expr(Expr::Var(name.to_string()))
+}
+
+// Subexpressions that are all evaluated, in the order they are evaluated. For *iterable this is the
+// iterable, so that the unpacking stays in the call
+fn operands(e: &mut PyType) -> Vec<&mut PyType> {
+  let mut operands = Vec::new();
+  if let PyType::Expr(expr) = e {
+    match expr {
+      Expr::List(items) | Expr::Tuple(items) | Expr::Set(items) => operands.extend(items.iter_mut()),
+      Expr::Dict(pairs) => {
+        for (key, value) in pairs {
+          operands.push(key);
+          operands.push(value);
+        }
+      }
+      Expr::Binop(_, left, right) | Expr::Subscript(left, right) => {
+        operands.push(&mut **left);
+        operands.push(&mut **right);
+      }
+      Expr::Not(e) | Expr::Attribute(e, _) | Expr::Starred(e) => operands.push(&mut **e),
+      Expr::Slice(start, stop, step) => operands.extend([start, stop, step].into_iter().flatten().map(|bound| &mut **bound)),
+      Expr::FunctionCall(func, args, kwargs) => {
+        operands.push(&mut **func);
+        for arg in args {
+          match arg {
+            PyType::Expr(Expr::Starred(iterable)) => operands.push(&mut **iterable),
+            _ => operands.push(arg),
+          }
+        }
+        operands.extend(kwargs.iter_mut().map(|(_, arg)| arg));
+      }
+      Expr::FString(parts) => fstring_operands(parts, &mut operands),
+      // The rest of a comprehension runs in its own scope, which can't contain a yield
+      Expr::Comprehension(_, _, clauses) => match &mut clauses[0] {
+        Clause::For(_, iterable) => operands.push(iterable),
+        Clause::If(_) => unreachable!("a comprehension starts with a for clause"),
+      },
+      _ => {}
+    }
+  }
+  operands
+}
+
+// Subexpressions of an assignment target that are evaluated before the value is assigned
+fn target_operands(target: &mut PyType) -> Vec<&mut PyType> {
+  match target {
+    PyType::Expr(Expr::Subscript(container, key)) => vec![&mut **container, &mut **key],
+    PyType::Expr(Expr::Attribute(object, _)) => vec![&mut **object],
+    _ => Vec::new(),
+  }
+}
+
+fn fstring_operands<'a>(parts: &'a mut [FStringPart], operands: &mut Vec<&'a mut PyType>) {
+  for part in parts {
+    if let FStringPart::Field(expr, _, spec) = part {
+      operands.push(&mut **expr);
+      fstring_operands(spec, operands);
+    }
+  }
+}
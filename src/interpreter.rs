@@ -1,366 +1,786 @@
-use crate::types::{PyType, Stmt, Expr, Op, Environment};
+use crate::builtins;
+use crate::format::{convert, format_value};
+use crate::generator::{self, Step};
+use crate::methods::{get_attribute, set_attribute};
+use crate::operators::{del_item, eval_binop, eval_inplace, get_item, set_item};
+use crate::types::{
+  body_contains_yield, local_names, push_unique, PyType, Statement, Stmt, Expr, FStringPart, Op, Comprehension, Clause, Declarations, Parameters,
+};
+use crate::value::{
+  self, assertion_error, keywords, merge_keywords, next_item, send_value, unpack_arguments, Caller, Dict, Function, FunctionBody, Resumed, Scope,
+  Signature, Value, MAX_CALL_DEPTH,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// What is known about a function's scope before it runs: the names it declares global or
+// nonlocal, the names bound in it, and the same for the function it is nested in. A name is local
+// to the whole body if it is bound anywhere in it, so reading it before it is bound doesn't fall
+// through to the enclosing scopes
+pub struct StaticScope {
+  declarations: Declarations,
+  locals: Vec<String>,
+  enclosing: Option<Rc<StaticScope>>,
+}
+
+impl StaticScope {
+  // Names starting with a dot are the hidden variables of generators and comprehensions
+  fn is_local(&self, name: &str) -> bool {
+    name.starts_with('.') || self.locals.iter().any(|local| local == name)
+  }
+}
+
+// Variables visible to the code being run: the module globals plus, inside a function call, the
+// local scopes of that call and of every enclosing function (innermost last)
+pub struct Environment {
+  globals: Scope,
+  scopes: Vec<Scope>,
+  names: Rc<StaticScope>, // the names of the innermost local scope and the ones enclosing it
+  depth: usize,           // number of active function calls
+  optimize: bool,         // skip assert statements (-O)
+}
+
+impl Environment {
+  pub fn new(optimize: bool) -> Environment {
+    let names = Rc::new(StaticScope { declarations: Declarations::default(), locals: Vec::new(), enclosing: None });
+    Environment { globals: Rc::new(RefCell::new(HashMap::new())), scopes: Vec::new(), names, depth: 0, optimize }
+  }
+
+  // The environment a function body runs in, sharing these globals
+  fn for_call(&self, scopes: Vec<Scope>, names: Rc<StaticScope>) -> Environment {
+    Environment { globals: self.globals.clone(), scopes, names, depth: self.depth + 1, optimize: self.optimize }
+  }
+
+  // The names of a scope nested in the one being run, which has no enclosing scope at module level
+  fn nested_scope(&self, declarations: Declarations, locals: Vec<String>) -> Rc<StaticScope> {
+    let enclosing = if self.scopes.is_empty() { None } else { Some(self.names.clone()) };
+    Rc::new(StaticScope { declarations, locals, enclosing })
+  }
+
+  // The names of a function defined here
+  fn function_scope(&self, parameters: &Parameters, body: &[Statement]) -> Rc<StaticScope> {
+    self.nested_scope(Declarations::of(body), local_names(parameters, body))
+  }
+
+  // The names of a comprehension run here, whose only locals are its loop variables
+  fn comprehension_scope(&self, clauses: &[Clause]) -> Rc<StaticScope> {
+    let mut locals = Vec::new();
+    for clause in clauses {
+      if let Clause::For(var, _) = clause {
+        push_unique(&mut locals, var);
+      }
+    }
+    self.nested_scope(Declarations::default(), locals)
+  }
+
+  // The index of the local scope a name belongs to, found from the innermost scope outwards, or
+  // None if it is global. A name declared global skips the remaining scopes, and one declared
+  // nonlocal skips the scope that declares it
+  fn resolve(&self, name: &str) -> Option<usize> {
+    let mut names = &self.names;
+    for i in (0..self.scopes.len()).rev() {
+      if names.declarations.is_global(name) {
+        return None;
+      }
+      if !names.declarations.is_nonlocal(name) && names.is_local(name) {
+        return Some(i);
+      }
+      match &names.enclosing {
+        Some(enclosing) => names = enclosing,
+        None => break,
+      }
+    }
+    None
+  }
+
+  // Looks a name up in the scope it belongs to, then for a global name in the builtins
+  fn get(&self, name: &str) -> Result<Value, String> {
+    match self.resolve(name) {
+      Some(i) => match self.scopes[i].borrow().get(name) {
+        Some(value) => Ok(value.clone()),
+        None => Err(self.unbound_error(name, i)),
+      },
+      None => match self.globals.borrow().get(name).cloned().or_else(|| builtins::lookup(name)) {
+        Some(value) => Ok(value),
+        None => Err(format!("NameError: name '{}' is not defined", name)),
+      },
+    }
+  }
+
+  fn unbound_error(&self, name: &str, i: usize) -> String {
+    if i + 1 == self.scopes.len() {
+      format!("UnboundLocalError: cannot access local variable '{}' where it is not associated with a value", name)
+    } else {
+      format!("NameError: cannot access free variable '{}' where it is not associated with a value in enclosing scope", name)
+    }
+  }
+
+  // The scope a name is bound in, which is the module scope outside of functions
+  fn scope_of(&self, name: &str) -> &Scope {
+    match self.resolve(name) {
+      Some(i) => &self.scopes[i],
+      None => &self.globals,
+    }
+  }
+
+  fn insert(&mut self, name: &str, value: Value) {
+    self.scope_of(name).borrow_mut().insert(name.to_string(), value);
+  }
+
+  // Unbinds a name from the scope it is bound in, where del finds it
+  fn remove(&mut self, name: &str) -> Result<(), String> {
+    match self.resolve(name) {
+      Some(i) if self.scopes[i].borrow_mut().remove(name).is_none() => Err(self.unbound_error(name, i)),
+      Some(_) => Ok(()),
+      None if self.globals.borrow_mut().remove(name).is_none() => Err(format!("NameError: name '{}' is not defined", name)),
+      None => Ok(()),
+    }
+  }
+}
 
-fn env_get(env: &Environment, target: &String) -> Option<PyType> {
-  env.iter().find(|(key, _)| key == target).map(|(_, expr)| expr.clone())
+impl Caller for Environment {
+  fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, String> {
+    call_function(function, args, Vec::new(), self)
+  }
+
+  fn resume(&mut self, frame: &mut Option<value::GeneratorFrame>, sent: Option<Value>) -> Result<Resumed, String> {
+    let frame = match frame {
+      Some(value::GeneratorFrame::Ast(frame)) => frame,
+      _ => unreachable!("the interpreter only resumes its own generators"),
+    };
+    if self.depth >= MAX_CALL_DEPTH {
+      return Err("RecursionError: maximum recursion depth exceeded".to_string());
+    }
+    let mut call_env = self.for_call(frame.scopes.clone(), frame.names.clone());
+    run_steps(frame, sent, &mut call_env)
+  }
 }
 
-fn env_insert(env: &mut Environment, name: &String, value: &PyType) {
-  // Search for existing name
-  for (key, val) in env.iter_mut() {
-    if key == name {
-      *val = value.clone();
-      return;
+// Where a suspended generator is in the steps of its body
+pub struct GeneratorFrame {
+  steps: Rc<Vec<Step>>,
+  pc: usize,          // the step to run next, which is the yield it is suspended at once it has started
+  scopes: Vec<Scope>, // the generator's local scope, innermost last
+  names: Rc<StaticScope>,
+}
+
+impl GeneratorFrame {
+  pub fn delegate(&self) -> Option<Value> {
+    match self.steps.get(self.pc) {
+      Some(Step::YieldFrom(iterator, _)) => self.scopes.last().unwrap().borrow().get(iterator).cloned(),
+      _ => None,
     }
   }
+}
 
-  env.push((name.clone(), value.clone()));
+// How control leaves a statement
+pub enum Flow {
+  Normal,
+  Return(Value),
+  Break,
+  Continue,
 }
 
-pub fn evaluate(expr: &PyType, env: &mut Environment) -> Result<PyType, String> {
-  match expr {
+// Runs one top-level line, returning the value of an expression so the REPL can echo it
+pub fn run(line: &PyType, env: &mut Environment) -> Result<Option<Value>, String> {
+  match line {
+    PyType::Expr(_) => evaluate(line, env).map(Some),
+    PyType::Stmt(_) => match execute(line, env)? {
+      Flow::Normal => Ok(None),
+      Flow::Return(_) => Err("SyntaxError: 'return' outside function".to_string()),
+      Flow::Break => Err("SyntaxError: 'break' outside loop".to_string()),
+      Flow::Continue => Err("SyntaxError: 'continue' not properly in loop".to_string()),
+    },
+  }
+}
 
-    // *** EXPRESSIONS ***
+pub fn execute(stmt: &PyType, env: &mut Environment) -> Result<Flow, String> {
+  match stmt {
+    // Expression statement, value is discarded
+    PyType::Expr(_) => {
+      evaluate(stmt, env)?;
+      Ok(Flow::Normal)
+    }
 
+    // Assign (the value is evaluated first, then assigned to each target from left to right)
+    PyType::Stmt(Stmt::Assign(targets, e)) => {
+      let value = evaluate(e, env)?;
+      for target in targets {
+        assign(target, value.clone(), env)?;
+      }
+      Ok(Flow::Normal)
+    }
+
+    // AugAssign (the parts of the target are evaluated once, before the value)
+    PyType::Stmt(Stmt::AugAssign(target, op, e)) => aug_assign(target, op, e, env),
+
+    // Del
+    PyType::Stmt(Stmt::Del(targets)) => {
+      for target in targets {
+        match target {
+          PyType::Expr(Expr::Var(v)) => env.remove(v)?,
+          PyType::Expr(Expr::Subscript(container, key)) => {
+            let container = evaluate(container, env)?;
+            del_item(&container, &evaluate(key, env)?)?;
+          }
+          PyType::Expr(Expr::Attribute(object, name)) => set_attribute(&evaluate(object, env)?, name, None)?,
+          _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+        }
+      }
+      Ok(Flow::Normal)
+    }
+
+    // If-Else Statement
+    PyType::Stmt(Stmt::If(condition, body, else_body)) => {
+      if evaluate(condition, env)?.is_truthy() {
+        execute_body(body, env)
+      } else if let Some(else_body_list) = else_body {
+        execute_body(else_body_list, env)
+      } else {
+        Ok(Flow::Normal)
+      }
+    }
+
+    // While Loop
+    PyType::Stmt(Stmt::While(condition, body)) => {
+      while evaluate(condition, env)?.is_truthy() {
+        match execute_body(body, env)? {
+          Flow::Break => break,
+          Flow::Return(value) => return Ok(Flow::Return(value)),
+          Flow::Normal | Flow::Continue => {}
+        }
+      }
+      Ok(Flow::Normal)
+    }
+
+    // For Loop
+    PyType::Stmt(Stmt::For(var, iterable, body)) => {
+      let iterator = evaluate(iterable, env)?.to_iterator()?;
+      while let Some(item) = next_item(&iterator, env)? {
+        env.insert(var, item);
+        match execute_body(body, env)? {
+          Flow::Break => break,
+          Flow::Return(value) => return Ok(Flow::Return(value)),
+          Flow::Normal | Flow::Continue => {}
+        }
+      }
+      Ok(Flow::Normal)
+    }
+
+    // Function Definition
+    PyType::Stmt(Stmt::Function(func_name, parameters, body)) => define_function(func_name, parameters, body, env),
+
+    // Return
+    PyType::Stmt(Stmt::Return(e)) => Ok(Flow::Return(evaluate(e, env)?)),
+
+    // Break
+    PyType::Stmt(Stmt::Break) => Ok(Flow::Break),
+
+    // Continue
+    PyType::Stmt(Stmt::Continue) => Ok(Flow::Continue),
+
+    // Assert, which -O skips
+    PyType::Stmt(Stmt::Assert(condition, message)) => {
+      if !env.optimize && !evaluate(condition, env)?.is_truthy() {
+        let message = match message {
+          Some(message) => Some(evaluate(message, env)?),
+          None => None,
+        };
+        return Err(assertion_error(message));
+      }
+      Ok(Flow::Normal)
+    }
+
+    // Pass, and declarations, which take effect when the function is defined
+    PyType::Stmt(Stmt::Pass) | PyType::Stmt(Stmt::Global(_)) | PyType::Stmt(Stmt::Nonlocal(_)) => Ok(Flow::Normal),
+  }
+}
+
+// The larger statements and expressions are run by functions of their own, since every local of
+// execute and evaluate takes room in each frame of the recursion through a Python call
+fn aug_assign(target: &PyType, op: &Op, e: &PyType, env: &mut Environment) -> Result<Flow, String> {
+  match target {
+    PyType::Expr(Expr::Var(v)) => {
+      let current = evaluate(target, env)?;
+      let value = evaluate(e, env)?;
+      let result = eval_inplace(op, &current, &value, env)?;
+      env.insert(v, result);
+    }
+    PyType::Expr(Expr::Subscript(container, key)) => {
+      let container = evaluate(container, env)?;
+      let key = evaluate(key, env)?;
+      let current = get_item(&container, &key)?;
+      let value = evaluate(e, env)?;
+      let result = eval_inplace(op, &current, &value, env)?;
+      set_item(&container, &key, result, env)?;
+    }
+    PyType::Expr(Expr::Attribute(object, name)) => {
+      let object = evaluate(object, env)?;
+      let current = get_attribute(&object, name)?;
+      let value = evaluate(e, env)?;
+      let result = eval_inplace(op, &current, &value, env)?;
+      set_attribute(&object, name, Some(result))?;
+    }
+    _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+  }
+  Ok(Flow::Normal)
+}
+
+fn define_function(func_name: &str, parameters: &Parameters, body: &[Statement], env: &mut Environment) -> Result<Flow, String> {
+  // Defaults are evaluated once, when the function is defined
+  let mut defaults = Vec::new();
+  for (_, default) in &parameters.positional {
+    if let Some(default) = default {
+      defaults.push(evaluate(default, env)?);
+    }
+  }
+  let mut kwdefaults = Vec::new();
+  for (name, default) in &parameters.kwonly {
+    if let Some(default) = default {
+      kwdefaults.push((name.clone(), evaluate(default, env)?));
+    }
+  }
+
+  let names = env.function_scope(parameters, body);
+  let body = if body_contains_yield(body) {
+    FunctionBody::Steps(Rc::new(generator::lower(body, env.optimize)), env.scopes.clone(), names)
+  } else {
+    FunctionBody::Ast(Rc::new(body.to_vec()), env.scopes.clone(), names)
+  };
+  let function = Function {
+    name: func_name.to_string(),
+    signature: Signature::new(parameters),
+    defaults,
+    kwdefaults,
+    body,
+    attributes: RefCell::default(),
+  };
+  env.insert(func_name, Value::Function(Rc::new(function)));
+  Ok(Flow::Normal)
+}
+
+// Binds a value to an assignment target: a name, an item or slice of a container, or an attribute
+fn assign(target: &PyType, value: Value, env: &mut Environment) -> Result<(), String> {
+  match target {
+    PyType::Expr(Expr::Var(v)) => {
+      env.insert(v, value);
+      Ok(())
+    }
+    PyType::Expr(Expr::Subscript(container, key)) => {
+      let container = evaluate(container, env)?;
+      let key = evaluate(key, env)?;
+      set_item(&container, &key, value, env)
+    }
+    PyType::Expr(Expr::Attribute(object, name)) => set_attribute(&evaluate(object, env)?, name, Some(value)),
+    _ => unreachable!("the parser only allows names, subscripts and attributes as targets"),
+  }
+}
+
+// Runs the statements of a body in order, stopping early on return, break or continue
+fn execute_body(body: &[Statement], env: &mut Environment) -> Result<Flow, String> {
+  for statement in body {
+    match execute(&statement.node, env)? {
+      Flow::Normal => {}
+      flow => return Ok(flow),
+    }
+  }
+  Ok(Flow::Normal)
+}
+
+pub fn evaluate(expr: &PyType, env: &mut Environment) -> Result<Value, String> {
+  match expr {
     // Int
-    PyType::Expr(Expr::Int(n)) => Ok(PyType::Expr(Expr::Int(*n))),
+    PyType::Expr(Expr::Int(n)) => Ok(Value::Int(*n)),
 
     // Float
-    PyType::Expr(Expr::Float(d)) => Ok(PyType::Expr(Expr::Float(*d))),
+    PyType::Expr(Expr::Float(d)) => Ok(Value::Float(*d)),
 
     // String
-    PyType::Expr(Expr::String(s)) => Ok(PyType::Expr(Expr::String(s.clone()))),
+    PyType::Expr(Expr::String(s)) => Ok(Value::new_str(s)),
+
+    // Bytes
+    PyType::Expr(Expr::Bytes(b)) => Ok(Value::Bytes(Rc::from(b.as_slice()))),
+
+    // F-string
+    PyType::Expr(Expr::FString(parts)) => Ok(Value::new_str(&eval_fstring(parts, env)?)),
 
     // Bool
-    PyType::Expr(Expr::Bool(b)) => Ok(PyType::Expr(Expr::Bool(*b))),
+    PyType::Expr(Expr::Bool(b)) => Ok(Value::Bool(*b)),
+
+    // None
+    PyType::Expr(Expr::None) => Ok(Value::None),
 
     // Var
-    PyType::Expr(Expr::Var(v)) => {
-      match env_get(env, v) {
-        Some(e) => evaluate(&e, env),
-        None => Err(format!("NameError: name {} is not defined", v))
+    PyType::Expr(Expr::Var(v)) => env.get(v),
+
+    // List
+    PyType::Expr(Expr::List(items)) => Ok(Value::new_list(evaluate_all(items, env)?)),
+
+    // Tuple
+    PyType::Expr(Expr::Tuple(items)) => Ok(Value::Tuple(Rc::new(evaluate_all(items, env)?))),
+
+    // Dict
+    PyType::Expr(Expr::Dict(pairs)) => eval_dict(pairs, env),
+
+    // Set
+    PyType::Expr(Expr::Set(items)) => {
+      let mut table = Dict::new();
+      for item in evaluate_all(items, env)? {
+        table.insert(item, Value::None)?;
       }
+      Ok(Value::Set(Rc::new(RefCell::new(table))))
     }
 
+    // Comprehension (the first iterable is evaluated here, everything else in a scope of its own so
+    // that the loop variables don't leak out)
+    PyType::Expr(Expr::Comprehension(kind, element, clauses)) => eval_comprehension(kind, element, clauses, env),
+
     // Not
-    PyType::Expr(Expr::Not(e)) => {
-      match evaluate(e, env) {
-        Ok(eval) => {
-          match eval.to_bool() {
-            Ok(b) => Ok(PyType::Expr(Expr::Bool(!b))),
-            Err(e) => Err(e)
-          }
-        },
-        Err(e) => Err(e)
+    PyType::Expr(Expr::Not(e)) => Ok(Value::Bool(!evaluate(e, env)?.is_truthy())),
+
+    // Or (right side only evaluated when the left side is falsy)
+    PyType::Expr(Expr::Binop(Op::Or, left, right)) => {
+      let left_eval = evaluate(left, env)?;
+      if left_eval.is_truthy() {
+        Ok(left_eval)
+      } else {
+        evaluate(right, env)
       }
-    }
-    
+    },
+
+    // And (right side only evaluated when the left side is truthy)
+    PyType::Expr(Expr::Binop(Op::And, left, right)) => {
+      let left_eval = evaluate(left, env)?;
+      if left_eval.is_truthy() {
+        evaluate(right, env)
+      } else {
+        Ok(left_eval)
+      }
+    },
+
     // Binop
     PyType::Expr(Expr::Binop(op, left, right)) => {
-      match (evaluate(left, env), evaluate(right, env)) {
-        (Ok(left_eval), Ok(right_eval)) => {
-          eval_binop(op, &left_eval, &right_eval)
-        },
-        (Err(e), _) => Err(e),
-        (_, Err(e)) => Err(e),
+      let left_eval = evaluate(left, env)?;
+      let right_eval = evaluate(right, env)?;
+      eval_binop(op, &left_eval, &right_eval)
+    },
+
+    // Ternary (only the selected branch is evaluated)
+    PyType::Expr(Expr::Ternary(condition, if_true, if_false)) => {
+      if evaluate(condition, env)?.is_truthy() {
+        evaluate(if_true, env)
+      } else {
+        evaluate(if_false, env)
       }
     },
 
-    // Function Call
-    PyType::Expr(Expr::FunctionCall(func_name, arguments)) => eval_function_call(func_name, arguments, env),
+    // Comparison chain
+    PyType::Expr(Expr::Compare(first, comparisons)) => eval_compare(first, comparisons, env),
 
-    // Return
-    PyType::Expr(Expr::Return(e)) => {
-      match evaluate(e, env) {
-        Ok(eval) => {
-          if let PyType::Expr(_) = eval {
-            return Ok(PyType::Expr(Expr::Return(Box::from(eval))));
-          }
-          return Err("TypeError: Return value is not an expression".to_string());
-        },
-        Err(e) => Err(e)
-      }
-    }
+    // Function Call
+    PyType::Expr(Expr::FunctionCall(func, args, kwargs)) => eval_call(func, args, kwargs, env),
 
-    // *** STATEMENTS ***
+    // *iterable is only allowed among the arguments of a call
+    PyType::Expr(Expr::Starred(_)) => Err("SyntaxError: can't use starred expression here".to_string()),
 
-    // VarAssign
-    PyType::Stmt(Stmt::VarAssign(v, e )) => {
-      match evaluate(e, env) {
-        Ok(PyType::Expr(eval)) => {
-          // replace env with new value is name already exists, otherwise push new entry to end
-          env_insert(env, v, &PyType::Expr(eval.clone()));
-          Ok(PyType::Stmt(Stmt::VarAssign(v.to_string(), Box::from(PyType::Expr(eval)))))
-        },
-        Ok(_) => Err("TypeError: variable not assigned to expressions".to_string()),
-        Err(e) => Err(e)
-      }
-    }
+    // A yield in a function becomes a step of the generator it is lowered to
+    PyType::Expr(Expr::Yield(_) | Expr::YieldFrom(_)) => Err("SyntaxError: 'yield' outside function".to_string()),
 
-    // If-Else Statement
-    PyType::Stmt(Stmt::If(condition, body, else_body)) => eval_if(condition, body, else_body, env),
+    // Attribute
+    PyType::Expr(Expr::Attribute(e, name)) => get_attribute(&evaluate(e, env)?, name),
 
-    // Function Definition
-    PyType::Stmt(Stmt::Function(func_name, _, _)) => {
-      env_insert(env, func_name, expr);
-      Ok(expr.clone())
+    // Subscript
+    PyType::Expr(Expr::Subscript(e, index)) => {
+      let container = evaluate(e, env)?;
+      let key = evaluate(index, env)?;
+      get_item(&container, &key)
     },
 
-    _ => Err("SyntaxError: unexpected expression".to_string())
+    // Slice
+    PyType::Expr(Expr::Slice(start, stop, step)) => {
+      let mut bounds = [Value::None, Value::None, Value::None];
+      for (i, bound) in [start, stop, step].into_iter().enumerate() {
+        if let Some(bound) = bound {
+          bounds[i] = evaluate(bound, env)?;
+        }
+      }
+      Ok(Value::Slice(Rc::new(bounds)))
+    },
 
-    
+    PyType::Stmt(_) => Err("SyntaxError: invalid syntax".to_string()),
   }
 }
 
-fn eval_function_call(func_name: &String, arguments: &Vec<PyType>, env: &mut Environment) -> Result<PyType, String> {
-  // Get function from environment, throw error if not there
-  // Check if same # of params & args
-  // For each parameter, add var & matching argument to environment 
-  // Evaluate function body line by line, return Expr if return Expr found
+// Evaluates the arguments of a call, unpacking *iterables and **mappings, and calls the function
+fn eval_call(func: &PyType, args: &[PyType], kwargs: &[(Option<String>, PyType)], env: &mut Environment) -> Result<Value, String> {
+  let function = evaluate(func, env)?;
+  let mut arg_values = Vec::with_capacity(args.len());
+  for arg in args {
+    match arg {
+      PyType::Expr(Expr::Starred(iterable)) => {
+        let iterable = evaluate(iterable, env)?;
+        arg_values.extend(unpack_arguments(&function, &iterable, env)?);
+      },
+      _ => arg_values.push(evaluate(arg, env)?),
+    }
+  }
 
-  match env_get(env, func_name) {
-    Some(PyType::Stmt(Stmt::Function(_, parameters, body))) => {
-      if arguments.len() != parameters.len() {
-        return Err(format!("TypeError: {} takes {} positional arguments but {} were given", func_name, parameters.len(), arguments.len()))
+  // With a **mapping, every keyword argument is merged in turn so that repeats are reported
+  let kwarg_values = if kwargs.iter().any(|(name, _)| name.is_none()) {
+    let mut merged = Dict::new();
+    for (name, arg) in kwargs {
+      let mut mapping = evaluate(arg, env)?;
+      if let Some(name) = name {
+        let mut entry = Dict::new();
+        entry.insert(Value::new_str(name), mapping)?;
+        mapping = Value::Dict(Rc::new(RefCell::new(entry)));
       }
+      merge_keywords(&function, &mut merged, &mapping)?;
+    }
+    keywords(&merged)
+  } else {
+    let mut kwarg_values = Vec::with_capacity(kwargs.len());
+    for (name, arg) in kwargs {
+      kwarg_values.push((name.clone().unwrap(), evaluate(arg, env)?));
+    }
+    kwarg_values
+  };
+  call_function(&function, arg_values, kwarg_values, env)
+}
 
-      // Add arguments & parameters to environment
-      for i in 0..parameters.len() {
-        let eval_result = evaluate(&arguments[i], env);
+fn eval_comprehension(kind: &Comprehension, element: &PyType, clauses: &[Clause], env: &mut Environment) -> Result<Value, String> {
+  let iterator = evaluate(clauses[0].node(), env)?.to_iterator()?;
+  if *kind == Comprehension::Generator {
+    return Ok(new_genexpr(element, clauses, iterator, env));
+  }
 
-        if let Err(e) = eval_result {
-          return Err(e);
-        }
-        if let Ok(PyType::Expr(eval_arg)) = eval_result {
-          env_insert(env, &parameters[i], &PyType::Expr(eval_arg))
-        } else {
-          return Err("TypeError: argument does not evaluate to an expression".to_string());
+  let mut items = Vec::new();
+  let mut table = Dict::new();
+  let names = env.comprehension_scope(clauses);
+  let names = std::mem::replace(&mut env.names, names);
+  env.scopes.push(Rc::new(RefCell::new(HashMap::new())));
+  let result = run_clauses(clauses, Some(iterator), env, &mut |env| match kind {
+    Comprehension::List => {
+      items.push(evaluate(element, env)?);
+      Ok(())
+    }
+    Comprehension::Set => table.insert(evaluate(element, env)?, Value::None),
+    Comprehension::Dict(value) => {
+      let key = evaluate(element, env)?;
+      let value = evaluate(value, env)?;
+      table.insert(key, value)
+    }
+    Comprehension::Generator => unreachable!("generator expressions are returned above"),
+  });
+  env.scopes.pop();
+  env.names = names;
+  result?;
+
+  match kind {
+    Comprehension::List => Ok(Value::new_list(items)),
+    Comprehension::Set => Ok(Value::Set(Rc::new(RefCell::new(table)))),
+    _ => Ok(Value::Dict(Rc::new(RefCell::new(table)))),
+  }
+}
+
+fn eval_fstring(parts: &[FStringPart], env: &mut Environment) -> Result<String, String> {
+  let mut result = String::new();
+  for part in parts {
+    match part {
+      FStringPart::Literal(s) => result.push_str(s),
+      FStringPart::Field(expr, conversion, spec) => {
+        let mut value = evaluate(expr, env)?;
+        if let Some(c) = conversion {
+          value = Value::new_str(&convert(&value, *c)?);
         }
+        let spec = eval_fstring(spec, env)?;
+        result.push_str(&format_value(&value, &spec)?);
       }
+    }
+  }
+  Ok(result)
+}
 
-      // Evaluate function line-by-line, return Expr if return found
-      // Value returned only if Return Expr found
-      for i in 0..body.len() {
-        let eval_line = evaluate(&body[i], env);
-        if let Err(e) = eval_line {
-          return Err(e);
-        }
-        // Return expression found, return function here
-        if let Ok(PyType::Expr(Expr::Return(expr))) = eval_line {
-          return Ok(*expr);
+fn eval_dict(pairs: &[(PyType, PyType)], env: &mut Environment) -> Result<Value, String> {
+  let mut table = Dict::new();
+  for (key, value) in pairs {
+    let key_eval = evaluate(key, env)?;
+    let value_eval = evaluate(value, env)?;
+    table.insert(key_eval, value_eval)?;
+  }
+  Ok(Value::Dict(Rc::new(RefCell::new(table))))
+}
+
+fn evaluate_all(exprs: &[PyType], env: &mut Environment) -> Result<Vec<Value>, String> {
+  exprs.iter().map(|expr| evaluate(expr, env)).collect()
+}
+
+fn call_function(function: &Value, args: Vec<Value>, kwargs: Vec<(String, Value)>, env: &mut Environment) -> Result<Value, String> {
+  match function {
+    Value::Function(func) => {
+      let args = func.bind_arguments(args, kwargs)?;
+      // Parameters are bound in a fresh local scope, nested inside the scopes the function was defined in
+      let locals: HashMap<String, Value> = func.signature.names.iter().cloned().zip(args).collect();
+      let (body, closure, names) = match &func.body {
+        FunctionBody::Ast(body, closure, names) => (body, closure, names),
+        // Calling a generator function doesn't run the body, which runs as the generator is resumed
+        FunctionBody::Steps(steps, closure, names) => {
+          let mut scopes = closure.clone();
+          scopes.push(Rc::new(RefCell::new(locals)));
+          let frame = GeneratorFrame { steps: steps.clone(), pc: 0, scopes, names: names.clone() };
+          return Ok(Value::new_generator(func.name.clone(), value::GeneratorFrame::Ast(Box::new(frame))));
         }
+        FunctionBody::Code(..) => unreachable!("bytecode functions only exist in the VM"),
+      };
+      if env.depth >= MAX_CALL_DEPTH {
+        return Err("RecursionError: maximum recursion depth exceeded".to_string());
+      }
+
+      let mut scopes = closure.clone();
+      scopes.push(Rc::new(RefCell::new(locals)));
+      let mut call_env = env.for_call(scopes, names.clone());
+
+      match execute_body(body, &mut call_env)? {
+        Flow::Return(value) => Ok(value),
+        // No return statement was reached, so the function returns None
+        Flow::Normal => Ok(Value::None),
+        Flow::Break => Err("SyntaxError: 'break' outside loop".to_string()),
+        Flow::Continue => Err("SyntaxError: 'continue' not properly in loop".to_string()),
       }
-      return Ok(PyType::Stmt(Stmt::None));
     },
-    Some(_) => Err("TypeError: object is not callable".to_string()),
-    None => Err(format!("NameError: name {} is not defined", func_name))
+    _ => builtins::call(function, args, &kwargs, env)
   }
 }
 
-fn eval_if(condition: &Box<PyType>, body: &Vec<PyType>, else_body: &Option<Vec<PyType>>, env: &mut Environment) -> Result<PyType, String> {
-  // Check if condition evaluates to boolean
-  match evaluate(condition, env) { 
-    Ok(PyType::Expr(Expr::Bool(b))) => {
-      if b {
-        // Process all lines, return result of last line
-        for i in 0..(body.len() - 1) {
-          let eval_line = evaluate(&body[i], env);
-
-          if let Err(e) = eval_line { 
-            return Err(e);
-          }
-          // Return found
-          else if let Ok(PyType::Expr(Expr::Return(_))) = eval_line {
-            return eval_line;
-          }
-        }
-        let result = evaluate(&body[body.len() - 1], env);
-        return result;
+// Runs the clauses of a comprehension in turn, calling add for each set of loop variables that gets
+// through all of them. The first clause is given the iterator made in the enclosing scope
+fn run_clauses(
+  clauses: &[Clause], first: Option<Value>, env: &mut Environment, add: &mut dyn FnMut(&mut Environment) -> Result<(), String>,
+) -> Result<(), String> {
+  match clauses.split_first() {
+    None => add(env),
+    Some((Clause::If(condition), rest)) => {
+      if evaluate(condition, env)?.is_truthy() {
+        run_clauses(rest, None, env, add)
       } else {
-        // Interpret else statement if it exists
-        if let Some(else_body_list) = else_body {
-          for i in 0..(else_body_list.len() - 1) {
-            let line_eval = evaluate(&else_body_list[i], env);
-            if let Err(e) = line_eval {
-              return Err(e);
-            }
-            // Return found
-            else if let Ok(PyType::Expr(Expr::Return(_))) = line_eval {
-              return line_eval;
-            }
-          }
-          let result = evaluate(&else_body_list[else_body_list.len() - 1], env);
-          return result;
-        } else {
-          return Ok(PyType::Stmt(Stmt::None));
-        }
+        Ok(())
       }
-    },
-    Ok(_) => Err("SyntaxError: condition of if statement does not evaluate to boolean".to_string()),
-    Err(e) => Err(e)
+    }
+    Some((Clause::For(var, iterable), rest)) => {
+      let iterator = match first {
+        Some(iterator) => iterator,
+        None => evaluate(iterable, env)?.to_iterator()?,
+      };
+      while let Some(item) = next_item(&iterator, env)? {
+        env.insert(var, item);
+        run_clauses(rest, None, env, add)?;
+      }
+      Ok(())
+    }
   }
 }
 
-fn eval_binop(op: &Op, left: &PyType, right: &PyType) -> Result<PyType, String> {
-  match (left, right) {
-    (PyType::Expr(left_expr), PyType::Expr(right_expr)) => {
-      match &op {
-        // Addition
-        Op::Add => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Int(n1 + n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float((*n1 as f32) + n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Float(n1 + (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float(n1 + n2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::String(s1.clone() + s2))), // String concatenation
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} + {}", left_expr, right_expr))
-          }
-        },
-    
-        // Subtraction
-        Op::Sub => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Int(n1 - n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float((*n1 as f32) - n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Float(n1 - (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float(n1 - n2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} - {}", left_expr, right_expr))
-          }
-        },
-    
-        // Multiplication
-        Op::Mult => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Int(n1 * n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float((*n1 as f32) * n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Float(n1 * (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float(n1 * n2))),
-            // String multiplication
-            (Expr::String(s), Expr::Int(n)) => {
-              let mut concat = String::new();
-              for _ in 0..*n {
-                concat.push_str(s);
-              }
-              Ok(PyType::Expr(Expr::String(concat)))
-            },
-    
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} * {}", left_expr, right_expr))
-          }
-        },
-
-        // Division
-        Op::Div => {
-          match (left_expr, right_expr) {
-            (_, Expr::Int(0)) | (_, Expr::Float(0.0)) => Err(format!("ZeroDivisionError: division by zero")),
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Float((*n1 as f32) / (*n2 as f32)))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float((*n1 as f32) / n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Float(n1 / (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Float(n1 / n2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} / {}", left_expr, right_expr))
-          }
-        },
+// A generator expression runs like a generator function whose body is its clauses as nested for and
+// if statements around a yield of the element. Like the VM, it is passed the first iterator as .0
+fn new_genexpr(element: &PyType, clauses: &[Clause], iterator: Value, env: &Environment) -> Value {
+  let mut body = vec![Statement { line: 0, node: PyType::Expr(Expr::Yield(Some(Box::new(element.clone())))) }];
+  for (i, clause) in clauses.iter().enumerate().rev() {
+    let node = match clause {
+      Clause::For(var, _) if i == 0 => Stmt::For(var.clone(), Box::new(PyType::Expr(Expr::Var(".0".to_string()))), body),
+      Clause::For(var, iterable) => Stmt::For(var.clone(), Box::new(iterable.clone()), body),
+      Clause::If(condition) => Stmt::If(Box::new(condition.clone()), body, None),
+    };
+    body = vec![Statement { line: 0, node: PyType::Stmt(node) }];
+  }
 
-        // Or
-        Op::Or => {
-          match left.to_bool() { // left_expr
-            Ok(b) => if !b {Ok(PyType::Expr(right_expr.clone()))} else {Ok(PyType::Expr(left_expr.clone()))},
-            Err(e) => Err(e),
-          }
-        },
-
-        // And
-        Op::And => {
-          match left.to_bool() { // left_expr
-            Ok(b) => {
-              if !b {
-                Ok(PyType::Expr(Expr::Bool(false)))
-              } else {
-                Ok(PyType::Expr(right_expr.clone()))
-              }
-            },
-            Err(e) => Err(e),
-          }
-        },
-
-        // Equals
-        Op::Equal => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 == n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) == *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 == (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 == n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 == b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 == s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} == {}", left_expr, right_expr))
-          }
-        },
-
-        // Not Equals
-        Op::NotEqual => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 != n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) != *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 != (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 != n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 != b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 == s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} != {}", left_expr, right_expr))
-          }
-        },
-    
-        // Less than
-        Op::Less => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 < n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) < *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 < (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 < n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 < b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 < s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} < {}", left_expr, right_expr))
-          }
-        },
-
-        // Greater than
-        Op::Greater => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 > n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) > *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 > (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 > n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 > b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 > s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} > {}", left_expr, right_expr))
-          }
-        },
-
-        // Less than or equal
-        Op::LessEqual => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 <= n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) <= *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 <= (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 <= n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 <= b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 <= s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} <= {}", left_expr, right_expr))
+  let mut scopes = env.scopes.clone();
+  scopes.push(Rc::new(RefCell::new(HashMap::from([(".0".to_string(), iterator)]))));
+  let frame = GeneratorFrame {
+    steps: Rc::new(generator::lower(&body, env.optimize)),
+    pc: 0,
+    scopes,
+    names: env.comprehension_scope(clauses),
+  };
+  Value::new_generator("<genexpr>".to_string(), value::GeneratorFrame::Ast(Box::new(frame)))
+}
+
+// Evaluates each link of a comparison chain, stopping at the first one that is false.
+// Every operand is evaluated at most once, so a < f() < c only calls f a single time
+fn eval_compare(first: &PyType, comparisons: &[(Op, PyType)], env: &mut Environment) -> Result<Value, String> {
+  let mut left = evaluate(first, env)?;
+  let mut result = Value::Bool(true);
+
+  for (op, operand) in comparisons {
+    let right = evaluate(operand, env)?;
+    result = eval_binop(op, &left, &right)?;
+
+    if !result.is_truthy() {
+      return Ok(result);
+    }
+    left = right;
+  }
+  Ok(result)
+}
+
+// Runs the steps of a generator from where it was suspended. The value sent on resuming is the
+// result of the yield it was suspended at
+fn run_steps(frame: &mut GeneratorFrame, mut sent: Option<Value>, env: &mut Environment) -> Result<Resumed, String> {
+  let steps = frame.steps.clone();
+  while let Some(step) = steps.get(frame.pc) {
+    match step {
+      Step::Run(node, targets) => match (execute(node, env)?, targets) {
+        (Flow::Normal, _) => frame.pc += 1,
+        (Flow::Return(value), _) => return Ok(Resumed::Return(value)),
+        (Flow::Break, Some(targets)) => frame.pc = targets.end,
+        (Flow::Continue, Some(targets)) => frame.pc = targets.start,
+        (Flow::Break, None) => return Err("SyntaxError: 'break' outside loop".to_string()),
+        (Flow::Continue, None) => return Err("SyntaxError: 'continue' not properly in loop".to_string()),
+      },
+      Step::Yield(value, result) => match sent.take() {
+        Some(sent) => {
+          env.insert(result, sent);
+          frame.pc += 1;
+        }
+        None => return Ok(Resumed::Yield(evaluate(value, env)?)),
+      },
+      // The iterator is sent None when the delegation starts
+      Step::YieldFrom(iterator, result) => {
+        let iterator = env.get(iterator)?;
+        match send_value(&iterator, sent.take().unwrap_or(Value::None), env)? {
+          Resumed::Yield(value) => return Ok(Resumed::Yield(value)),
+          Resumed::Return(value) => {
+            env.insert(result, value);
+            frame.pc += 1;
           }
-        },
-
-        // Greater than or equal
-        Op::GreaterEqual => {
-          match (left_expr, right_expr) {
-            (Expr::Int(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(n1 >= n2))),
-            (Expr::Int(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool((*n1 as f32) >= *n2))),
-            (Expr::Float(n1), Expr::Int(n2)) => Ok(PyType::Expr(Expr::Bool(*n1 >= (*n2 as f32)))),
-            (Expr::Float(n1), Expr::Float(n2)) => Ok(PyType::Expr(Expr::Bool(n1 >= n2))),
-            (Expr::Bool(b1), Expr::Bool(b2)) => Ok(PyType::Expr(Expr::Bool(b1 >= b2))),
-            (Expr::String(s1), Expr::String(s2)) => Ok(PyType::Expr(Expr::Bool(s1 >= s2))),
-            _ => Err(format!("TypeError: Invalid type(s) evaluating {} >= {}", left_expr, right_expr))
+        }
+      }
+      Step::GetIter(iterable, iterator) => {
+        let value = evaluate(iterable, env)?.to_iterator()?;
+        env.insert(iterator, value);
+        frame.pc += 1;
+      }
+      Step::ForIter(iterator, var, end) => {
+        let iterator = env.get(iterator)?;
+        match next_item(&iterator, env)? {
+          Some(item) => {
+            env.insert(var, item);
+            frame.pc += 1;
           }
+          None => frame.pc = *end,
         }
       }
-    },
-    _ => Err(format!("TypeError: Invalid type(s) evaluating {} {} {}", left, op, right))
+      Step::JumpIfFalse(condition, target) => {
+        if evaluate(condition, env)?.is_truthy() {
+          frame.pc += 1;
+        } else {
+          frame.pc = *target;
+        }
+      }
+      Step::Jump(target) => frame.pc = *target,
+      Step::Return(value) => return Ok(Resumed::Return(evaluate(value, env)?)),
+    }
   }
-  
-}
\ No newline at end of file
+  // Reaching the end of the body returns None
+  Ok(Resumed::Return(Value::None))
+}
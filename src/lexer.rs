@@ -1,273 +1,900 @@
-use crate::types::Token;
-use regex::Regex;
-
-// If Ok, returns token list and indentation size (number of spaces)
-pub fn tokenize(input: &str, prev_indent_spaces: &i32) -> Result<(Vec<Token>, i32), String> {
-  let mut input = input;
-
-  // Regex Patterns
-  let re_whitespace = Regex::new(r"^(\s+)").unwrap();
-  let re_singlespace = Regex::new(r"^\s").unwrap();
-  let re_pos_int = Regex::new(r"^(\d+)").unwrap();
-  let re_neg_int = Regex::new(r"^(-)(\d+)").unwrap();
-  let re_pos_float = Regex::new(r"^(\d*\.\d+)").unwrap();
-  let re_neg_float = Regex::new(r"^(-)(\d*\.\d+)").unwrap();
-  let re_bool = Regex::new(r"^(True|False)$").unwrap();
-  let re_plus = Regex::new(r"^(\+)").unwrap();
-  let re_minus = Regex::new(r"^(-)").unwrap();
-  let re_mult = Regex::new(r"^(\*)").unwrap();
-  let re_div = Regex::new(r"^(/)").unwrap();
-  let re_lparen = Regex::new(r"^(\()").unwrap();
-  let re_rparen = Regex::new(r"^(\))").unwrap();
-  let re_or = Regex::new(r"^(or)$").unwrap();
-  let re_and = Regex::new(r"^(and)$").unwrap();
-  let re_double_equal = Regex::new(r"^(==)").unwrap();
-  let re_not_equal = Regex::new(r"^(!=)").unwrap();
-  let re_less = Regex::new(r"^(<)").unwrap();
-  let re_greater = Regex::new(r"^(>)").unwrap();
-  let re_less_equal = Regex::new(r"^(<=)").unwrap();
-  let re_greater_equal = Regex::new(r"^(>=)").unwrap();
-  let re_string = Regex::new(r#"^("[^"]*"|'[^"']*')"#).unwrap();
-  let re_variable = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)").unwrap();
-  let re_assignment = Regex::new(r"^=").unwrap();
-  let re_if = Regex::new(r"^if$").unwrap();
-  let re_elif = Regex::new(r"^elif$").unwrap();
-  let re_else = Regex::new(r"^else$").unwrap();
-  let re_colon = Regex::new(r"^:").unwrap();
-  let re_not = Regex::new(r"^not$").unwrap();
-  let re_def = Regex::new(r"^def$").unwrap();
-  let re_return = Regex::new(r"^return$").unwrap();
-  let re_comma = Regex::new(r"^,").unwrap();
-
-  let mut tokens = Vec::new();
-
-  // Get indentation of line
-  let mut indentation: i32 = 0;
-  while let Some(_) = re_singlespace.captures(input) {
-    indentation += 1;
-    input = &input[1..];
-  }
-
-  if input.is_empty() {
-    indentation -= 1;
-  }
-
-  if indentation > *prev_indent_spaces {
-    tokens.push(Token::TokIndent(indentation));
-  } else if indentation < *prev_indent_spaces {
-    tokens.push(Token::TokDedent(indentation));
-  }
-
-  while input.len() > 0 {
-    // Whitespace
-    if let Some(capture) = re_whitespace.captures(input) {
-      let capture_str = capture.get(0).unwrap().as_str();
-      input = &input[capture_str.len()..];
-    }
+use crate::types::{FStringToken, Op, Token};
+
+// Tokenizes a line entered in the REPL, which starts on the given line number. Indentation is
+// checked against the blocks open in indenter. If Ok, returns the token list, or None when the line
+// ends inside brackets or with a backslash, so the statement continues on the next line
+pub fn tokenize(input: &str, line: u32, indenter: &mut Indenter) -> Result<Option<Vec<Token>>, String> {
+  let mut scanner = Scanner::new(input, line);
+  match scanner.scan_line()? {
+    // A blank line closes every open block
+    Some((tokens, _)) if tokens.is_empty() => Ok(Some(indenter.close())),
+    Some((tokens, indent)) => Ok(Some(indenter.mark(tokens, indent, line)?)),
+    None => Ok(None),
+  }
+}
+
+// Tokenizes a whole source file in one pass, giving the tokens of each logical line along with the
+// number of its first physical line. Blank lines and lines holding only a comment are left out.
+// The blocks still open at the end of the file are closed by a final line of DEDENTs
+pub fn tokenize_source(source: &str) -> Result<Vec<(u32, Vec<Token>)>, String> {
+  let mut scanner = Scanner::new(source, 1);
+  let mut indenter = Indenter::new();
+  let mut lines = Vec::new();
+
+  while !scanner.at_end() {
+    let line = scanner.line;
+    match scanner.scan_line()? {
+      Some((tokens, _)) if tokens.is_empty() => {}
+      Some((tokens, indent)) => lines.push((line, indenter.mark(tokens, indent, line)?)),
+      None => return Err(scanner.eof_error()),
+    }
+  }
+
+  let dedents = indenter.close();
+  if !dedents.is_empty() {
+    lines.push((scanner.line, dedents));
+  }
+  Ok(lines)
+}
+
+// Width of a line's leading whitespace, where a tab moves to the next multiple of 8 columns as in
+// CPython. alt_width counts a tab as one column. When the two widths disagree on whether a line is
+// indented more, less or the same as a block, its meaning depends on the tab size, which is a TabError
+#[derive(Clone, Copy)]
+pub struct Indent {
+  width: usize,
+  alt_width: usize,
+}
+
+// The indentation of the open blocks, innermost last
+pub struct Indenter {
+  stack: Vec<Indent>,
+}
+
+impl Indenter {
+  pub fn new() -> Indenter {
+    Indenter { stack: vec![Indent { width: 0, alt_width: 0 }] }
+  }
 
-    // Non-negative Float
-    else if let Some(capture) = re_pos_float.captures(input) {
-      let capture_str = capture.get(0).unwrap().as_str();
-      tokens.push(Token::TokFloat(capture_str.parse::<f32>().unwrap()));
-      input = &input[capture_str.len()..];
-    }
-
-    // Negative Float
-    else if let Some(capture) = re_neg_float.captures(input) {
-      let capture_str = capture.get(2).unwrap().as_str();
-      tokens.push(Token::TokUnaryMinus);
-      tokens.push(Token::TokFloat(capture_str.parse::<f32>().unwrap()));
-      input = &input[(capture_str.len() + 1)..]; // + 1 to account for minus sign
-    }
-
-    // Non-negative Int
-    else if let Some(capture) = re_pos_int.captures(input) {
-      let capture_str = capture.get(0).unwrap().as_str();
-      tokens.push(Token::TokInt(capture_str.parse::<i32>().unwrap()));
-      input = &input[capture_str.len()..];
-    }
+  // Starts a line's tokens with an INDENT when it is indented further than the current block, or
+  // with a DEDENT for each block it closes
+  pub fn mark(&mut self, tokens: Vec<Token>, indent: Indent, line: u32) -> Result<Vec<Token>, String> {
+    let inconsistent = || format!("TabError: inconsistent use of tabs and spaces in indentation (line {})", line);
+    let current = *self.stack.last().unwrap();
+    let mut marked = Vec::with_capacity(tokens.len() + 1);
 
-    // Negative Int
-    else if let Some(capture) = re_neg_int.captures(input) {
-      let capture_str = capture.get(2).unwrap().as_str();
-      tokens.push(Token::TokUnaryMinus);
-      tokens.push(Token::TokInt(capture_str.parse::<i32>().unwrap()));
-      input = &input[(capture_str.len() + 1)..]; // + 1 to account for minus sign
+    if indent.width == current.width {
+      if indent.alt_width != current.alt_width {
+        return Err(inconsistent());
+      }
+    } else if indent.width > current.width {
+      if indent.alt_width <= current.alt_width {
+        return Err(inconsistent());
+      }
+      self.stack.push(indent);
+      marked.push(Token::TokIndent);
+    } else {
+      while indent.width < self.stack.last().unwrap().width {
+        self.stack.pop();
+        marked.push(Token::TokDedent);
+      }
+      let current = self.stack.last().unwrap();
+      if indent.width != current.width {
+        return Err(format!("IndentationError: unindent does not match any outer indentation level (line {})", line));
+      }
+      if indent.alt_width != current.alt_width {
+        return Err(inconsistent());
+      }
     }
 
-    // String
-    else if let Some(capture) = re_string.captures(input) {
-      let capture_str = capture.get(0).unwrap().as_str();
-      let mut chars = capture_str.chars(); // To remove quotes surrounding string
-      chars.next();
-      chars.next_back();
-      tokens.push(Token::TokString(String::from(chars.as_str())));
-      input = &input[(capture_str.len())..];
+    marked.extend(tokens);
+    Ok(marked)
+  }
+
+  // DEDENTs closing every open block, as at the end of input
+  pub fn close(&mut self) -> Vec<Token> {
+    let dedents = vec![Token::TokDedent; self.stack.len() - 1];
+    self.stack.truncate(1);
+    dedents
+  }
+}
+
+// Walks the source one character at a time, keeping the line and column of the next character
+struct Scanner<'a> {
+  source: &'a str,
+  pos: usize, // byte offset of the next character
+  line: u32,
+  col: u32,
+  brackets: Vec<(char, u32, u32)>, // open brackets with their line and column
+  open_string: Option<(bool, u32, u32)>, // string literal left open by the end of input: whether it is triple-quoted, its line and column
+}
+
+impl<'a> Scanner<'a> {
+  fn new(source: &'a str, line: u32) -> Scanner<'a> {
+    Scanner { source, pos: 0, line, col: 1, brackets: Vec::new(), open_string: None }
+  }
+
+  fn at_end(&self) -> bool {
+    self.pos >= self.source.len()
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.source[self.pos..].chars().next()
+  }
+
+  fn peek_at(&self, n: usize) -> Option<char> {
+    self.source[self.pos..].chars().nth(n)
+  }
+
+  fn advance(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    if c == '\n' {
+      self.line += 1;
+      self.col = 1;
+    } else {
+      self.col += 1;
     }
+    Some(c)
+  }
 
-    // Plus
-    else if let Some(_) = re_plus.captures(input) {
-      tokens.push(Token::TokPlus);
-      input = &input[1..];
+  // Consumes the next character if it is `expected`
+  fn advance_if(&mut self, expected: char) -> bool {
+    let matched = self.peek() == Some(expected);
+    if matched {
+      self.advance();
     }
+    matched
+  }
+
+  fn error(&self, message: &str, line: u32, col: u32) -> String {
+    format!("SyntaxError: {} (line {}, column {})", message, line, col)
+  }
 
-    // Minus
-    else if let Some(_) = re_minus.captures(input) {
-      tokens.push(Token::TokMinus);
-      input = &input[1..];
+  // Error for input that ended in the middle of a logical line
+  fn eof_error(&self) -> String {
+    if let Some((triple, line, col)) = self.open_string {
+      let kind = if triple { "triple-quoted string literal" } else { "string literal" };
+      let last_line = if self.source.ends_with('\n') { self.line - 1 } else { self.line };
+      return self.error(&format!("unterminated {} (detected at line {})", kind, last_line), line, col);
+    }
+    match self.brackets.last() {
+      Some((bracket, line, col)) => self.error(&format!("'{}' was never closed", bracket), *line, *col),
+      None => self.error("unexpected EOF while parsing", self.line, self.col),
     }
+  }
 
-    // Mult
-    else if let Some(_) = re_mult.captures(input) {
-      tokens.push(Token::TokMult);
-      input = &input[1..];
+  // Scans the rest of the current logical line, including its newline. Returns None if the input
+  // ends before the line does
+  fn scan_line(&mut self) -> Result<Option<(Vec<Token>, Indent)>, String> {
+    let mut tokens = Vec::new();
+
+    // Get indentation of line
+    let mut indent = Indent { width: 0, alt_width: 0 };
+    loop {
+      match self.peek() {
+        Some(' ') => indent.width += 1,
+        Some('\t') => indent.width = (indent.width / 8 + 1) * 8,
+        _ => break,
+      }
+      indent.alt_width += 1;
+      self.advance();
     }
 
-    // Div
-    else if let Some(_) = re_div.captures(input) {
-      tokens.push(Token::TokDiv);
-      input = &input[1..];
+    loop {
+      let (line, col) = (self.line, self.col);
+      let c = match self.peek() {
+        Some(c) => c,
+        None if self.brackets.is_empty() => break,
+        None => return Ok(None),
+      };
+
+      match c {
+        // End of line, unless inside brackets
+        '\n' => {
+          self.advance();
+          if self.brackets.is_empty() {
+            break;
+          }
+        }
+
+        // Comment
+        '#' => {
+          while !matches!(self.peek(), None | Some('\n')) {
+            self.advance();
+          }
+        }
+
+        // Backslash joins the next line onto this one
+        '\\' => {
+          self.advance();
+          self.advance_if('\r');
+          if !self.at_end() && !self.advance_if('\n') {
+            return Err(self.error("unexpected character after line continuation character", line, col));
+          }
+          if self.at_end() {
+            return Ok(None);
+          }
+        }
+
+        // Whitespace
+        c if c.is_whitespace() => {
+          self.advance();
+        }
+
+        // Number
+        '0'..='9' => tokens.push(self.scan_number()?),
+        '.' if matches!(self.peek_at(1), Some('0'..='9')) => tokens.push(self.scan_number()?),
+
+        // String
+        '"' | '\'' => match self.scan_string("", line, col)? {
+          Some(token) => tokens.push(token),
+          None => return Ok(None),
+        },
+
+        // Protected keywords, variable names and prefixed strings
+        c if c.is_alphabetic() || c == '_' => {
+          let name = self.scan_name();
+          if matches!(self.peek(), Some('"' | '\'')) && is_string_prefix(name) {
+            match self.scan_string(name, line, col)? {
+              Some(token) => tokens.push(token),
+              None => return Ok(None),
+            }
+          } else {
+            tokens.push(keyword(name).unwrap_or_else(|| Token::TokVar(name.to_string())));
+          }
+        }
+
+        // Brackets, which must be closed by the matching kind
+        '(' | '[' | '{' => {
+          self.advance();
+          self.brackets.push((c, line, col));
+          tokens.push(match c {
+            '(' => Token::TokLParen,
+            '[' => Token::TokLBracket,
+            _ => Token::TokLBrace,
+          });
+        }
+        ')' | ']' | '}' => {
+          self.advance();
+          let opening = match c {
+            ')' => '(',
+            ']' => '[',
+            _ => '{',
+          };
+          match self.brackets.pop() {
+            Some((open, ..)) if open == opening => {}
+            Some((open, open_line, _)) if open_line != line => {
+              let message = format!("closing parenthesis '{}' does not match opening parenthesis '{}' on line {}", c, open, open_line);
+              return Err(self.error(&message, line, col));
+            }
+            Some((open, ..)) => {
+              return Err(self.error(&format!("closing parenthesis '{}' does not match opening parenthesis '{}'", c, open), line, col));
+            }
+            None => return Err(self.error(&format!("unmatched '{}'", c), line, col)),
+          }
+          tokens.push(match c {
+            ')' => Token::TokRParen,
+            ']' => Token::TokRBracket,
+            _ => Token::TokRBrace,
+          });
+        }
+
+        // Operators and delimiters
+        _ => {
+          self.advance();
+          let token = match c {
+            '+' if self.advance_if('=') => Token::TokAugAssign(Op::Add),
+            '+' => Token::TokPlus,
+            '-' if self.advance_if('=') => Token::TokAugAssign(Op::Sub),
+            '-' => Token::TokMinus,
+            '*' if self.advance_if('*') => if self.advance_if('=') { Token::TokAugAssign(Op::Pow) } else { Token::TokDoubleStar },
+            '*' if self.advance_if('=') => Token::TokAugAssign(Op::Mult),
+            '*' => Token::TokMult,
+            '/' if self.advance_if('/') => if self.advance_if('=') { Token::TokAugAssign(Op::FloorDiv) } else { Token::TokDoubleSlash },
+            '/' if self.advance_if('=') => Token::TokAugAssign(Op::Div),
+            '/' => Token::TokDiv,
+            '%' if self.advance_if('=') => Token::TokAugAssign(Op::Mod),
+            '%' => Token::TokPercent,
+            '&' if self.advance_if('=') => Token::TokAugAssign(Op::BitAnd),
+            '&' => Token::TokAmpersand,
+            '|' if self.advance_if('=') => Token::TokAugAssign(Op::BitOr),
+            '|' => Token::TokPipe,
+            '^' if self.advance_if('=') => Token::TokAugAssign(Op::BitXor),
+            '^' => Token::TokCaret,
+            ':' => Token::TokColon,
+            ',' => Token::TokComma,
+            '.' => Token::TokDot,
+            '=' if self.advance_if('=') => Token::TokDoubleEqual,
+            '=' => Token::TokAssign,
+            '!' if self.advance_if('=') => Token::TokNotEqual,
+            '<' if self.advance_if('<') => if self.advance_if('=') { Token::TokAugAssign(Op::LShift) } else { Token::TokLeftShift },
+            '<' if self.advance_if('=') => Token::TokLessEqual,
+            '<' => Token::TokLess,
+            '>' if self.advance_if('>') => if self.advance_if('=') { Token::TokAugAssign(Op::RShift) } else { Token::TokRightShift },
+            '>' if self.advance_if('=') => Token::TokGreaterEqual,
+            '>' => Token::TokGreater,
+            _ => return Err(self.error(&format!("invalid character '{}' (U+{:04X})", c, c as u32), line, col)),
+          };
+          tokens.push(token);
+        }
+      }
     }
 
-    // Left Parenthesis
-    else if let Some(_) = re_lparen.captures(input) {
-      tokens.push(Token::TokLParen);
-      input = &input[1..];
+    Ok(Some((tokens, indent)))
+  }
+
+  // Ints (decimal, or 0x, 0o and 0b prefixed) and floats with a fraction and/or exponent.
+  // Digits may be separated by underscores
+  fn scan_number(&mut self) -> Result<Token, String> {
+    let (line, col) = (self.line, self.col);
+    let start = self.pos;
+
+    let radix = match (self.peek(), self.peek_at(1)) {
+      (Some('0'), Some('x' | 'X')) => 16,
+      (Some('0'), Some('o' | 'O')) => 8,
+      (Some('0'), Some('b' | 'B')) => 2,
+      _ => 10,
+    };
+
+    if radix != 10 {
+      self.advance();
+      self.advance();
+      let digits_start = self.pos;
+      while matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '_') {
+        self.advance();
+      }
+      let digits = self.source[digits_start..self.pos].replace('_', "");
+      if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(self.error("invalid number literal", line, col));
+      }
+      return i64::from_str_radix(&digits, radix)
+        .map(Token::TokInt)
+        .map_err(|_| format!("OverflowError: integer literal {} is too large", &self.source[start..self.pos]));
     }
 
-    // Right Parenthesis
-    else if let Some(_) = re_rparen.captures(input) {
-      tokens.push(Token::TokRParen);
-      input = &input[1..];
+    let mut is_float = false;
+    self.skip_digits();
+    if self.peek() == Some('.') {
+      is_float = true;
+      self.advance();
+      self.skip_digits();
     }
-    
-    // ==
-    else if let Some(_) = re_double_equal.captures(input) {
-      tokens.push(Token::TokDoubleEqual);
-      input = &input[2..];
+
+    // An exponent needs at least one digit after e and its optional sign
+    if let Some('e' | 'E') = self.peek() {
+      let digit_at = if let Some('+' | '-') = self.peek_at(1) { 2 } else { 1 };
+      if let Some('0'..='9') = self.peek_at(digit_at) {
+        is_float = true;
+        for _ in 0..digit_at {
+          self.advance();
+        }
+        self.skip_digits();
+      }
     }
 
-    // !=
-    else if let Some(_) = re_not_equal.captures(input) {
-      tokens.push(Token::TokNotEqual);
-      input = &input[2..];
+    if matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '_') {
+      return Err(self.error("invalid decimal literal", line, col));
     }
 
-    // <=
-    else if let Some(_) = re_less_equal.captures(input) {
-      tokens.push(Token::TokLessEqual);
-      input = &input[2..];
+    let text = self.source[start..self.pos].replace('_', "");
+    if is_float {
+      text.parse::<f64>().map(Token::TokFloat).map_err(|_| self.error("invalid decimal literal", line, col))
+    } else {
+      text.parse::<i64>().map(Token::TokInt).map_err(|_| format!("OverflowError: integer literal {} is too large", text))
     }
+  }
 
-    // >=
-    else if let Some(_) = re_greater_equal.captures(input) {
-      tokens.push(Token::TokGreaterEqual);
-      input = &input[2..];
+  fn skip_digits(&mut self) {
+    while let Some('0'..='9' | '_') = self.peek() {
+      self.advance();
     }
+  }
 
-    // <
-    else if let Some(_) = re_less.captures(input) {
-      tokens.push(Token::TokLess);
-      input = &input[1..];
+  // String or bytes literal, after any prefix letters (r, b, u, f, in either case). Triple-quoted
+  // literals can span lines. Returns None if the input ends inside a literal that may continue
+  fn scan_string(&mut self, prefix: &str, line: u32, col: u32) -> Result<Option<Token>, String> {
+    let quote = self.advance().unwrap();
+    let triple = self.peek() == Some(quote) && self.peek_at(1) == Some(quote);
+    if triple {
+      self.advance();
+      self.advance();
     }
-    // >
-    else if let Some(_) = re_greater.captures(input) {
-      tokens.push(Token::TokGreater);
-      input = &input[1..];
+    let start = self.pos;
+
+    loop {
+      match self.peek() {
+        Some(c) if c == quote && (!triple || (self.peek_at(1) == Some(quote) && self.peek_at(2) == Some(quote))) => break,
+        Some('\\') => {
+          self.advance();
+          // An escaped newline continues the literal on the next line
+          if self.advance() == Some('\n') && self.at_end() {
+            self.open_string = Some((triple, line, col));
+            return Ok(None);
+          }
+        }
+        Some('\n') if !triple => {
+          return Err(self.error(&format!("unterminated string literal (detected at line {})", line), line, col));
+        }
+        Some(_) => {
+          self.advance();
+        }
+        None if triple || self.source[..self.pos].ends_with('\n') => {
+          self.open_string = Some((triple, line, col));
+          return Ok(None);
+        }
+        None => {
+          return Err(self.error(&format!("unterminated string literal (detected at line {})", line), line, col));
+        }
+      }
     }
 
-    // =
-    else if let Some(_) = re_assignment.captures(input) {
-      tokens.push(Token::TokAssign);
-      input = &input[1..];
+    let body = &self.source[start..self.pos];
+    for _ in 0..if triple { 3 } else { 1 } {
+      self.advance();
     }
 
-    // Colon
-    else if let Some(_) = re_colon.captures(input) {
-      tokens.push(Token::TokColon);
-      input = &input[1..];
-    }
+    let raw = prefix.contains(['r', 'R']);
+    let token = if prefix.contains(['f', 'F']) {
+      let mut pos = 0;
+      Token::TokFString(self.split_fstring(body, &mut pos, raw, 0, line, col)?)
+    } else if prefix.contains(['b', 'B']) {
+      if !body.is_ascii() {
+        return Err(self.error("bytes can only contain ASCII literal characters", line, col));
+      }
+      if raw {
+        Token::TokBytes(body.as_bytes().to_vec())
+      } else {
+        let values = decode_escapes(body, true).map_err(|e| self.error(&e, line, col))?;
+        Token::TokBytes(values.into_iter().map(|b| b as u8).collect())
+      }
+    } else if raw {
+      Token::TokString(body.to_string())
+    } else {
+      let code_points = decode_escapes(body, false).map_err(|e| self.error(&e, line, col))?;
+      Token::TokString(code_points.into_iter().map(|c| char::from_u32(c).unwrap()).collect())
+    };
+    Ok(Some(token))
+  }
 
-    // Comma
-    else if let Some(_) = re_comma.captures(input) {
-      tokens.push(Token::TokComma);
-      input = &input[1..];
+  // Splits the body of an f-string into literal text and replacement fields, starting at *pos. A
+  // format spec is split the same way, up to the '}' that closes its field, which is left at *pos.
+  // spec_depth counts the specs the text is in, as a spec nested in a spec can't have fields
+  fn split_fstring(&self, body: &str, pos: &mut usize, raw: bool, spec_depth: u32, line: u32, col: u32) -> Result<Vec<FStringToken>, String> {
+    let in_spec = spec_depth > 0;
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+
+    while let Some(c) = body[*pos..].chars().next() {
+      match c {
+        // Doubled braces stand for themselves outside a spec
+        '{' | '}' if !in_spec && body[*pos + 1..].starts_with(c) => {
+          literal.push(c);
+          *pos += 2;
+        }
+        '}' if in_spec => break,
+        '}' => return Err(self.error("f-string: single '}' is not allowed", line, col)),
+        '{' if spec_depth >= 2 => return Err(self.error("f-string: expressions nested too deeply", line, col)),
+        '{' => {
+          if !literal.is_empty() {
+            parts.push(FStringToken::Literal(self.decode_fstring_literal(&literal, raw, line, col)?));
+            literal.clear();
+          }
+          *pos += 1;
+          let (debug_text, field) = self.scan_field(body, pos, raw, spec_depth, line, col)?;
+          // f"{x = }" shows the expression text and the '=' with its spacing before the value
+          if let Some(text) = debug_text {
+            match parts.last_mut() {
+              Some(FStringToken::Literal(literal)) => literal.push_str(text),
+              _ => parts.push(FStringToken::Literal(text.to_string())),
+            }
+          }
+          parts.push(field);
+        }
+        // Escapes are decoded once the literal text is complete. A \N{...} escape keeps its braces
+        '\\' if !raw => {
+          let escape_len = match body[*pos + 1..].chars().next() {
+            Some('N') if body[*pos + 2..].starts_with('{') => match body[*pos..].find('}') {
+              Some(end) => end + 1,
+              None => 2,
+            },
+            Some(next) => 1 + next.len_utf8(),
+            None => 1,
+          };
+          literal.push_str(&body[*pos..*pos + escape_len]);
+          *pos += escape_len;
+        }
+        _ => {
+          literal.push(c);
+          *pos += c.len_utf8();
+        }
+      }
     }
 
-    // Protected keywords and variable names
-    else if let Some(capture) = re_variable.captures(input) {
-      let capture_str = capture.get(0).unwrap().as_str();
+    if !literal.is_empty() {
+      parts.push(FStringToken::Literal(self.decode_fstring_literal(&literal, raw, line, col)?));
+    }
+    Ok(parts)
+  }
 
-      // Bool
-      if let Some(_) = re_bool.captures(capture_str) {
-        if capture_str == "True" {
-          tokens.push(Token::TokBool(true));
-        } else {
-          tokens.push(Token::TokBool(false));
+  // Replacement field of an f-string, after its '{': an expression, then an optional '=', !conversion
+  // and :spec, up to and including the closing '}'. Along with the field comes the text a debug
+  // field shows before its value
+  fn scan_field<'b>(&self, body: &'b str, pos: &mut usize, raw: bool, spec_depth: u32, line: u32, col: u32) -> Result<(Option<&'b str>, FStringToken), String> {
+    let expecting = || self.error("f-string: expecting '}'", line, col);
+    let start = *pos;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut debug = false;
+
+    // The expression ends at the first '}', '!', ':' or '=' outside brackets and strings that is
+    // not part of an operator
+    loop {
+      let c = match body[*pos..].chars().next() {
+        Some(c) => c,
+        None => return Err(expecting()),
+      };
+      let next = body[*pos + c.len_utf8()..].chars().next();
+      match (quote, c) {
+        (_, '\\') => return Err(self.error("f-string expression part cannot include a backslash", line, col)),
+        (Some(q), c) if c == q => quote = None,
+        (Some(_), _) => {}
+        (None, '\'' | '"') => quote = Some(c),
+        (None, '#') => return Err(self.error("f-string expression part cannot include '#'", line, col)),
+        (None, '(' | '[' | '{') => depth += 1,
+        (None, ')' | ']' | '}') if depth > 0 => depth -= 1,
+        (None, _) if depth > 0 => {}
+        (None, '}' | ':') => break,
+        (None, '!' | '=' | '<' | '>') if next == Some('=') => *pos += 1,
+        (None, '!') => break,
+        (None, '=') => {
+          debug = true;
+          break;
         }
-        input = &input[capture_str.len()..];
+        _ => {}
       }
+      *pos += c.len_utf8();
+    }
 
-      // And
-      else if let Some(_) = re_and.captures(capture_str) {
-        tokens.push(Token::TokAnd);
-        input = &input[3..];
-      }
+    let expr_text = &body[start..*pos];
+    if expr_text.trim().is_empty() {
+      return Err(self.error("f-string: empty expression not allowed", line, col));
+    }
 
-      // Or
-      else if let Some(_) = re_or.captures(capture_str) {
-        tokens.push(Token::TokOr);
-        input = &input[2..];
+    let mut debug_text = None;
+    if debug {
+      *pos += 1;
+      while matches!(body[*pos..].chars().next(), Some(c) if c.is_whitespace()) {
+        *pos += 1;
       }
+      debug_text = Some(&body[start..*pos]);
+    }
 
-      // Not
-      else if let Some(_) = re_not.captures(capture_str) {
-        tokens.push(Token::TokNot);
-        input = &input[3..];
+    // The expression is scanned as if it were in parentheses, so it may span lines
+    let wrapped = format!("({})", expr_text);
+    let mut scanner = Scanner::new(&wrapped, line);
+    let tokens = match scanner.scan_line()? {
+      Some((tokens, _)) if scanner.at_end() => tokens,
+      _ => return Err(expecting()),
+    };
+
+    let mut conversion = None;
+    if body[*pos..].starts_with('!') {
+      *pos += 1;
+      match body[*pos..].chars().next() {
+        Some(c @ ('s' | 'r' | 'a')) => {
+          conversion = Some(c);
+          *pos += 1;
+        }
+        _ => return Err(self.error("f-string: invalid conversion character: expected 's', 'r', or 'a'", line, col)),
       }
+    }
 
-      // If
-      else if let Some(_) = re_if.captures(capture_str) {
-        tokens.push(Token::TokIf);
-        input = &input[2..];
-      }
+    let mut spec = Vec::new();
+    if body[*pos..].starts_with(':') {
+      *pos += 1;
+      spec = self.split_fstring(body, pos, raw, spec_depth + 1, line, col)?;
+    }
 
-      // Elif
-      else if let Some(_) = re_elif.captures(capture_str) {
-        tokens.push(Token::TokElif);
-        input = &input[4..];
-      }
+    if !body[*pos..].starts_with('}') {
+      return Err(expecting());
+    }
+    *pos += 1;
+
+    // Debug fields show the repr unless a conversion or spec says otherwise
+    if debug && conversion.is_none() && spec.is_empty() {
+      conversion = Some('r');
+    }
+    Ok((debug_text, FStringToken::Field(tokens, conversion, spec)))
+  }
+
+  fn decode_fstring_literal(&self, literal: &str, raw: bool, line: u32, col: u32) -> Result<String, String> {
+    if raw {
+      return Ok(literal.to_string());
+    }
+    let code_points = decode_escapes(literal, false).map_err(|e| self.error(&e, line, col))?;
+    Ok(code_points.into_iter().map(|c| char::from_u32(c).unwrap()).collect())
+  }
+
+  fn scan_name(&mut self) -> &'a str {
+    let start = self.pos;
+    while matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '_') {
+      self.advance();
+    }
+    &self.source[start..self.pos]
+  }
+}
+
+fn is_string_prefix(name: &str) -> bool {
+  matches!(name.to_ascii_lowercase().as_str(), "r" | "u" | "b" | "rb" | "br" | "f" | "rf" | "fr")
+}
+
+// Decodes the backslash escapes of a string (as code points) or bytes literal (as byte values).
+// Unrecognized escapes are kept as written, as are \u, \U and \N in bytes
+fn decode_escapes(body: &str, bytes: bool) -> Result<Vec<u32>, String> {
+  // CPython decodes the literal with each non-ASCII character written as a \UXXXXXXXX escape, so
+  // positions count those characters as ten bytes
+  let position = |byte: usize| body[..byte].chars().map(|c| if c.is_ascii() { 1 } else { 10 }).sum::<usize>();
+  let codec_error = |start: usize, end: usize, reason: &str| {
+    if bytes {
+      format!("(value error) {} at position {}", reason, start)
+    } else {
+      format!("(unicode error) 'unicodeescape' codec can't decode bytes in position {}-{}: {}", position(start), position(end) - 1, reason)
+    }
+  };
+
+  let mut result = Vec::with_capacity(body.len());
+  let mut chars = body.char_indices().peekable();
+
+  while let Some((i, c)) = chars.next() {
+    if c != '\\' {
+      result.push(c as u32);
+      continue;
+    }
 
-      // Else
-      else if let Some(_) = re_else.captures(capture_str) {
-        tokens.push(Token::TokElse);
-        input = &input[4..];
+    let (_, escape) = chars.next().unwrap();
+    match escape {
+      '\n' => {}
+      '\\' | '\'' | '"' => result.push(escape as u32),
+      'a' => result.push(0x07),
+      'b' => result.push(0x08),
+      'f' => result.push(0x0c),
+      'n' => result.push(0x0a),
+      'r' => result.push(0x0d),
+      't' => result.push(0x09),
+      'v' => result.push(0x0b),
+
+      // Up to three octal digits
+      '0'..='7' => {
+        let mut value = escape.to_digit(8).unwrap();
+        for _ in 0..2 {
+          match chars.peek() {
+            Some((_, d)) if d.is_digit(8) => {
+              value = value * 8 + d.to_digit(8).unwrap();
+              chars.next();
+            }
+            _ => break,
+          }
+        }
+        result.push(if bytes { value & 0xff } else { value });
       }
 
-      // Def
-      else if let Some(_) = re_def.captures(capture_str) {
-        tokens.push(Token::TokDef);
-        input = &input[3..];
+      // A fixed number of hex digits
+      'x' | 'u' | 'U' if escape == 'x' || !bytes => {
+        let (digits, name) = match escape {
+          'x' => (2, "\\xXX"),
+          'u' => (4, "\\uXXXX"),
+          _ => (8, "\\UXXXXXXXX"),
+        };
+        let mut value = 0;
+        for n in 0..digits {
+          match chars.peek() {
+            Some((_, d)) if d.is_ascii_hexdigit() => {
+              value = value * 16 + d.to_digit(16).unwrap();
+              chars.next();
+            }
+            _ if bytes => return Err(codec_error(i, i + 2 + n, "invalid \\x escape")),
+            _ => return Err(codec_error(i, i + 2 + n, &format!("truncated {} escape", name))),
+          }
+        }
+        if char::from_u32(value).is_none() {
+          return Err(codec_error(i, i + 2 + digits, "illegal Unicode character"));
+        }
+        result.push(value);
       }
 
-      // Return
-      else if let Some(_) = re_return.captures(capture_str) {
-        tokens.push(Token::TokReturn);
-        input = &input[6..];
+      // A character by its Unicode name
+      'N' if !bytes => {
+        let rest = &body[i + 2..];
+        let name = rest.strip_prefix('{').and_then(|rest| rest.split_once('}')).map(|(name, _)| name);
+        match name.and_then(unicode_names2::character) {
+          Some(named) => {
+            let end = i + 2 + name.unwrap().len() + 2;
+            while matches!(chars.peek(), Some((j, _)) if *j < end) {
+              chars.next();
+            }
+            result.push(named as u32);
+          }
+          // Without a { the escape is just \N, and an unclosed { runs to the end of the literal
+          None if !rest.starts_with('{') => return Err(codec_error(i, i + 2, "malformed \\N character escape")),
+          None if name.is_none() => return Err(codec_error(i, body.len(), "malformed \\N character escape")),
+          None if name == Some("") => return Err(codec_error(i, i + 3, "malformed \\N character escape")),
+          None => return Err(codec_error(i, i + 2 + name.unwrap().len() + 2, "unknown Unicode character name")),
+        }
       }
 
-      // Variable name
-      else {
-        tokens.push(Token::TokVar(String::from(capture_str)));
-        input = &input[capture_str.len()..];
+      _ => {
+        result.push('\\' as u32);
+        result.push(escape as u32);
       }
     }
+  }
+  Ok(result)
+}
+
+// Keyword lookup table
+fn keyword(name: &str) -> Option<Token> {
+  let token = match name {
+    "True" => Token::TokBool(true),
+    "False" => Token::TokBool(false),
+    "None" => Token::TokNone,
+    "and" => Token::TokAnd,
+    "or" => Token::TokOr,
+    "not" => Token::TokNot,
+    "in" => Token::TokIn,
+    "is" => Token::TokIs,
+    "if" => Token::TokIf,
+    "elif" => Token::TokElif,
+    "else" => Token::TokElse,
+    "def" => Token::TokDef,
+    "return" => Token::TokReturn,
+    "yield" => Token::TokYield,
+    "from" => Token::TokFrom,
+    "while" => Token::TokWhile,
+    "for" => Token::TokFor,
+    "break" => Token::TokBreak,
+    "continue" => Token::TokContinue,
+    "del" => Token::TokDel,
+    "pass" => Token::TokPass,
+    "assert" => Token::TokAssert,
+    "global" => Token::TokGlobal,
+    "nonlocal" => Token::TokNonlocal,
+    "import" => Token::TokImport,
+    _ => return None,
+  };
+  Some(token)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fmt::Write;
+  use std::time::{Duration, Instant};
+
+  // Each logical line as its number and the names of its tokens
+  fn token_lines(source: &str) -> Vec<(u32, String)> {
+    let lines = tokenize_source(source).unwrap();
+    lines.into_iter().map(|(line, tokens)| (line, tokens.iter().map(|token| token.to_string()).collect::<Vec<_>>().join(" "))).collect()
+  }
+
+  fn lines(expected: &[(u32, &str)]) -> Vec<(u32, String)> {
+    expected.iter().map(|&(line, tokens)| (line, tokens.to_string())).collect()
+  }
+
+  #[test]
+  fn one_dedent_per_closed_block() {
+    let source = "if a:\n    if b:\n        c\nd\n";
+    let expected = [(1, "TokIf TokVar(a) TokColon"), (2, "TokIndent TokIf TokVar(b) TokColon"), (3, "TokIndent TokVar(c)"), (4, "TokDedent TokDedent TokVar(d)")];
+    assert_eq!(token_lines(source), lines(&expected));
+  }
 
-    // Invalid Input
-    else {
-      return Err(format!("LexerError: unexpected token {}", input));
+  #[test]
+  fn blocks_open_at_the_end_are_closed() {
+    let source = "while a:\n  for b in c:\n      d\n";
+    let tokens = token_lines(source);
+    assert_eq!(tokens.last().unwrap(), &(4, "TokDedent TokDedent".to_string()));
+  }
+
+  #[test]
+  fn blank_lines_comments_and_brackets_leave_indentation_alone() {
+    let source = "if a:\n\n        # a comment further in\n    b = [1,\n  2]\n# back at the margin\n    c\n";
+    let expected = [(1, "TokIf TokVar(a) TokColon"), (4, "TokIndent TokVar(b) TokAssign TokLBracket TokInt(1) TokComma TokInt(2) TokRBracket"), (7, "TokVar(c)"), (8, "TokDedent")];
+    assert_eq!(token_lines(source), lines(&expected));
+  }
+
+  #[test]
+  fn tabs_and_spaces() {
+    // A tab moves to the next multiple of 8, so a tab and 8 spaces are the same width, but the
+    // meaning of mixing them depends on the tab size
+    assert!(tokenize_source("if a:\n\tb\n\tc\n").is_ok());
+    assert!(tokenize_source("if a:\n  \tb\n  \tc\n").is_ok());
+    let tab_error = |line| Err(format!("TabError: inconsistent use of tabs and spaces in indentation (line {})", line));
+    assert_eq!(tokenize_source("if a:\n\tb\n        c\n"), tab_error(3));
+    assert_eq!(tokenize_source("if a:\n        b\n\tc\n"), tab_error(3));
+    assert_eq!(tokenize_source("if a:\n  \tb\n\t  c\n"), tab_error(3));
+    assert_eq!(tokenize_source("if a:\n    if b:\n\tc\n"), tab_error(3));
+  }
+
+  #[test]
+  fn unindent_to_an_unknown_level() {
+    let source = "if a:\n    if b:\n        c\n  d\n";
+    assert_eq!(
+      tokenize_source(source),
+      Err("IndentationError: unindent does not match any outer indentation level (line 4)".to_string())
+    );
+  }
+
+  #[test]
+  fn repl_lines_share_the_open_blocks() {
+    let mut indenter = Indenter::new();
+    assert_eq!(tokenize("if a:\n", 1, &mut indenter), Ok(Some(vec![Token::TokIf, Token::TokVar("a".to_string()), Token::TokColon])));
+    assert_eq!(tokenize("    b\n", 2, &mut indenter), Ok(Some(vec![Token::TokIndent, Token::TokVar("b".to_string())])));
+    // A line ending inside brackets continues on the next one
+    assert_eq!(tokenize("    c = (1,\n", 3, &mut indenter), Ok(None));
+    // A blank line ends the statement, closing every block
+    assert_eq!(tokenize("\n", 4, &mut indenter), Ok(Some(vec![Token::TokDedent])));
+    assert_eq!(tokenize("d\n", 5, &mut indenter), Ok(Some(vec![Token::TokVar("d".to_string())])));
+  }
+
+  // A script of the given number of functions, each a dozen lines using most kinds of token:
+  // keywords, names, numbers in every base, strings, f-strings, comments, brackets spanning lines
+  // and nested blocks
+  fn generated_source(functions: usize) -> String {
+    let mut source = String::new();
+    for i in 0..functions {
+      writeln!(source, "# function number {}", i).unwrap();
+      writeln!(source, "def function_{}(a, b=0x{:x}, *args, key=None, **kwargs):", i, i).unwrap();
+      writeln!(source, "    total = a * {} + b // 3 - 0o17 + 0b1010 + 1_000", i).unwrap();
+      writeln!(source, "    values = [1.5e3, 2.25, .5, 'single', \"double\", b'bytes',").unwrap();
+      writeln!(source, "              {{'key': total, 'other': (a, b)}}]").unwrap();
+      writeln!(source, "    for index in range(len(values)):").unwrap();
+      writeln!(source, "        if index % 2 == 0 and not total <= 10 or index is None:").unwrap();
+      writeln!(source, "            total += index ** 2").unwrap();
+      writeln!(source, "        else:").unwrap();
+      writeln!(source, "            if index != {}:", i % 7).unwrap();
+      writeln!(source, "                continue").unwrap();
+      writeln!(source, "            break").unwrap();
+      writeln!(source, "    return f\"{{total:>10}} {{values[0]!r}} {{a}}\" + '''triple").unwrap();
+      writeln!(source, "quoted''' \\").unwrap();
+      writeln!(source, "        + \"\\t\\u00e9\\n\"").unwrap();
+      writeln!(source).unwrap();
     }
+    source
   }
 
-  return Ok((tokens, indentation));
-}
\ No newline at end of file
+  #[test]
+  fn generated_source_tokenizes() {
+    // Each function is 11 logical lines, as the list and the return statement span two and three
+    // physical lines, and the comment and blank lines give none. The last line closes the last body
+    let lines = tokenize_source(&generated_source(3)).unwrap();
+    assert_eq!(lines.len(), 3 * 11 + 1);
+    assert_eq!((lines[0].0, lines[11].0), (2, 18));
+    assert_eq!(lines.last().unwrap().1, vec![Token::TokDedent; 1]);
+  }
+
+  // Run with: cargo test --release -- --ignored --nocapture lexer_benchmark
+  #[test]
+  #[ignore]
+  fn lexer_benchmark() {
+    let source = generated_source(10_000);
+    let line_count = source.lines().count();
+    let mut best = Duration::MAX;
+    let mut tokens = 0;
+    for _ in 0..5 {
+      let start = Instant::now();
+      let lines = tokenize_source(&source).unwrap();
+      best = best.min(start.elapsed());
+      tokens = lines.iter().map(|(_, line)| line.len()).sum();
+    }
+    let seconds = best.as_secs_f64();
+    println!(
+      "tokenized {} lines ({} bytes, {} tokens) in {:.1} ms: {:.0} lines/s, {:.1} MB/s",
+      line_count,
+      source.len(),
+      tokens,
+      seconds * 1000.0,
+      line_count as f64 / seconds,
+      source.len() as f64 / seconds / 1e6
+    );
+  }
+}
@@ -0,0 +1,39 @@
+mod builtins;
+pub mod bytecode;
+pub mod cache;
+pub mod compiler;
+pub mod disassembler;
+mod format;
+mod generator;
+pub mod interpreter;
+mod lexer;
+mod methods;
+mod operators;
+pub mod parser;
+mod stdio;
+pub mod types;
+mod unicode;
+pub mod value;
+pub mod vm;
+
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// Runs a script on the bytecode VM with print() writing to output, print(file=sys.stderr) to
+// errors and input() reading from input, giving the error that stopped it if there is one. The
+// engines recurse on the Rust stack, so a program that nests calls deeply needs a thread with a
+// stack as large as the one the command line gives them
+pub fn run(source: &str, output: Box<dyn Write>, errors: Box<dyn Write>, input: Box<dyn BufRead>) -> Result<(), String> {
+  let previous_output = stdio::set_output(stdio::Stream::Stdout, output);
+  let previous_errors = stdio::set_output(stdio::Stream::Stderr, errors);
+  let previous_input = stdio::set_input(Some(input));
+
+  let result = compiler::compile_script(source, false).and_then(|code| vm::Vm::new(false).run_module(Rc::new(code)));
+  // What was printed before an error is kept
+  let flushed = stdio::flush(stdio::Stream::Stdout).and(stdio::flush(stdio::Stream::Stderr));
+
+  stdio::set_output(stdio::Stream::Stdout, previous_output);
+  stdio::set_output(stdio::Stream::Stderr, previous_errors);
+  stdio::set_input(previous_input);
+  result.and(flushed)
+}
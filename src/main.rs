@@ -1,78 +1,185 @@
-mod interpreter;
-mod lexer;
-mod parser;
-mod types;
-use crate::types::{Expr, PyType, Stmt};
-use std::io::{self, Write};
+use tompython::interpreter::{self, Environment};
+use tompython::parser::{self, LineSource};
+use tompython::types::Statement;
+use tompython::value::{Value, MAX_CALL_DEPTH};
+use tompython::vm::Vm;
+use tompython::{bytecode, cache, compiler, disassembler};
+use std::rc::Rc;
+use std::{env, fs, process, thread};
 
-use types::{print_env, Environment};
+// The tree-walker recurses on the Rust stack for every Python call, and the VM for every generator
+// it resumes, so the engines run on a thread with room for the recursion limit's worth of them. A
+// call takes around 20 KiB in a debug build and 3 KiB in a release build
+const STACK_SIZE: usize = MAX_CALL_DEPTH * 64 * 1024;
+
+// Programs run on the bytecode VM by default. The tree-walking interpreter is kept behind
+// --tree-walk so the two engines can be checked against each other
+enum Engine {
+  Vm(Vm),
+  TreeWalk(Environment),
+}
+
+impl Engine {
+  fn run(&mut self, statement: &Statement) -> Result<Option<Value>, String> {
+    match self {
+      Engine::Vm(vm) => vm.run(statement),
+      Engine::TreeWalk(env) => interpreter::run(&statement.node, env),
+    }
+  }
+}
 
 fn main() {
+  // When there is no memory for a stack that size, the engines run on the main thread instead
+  match thread::Builder::new().stack_size(STACK_SIZE).spawn(start) {
+    Ok(engine) => {
+      if engine.join().is_err() {
+        process::exit(101);
+      }
+    }
+    Err(_) => start(),
+  }
+}
+
+fn start() {
+  let mut tree_walk = false;
+  let mut dis = false;
+  let mut optimize = false;
+  let mut path = None;
+
+  for arg in env::args().skip(1) {
+    match arg.as_str() {
+      "--tree-walk" => tree_walk = true,
+      "-O" => optimize = true,
+      "--dis" => dis = true,
+      _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+      _ => usage(),
+    }
+  }
+
+  match (path, dis, tree_walk) {
+    (Some(path), true, _) => dis_script(&path, optimize),
+    (None, true, _) => usage(),
+    (Some(path), false, true) => run_script_tree_walk(&path, optimize),
+    (Some(path), false, false) => run_script(&path, optimize),
+    (None, false, true) => repl(&mut Engine::TreeWalk(Environment::new(optimize))),
+    (None, false, false) => repl(&mut Engine::Vm(Vm::new(optimize))),
+  }
+}
+
+fn usage() -> ! {
+  eprintln!("usage: tompython [-O] [--tree-walk] [file]");
+  eprintln!("       tompython [-O] --dis file");
+  process::exit(2);
+}
+
+fn repl(engine: &mut Engine) {
   println!("TomPython Version 1.0");
   println!("Type 'q' or 'quit' to quit");
   println!("Type 'help' for more information");
 
-  let stdin = io::stdin();
-  let mut env: Environment = Vec::new();
-
-  loop {
-    print!(">>> ");
-    io::stdout().flush().unwrap(); // Required for Rust to print string without newline character
+  let mut source = LineSource::stdin();
 
-    let mut input = String::new();
-    stdin.read_line(&mut input).unwrap();
-    input.pop();
+  while let Some(input) = source.next_line(">>> ") {
+    let command = input.trim_end();
 
     // Exit condition
-    if input == "q" || input == "quit" {
+    if command == "q" || command == "quit" {
       break;
-    } else if input == "help" {
-      println!("Implemented features: math expressions, variable assignment, if-else statements, and functions");
+    } else if command == "help" {
+      println!("Implemented features: math expressions, variable assignment, if-else statements, loops, lists, tuples, dicts, sets, functions, and built-in functions such as len, range, sorted and map");
       println!("To view complete syntax for TomPython see README.md at https://github.com/THuitema/TomPython");
+      continue;
     }
 
-    execute(&input, &mut env);
-  }
-
-  println!("Goodbye...");
-}
-
-fn execute(input: &str, env: &mut Environment) {
-  let mut prev_indent = 0; // could change to curr_indent that tracks current indent, not always pushed to stack
-  let mut indent_stack = Vec::<i32>::new(); // should be able to replace prev_indent and original_indent
-
-  match lexer::tokenize(input, &mut prev_indent) {
-    Ok((t, indentation)) => {
-      // print!("Tokens: ");
-      // for token in &t {
-      //     print!("{}, ", token)
-      // }
-      // println!("");
-      prev_indent = indentation;
-      match parser::parse(&t, &mut prev_indent, &mut indent_stack, false) {
-        Ok((tokens_res, expr)) => {
-          match tokens_res[..] {
-            [] => {
-              // print!("Parse Tree: {}\n", expr);
-              match interpreter::evaluate(&expr, env) {
-                Ok(PyType::Expr(result)) => println!("{}", result),
-                Ok(_) => print!(""), // PyType::Stmt (print nothing)
-                Err(e) => println!("{}", e),
-              }
-            }
-            _ => {
-              println!("SyntaxError: invalid syntax");
-              print!("Tokens: ");
-              for token in &tokens_res {
-                print!("{}, ", token)
+    match source.tokenize_statement(input).and_then(|tokens| parser::parse_statements(tokens, &mut source)) {
+      Ok(statements) => {
+        for statement in statements {
+          match engine.run(&statement) {
+            Ok(Some(Value::None)) | Ok(None) => {} // None is not echoed
+            Ok(Some(result)) => match result.repr() {
+              Ok(text) => println!("{}", text),
+              Err(e) => {
+                println!("{}", e);
+                break;
               }
-              println!("");
+            },
+            Err(e) => {
+              println!("{}", e);
+              break;
             }
           }
         }
-        Err(e) => println!("{}", e),
       }
+      Err(e) => println!("{}", e),
     }
-    Err(e) => println!("{}", e),
+  }
+
+  println!("Goodbye...");
+}
+
+fn read_script(path: &str) -> String {
+  match fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(e) => {
+      eprintln!("tompython: can't open file '{}': {}", path, e);
+      process::exit(2);
+    }
+  }
 }
+
+// Parses a script, exiting with its syntax error if it has one
+fn parse_script(contents: &str) -> Vec<Statement> {
+  match parser::parse_script(contents) {
+    Ok(statements) => statements,
+    Err(e) => {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  }
+}
+
+fn compile_script(contents: &str, optimize: bool) -> bytecode::CodeObject {
+  match compiler::compile_script(contents, optimize) {
+    Ok(code) => code,
+    Err(e) => {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  }
+}
+
+// Runs a script on the VM, reusing its cached bytecode when the source has not changed
+fn run_script(path: &str, optimize: bool) {
+  let contents = read_script(path);
+
+  let code = match cache::load(path, &contents, optimize) {
+    Some(code) => code,
+    None => {
+      let code = compile_script(&contents, optimize);
+      let _ = cache::store(path, &contents, optimize, &code);
+      code
+    }
+  };
+
+  if let Err(e) = Vm::new(optimize).run_module(Rc::new(code)) {
+    eprintln!("{}", e);
+    process::exit(1);
+  }
+}
+
+fn run_script_tree_walk(path: &str, optimize: bool) {
+  let mut env = Environment::new(optimize);
+
+  for statement in parse_script(&read_script(path)) {
+    if let Err(e) = interpreter::run(&statement.node, &mut env) {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  }
+}
+
+fn dis_script(path: &str, optimize: bool) {
+  let contents = read_script(path);
+  let code = compile_script(&contents, optimize);
+  print!("{}", disassembler::disassemble(&code, code.source_lines().as_deref()));
 }
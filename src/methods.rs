@@ -0,0 +1,920 @@
+use crate::format::{convert, str_format};
+use crate::operators::slice_bound;
+use crate::stdio::{self, Stream};
+use crate::unicode;
+use crate::builtins::EXCEPTIONS;
+use crate::value::{char_count, next_item, stop_iteration, Builtin, Caller, Exception, Generator, Iter, Keywords, Method, Resumed, Value};
+use std::rc::Rc;
+
+// Methods of the built-in types. Looking one up on a value binds it to that value, which the
+// method then receives as its first argument
+
+const STR_METHODS: &[Builtin] = &[
+  Builtin { name: "capitalize", func: str_capitalize },
+  Builtin { name: "casefold", func: str_casefold },
+  Builtin { name: "center", func: str_center },
+  Builtin { name: "count", func: str_count },
+  Builtin { name: "encode", func: str_encode },
+  Builtin { name: "endswith", func: str_endswith },
+  Builtin { name: "expandtabs", func: str_expandtabs },
+  Builtin { name: "find", func: str_find },
+  Builtin { name: "format", func: str_format_method },
+  Builtin { name: "index", func: str_index },
+  Builtin { name: "isalnum", func: str_isalnum },
+  Builtin { name: "isalpha", func: str_isalpha },
+  Builtin { name: "isascii", func: str_isascii },
+  Builtin { name: "isdecimal", func: str_isdecimal },
+  Builtin { name: "isdigit", func: str_isdigit },
+  Builtin { name: "isidentifier", func: str_isidentifier },
+  Builtin { name: "islower", func: str_islower },
+  Builtin { name: "isnumeric", func: str_isnumeric },
+  Builtin { name: "isprintable", func: str_isprintable },
+  Builtin { name: "isspace", func: str_isspace },
+  Builtin { name: "istitle", func: str_istitle },
+  Builtin { name: "isupper", func: str_isupper },
+  Builtin { name: "join", func: str_join },
+  Builtin { name: "ljust", func: str_ljust },
+  Builtin { name: "lower", func: str_lower },
+  Builtin { name: "lstrip", func: str_lstrip },
+  Builtin { name: "partition", func: str_partition },
+  Builtin { name: "removeprefix", func: str_removeprefix },
+  Builtin { name: "removesuffix", func: str_removesuffix },
+  Builtin { name: "replace", func: str_replace },
+  Builtin { name: "rfind", func: str_rfind },
+  Builtin { name: "rindex", func: str_rindex },
+  Builtin { name: "rjust", func: str_rjust },
+  Builtin { name: "rpartition", func: str_rpartition },
+  Builtin { name: "rsplit", func: str_rsplit },
+  Builtin { name: "rstrip", func: str_rstrip },
+  Builtin { name: "split", func: str_split },
+  Builtin { name: "splitlines", func: str_splitlines },
+  Builtin { name: "startswith", func: str_startswith },
+  Builtin { name: "strip", func: str_strip },
+  Builtin { name: "swapcase", func: str_swapcase },
+  Builtin { name: "title", func: str_title },
+  Builtin { name: "upper", func: str_upper },
+  Builtin { name: "zfill", func: str_zfill },
+];
+
+const GENERATOR_METHODS: &[Builtin] = &[
+  Builtin { name: "close", func: generator_close },
+  Builtin { name: "send", func: generator_send },
+  Builtin { name: "throw", func: generator_throw },
+];
+
+const STREAM_METHODS: &[Builtin] = &[
+  Builtin { name: "flush", func: stream_flush },
+  Builtin { name: "write", func: stream_write },
+];
+
+// Attribute access value.name. Functions also have the attributes assigned to them
+pub fn get_attribute(value: &Value, name: &str) -> Result<Value, String> {
+  if let Value::Function(function) = value {
+    if let Some(attribute) = function.attributes.borrow().get(name) {
+      return Ok(attribute.clone());
+    }
+  }
+  if let Value::Module(module) = value {
+    return match (*module, name) {
+      ("sys", "stdout") => Ok(Value::Stream(Stream::Stdout)),
+      ("sys", "stderr") => Ok(Value::Stream(Stream::Stderr)),
+      _ => Err(format!("AttributeError: module '{}' has no attribute '{}'", module, name)),
+    };
+  }
+  let methods = match value {
+    Value::Str(_) => STR_METHODS,
+    Value::Iterator(it) if matches!(it.try_borrow().as_deref(), Ok(Iter::Generator(_))) => GENERATOR_METHODS,
+    Value::Stream(_) => STREAM_METHODS,
+    _ => &[],
+  };
+  match methods.iter().find(|method| method.name == name) {
+    Some(builtin) => Ok(Value::Method(Rc::new(Method { receiver: value.clone(), builtin: *builtin }))),
+    None => Err(format!("AttributeError: '{}' object has no attribute '{}'", value.type_name(), name)),
+  }
+}
+
+// object.name = value, or del object.name when value is None. Functions take any attribute; the
+// other built-in types have no writable attributes, so for them this reports why it can't be set
+pub fn set_attribute(object: &Value, name: &str, value: Option<Value>) -> Result<(), String> {
+  match (object, value) {
+    (Value::Function(function), Some(value)) => {
+      function.attributes.borrow_mut().insert(name.to_string(), value);
+      Ok(())
+    }
+    (Value::Function(function), None) => match function.attributes.borrow_mut().remove(name) {
+      Some(_) => Ok(()),
+      None => Err(format!("AttributeError: 'function' object has no attribute '{}'", name)),
+    },
+    _ => match get_attribute(object, name) {
+      Ok(_) => Err(format!("AttributeError: '{}' object attribute '{}' is read-only", object.type_name(), name)),
+      Err(e) => Err(e),
+    },
+  }
+}
+
+fn receiver_str(args: &[Value]) -> &str {
+  match args.first() {
+    Some(Value::Str(s)) => s,
+    _ => unreachable!("str methods are bound to a str"),
+  }
+}
+
+// Checks the arguments of a method that only takes them by position, returning them without the receiver
+fn positional<'a>(name: &str, args: &'a [Value], kwargs: &Keywords, min: usize, max: usize) -> Result<&'a [Value], String> {
+  let given = args.len() - 1;
+  let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+  if !kwargs.is_empty() {
+    Err(format!("TypeError: str.{}() takes no keyword arguments", name))
+  } else if max == 0 && given > 0 {
+    Err(format!("TypeError: str.{}() takes no arguments ({} given)", name, given))
+  } else if min == max && given != min {
+    Err(format!("TypeError: {}() takes exactly {} {} ({} given)", name, min, plural(min), given))
+  } else if given < min {
+    Err(format!("TypeError: {}() takes at least {} {} ({} given)", name, min, plural(min), given))
+  } else if given > max {
+    Err(format!("TypeError: {}() takes at most {} {} ({} given)", name, max, plural(max), given))
+  } else {
+    Ok(&args[1..])
+  }
+}
+
+// Matches the arguments of a method that also takes them by keyword to its parameters, all of
+// which are optional
+fn keyword_arguments(name: &str, args: &[Value], kwargs: &Keywords, params: &[&str]) -> Result<Vec<Option<Value>>, String> {
+  let given = &args[1..];
+  if given.len() > params.len() {
+    return Err(format!("TypeError: {}() takes at most {} arguments ({} given)", name, params.len(), given.len()));
+  }
+  let mut bound: Vec<Option<Value>> = params.iter().enumerate().map(|(i, _)| given.get(i).cloned()).collect();
+  for (key, value) in kwargs {
+    match params.iter().position(|param| param == key) {
+      Some(i) if i < given.len() => return Err(format!("TypeError: argument for {}() given by name ('{}') and position ({})", name, key, i + 1)),
+      Some(i) => bound[i] = Some(value.clone()),
+      None => return Err(format!("TypeError: '{}' is an invalid keyword argument for {}()", key, name)),
+    }
+  }
+  Ok(bound)
+}
+
+fn str_arg(value: &Value) -> Result<&str, String> {
+  match value {
+    Value::Str(s) => Ok(s),
+    _ => Err(format!("TypeError: must be str, not {}", value.type_name())),
+  }
+}
+
+pub fn int_arg(value: &Value) -> Result<i64, String> {
+  match value {
+    Value::Int(n) => Ok(*n),
+    Value::Bool(b) => Ok(*b as i64),
+    _ => Err(format!("TypeError: '{}' object cannot be interpreted as an integer", value.type_name())),
+  }
+}
+
+// Byte offset of the code point at index i, or the end of the string
+fn byte_offset(s: &str, i: usize) -> usize {
+  s.char_indices().nth(i).map_or(s.len(), |(offset, _)| offset)
+}
+
+// The part of a string between the optional start and end arguments of find(), count() and the
+// like, which count code points and work like slice indices. Also gives the code point index the
+// part starts at. None when start is past the end of the string or of the part
+fn substring<'a>(s: &'a str, start: Option<&Value>, end: Option<&Value>) -> Result<Option<(&'a str, usize)>, String> {
+  let len = char_count(s) as i64;
+  let clamp = |i: i64| if i < 0 { (i + len).max(0) } else { i };
+  let start = start.map(slice_bound).transpose()?.flatten().map_or(0, clamp);
+  let end = end.map(slice_bound).transpose()?.flatten().map_or(len, clamp).min(len);
+  if start > len || start > end {
+    return Ok(None);
+  }
+  let (start, end) = (start as usize, end as usize);
+  let from = byte_offset(s, start);
+  Ok(Some((&s[from..from + byte_offset(&s[from..], end - start)], start)))
+}
+
+// Fills out a string to width code points, splitting the padding between the left and right the
+// way Python's str.center() does when asked to
+fn justify(s: &str, width: &Value, fill: Option<&Value>, align: char) -> Result<Value, String> {
+  let width = int_arg(width)?;
+  let fill = match fill {
+    None => ' ',
+    Some(Value::Str(f)) if f.chars().count() == 1 => f.chars().next().unwrap(),
+    Some(Value::Str(_)) => return Err("TypeError: The fill character must be exactly one character long".to_string()),
+    Some(value) => return Err(format!("TypeError: The fill character must be a unicode character, not {}", value.type_name())),
+  };
+  let len = char_count(s) as i64;
+  if width <= len {
+    return Ok(Value::new_str(s));
+  }
+  let margin = width - len;
+  let left = match align {
+    '<' => 0,
+    '>' => margin,
+    _ => margin / 2 + (margin & width & 1),
+  };
+  let padding = |n: i64| fill.to_string().repeat(n as usize);
+  Ok(Value::new_str(&format!("{}{}{}", padding(left), s, padding(margin - left))))
+}
+
+// str.format(*args, **kwargs)
+fn str_format_method(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  Ok(Value::new_str(&str_format(receiver_str(args), &args[1..], kwargs)?))
+}
+
+// str.split(sep=None, maxsplit=-1) and str.rsplit(sep=None, maxsplit=-1). Without a separator the
+// string is split on runs of whitespace, ignoring any at the ends
+fn split(name: &str, args: &[Value], kwargs: &Keywords, from_right: bool) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let bound = keyword_arguments(name, args, kwargs, &["sep", "maxsplit"])?;
+  let maxsplit = match &bound[1] {
+    Some(value) => usize::try_from(int_arg(value)?).ok(),
+    None => None,
+  };
+
+  let parts: Vec<&str> = match &bound[0] {
+    None | Some(Value::None) => {
+      let mut parts = Vec::new();
+      let mut rest = if from_right { s.trim_end_matches(unicode::is_space) } else { s.trim_start_matches(unicode::is_space) };
+      while !rest.is_empty() {
+        if maxsplit == Some(parts.len()) {
+          parts.push(rest);
+          break;
+        }
+        if from_right {
+          match rest.rfind(unicode::is_space) {
+            Some(i) => {
+              parts.push(rest[i..].trim_start_matches(unicode::is_space));
+              rest = rest[..i].trim_end_matches(unicode::is_space);
+            }
+            None => {
+              parts.push(rest);
+              break;
+            }
+          }
+        } else {
+          match rest.find(unicode::is_space) {
+            Some(i) => {
+              parts.push(&rest[..i]);
+              rest = rest[i..].trim_start_matches(unicode::is_space);
+            }
+            None => {
+              parts.push(rest);
+              break;
+            }
+          }
+        }
+      }
+      if from_right {
+        parts.reverse();
+      }
+      parts
+    }
+    Some(Value::Str(sep)) if sep.is_empty() => return Err("ValueError: empty separator".to_string()),
+    Some(Value::Str(sep)) => match (from_right, maxsplit) {
+      (false, Some(n)) => s.splitn(n + 1, &**sep).collect(),
+      (false, None) => s.split(&**sep).collect(),
+      (true, Some(n)) => s.rsplitn(n + 1, &**sep).collect::<Vec<_>>().into_iter().rev().collect(),
+      (true, None) => s.rsplit(&**sep).collect::<Vec<_>>().into_iter().rev().collect(),
+    },
+    Some(value) => return Err(format!("TypeError: must be str or None, not {}", value.type_name())),
+  };
+  Ok(Value::new_list(parts.into_iter().map(Value::new_str).collect()))
+}
+
+fn str_split(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  split("split", args, kwargs, false)
+}
+
+fn str_rsplit(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  split("rsplit", args, kwargs, true)
+}
+
+// str.splitlines(keepends=False)
+fn str_splitlines(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let keepends = match &keyword_arguments("splitlines", args, kwargs, &["keepends"])?[0] {
+    Some(value) => int_arg(value)? != 0,
+    None => false,
+  };
+
+  let mut lines = Vec::new();
+  let mut start = 0;
+  let mut chars = s.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    if !unicode::is_linebreak(c) {
+      continue;
+    }
+    let mut end = i + c.len_utf8();
+    // \r\n is a single line break
+    if c == '\r' {
+      if let Some((_, '\n')) = chars.peek() {
+        chars.next();
+        end += 1;
+      }
+    }
+    lines.push(Value::new_str(&s[start..if keepends { end } else { i }]));
+    start = end;
+  }
+  if start < s.len() {
+    lines.push(Value::new_str(&s[start..]));
+  }
+  Ok(Value::new_list(lines))
+}
+
+// str.join(iterable)
+fn str_join(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  let separator = receiver_str(args);
+  let iterator = match positional("join", args, kwargs, 1, 1)?[0].to_iterator() {
+    Ok(iterator) => iterator,
+    Err(_) => return Err("TypeError: can only join an iterable".to_string()),
+  };
+
+  let mut result = String::new();
+  let mut i = 0;
+  while let Some(item) = next_item(&iterator, caller)? {
+    if i > 0 {
+      result.push_str(separator);
+    }
+    match &item {
+      Value::Str(s) => result.push_str(s),
+      _ => return Err(format!("TypeError: sequence item {}: expected str instance, {} found", i, item.type_name())),
+    }
+    i += 1;
+  }
+  Ok(Value::new_str(&result))
+}
+
+// str.strip(chars=None), str.lstrip(chars=None) and str.rstrip(chars=None). Whitespace is removed
+// unless other characters are given
+fn strip(name: &str, args: &[Value], kwargs: &Keywords, left: bool, right: bool) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let chars: Option<Vec<char>> = match positional(name, args, kwargs, 0, 1)?.first() {
+    None | Some(Value::None) => None,
+    Some(Value::Str(chars)) => Some(chars.chars().collect()),
+    Some(_) => return Err(format!("TypeError: {} arg must be None or str", name)),
+  };
+  let strips = |c: char| match &chars {
+    Some(chars) => chars.contains(&c),
+    None => unicode::is_space(c),
+  };
+
+  let mut result = s;
+  if left {
+    result = result.trim_start_matches(strips);
+  }
+  if right {
+    result = result.trim_end_matches(strips);
+  }
+  Ok(Value::new_str(result))
+}
+
+fn str_strip(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  strip("strip", args, kwargs, true, true)
+}
+
+fn str_lstrip(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  strip("lstrip", args, kwargs, true, false)
+}
+
+fn str_rstrip(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  strip("rstrip", args, kwargs, false, true)
+}
+
+// str.removeprefix(prefix)
+fn str_removeprefix(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let prefix = str_arg(&positional("removeprefix", args, kwargs, 1, 1)?[0])?;
+  Ok(Value::new_str(s.strip_prefix(prefix).unwrap_or(s)))
+}
+
+// str.removesuffix(suffix)
+fn str_removesuffix(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let suffix = str_arg(&positional("removesuffix", args, kwargs, 1, 1)?[0])?;
+  Ok(Value::new_str(s.strip_suffix(suffix).unwrap_or(s)))
+}
+
+// str.replace(old, new, count=-1)
+fn str_replace(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional("replace", args, kwargs, 2, 3)?;
+  let (old, new) = (str_arg(&args[0])?, str_arg(&args[1])?);
+  match args.get(2).map(int_arg).transpose()? {
+    Some(count) if count >= 0 => Ok(Value::new_str(&s.replacen(old, new, count as usize))),
+    _ => Ok(Value::new_str(&s.replace(old, new))),
+  }
+}
+
+// Code point index of the first or last occurrence of the first argument, for str.find() and the like
+fn search(name: &str, args: &[Value], kwargs: &Keywords, from_right: bool) -> Result<Option<usize>, String> {
+  let s = receiver_str(args);
+  let args = positional(name, args, kwargs, 1, 3)?;
+  let sub = str_arg(&args[0])?;
+  let (part, offset) = match substring(s, args.get(1), args.get(2))? {
+    Some(found) => found,
+    None => return Ok(None),
+  };
+  let found = if from_right { part.rfind(sub) } else { part.find(sub) };
+  Ok(found.map(|i| offset + part[..i].chars().count()))
+}
+
+// str.find(sub, start=None, end=None)
+fn str_find(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  Ok(Value::Int(search("find", args, kwargs, false)?.map_or(-1, |i| i as i64)))
+}
+
+// str.rfind(sub, start=None, end=None)
+fn str_rfind(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  Ok(Value::Int(search("rfind", args, kwargs, true)?.map_or(-1, |i| i as i64)))
+}
+
+// str.index(sub, start=None, end=None), like find() but raising an error when it is missing
+fn str_index(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  match search("index", args, kwargs, false)? {
+    Some(i) => Ok(Value::Int(i as i64)),
+    None => Err("ValueError: substring not found".to_string()),
+  }
+}
+
+// str.rindex(sub, start=None, end=None)
+fn str_rindex(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  match search("rindex", args, kwargs, true)? {
+    Some(i) => Ok(Value::Int(i as i64)),
+    None => Err("ValueError: substring not found".to_string()),
+  }
+}
+
+// str.count(sub, start=None, end=None): the number of non-overlapping occurrences
+fn str_count(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional("count", args, kwargs, 1, 3)?;
+  let sub = str_arg(&args[0])?;
+  match substring(s, args.get(1), args.get(2))? {
+    Some((part, _)) => Ok(Value::Int(part.matches(sub).count() as i64)),
+    None => Ok(Value::Int(0)),
+  }
+}
+
+// str.startswith(prefix, start=None, end=None) and str.endswith(suffix, start=None, end=None),
+// where a tuple checks each of the strings in it
+fn affix(name: &str, args: &[Value], kwargs: &Keywords, at_end: bool) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional(name, args, kwargs, 1, 3)?;
+  let candidates: Vec<&str> = match &args[0] {
+    Value::Str(affix) => vec![affix],
+    Value::Tuple(items) => {
+      let mut candidates = Vec::new();
+      for item in items.iter() {
+        match item {
+          Value::Str(affix) => candidates.push(&**affix),
+          _ => return Err(format!("TypeError: tuple for {} must only contain str, not {}", name, item.type_name())),
+        }
+      }
+      candidates
+    }
+    value => return Err(format!("TypeError: {} first arg must be str or a tuple of str, not {}", name, value.type_name())),
+  };
+  let part = match substring(s, args.get(1), args.get(2))? {
+    Some((part, _)) => part,
+    None => return Ok(Value::Bool(false)),
+  };
+  Ok(Value::Bool(candidates.iter().any(|affix| if at_end { part.ends_with(affix) } else { part.starts_with(affix) })))
+}
+
+fn str_startswith(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  affix("startswith", args, kwargs, false)
+}
+
+fn str_endswith(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  affix("endswith", args, kwargs, true)
+}
+
+// str.partition(sep) and str.rpartition(sep): the parts before and after the first or last
+// occurrence of sep, with sep itself in between
+fn partition(name: &str, args: &[Value], kwargs: &Keywords, from_right: bool) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let sep = str_arg(&positional(name, args, kwargs, 1, 1)?[0])?;
+  if sep.is_empty() {
+    return Err("ValueError: empty separator".to_string());
+  }
+  let found = if from_right { s.rfind(sep) } else { s.find(sep) };
+  let parts = match found {
+    Some(i) => [&s[..i], sep, &s[i + sep.len()..]],
+    None if from_right => ["", "", s],
+    None => [s, "", ""],
+  };
+  Ok(Value::Tuple(Rc::new(parts.iter().map(|part| Value::new_str(part)).collect())))
+}
+
+fn str_partition(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  partition("partition", args, kwargs, false)
+}
+
+fn str_rpartition(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  partition("rpartition", args, kwargs, true)
+}
+
+// str.upper()
+fn str_upper(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("upper", args, kwargs, 0, 0)?;
+  Ok(Value::new_str(&receiver_str(args).to_uppercase()))
+}
+
+// str.lower()
+fn str_lower(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("lower", args, kwargs, 0, 0)?;
+  Ok(Value::new_str(&receiver_str(args).to_lowercase()))
+}
+
+// str.casefold(): a lower case form for caseless comparison
+fn str_casefold(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("casefold", args, kwargs, 0, 0)?;
+  Ok(Value::new_str(&receiver_str(args).chars().map(unicode::casefold).collect::<String>()))
+}
+
+// str.swapcase()
+fn str_swapcase(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("swapcase", args, kwargs, 0, 0)?;
+  let mut result = String::new();
+  for c in receiver_str(args).chars() {
+    if c.is_uppercase() {
+      result.extend(c.to_lowercase());
+    } else if c.is_lowercase() {
+      result.extend(c.to_uppercase());
+    } else {
+      result.push(c);
+    }
+  }
+  Ok(Value::new_str(&result))
+}
+
+// str.title(): each word starts in title case and continues in lower case, where a word is a run
+// of cased characters
+fn str_title(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("title", args, kwargs, 0, 0)?;
+  let mut result = String::new();
+  let mut previous_cased = false;
+  for c in receiver_str(args).chars() {
+    if previous_cased {
+      result.extend(c.to_lowercase());
+    } else {
+      result.push_str(&unicode::to_titlecase(c));
+    }
+    previous_cased = unicode::is_cased(c);
+  }
+  Ok(Value::new_str(&result))
+}
+
+// str.capitalize()
+fn str_capitalize(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("capitalize", args, kwargs, 0, 0)?;
+  let s = receiver_str(args);
+  let mut chars = s.chars();
+  let result = match chars.next() {
+    Some(first) => unicode::to_titlecase(first) + &chars.as_str().to_lowercase(),
+    None => String::new(),
+  };
+  Ok(Value::new_str(&result))
+}
+
+// The is*() methods that hold when every character, of which there is at least one, passes a test
+fn all_chars(name: &str, args: &[Value], kwargs: &Keywords, test: fn(char) -> bool) -> Result<Value, String> {
+  positional(name, args, kwargs, 0, 0)?;
+  let s = receiver_str(args);
+  Ok(Value::Bool(!s.is_empty() && s.chars().all(test)))
+}
+
+fn str_isalnum(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isalnum", args, kwargs, |c| unicode::is_alpha(c) || unicode::is_numeric(c))
+}
+
+fn str_isalpha(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isalpha", args, kwargs, unicode::is_alpha)
+}
+
+fn str_isdecimal(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isdecimal", args, kwargs, unicode::is_decimal)
+}
+
+fn str_isdigit(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isdigit", args, kwargs, unicode::is_digit)
+}
+
+fn str_isnumeric(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isnumeric", args, kwargs, unicode::is_numeric)
+}
+
+fn str_isspace(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  all_chars("isspace", args, kwargs, unicode::is_space)
+}
+
+// str.isascii() and str.isprintable() also hold for the empty string
+fn str_isascii(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("isascii", args, kwargs, 0, 0)?;
+  Ok(Value::Bool(receiver_str(args).is_ascii()))
+}
+
+fn str_isprintable(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("isprintable", args, kwargs, 0, 0)?;
+  Ok(Value::Bool(receiver_str(args).chars().all(unicode::is_printable)))
+}
+
+// str.isidentifier(): whether the string could be a variable name
+fn str_isidentifier(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("isidentifier", args, kwargs, 0, 0)?;
+  let mut chars = receiver_str(args).chars();
+  let valid = match chars.next() {
+    Some(first) => (first == '_' || unicode::is_alpha(first)) && chars.all(|c| c == '_' || c.is_alphanumeric()),
+    None => false,
+  };
+  Ok(Value::Bool(valid))
+}
+
+// str.islower() and str.isupper(): there is a cased character, and all of them are in the one case
+fn str_islower(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("islower", args, kwargs, 0, 0)?;
+  let s = receiver_str(args);
+  Ok(Value::Bool(s.chars().any(char::is_lowercase) && !s.chars().any(|c| c.is_uppercase() || unicode::is_titlecase(c))))
+}
+
+fn str_isupper(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("isupper", args, kwargs, 0, 0)?;
+  let s = receiver_str(args);
+  Ok(Value::Bool(s.chars().any(char::is_uppercase) && !s.chars().any(|c| c.is_lowercase() || unicode::is_titlecase(c))))
+}
+
+// str.istitle(): upper and title case characters only start words and lower case ones only continue them
+fn str_istitle(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  positional("istitle", args, kwargs, 0, 0)?;
+  let mut previous_cased = false;
+  let mut cased = false;
+  for c in receiver_str(args).chars() {
+    if c.is_uppercase() || unicode::is_titlecase(c) {
+      if previous_cased {
+        return Ok(Value::Bool(false));
+      }
+      previous_cased = true;
+      cased = true;
+    } else if c.is_lowercase() {
+      if !previous_cased {
+        return Ok(Value::Bool(false));
+      }
+      previous_cased = true;
+      cased = true;
+    } else {
+      previous_cased = false;
+    }
+  }
+  Ok(Value::Bool(cased))
+}
+
+// str.zfill(width): pads with zeros on the left, after any sign
+fn str_zfill(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let width = int_arg(&positional("zfill", args, kwargs, 1, 1)?[0])?;
+  let len = char_count(s) as i64;
+  if width <= len {
+    return Ok(Value::new_str(s));
+  }
+  let zeros = "0".repeat((width - len) as usize);
+  let result = match s.strip_prefix(['+', '-']) {
+    Some(rest) => format!("{}{}{}", &s[..1], zeros, rest),
+    None => zeros + s,
+  };
+  Ok(Value::new_str(&result))
+}
+
+// str.center(width, fillchar=' ')
+fn str_center(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional("center", args, kwargs, 1, 2)?;
+  justify(s, &args[0], args.get(1), '^')
+}
+
+// str.ljust(width, fillchar=' ')
+fn str_ljust(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional("ljust", args, kwargs, 1, 2)?;
+  justify(s, &args[0], args.get(1), '<')
+}
+
+// str.rjust(width, fillchar=' ')
+fn str_rjust(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let args = positional("rjust", args, kwargs, 1, 2)?;
+  justify(s, &args[0], args.get(1), '>')
+}
+
+// str.expandtabs(tabsize=8): replaces tabs with spaces up to the next multiple of tabsize columns
+fn str_expandtabs(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let tabsize = match &keyword_arguments("expandtabs", args, kwargs, &["tabsize"])?[0] {
+    Some(value) => int_arg(value)?,
+    None => 8,
+  };
+  let mut result = String::new();
+  let mut column = 0;
+  for c in receiver_str(args).chars() {
+    match c {
+      '\t' if tabsize > 0 => {
+        let spaces = tabsize - column % tabsize;
+        result.push_str(&" ".repeat(spaces as usize));
+        column += spaces;
+      }
+      '\t' => {}
+      '\n' | '\r' => {
+        result.push(c);
+        column = 0;
+      }
+      _ => {
+        result.push(c);
+        column += 1;
+      }
+    }
+  }
+  Ok(Value::new_str(&result))
+}
+
+// str.encode(encoding='utf-8', errors='strict'). Supports UTF-8, ASCII and Latin-1, with the strict,
+// ignore, replace, backslashreplace and xmlcharrefreplace error handlers
+fn str_encode(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  let s = receiver_str(args);
+  let bound = keyword_arguments("encode", args, kwargs, &["encoding", "errors"])?;
+  let mut options = ["utf-8", "strict"].map(String::from);
+  for (i, name) in ["encoding", "errors"].iter().enumerate() {
+    match &bound[i] {
+      Some(Value::Str(option)) => options[i] = option.to_string(),
+      Some(value) => return Err(format!("TypeError: encode() argument '{}' must be str, not {}", name, value.type_name())),
+      None => {}
+    }
+  }
+  let [encoding, errors] = options;
+
+  let (codec, limit) = match encoding.to_lowercase().replace('_', "-").as_str() {
+    "utf-8" | "utf8" | "u8" => return Ok(Value::Bytes(Rc::from(s.as_bytes()))),
+    "ascii" | "us-ascii" => ("ascii", 0x80),
+    "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" | "l1" => ("latin-1", 0x100),
+    _ => return Err(format!("LookupError: unknown encoding: {}", encoding)),
+  };
+
+  let chars: Vec<char> = s.chars().collect();
+  let mut bytes = Vec::with_capacity(chars.len());
+  let mut i = 0;
+  while i < chars.len() {
+    let code = chars[i] as u32;
+    if code < limit {
+      bytes.push(code as u8);
+      i += 1;
+      continue;
+    }
+    match errors.as_str() {
+      "strict" => {
+        // Consecutive characters that cannot be encoded are reported together
+        let run = chars[i..].iter().take_while(|c| (**c as u32) >= limit).count();
+        let what = match run {
+          1 => format!("character {} in position {}", convert(&Value::new_str(&chars[i].to_string()), 'a')?, i),
+          _ => format!("characters in position {}-{}", i, i + run - 1),
+        };
+        return Err(format!("UnicodeEncodeError: '{}' codec can't encode {}: ordinal not in range({})", codec, what, limit));
+      }
+      "ignore" => {}
+      "replace" => bytes.push(b'?'),
+      "backslashreplace" => {
+        let escape = match code {
+          0..=0xff => format!("\\x{:02x}", code),
+          0x100..=0xffff => format!("\\u{:04x}", code),
+          _ => format!("\\U{:08x}", code),
+        };
+        bytes.extend(escape.bytes());
+      }
+      "xmlcharrefreplace" => bytes.extend(format!("&#{};", code).bytes()),
+      _ => return Err(format!("LookupError: unknown error handler name '{}'", errors)),
+    }
+    i += 1;
+  }
+  Ok(Value::Bytes(Rc::from(bytes)))
+}
+
+// Runs f on the generator a generator method is bound to, which can't be done while it is running
+fn with_generator<T>(args: &[Value], f: impl FnOnce(&mut Generator) -> Result<T, String>) -> Result<T, String> {
+  match args.first() {
+    Some(Value::Iterator(it)) => match it.try_borrow_mut() {
+      Ok(mut it) => match &mut *it {
+        Iter::Generator(generator) => f(generator),
+        _ => unreachable!("generator methods are bound to a generator"),
+      },
+      Err(_) => Err("ValueError: generator already executing".to_string()),
+    },
+    _ => unreachable!("generator methods are bound to a generator"),
+  }
+}
+
+// generator.send(value): resumes the generator with value as the result of the yield it is
+// suspended at, giving the next value it yields
+fn generator_send(args: &[Value], kwargs: &Keywords, caller: &mut dyn Caller) -> Result<Value, String> {
+  if !kwargs.is_empty() {
+    return Err("TypeError: generator.send() takes no keyword arguments".to_string());
+  }
+  if args.len() != 2 {
+    return Err(format!("TypeError: generator.send() takes exactly one argument ({} given)", args.len() - 1));
+  }
+  match with_generator(args, |generator| generator.resume(args[1].clone(), caller))? {
+    Resumed::Yield(value) => Ok(value),
+    Resumed::Return(value) => Err(stop_iteration(&value)),
+  }
+}
+
+// generator.close(): finishes the generator where it is suspended
+fn generator_close(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  if !kwargs.is_empty() {
+    return Err("TypeError: generator.close() takes no keyword arguments".to_string());
+  }
+  if args.len() != 1 {
+    return Err(format!("TypeError: generator.close() takes no arguments ({} given)", args.len() - 1));
+  }
+  with_generator(args, |generator| {
+    generator.close();
+    Ok(Value::None)
+  })
+}
+
+// generator.throw(exception) or generator.throw(type, value=None, traceback=None): raises the
+// exception where the generator is suspended. Without a try statement nothing can catch it there,
+// so the generator finishes and the exception propagates to the caller
+fn generator_throw(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  if !kwargs.is_empty() {
+    return Err("TypeError: generator.throw() takes no keyword arguments".to_string());
+  }
+  let given = &args[1..];
+  match given.len() {
+    0 => return Err("TypeError: throw expected at least 1 argument, got 0".to_string()),
+    1..=3 => {}
+    n => return Err(format!("TypeError: throw expected at most 3 arguments, got {}", n)),
+  }
+  if !matches!(given.get(2), None | Some(Value::None)) {
+    return Err("TypeError: throw() third argument must be a traceback object".to_string());
+  }
+
+  // A value given with an exception type holds the arguments it is created with
+  let value = given.get(1).cloned().unwrap_or(Value::None);
+  let exception = match (&given[0], &value) {
+    (Value::Exception(exception), Value::None) => exception.clone(),
+    (Value::Exception(_), _) => return Err("TypeError: instance exception may not have a separate value".to_string()),
+    (Value::Type(kind), _) if EXCEPTIONS.contains(kind) => {
+      let args = match &value {
+        Value::None => Vec::new(),
+        Value::Tuple(items) => items.to_vec(),
+        value => vec![value.clone()],
+      };
+      Rc::new(Exception { kind, args })
+    }
+    (value, _) => {
+      return Err(format!(
+        "TypeError: exceptions must be classes or instances deriving from BaseException, not {}",
+        value.type_name()
+      ))
+    }
+  };
+
+  with_generator(args, |generator| {
+    let suspended = generator.frame.is_some();
+    generator.close();
+    if suspended && exception.kind == "StopIteration" {
+      return Err("RuntimeError: generator raised StopIteration".to_string());
+    }
+    Err(exception.error())
+  })
+}
+
+fn receiver_stream(args: &[Value]) -> Stream {
+  match args.first() {
+    Some(Value::Stream(stream)) => *stream,
+    _ => unreachable!("stream methods are bound to a stream"),
+  }
+}
+
+// stream.write(s): writes s, giving the number of characters written
+fn stream_write(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  if !kwargs.is_empty() {
+    return Err("TypeError: TextIOWrapper.write() takes no keyword arguments".to_string());
+  }
+  match &args[1..] {
+    [Value::Str(s)] => {
+      stdio::write(receiver_stream(args), s)?;
+      Ok(Value::Int(char_count(s) as i64))
+    }
+    [value] => Err(format!("TypeError: write() argument must be str, not {}", value.type_name())),
+    _ => Err(format!("TypeError: TextIOWrapper.write() takes exactly one argument ({} given)", args.len() - 1)),
+  }
+}
+
+// stream.flush()
+fn stream_flush(args: &[Value], kwargs: &Keywords, _: &mut dyn Caller) -> Result<Value, String> {
+  if !kwargs.is_empty() {
+    return Err("TypeError: TextIOWrapper.flush() takes no keyword arguments".to_string());
+  }
+  if args.len() != 1 {
+    return Err(format!("TypeError: TextIOWrapper.flush() takes no arguments ({} given)", args.len() - 1));
+  }
+  stdio::flush(receiver_stream(args))?;
+  Ok(Value::None)
+}
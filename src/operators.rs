@@ -0,0 +1,557 @@
+use crate::format::printf_format;
+use crate::types::Op;
+use crate::value::{char_count, range_len, Caller, Dict, Number, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+// Operator semantics shared by the tree-walking interpreter and the bytecode VM
+
+// Membership test for `item in container`
+fn eval_contains(container: &Value, item: &Value) -> Result<bool, String> {
+  match (container, item) {
+    // Substring search
+    (Value::Str(haystack), Value::Str(needle)) => Ok(haystack.contains(&**needle)),
+    (Value::Str(_), _) => Err(format!("TypeError: 'in <string>' requires string as left operand, not {}", item.type_name())),
+    (Value::Bytes(haystack), Value::Bytes(needle)) => Ok(needle.is_empty() || haystack.windows(needle.len()).any(|window| window == &needle[..])),
+    (Value::Bytes(haystack), Value::Int(n)) if (0..256).contains(n) => Ok(haystack.contains(&(*n as u8))),
+    (Value::Bytes(_), Value::Int(_)) => Err("ValueError: byte must be in range(0, 256)".to_string()),
+    (Value::Bytes(_), _) => Err(format!("TypeError: a bytes-like object is required, not '{}'", item.type_name())),
+    (Value::List(items), _) => contains_item(&items.borrow(), item),
+    (Value::Tuple(items), _) => contains_item(items, item),
+    (Value::Dict(table), _) | (Value::Set(table), _) => table.borrow().contains(item),
+    // Ints are checked arithmetically, other values against each item
+    (Value::Range(start, stop, step), _) => match item.as_number() {
+      Some(Number::Int(n)) => {
+        let len = range_len(*start, *stop, *step) as i128;
+        let (offset, step) = (n as i128 - *start as i128, *step as i128);
+        Ok(offset % step == 0 && (0..len).contains(&(offset / step)))
+      }
+      _ => {
+        let (start, step) = (*start, *step);
+        Ok((0..range_len(start, *stop, step) as i64).any(|k| Value::Int(start + k * step).equals(item) == Ok(true)))
+      }
+    },
+    _ => Err(format!("TypeError: argument of type '{}' is not iterable", container.type_name()))
+  }
+}
+
+// Membership in a list or tuple, where each item is checked for identity before equality
+fn contains_item(items: &[Value], item: &Value) -> Result<bool, String> {
+  for element in items {
+    if element.is_identical(item) || element.equals(item)? {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+fn unsupported_operands(op: &Op, left: &Value, right: &Value) -> String {
+  format!("TypeError: unsupported operand type(s) for {}: '{}' and '{}'", op, left.type_name(), right.type_name())
+}
+
+// Applies an arithmetic operator to two numbers, using int_op when both are ints and float_op otherwise
+fn eval_arithmetic(op: &Op, left: &Value, right: &Value, int_op: fn(i64, i64) -> Option<i64>, float_op: fn(f64, f64) -> f64) -> Result<Value, String> {
+  match (left.as_number(), right.as_number()) {
+    (Some(Number::Int(n1)), Some(Number::Int(n2))) => {
+      int_op(n1, n2).map(Value::Int).ok_or_else(|| "OverflowError: integer overflow".to_string())
+    },
+    (Some(n1), Some(n2)) => Ok(Value::Float(float_op(n1.as_f64(), n2.as_f64()))),
+    _ => Err(unsupported_operands(op, left, right))
+  }
+}
+
+// Repeats a sequence n times (negative counts give an empty sequence)
+fn repeat<T: Clone>(items: &[T], n: i64) -> Vec<T> {
+  let mut result = Vec::new();
+  for _ in 0..n.max(0) {
+    result.extend_from_slice(items);
+  }
+  result
+}
+
+// Floor division of floats, rounded the way Python does so that it agrees with %
+fn float_floor_div(d1: f64, d2: f64) -> f64 {
+  let remainder = d1 % d2;
+  let mut div = (d1 - remainder) / d2;
+  if remainder != 0.0 && (d2 < 0.0) != (remainder < 0.0) {
+    div -= 1.0;
+  }
+  if div == 0.0 {
+    return 0.0f64.copysign(d1 / d2);
+  }
+  let floor = div.floor();
+  if div - floor > 0.5 { floor + 1.0 } else { floor }
+}
+
+// Raises an int to a non-negative int power
+fn int_pow(base: i64, exponent: i64) -> Result<Value, String> {
+  let result = match (base, u32::try_from(exponent)) {
+    (_, Ok(exponent)) => base.checked_pow(exponent),
+    // Only these bases stay in range for huge exponents
+    (0 | 1, Err(_)) => Some(base),
+    (-1, Err(_)) => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+    _ => None,
+  };
+  result.map(Value::Int).ok_or_else(|| "OverflowError: integer overflow".to_string())
+}
+
+// Set algebra for |, &, - and ^. Items keep the order they are first seen in
+fn set_operation(op: &Op, s1: &Dict, s2: &Dict) -> Result<Dict, String> {
+  let mut result = Dict::new();
+  for (item, _) in s1.entries() {
+    let keep = match op {
+      Op::BitOr => true,
+      Op::BitAnd => s2.contains(item)?,
+      _ => !s2.contains(item)?,
+    };
+    if keep {
+      result.insert(item.clone(), Value::None)?;
+    }
+  }
+  if matches!(op, Op::BitOr | Op::BitXor) {
+    for (item, _) in s2.entries() {
+      if !s1.contains(item)? {
+        result.insert(item.clone(), Value::None)?;
+      }
+    }
+  }
+  Ok(result)
+}
+
+// d1 | d2, with the entries of d2 winning
+fn merge_dicts(d1: &Dict, d2: &Dict) -> Result<Dict, String> {
+  let mut result = Dict::new();
+  for (key, value) in d1.entries().iter().chain(d2.entries()) {
+    result.insert(key.clone(), value.clone())?;
+  }
+  Ok(result)
+}
+
+// Orders two values for <, >, <= and >=. None means the values are unordered (NaN)
+fn compare_values(op: &Op, left: &Value, right: &Value) -> Result<Option<Ordering>, String> {
+  match (left, right) {
+    (Value::Str(s1), Value::Str(s2)) => Ok(Some(s1.cmp(s2))),
+    (Value::Bytes(b1), Value::Bytes(b2)) => Ok(Some(b1.cmp(b2))),
+    (Value::List(l1), Value::List(l2)) => compare_sequences(op, &l1.borrow(), &l2.borrow()),
+    (Value::Tuple(t1), Value::Tuple(t2)) => compare_sequences(op, t1, t2),
+    _ => match (left.as_number(), right.as_number()) {
+      (Some(Number::Int(n1)), Some(Number::Int(n2))) => Ok(Some(n1.cmp(&n2))),
+      (Some(n1), Some(n2)) => Ok(n1.as_f64().partial_cmp(&n2.as_f64())),
+      _ => Err(format!("TypeError: '{}' not supported between instances of '{}' and '{}'", op, left.type_name(), right.type_name()))
+    }
+  }
+}
+
+// Lexicographic ordering, decided by the first pair of items that differ
+fn compare_sequences(op: &Op, items1: &[Value], items2: &[Value]) -> Result<Option<Ordering>, String> {
+  for (item1, item2) in items1.iter().zip(items2) {
+    if !item1.equals(item2)? {
+      return compare_values(op, item1, item2);
+    }
+  }
+  Ok(Some(items1.len().cmp(&items2.len())))
+}
+
+// A start, stop or step of a slice, None when it was left out
+pub fn slice_bound(value: &Value) -> Result<Option<i64>, String> {
+  match value {
+    Value::None => Ok(None),
+    Value::Int(n) => Ok(Some(*n)),
+    Value::Bool(b) => Ok(Some(*b as i64)),
+    _ => Err("TypeError: slice indices must be integers or None or have an __index__ method".to_string()),
+  }
+}
+
+// Positions a slice selects from a sequence of len items, as a start, a step and a count. Negative
+// bounds count from the end and bounds out of range are clipped, following Python's slice.indices()
+fn slice_indices(bounds: &[Value; 3], len: usize) -> Result<(i64, i64, usize), String> {
+  let len = len as i64;
+  let step = match slice_bound(&bounds[2])? {
+    Some(0) => return Err("ValueError: slice step cannot be zero".to_string()),
+    Some(step) => step.max(-i64::MAX),
+    None => 1,
+  };
+  let adjust = |bound: Option<i64>, default: i64| match bound {
+    None => default,
+    Some(i) if i < 0 => (i.saturating_add(len)).max(if step < 0 { -1 } else { 0 }),
+    Some(i) => i.min(if step < 0 { len - 1 } else { len }),
+  };
+  let (start, stop) = if step < 0 {
+    (adjust(slice_bound(&bounds[0])?, len - 1), adjust(slice_bound(&bounds[1])?, -1))
+  } else {
+    (adjust(slice_bound(&bounds[0])?, 0), adjust(slice_bound(&bounds[1])?, len))
+  };
+  let count = match step {
+    _ if step < 0 && stop < start => (start - stop - 1) / -step + 1,
+    _ if step > 0 && start < stop => (stop - start - 1) / step + 1,
+    _ => 0,
+  };
+  Ok((start, step, count as usize))
+}
+
+// The items of a sequence that a slice selects, in order
+fn slice_items<T: Clone>(items: &[T], bounds: &[Value; 3]) -> Result<Vec<T>, String> {
+  let (start, step, count) = slice_indices(bounds, items.len())?;
+  Ok((0..count as i64).map(|k| items[(start + k * step) as usize].clone()).collect())
+}
+
+// The code points of a string that a slice selects. ASCII strings are sliced by byte, and a slice
+// with a step of 1 is a substring
+fn slice_str(s: &str, bounds: &[Value; 3]) -> Result<String, String> {
+  if !s.is_ascii() {
+    let chars: Vec<char> = s.chars().collect();
+    return Ok(slice_items(&chars, bounds)?.into_iter().collect());
+  }
+  let (start, step, count) = slice_indices(bounds, s.len())?;
+  if step == 1 {
+    return Ok(s[start as usize..start as usize + count].to_string());
+  }
+  let bytes = s.as_bytes();
+  Ok((0..count as i64).map(|k| bytes[(start + k * step) as usize] as char).collect())
+}
+
+// Subscription container[key] with an int index into a sequence (negative counting from the end),
+// a slice of a sequence or a key into a dict. Strings are indexed by code point
+pub fn get_item(container: &Value, key: &Value) -> Result<Value, String> {
+  if let Value::Slice(bounds) = key {
+    return match container {
+      Value::List(items) => Ok(Value::new_list(slice_items(&items.borrow(), bounds)?)),
+      Value::Tuple(items) => Ok(Value::Tuple(Rc::new(slice_items(items, bounds)?))),
+      Value::Str(s) => Ok(Value::new_str(&slice_str(s, bounds)?)),
+      Value::Bytes(b) => Ok(Value::Bytes(Rc::from(slice_items(b, bounds)?))),
+      // A slice of a range is another range
+      Value::Range(start, stop, step) => {
+        let (first, slice_step, count) = slice_indices(bounds, range_len(*start, *stop, *step) as usize)?;
+        let new_start = start + first * step;
+        let new_step = step * slice_step;
+        Ok(Value::Range(new_start, new_start + count as i64 * new_step, new_step))
+      }
+      Value::Dict(_) => Err("TypeError: unhashable type: 'slice'".to_string()),
+      _ => Err(format!("TypeError: '{}' object is not subscriptable", container.type_name())),
+    };
+  }
+
+  let index = |len: usize| item_index(container, key, len);
+
+  match container {
+    Value::List(items) => {
+      let items = items.borrow();
+      Ok(items[index(items.len())?].clone())
+    }
+    Value::Tuple(items) => Ok(items[index(items.len())?].clone()),
+    // ASCII strings are indexed by byte
+    Value::Str(s) if s.is_ascii() => {
+      let i = index(s.len())?;
+      Ok(Value::new_str(&s[i..i + 1]))
+    }
+    Value::Str(s) => {
+      let c = s.chars().nth(index(char_count(s))?).unwrap();
+      Ok(Value::new_str(&c.to_string()))
+    }
+    Value::Bytes(b) => Ok(Value::Int(b[index(b.len())?] as i64)),
+    Value::Range(start, stop, step) => Ok(Value::Int(start + index(range_len(*start, *stop, *step) as usize)? as i64 * step)),
+    Value::Dict(table) => match table.borrow().get(key)? {
+      Some(value) => Ok(value),
+      None => Err(format!("KeyError: {}", key.repr()?)),
+    },
+    _ => Err(format!("TypeError: '{}' object is not subscriptable", container.type_name())),
+  }
+}
+
+// Position of an int index into a sequence of len items, with negative indices counting from the end
+fn item_index(container: &Value, key: &Value, len: usize) -> Result<usize, String> {
+  let n = match key {
+    Value::Int(n) => *n,
+    Value::Bool(b) => *b as i64,
+    _ if matches!(container, Value::Str(_)) => return Err(format!("TypeError: string indices must be integers, not '{}'", key.type_name())),
+    _ => return Err(format!("TypeError: {} indices must be integers or slices, not {}", container.type_name(), key.type_name())),
+  };
+  let position = if n < 0 { n + len as i64 } else { n };
+  if (0..len as i64).contains(&position) {
+    Ok(position as usize)
+  } else {
+    let kind = match container {
+      Value::List(_) => "list ",
+      Value::Tuple(_) => "tuple ",
+      Value::Str(_) => "string ",
+      Value::Range(..) => "range object ",
+      _ => "",
+    };
+    Err(format!("IndexError: {}index out of range", kind))
+  }
+}
+
+// Like item_index, for assigning to or deleting an item of a list
+fn assignment_index(container: &Value, key: &Value, len: usize) -> Result<usize, String> {
+  match item_index(container, key, len) {
+    Err(e) if e.starts_with("IndexError") => Err("IndexError: list assignment index out of range".to_string()),
+    result => result,
+  }
+}
+
+// Item assignment container[key] = value, into a list by index or a dict by key. Assigning an
+// iterable to a slice of a list replaces the items it selects, and with a step other than 1 must
+// replace them one for one
+pub fn set_item(container: &Value, key: &Value, value: Value, caller: &mut dyn Caller) -> Result<(), String> {
+  match (container, key) {
+    (Value::List(items), Value::Slice(bounds)) => {
+      if value.to_iterator().is_err() {
+        return Err("TypeError: can only assign an iterable".to_string());
+      }
+      let new_items = value.items(caller)?;
+      let mut items = items.borrow_mut();
+      let (start, step, count) = slice_indices(bounds, items.len())?;
+      if step == 1 {
+        items.splice(start as usize..start as usize + count, new_items);
+      } else if new_items.len() != count {
+        return Err(format!("ValueError: attempt to assign sequence of size {} to extended slice of size {}", new_items.len(), count));
+      } else {
+        for (k, item) in new_items.into_iter().enumerate() {
+          items[(start + k as i64 * step) as usize] = item;
+        }
+      }
+      Ok(())
+    }
+    (Value::List(items), _) => {
+      let len = items.borrow().len();
+      let i = assignment_index(container, key, len)?;
+      items.borrow_mut()[i] = value;
+      Ok(())
+    }
+    (Value::Dict(table), _) => table.borrow_mut().insert(key.clone(), value),
+    _ => Err(format!("TypeError: '{}' object does not support item assignment", container.type_name())),
+  }
+}
+
+// del container[key], removing an item or a slice of a list or an entry of a dict
+pub fn del_item(container: &Value, key: &Value) -> Result<(), String> {
+  match (container, key) {
+    (Value::List(items), Value::Slice(bounds)) => {
+      let mut items = items.borrow_mut();
+      let (start, step, count) = slice_indices(bounds, items.len())?;
+      let mut positions: Vec<usize> = (0..count as i64).map(|k| (start + k * step) as usize).collect();
+      // Removing from the back keeps the positions still to be removed in place
+      positions.sort_unstable();
+      for i in positions.into_iter().rev() {
+        items.remove(i);
+      }
+      Ok(())
+    }
+    (Value::List(items), _) => {
+      let len = items.borrow().len();
+      let i = assignment_index(container, key, len)?;
+      items.borrow_mut().remove(i);
+      Ok(())
+    }
+    (Value::Dict(table), _) => match table.borrow_mut().remove(key)? {
+      Some(_) => Ok(()),
+      None => Err(format!("KeyError: {}", key.repr()?)),
+    },
+    _ => Err(format!("TypeError: '{}' object doesn't support item deletion", container.type_name())),
+  }
+}
+
+// Augmented assignment. Lists, sets and dicts are updated in place, as their __iadd__, __ior__ and
+// so on do in Python, so every reference to them sees the change. Anything else falls back to the
+// binary operator
+pub fn eval_inplace(op: &Op, left: &Value, right: &Value, caller: &mut dyn Caller) -> Result<Value, String> {
+  match (op, left, right) {
+    // list += iterable extends the list
+    (Op::Add, Value::List(items), _) => {
+      let added = right.items(caller)?;
+      items.borrow_mut().extend(added);
+    }
+    (Op::Mult, Value::List(items), Value::Int(n)) => {
+      let repeated = repeat(&items.borrow(), *n);
+      *items.borrow_mut() = repeated;
+    }
+    (Op::BitOr | Op::BitAnd | Op::BitXor | Op::Sub, Value::Set(s1), Value::Set(s2)) => {
+      let result = set_operation(op, &s1.borrow(), &s2.borrow())?;
+      *s1.borrow_mut() = result;
+    }
+    (Op::BitOr, Value::Dict(d1), Value::Dict(d2)) => {
+      let result = merge_dicts(&d1.borrow(), &d2.borrow())?;
+      *d1.borrow_mut() = result;
+    }
+    // The error names the augmented operator
+    _ => {
+      return match eval_binop(op, left, right) {
+        Err(e) if e == unsupported_operands(op, left, right) => {
+          Err(format!("TypeError: unsupported operand type(s) for {}=: '{}' and '{}'", op, left.type_name(), right.type_name()))
+        }
+        result => result,
+      };
+    }
+  }
+  Ok(left.clone())
+}
+
+pub fn eval_binop(op: &Op, left: &Value, right: &Value) -> Result<Value, String> {
+  match op {
+    // Addition
+    Op::Add => {
+      match (left, right) {
+        (Value::Str(s1), Value::Str(s2)) => Ok(Value::new_str(&format!("{}{}", s1, s2))), // String concatenation
+        (Value::Bytes(b1), Value::Bytes(b2)) => Ok(Value::Bytes(Rc::from([&b1[..], &b2[..]].concat()))),
+        (Value::List(l1), Value::List(l2)) => Ok(Value::new_list([l1.borrow().as_slice(), l2.borrow().as_slice()].concat())),
+        (Value::Tuple(t1), Value::Tuple(t2)) => Ok(Value::Tuple(Rc::new([t1.as_slice(), t2.as_slice()].concat()))),
+        _ => eval_arithmetic(op, left, right, i64::checked_add, |d1, d2| d1 + d2)
+      }
+    },
+
+    // Subtraction, or the difference of two sets
+    Op::Sub => {
+      match (left, right) {
+        (Value::Set(s1), Value::Set(s2)) => Ok(Value::Set(Rc::new(RefCell::new(set_operation(op, &s1.borrow(), &s2.borrow())?)))),
+        _ => eval_arithmetic(op, left, right, i64::checked_sub, |d1, d2| d1 - d2)
+      }
+    },
+
+    // Multiplication
+    Op::Mult => {
+      match (left, right) {
+        // Sequence repetition
+        (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => Ok(Value::new_str(&s.repeat((*n).max(0) as usize))),
+        (Value::Bytes(b), Value::Int(n)) | (Value::Int(n), Value::Bytes(b)) => Ok(Value::Bytes(Rc::from(repeat(b, *n)))),
+        (Value::List(items), Value::Int(n)) | (Value::Int(n), Value::List(items)) => Ok(Value::new_list(repeat(&items.borrow(), *n))),
+        (Value::Tuple(items), Value::Int(n)) | (Value::Int(n), Value::Tuple(items)) => Ok(Value::Tuple(Rc::new(repeat(items, *n)))),
+        _ => eval_arithmetic(op, left, right, i64::checked_mul, |d1, d2| d1 * d2)
+      }
+    },
+
+    // Division (always produces a float)
+    Op::Div => {
+      match (left.as_number(), right.as_number()) {
+        (Some(Number::Int(_)), Some(Number::Int(0))) => Err("ZeroDivisionError: division by zero".to_string()),
+        (Some(_), Some(n2)) if n2.as_f64() == 0.0 => Err("ZeroDivisionError: float division by zero".to_string()),
+        (Some(n1), Some(n2)) => Ok(Value::Float(n1.as_f64() / n2.as_f64())),
+        _ => Err(unsupported_operands(op, left, right))
+      }
+    },
+
+    // Floor division, rounding towards negative infinity
+    Op::FloorDiv => {
+      match (left.as_number(), right.as_number()) {
+        (Some(Number::Int(_)), Some(Number::Int(0))) => Err("ZeroDivisionError: integer division or modulo by zero".to_string()),
+        (Some(Number::Int(n1)), Some(Number::Int(n2))) => {
+          let quotient = n1.checked_div(n2).ok_or_else(|| "OverflowError: integer overflow".to_string())?;
+          let rounded_up = n1 % n2 != 0 && (n1 < 0) != (n2 < 0);
+          Ok(Value::Int(quotient - rounded_up as i64))
+        },
+        (Some(_), Some(n2)) if n2.as_f64() == 0.0 => Err("ZeroDivisionError: float floor division by zero".to_string()),
+        (Some(n1), Some(n2)) => Ok(Value::Float(float_floor_div(n1.as_f64(), n2.as_f64()))),
+        _ => Err(unsupported_operands(op, left, right))
+      }
+    },
+
+    // Exponentiation. An int raised to a negative power gives a float
+    Op::Pow => {
+      match (left.as_number(), right.as_number()) {
+        (Some(Number::Int(n1)), Some(Number::Int(n2))) if n2 >= 0 => int_pow(n1, n2),
+        (Some(n1), Some(n2)) => {
+          let (d1, d2) = (n1.as_f64(), n2.as_f64());
+          if d1 == 0.0 && d2 < 0.0 {
+            return Err("ZeroDivisionError: 0.0 cannot be raised to a negative power".to_string());
+          }
+          if d1 < 0.0 && d2.fract() != 0.0 && d2.is_finite() {
+            return Err("ValueError: negative number cannot be raised to a fractional power".to_string());
+          }
+          let result = d1.powf(d2);
+          if result.is_infinite() && d1.is_finite() && d2.is_finite() {
+            return Err("OverflowError: (34, 'Numerical result out of range')".to_string());
+          }
+          Ok(Value::Float(result))
+        },
+        _ => Err(unsupported_operands(op, left, right))
+      }
+    },
+
+    // Bitwise operators on ints (bools give bools), set algebra and merging dicts with |
+    Op::BitAnd | Op::BitOr | Op::BitXor => {
+      match (left, right) {
+        (Value::Bool(b1), Value::Bool(b2)) => Ok(Value::Bool(match op {
+          Op::BitAnd => b1 & b2,
+          Op::BitOr => b1 | b2,
+          _ => b1 ^ b2,
+        })),
+        (Value::Set(s1), Value::Set(s2)) => Ok(Value::Set(Rc::new(RefCell::new(set_operation(op, &s1.borrow(), &s2.borrow())?)))),
+        (Value::Dict(d1), Value::Dict(d2)) if *op == Op::BitOr => Ok(Value::Dict(Rc::new(RefCell::new(merge_dicts(&d1.borrow(), &d2.borrow())?)))),
+        _ => match (left.as_number(), right.as_number()) {
+          (Some(Number::Int(n1)), Some(Number::Int(n2))) => Ok(Value::Int(match op {
+            Op::BitAnd => n1 & n2,
+            Op::BitOr => n1 | n2,
+            _ => n1 ^ n2,
+          })),
+          _ => Err(unsupported_operands(op, left, right))
+        }
+      }
+    },
+
+    // Shifts. Shifting right rounds towards negative infinity, and shifting left must not lose bits
+    Op::LShift | Op::RShift => {
+      match (left.as_number(), right.as_number()) {
+        (Some(Number::Int(_)), Some(Number::Int(n2))) if n2 < 0 => Err("ValueError: negative shift count".to_string()),
+        (Some(Number::Int(n1)), Some(Number::Int(n2))) if *op == Op::RShift => Ok(Value::Int(n1 >> n2.min(63))),
+        (Some(Number::Int(0)), Some(Number::Int(_))) => Ok(Value::Int(0)),
+        (Some(Number::Int(n1)), Some(Number::Int(n2))) => {
+          let shifted = if n2 < 64 { Some(n1 << n2).filter(|shifted| shifted >> n2 == n1) } else { None };
+          shifted.map(Value::Int).ok_or_else(|| "OverflowError: integer overflow".to_string())
+        },
+        _ => Err(unsupported_operands(op, left, right))
+      }
+    },
+
+    // Modulo, which takes the sign of the divisor, or printf-style formatting of a string
+    Op::Mod => {
+      match (left, left.as_number(), right.as_number()) {
+        (Value::Str(template), _, _) => Ok(Value::new_str(&printf_format(template, right)?)),
+        (_, Some(Number::Int(_)), Some(Number::Int(0))) => Err("ZeroDivisionError: integer modulo by zero".to_string()),
+        (_, Some(Number::Int(n1)), Some(Number::Int(n2))) => {
+          let remainder = n1.wrapping_rem(n2);
+          Ok(Value::Int(if remainder != 0 && (remainder < 0) != (n2 < 0) { remainder + n2 } else { remainder }))
+        },
+        (_, Some(_), Some(n2)) if n2.as_f64() == 0.0 => Err("ZeroDivisionError: float modulo".to_string()),
+        (_, Some(n1), Some(n2)) => {
+          let (d1, d2) = (n1.as_f64(), n2.as_f64());
+          let remainder = d1 % d2;
+          Ok(Value::Float(match remainder {
+            0.0 => 0.0f64.copysign(d2),
+            _ if (remainder < 0.0) != (d2 < 0.0) => remainder + d2,
+            _ => remainder,
+          }))
+        },
+        _ => Err(unsupported_operands(op, left, right))
+      }
+    },
+
+    // And/Or short-circuit, so they are evaluated in evaluate() rather than here
+    Op::Or | Op::And => unreachable!("{} is evaluated lazily", op),
+
+    // Equals
+    Op::Equal => Ok(Value::Bool(left.equals(right)?)),
+
+    // Not Equals
+    Op::NotEqual => Ok(Value::Bool(!left.equals(right)?)),
+
+    // Ordering
+    Op::Less | Op::Greater | Op::LessEqual | Op::GreaterEqual => {
+      let ordering = compare_values(op, left, right)?;
+      Ok(Value::Bool(match (op, ordering) {
+        (_, None) => false,
+        (Op::Less, Some(ord)) => ord == Ordering::Less,
+        (Op::Greater, Some(ord)) => ord == Ordering::Greater,
+        (Op::LessEqual, Some(ord)) => ord != Ordering::Greater,
+        (_, Some(ord)) => ord != Ordering::Less,
+      }))
+    },
+
+    // Membership
+    Op::In => eval_contains(right, left).map(Value::Bool),
+    Op::NotIn => eval_contains(right, left).map(|b| Value::Bool(!b)),
+
+    // Identity
+    Op::Is => Ok(Value::Bool(left.is_identical(right))),
+    Op::IsNot => Ok(Value::Bool(!left.is_identical(right))),
+  }
+}
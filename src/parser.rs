@@ -1,20 +1,21 @@
-use crate::lexer::tokenize; 
-use crate::types::{Token, PyType, Stmt, Expr, Op};
+use crate::lexer::{tokenize, tokenize_source, Indenter};
+use crate::types::{Token, PyType, Statement, Stmt, Expr, Op, Parameters, FStringToken, FStringPart, Comprehension, Clause,
+  Declarations, bound_names, comprehension_scope, local_names, push_unique};
 use std::io::{self, Write};
 
-fn lookahead(tokens: &Vec<Token>) -> Option<&Token> {
-  tokens.get(0)
+fn lookahead(tokens: &[Token]) -> Option<&Token> {
+  tokens.first()
 }
 
-fn lookahead_at(tokens: &Vec<Token>, index: usize) -> Option<&Token> {
+fn lookahead_at(tokens: &[Token], index: usize) -> Option<&Token> {
   tokens.get(index)
 }
 
-fn match_token(tokens: &Vec<Token>, token: &Token) -> Result<Vec<Token>, String> {
-  match tokens.get(0) {
+fn match_token<'a>(tokens: &'a [Token], token: &Token) -> Result<&'a [Token], String> {
+  match tokens.first() {
     Some(first_token) => {
       if first_token == token {
-        Ok(tokens[1..].to_vec())
+        Ok(&tokens[1..])
       } else {
         Err(format!("SyntaxError: Expected {}, but got {}", token, first_token))
       }
@@ -23,69 +24,386 @@ fn match_token(tokens: &Vec<Token>, token: &Token) -> Result<Vec<Token>, String>
   }
 }
 
-fn read_body_line(prev_indent: &mut i32) -> Result<(Vec<Token>, i32), String> {
-  print!("... ");
-  io::stdout().flush().unwrap();
-  let mut input = String::new();
-  io::stdin().read_line(&mut input).unwrap();
-  
-  tokenize(&input, prev_indent)
+// Where lines come from: the terminal, which prompts for each one, or a script tokenized up front
+pub struct LineSource {
+  script: Option<std::vec::IntoIter<(u32, Vec<Token>)>>, // None reads from the terminal
+  indenter: Indenter, // blocks open in the statement being entered at the terminal
+  line: u32, // number of the last line read
 }
 
-pub fn parse(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut Vec<i32>, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
+impl LineSource {
+  pub fn stdin() -> LineSource {
+    LineSource { script: None, indenter: Indenter::new(), line: 0 }
+  }
+
+  pub fn script(contents: &str) -> Result<LineSource, String> {
+    let lines = tokenize_source(contents)?;
+    Ok(LineSource { script: Some(lines.into_iter()), indenter: Indenter::new(), line: 0 })
+  }
+
+  pub fn line(&self) -> u32 {
+    self.line
+  }
+
+  // Returns the next line from the terminal with its newline, or None at the end of input
+  pub fn next_line(&mut self, prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    let input = match io::stdin().read_line(&mut input) {
+      Ok(0) | Err(_) => None,
+      Ok(_) if input.ends_with('\n') => Some(input),
+      Ok(_) => Some(input + "\n"),
+    };
+    if input.is_some() {
+      self.line += 1;
+    }
+    input
+  }
+
+  // Tokenizes the first line of a statement entered at the terminal
+  pub fn tokenize_statement(&mut self, input: String) -> Result<Vec<Token>, String> {
+    // Like CPython, lines are numbered from the start of each statement
+    self.line = 1;
+    self.indenter = Indenter::new();
+    self.tokenize_line(input)
+  }
+
+  // Tokenizes a line read from the terminal, reading more lines while it is continued by open
+  // brackets or a backslash
+  fn tokenize_line(&mut self, mut input: String) -> Result<Vec<Token>, String> {
+    let line = self.line;
+    loop {
+      if let Some(tokens) = tokenize(&input, line, &mut self.indenter)? {
+        return Ok(tokens);
+      }
+      match self.next_line("... ") {
+        Some(next) => input.push_str(&next),
+        None => return Err("SyntaxError: unexpected EOF while parsing".to_string()),
+      }
+    }
+  }
+
+  // Returns the tokens of the next line, or None at the end of input. Lines holding only a comment
+  // are skipped, but a blank line from the terminal is kept, as it ends the block being entered
+  pub fn next_tokens(&mut self, prompt: &str) -> Option<Result<Vec<Token>, String>> {
+    match &mut self.script {
+      None => loop {
+        let input = self.next_line(prompt)?;
+        if !input.trim_start().starts_with('#') {
+          return Some(self.tokenize_line(input));
+        }
+      },
+      Some(lines) => {
+        let (line, tokens) = lines.next()?;
+        self.line = line;
+        Some(Ok(tokens))
+      }
+    }
+  }
+
+  // DEDENTs for the blocks still open when the input ends
+  fn close_blocks(&mut self) -> Vec<Token> {
+    self.indenter.close()
+  }
+}
+
+fn read_body_line(source: &mut LineSource) -> Result<Vec<Token>, String> {
+  // The end of input closes any open blocks, like an empty line
+  match source.next_tokens("... ") {
+    Some(tokens) => tokens,
+    None => Ok(source.close_blocks()),
+  }
+}
+
+// Parses the statements starting on the first line of an unindented statement, reading further
+// lines from source for blocks
+pub fn parse_statements(tokens: Vec<Token>, source: &mut LineSource) -> Result<Vec<Statement>, String> {
+  let mut statements = Vec::new();
+  parse_line(tokens, source, false, &mut statements)?;
+  Ok(statements)
+}
+
+// Parses every statement of a script before any of it runs
+pub fn parse_script(contents: &str) -> Result<Vec<Statement>, String> {
+  let mut source = LineSource::script(contents)?;
+  let mut statements = Vec::new();
+  while let Some(tokens) = source.next_tokens("") {
+    statements.extend(parse_statements(tokens?, &mut source)?);
+  }
+  Ok(statements)
+}
+
+// Parses statements up to the end of a line or a DEDENT, returning the tokens left from there.
+// After a block, the line that ended it can hold the next statement
+fn parse_line(mut tokens: Vec<Token>, source: &mut LineSource, in_function: bool, statements: &mut Vec<Statement>) -> Result<Vec<Token>, String> {
+  loop {
+    match lookahead(&tokens) {
+      None | Some(Token::TokDedent) => return Ok(tokens),
+      Some(Token::TokIndent) => return Err(format!("IndentationError: unexpected indent (line {})", source.line())),
+      _ => {}
+    }
+
+    let line = source.line();
+    let (tokens2, node) = parse(&tokens, source, in_function)?;
+
+    if !in_function && node.contains_yield() {
+      return Err("SyntaxError: 'yield' outside function".to_string());
+    }
+
+    let is_block = matches!(node, PyType::Stmt(Stmt::If(..) | Stmt::While(..) | Stmt::For(..) | Stmt::Function(..)));
+    if !is_block && !tokens2.is_empty() {
+      return Err("SyntaxError: invalid syntax".to_string());
+    }
+
+    statements.push(Statement { line, node });
+    tokens = tokens2;
+  }
+}
+
+pub fn parse(tokens: &[Token], source: &mut LineSource, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
   match (lookahead(tokens), lookahead_at(tokens, 1)) {
-    // AssignStatement
-    (Some(Token::TokVar(v)), Some(Token::TokAssign)) => parse_assign(&match_token(&tokens, &Token::TokVar(v.clone())).unwrap(), v),
-    
     // IfStatement
-    (Some(Token::TokIf), _) => parse_if(&match_token(&tokens, &Token::TokIf).unwrap(), prev_indent, indent_stack, in_function),
+    (Some(Token::TokIf), _) => parse_if(match_token(tokens, &Token::TokIf).unwrap(), source, in_function),
 
-    // FunctionStatement
-    (Some(Token::TokDef), _) => parse_function(&match_token(&tokens, &Token::TokDef).unwrap(), prev_indent, indent_stack),
-    
-    // Expr
-    _ => parse_expr(tokens)
+    // FunctionStatement. The declarations in it are checked once the outermost function is complete,
+    // as a nonlocal name can be bound by any function around it
+    (Some(Token::TokDef), _) => {
+      let (tokens2, function) = parse_function(match_token(tokens, &Token::TokDef).unwrap(), source)?;
+      if !in_function {
+        check_declarations(&function)?;
+      }
+      Ok((tokens2, function))
+    }
+
+    // WhileStatement
+    (Some(Token::TokWhile), _) => parse_while(match_token(tokens, &Token::TokWhile).unwrap(), source, in_function),
+
+    // ForStatement
+    (Some(Token::TokFor), _) => parse_for(match_token(tokens, &Token::TokFor).unwrap(), source, in_function),
+
+    // Statements without a block, which end on the line they start on
+    _ => match parse_simple_statement(tokens, in_function) {
+      Ok((tokens2, node)) => Ok((tokens2.to_vec(), node)),
+      Err(e) => Err(e)
+    }
+  }
+}
+
+fn parse_simple_statement(tokens: &[Token], in_function: bool) -> Result<(&[Token], PyType), String> {
+  match (lookahead(tokens), lookahead_at(tokens, 1)) {
+    // ReturnStatement
+    (Some(Token::TokReturn), _) => parse_return(match_token(tokens, &Token::TokReturn).unwrap(), in_function),
+
+    // YieldExpr as a statement
+    (Some(Token::TokYield), _) => parse_yield(match_token(tokens, &Token::TokYield).unwrap()),
+
+    // BreakStatement
+    (Some(Token::TokBreak), _) => Ok((match_token(tokens, &Token::TokBreak).unwrap(), PyType::Stmt(Stmt::Break))),
+
+    // ContinueStatement
+    (Some(Token::TokContinue), _) => Ok((match_token(tokens, &Token::TokContinue).unwrap(), PyType::Stmt(Stmt::Continue))),
+
+    // DelStatement
+    (Some(Token::TokDel), _) => parse_del(match_token(tokens, &Token::TokDel).unwrap()),
+
+    // PassStatement
+    (Some(Token::TokPass), _) => Ok((match_token(tokens, &Token::TokPass).unwrap(), PyType::Stmt(Stmt::Pass))),
+
+    // AssertStatement
+    (Some(Token::TokAssert), _) => parse_assert(match_token(tokens, &Token::TokAssert).unwrap()),
+
+    // GlobalStatement
+    (Some(Token::TokGlobal), _) => match parse_names(match_token(tokens, &Token::TokGlobal).unwrap()) {
+      Ok((tokens2, names)) => Ok((tokens2, PyType::Stmt(Stmt::Global(names)))),
+      Err(e) => Err(e)
+    },
+
+    // NonlocalStatement
+    (Some(Token::TokNonlocal), _) if !in_function => Err("SyntaxError: nonlocal declaration not allowed at module level".to_string()),
+    (Some(Token::TokNonlocal), _) => match parse_names(match_token(tokens, &Token::TokNonlocal).unwrap()) {
+      Ok((tokens2, names)) => Ok((tokens2, PyType::Stmt(Stmt::Nonlocal(names)))),
+      Err(e) => Err(e)
+    },
+
+    // ImportStatement
+    (Some(Token::TokImport), _) => parse_import(match_token(tokens, &Token::TokImport).unwrap()),
+
+    // AssignStatement, AugAssignStatement or Expr
+    _ => match parse_expr(tokens) {
+      Ok((tokens2, e)) => match lookahead(tokens2) {
+        Some(Token::TokAssign) => parse_assign(tokens2, e),
+        Some(Token::TokAugAssign(op)) => parse_aug_assign(&tokens2[1..], e, *op),
+        _ => Ok((tokens2, e))
+      },
+      Err(e) => Err(e)
+    }
   }
 }
 
-fn parse_assign(tokens: &Vec<Token>, var_name: &String) -> Result<(Vec<Token>, PyType), String> {
-  match parse_expr(&match_token(&tokens, &Token::TokAssign).unwrap()) {
-    Ok((tokens2, e)) => Ok((tokens2, PyType::Stmt(Stmt::VarAssign(var_name.clone(), Box::from(e))))),
+// What kind of expression a node is, for errors about using it as an assignment or del target.
+// None for the names, subscripts and attributes that can be targets
+fn target_error_kind(target: &PyType) -> Option<&'static str> {
+  match target {
+    PyType::Expr(Expr::Var(_) | Expr::Subscript(..) | Expr::Attribute(..)) => None,
+    PyType::Expr(Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::String(_) | Expr::Bytes(_) | Expr::FString(_) | Expr::None) => Some("literal"),
+    PyType::Expr(Expr::FunctionCall(..)) => Some("function call"),
+    PyType::Expr(Expr::Tuple(_)) => Some("tuple"),
+    PyType::Expr(Expr::List(_)) => Some("list"),
+    PyType::Expr(Expr::Compare(..)) => Some("comparison"),
+    PyType::Expr(Expr::Ternary(..)) => Some("conditional expression"),
+    PyType::Expr(Expr::Yield(_) | Expr::YieldFrom(_)) => Some("yield expression"),
+    _ => Some("expression"),
+  }
+}
+
+// AssignStatement -> (Target =)+ (Expr | YieldExpr)
+// Parses from the first =, given the first target. Each target is assigned the value in turn
+fn parse_assign(mut tokens: &[Token], first: PyType) -> Result<(&[Token], PyType), String> {
+  let mut targets = vec![first];
+  loop {
+    if let Some(kind) = target_error_kind(targets.last().unwrap()) {
+      return Err(format!("SyntaxError: cannot assign to {} here. Maybe you meant '==' instead of '='?", kind));
+    }
+
+    let tokens2 = match_token(tokens, &Token::TokAssign).unwrap();
+    let (tokens3, e) = match lookahead(tokens2) {
+      Some(Token::TokYield) => parse_yield(match_token(tokens2, &Token::TokYield).unwrap())?,
+      _ => parse_expr(tokens2)?
+    };
+    if lookahead(tokens3) != Some(&Token::TokAssign) {
+      return Ok((tokens3, PyType::Stmt(Stmt::Assign(targets, Box::from(e)))));
+    }
+    targets.push(e);
+    tokens = tokens3;
+  }
+}
+
+// AugAssignStatement -> Target AugOperator (Expr | YieldExpr)
+fn parse_aug_assign(tokens: &[Token], target: PyType, op: Op) -> Result<(&[Token], PyType), String> {
+  if let Some(kind) = target_error_kind(&target) {
+    return Err(format!("SyntaxError: '{}' is an illegal expression for augmented assignment", kind));
+  }
+
+  let value = match lookahead(tokens) {
+    Some(Token::TokYield) => parse_yield(match_token(tokens, &Token::TokYield).unwrap()),
+    _ => parse_expr(tokens)
+  };
+  match value {
+    Ok((tokens2, e)) => Ok((tokens2, PyType::Stmt(Stmt::AugAssign(Box::from(target), op, Box::from(e))))),
     Err(e) => Err(e)
   }
 }
 
-fn parse_function(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut Vec<i32>) -> Result<(Vec<Token>, PyType), String> {
-  indent_stack.push(*prev_indent);
+// DelStatement -> del Target (, Target)* ,?
+fn parse_del(mut tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  let mut targets = Vec::new();
+  loop {
+    let (tokens2, target) = parse_expr(tokens)?;
+    if let Some(kind) = target_error_kind(&target) {
+      return Err(format!("SyntaxError: cannot delete {}", kind));
+    }
+    targets.push(target);
+    tokens = tokens2;
+
+    match (lookahead(tokens), lookahead_at(tokens, 1)) {
+      (Some(Token::TokComma), None) => return Ok((&tokens[1..], PyType::Stmt(Stmt::Del(targets)))),
+      (Some(Token::TokComma), _) => tokens = &tokens[1..],
+      _ => return Ok((tokens, PyType::Stmt(Stmt::Del(targets))))
+    }
+  }
+}
+
+// AssertStatement -> assert Expr (, Expr)?
+fn parse_assert(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  if tokens.is_empty() {
+    return Err("SyntaxError: invalid syntax".to_string());
+  }
+  let (tokens2, condition) = parse_expr(tokens)?;
+  match (lookahead(tokens2), lookahead_at(tokens2, 1)) {
+    (Some(Token::TokComma), None) => Err("SyntaxError: invalid syntax".to_string()),
+    (Some(Token::TokComma), _) => match parse_expr(&tokens2[1..]) {
+      Ok((tokens3, message)) => Ok((tokens3, PyType::Stmt(Stmt::Assert(Box::from(condition), Some(Box::from(message)))))),
+      Err(e) => Err(e)
+    },
+    _ => Ok((tokens2, PyType::Stmt(Stmt::Assert(Box::from(condition), None))))
+  }
+}
+
+// ImportStatement -> import TokVar
+// Binds the name to the module, as the assignment name = __import__('name') would
+fn parse_import(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match lookahead(tokens) {
+    Some(Token::TokVar(name)) => {
+      let import = PyType::Expr(Expr::Var("__import__".to_string()));
+      let call = Expr::FunctionCall(Box::from(import), vec![PyType::Expr(Expr::String(name.to_string()))], Vec::new());
+      Ok((&tokens[1..], PyType::Stmt(Stmt::Assign(vec![PyType::Expr(Expr::Var(name.to_string()))], Box::from(PyType::Expr(call))))))
+    }
+    _ => Err("SyntaxError: invalid syntax".to_string()),
+  }
+}
+
+// The names of a global or nonlocal declaration: TokVar (, TokVar)*
+fn parse_names(mut tokens: &[Token]) -> Result<(&[Token], Vec<String>), String> {
+  let mut names = Vec::new();
+  loop {
+    match lookahead(tokens) {
+      Some(Token::TokVar(name)) => names.push(name.to_string()),
+      _ => return Err("SyntaxError: invalid syntax".to_string()),
+    }
+    match lookahead_at(tokens, 1) {
+      Some(Token::TokComma) => tokens = &tokens[2..],
+      _ => return Ok((&tokens[1..], names)),
+    }
+  }
+}
+
+// YieldExpr -> yield Expr? | yield from Expr
+fn parse_yield(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match lookahead(tokens) {
+    Some(Token::TokFrom) => match parse_expr(match_token(tokens, &Token::TokFrom).unwrap()) {
+      Ok((tokens2, e)) => Ok((tokens2, PyType::Expr(Expr::YieldFrom(Box::from(e))))),
+      Err(e) => Err(e)
+    },
+    // A bare yield yields None
+    None | Some(Token::TokRParen) => Ok((tokens, PyType::Expr(Expr::Yield(None)))),
+    _ => match parse_expr(tokens) {
+      Ok((tokens2, e)) => Ok((tokens2, PyType::Expr(Expr::Yield(Some(Box::from(e)))))),
+      Err(e) => Err(e)
+    }
+  }
+}
+
+fn parse_return(tokens: &[Token], in_function: bool) -> Result<(&[Token], PyType), String> {
+  if !in_function {
+    return Err("SyntaxError: 'return' outside function".to_string());
+  }
+
+  match lookahead(tokens) {
+    // A bare return returns None
+    None => Ok((tokens, PyType::Stmt(Stmt::Return(Box::from(PyType::Expr(Expr::None)))))),
+    _ => match parse_expr(tokens) {
+      Ok((tokens2, e)) => Ok((tokens2, PyType::Stmt(Stmt::Return(Box::from(e))))),
+      Err(e) => Err(e)
+    }
+  }
+}
 
+fn parse_function(tokens: &[Token], source: &mut LineSource) -> Result<(Vec<Token>, PyType), String> {
   // Parse function header
   match lookahead(tokens) {
     Some(Token::TokVar(func_name)) => {
       // Parse parameters
-      let tokens2 = match_token(&match_token(&tokens, &Token::TokVar(func_name.to_string())).unwrap(), &&Token::TokLParen).unwrap(); // match name and (
-      
-      match parse_parameters(&tokens2, &mut Vec::new()) {
-        Ok((tokens3, parameters)) if tokens3.is_empty() => {
-          // Read indent and first line, then parse rest
-          match read_body_line(prev_indent) {
-            Ok((line_tokens, indentation)) => {
-              *prev_indent = indentation;
-              match lookahead(&line_tokens) {
-                Some(Token::TokIndent(n)) => {
-                  // Parse body of function
-                  let mut body_contents = Vec::<PyType>::new();
-                  match parse_body(&match_token(&line_tokens, &Token::TokIndent(*n)).unwrap(), prev_indent, &mut body_contents, indent_stack, true) {
-                    Ok((tokens4, _)) => Ok((tokens4, PyType::Stmt(Stmt::Function(func_name.to_string(), parameters, body_contents.to_vec())))),
-                    Err(e) => Err(e)
-                  }
-                }, 
-                _ => Err("IndentationError: expected indent".to_string())
-              }
-            },
+      let tokens2 = match_token(&tokens[1..], &Token::TokLParen)?;
+
+      match parse_parameters(tokens2) {
+        Ok((tokens3, parameters)) => {
+          // Parse body of function
+          match parse_block(tokens3, source, "function definition", true) {
+            Ok((tokens4, body)) => Ok((tokens4, PyType::Stmt(Stmt::Function(func_name.to_string(), parameters, body)))),
             Err(e) => Err(e)
           }
         },
-        Ok(_) => Err("SyntaxError: incorrect syntax".to_string()),
         Err(e) => Err(e)
       }
     },
@@ -93,111 +411,310 @@ fn parse_function(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut
   }
 }
 
-fn parse_parameters(tokens: &Vec<Token>, parameters: &mut Vec<String>) -> Result<(Vec<Token>, Vec<String>), String> {
-  // ((TokVar TokComma)* TokVar? TokRParen Tok Colon
-  match lookahead(tokens) {
-    // Match parameter
-    Some(Token::TokVar(p)) => {
-      parameters.push(p.to_string());
-      let tokens2 = match_token(&tokens, &Token::TokVar(p.to_string())).unwrap();
-      match lookahead(&tokens2) {
-        // End of parameters
-        Some(Token::TokRParen) => {
-          let tokens3 = match_token(&tokens2, &Token::TokRParen).unwrap();
-          match lookahead(&tokens3) {
-            Some(Token::TokColon) => Ok((match_token(&tokens3, &Token::TokColon).unwrap(), parameters.to_vec())),
-            _ => Err("SyntaxError: function header missing \":\"".to_string())
-          }
-        },
-        // Another parameter 
-        Some(Token::TokComma) => parse_parameters(&match_token(&tokens2, &Token::TokComma).unwrap(), parameters),
+// Checks the global and nonlocal declarations of a function and of the functions nested in it, in
+// two passes like Python's: first that each declaration comes before the name is otherwise used,
+// then that the declared names can be resolved
+fn check_declarations(function: &PyType) -> Result<(), String> {
+  check_declaration_order(function)?;
+  check_nonlocal_bindings(function, &[])
+}
+
+// What a function body has done with each name so far
+struct SeenNames {
+  parameters: Vec<String>,
+  used: Vec<String>,
+  assigned: Vec<String>,
+}
 
-        _ => Err("SyntaxError: incorrect syntax in function header".to_string())
+fn check_declaration_order(function: &PyType) -> Result<(), String> {
+  if let PyType::Stmt(Stmt::Function(_, parameters, body)) = function {
+    let mut seen = SeenNames { parameters: parameters.names(), used: Vec::new(), assigned: Vec::new() };
+    check_body_order(body, &mut seen)?;
+  }
+  Ok(())
+}
+
+fn check_body_order(body: &[Statement], seen: &mut SeenNames) -> Result<(), String> {
+  for statement in body {
+    match &statement.node {
+      PyType::Stmt(Stmt::Global(names)) => check_declared(names, "global", seen)?,
+      PyType::Stmt(Stmt::Nonlocal(names)) => check_declared(names, "nonlocal", seen)?,
+      PyType::Stmt(Stmt::If(condition, body, else_body)) => {
+        read_names(condition, &mut seen.used);
+        check_body_order(body, seen)?;
+        if let Some(else_body) = else_body {
+          check_body_order(else_body, seen)?;
+        }
       }
-    },
+      PyType::Stmt(Stmt::While(condition, body)) => {
+        read_names(condition, &mut seen.used);
+        check_body_order(body, seen)?;
+      }
+      PyType::Stmt(Stmt::For(var, iterable, body)) => {
+        read_names(iterable, &mut seen.used);
+        push_unique(&mut seen.assigned, var);
+        check_body_order(body, seen)?;
+      }
+      node => {
+        read_names(node, &mut seen.used);
+        bound_names(std::slice::from_ref(statement), &mut seen.assigned);
+        // A nested function's body is a scope of its own
+        check_declaration_order(node)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn check_declared(names: &[String], kind: &str, seen: &SeenNames) -> Result<(), String> {
+  for name in names {
+    if seen.parameters.contains(name) {
+      return Err(format!("SyntaxError: name '{}' is parameter and {}", name, kind));
+    } else if seen.used.contains(name) {
+      return Err(format!("SyntaxError: name '{}' is used prior to {} declaration", name, kind));
+    } else if seen.assigned.contains(name) {
+      return Err(format!("SyntaxError: name '{}' is assigned to before {} declaration", name, kind));
+    }
+  }
+  Ok(())
+}
 
-    // End of parameters
-    Some(Token::TokRParen) => {
-      let tokens2 = match_token(&tokens, &Token::TokRParen).unwrap();
-      match lookahead(&tokens2) {
-        Some(Token::TokColon) => Ok((match_token(&tokens2, &Token::TokColon).unwrap(), parameters.to_vec())),
-        _ => Err("SyntaxError: function header missing \":\"".to_string())
+// Names a node reads in the scope it runs in. Nested functions and comprehensions only read their
+// defaults and first iterable there, and the names a statement assigns or deletes aren't read
+fn read_names(node: &PyType, names: &mut Vec<String>) {
+  let is_name = |target: &&PyType| matches!(target, PyType::Expr(Expr::Var(_)));
+  match node {
+    PyType::Expr(Expr::Var(v)) => push_unique(names, v),
+    PyType::Expr(Expr::Comprehension(_, _, clauses)) => read_names(clauses[0].node(), names),
+    PyType::Stmt(Stmt::Function(_, parameters, _)) => parameters.defaults().for_each(|default| read_names(default, names)),
+    PyType::Stmt(Stmt::Assign(targets, e)) => {
+      targets.iter().filter(|target| !is_name(target)).for_each(|target| read_names(target, names));
+      read_names(e, names);
+    }
+    PyType::Stmt(Stmt::Del(targets)) => targets.iter().filter(|target| !is_name(target)).for_each(|target| read_names(target, names)),
+    PyType::Stmt(Stmt::AugAssign(target, _, e)) => {
+      if !is_name(&&**target) {
+        read_names(target, names);
       }
-    },
-    _ => Err("SyntaxError: incorrect syntax in function header".to_string())
+      read_names(e, names);
+    }
+    _ => node.children().into_iter().for_each(|child| read_names(child, names)),
+  }
+}
+
+// Checks that every name a function declares nonlocal is bound by a function around it, given the
+// names those functions bind. A name declared both global and nonlocal is an error as well
+fn check_nonlocal_bindings(function: &PyType, enclosing: &[String]) -> Result<(), String> {
+  let (parameters, body) = match function {
+    PyType::Stmt(Stmt::Function(_, parameters, body)) => (parameters, body),
+    _ => return Ok(()),
+  };
+  let declarations = Declarations::of(body);
+  for name in &declarations.nonlocals {
+    if declarations.is_global(name) {
+      return Err(format!("SyntaxError: name '{}' is nonlocal and global", name));
+    } else if !enclosing.contains(name) {
+      return Err(format!("SyntaxError: no binding for nonlocal '{}' found", name));
+    }
+  }
+
+  // The functions nested in this one can also refer to its own names, and to the nonlocal ones it
+  // shares with the functions around it, but not to those it declares global
+  let mut bound: Vec<String> = enclosing.iter().filter(|name| !declarations.is_global(name)).cloned().collect();
+  for name in local_names(parameters, body).iter().chain(&declarations.nonlocals) {
+    push_unique(&mut bound, name);
+  }
+  nested_functions(body).into_iter().try_for_each(|nested| check_nonlocal_bindings(nested, &bound))
+}
+
+// The functions defined directly in a body, including inside its if statements and loops
+fn nested_functions(body: &[Statement]) -> Vec<&PyType> {
+  let mut functions = Vec::new();
+  for statement in body {
+    match &statement.node {
+      PyType::Stmt(Stmt::Function(..)) => functions.push(&statement.node),
+      PyType::Stmt(Stmt::If(_, body, else_body)) => {
+        functions.extend(nested_functions(body));
+        functions.extend(else_body.iter().flat_map(|else_body| nested_functions(else_body)));
+      }
+      PyType::Stmt(Stmt::While(_, body)) | PyType::Stmt(Stmt::For(_, _, body)) => functions.extend(nested_functions(body)),
+      _ => {}
+    }
+  }
+  functions
+}
+
+// Parameters up to and including the closing parenthesis, each one of
+//   TokVar (= Expr)? | TokDiv | TokMult TokVar? | TokDoubleStar TokVar
+// where / ends the positional-only parameters, * or *args starts the keyword-only ones and
+// **kwargs comes last
+fn parse_parameters(mut tokens: &[Token]) -> Result<(&[Token], Parameters), String> {
+  let mut parameters = Parameters { positional: Vec::new(), posonly: 0, varargs: None, kwonly: Vec::new(), varkw: None };
+  let mut slash = false;
+  let mut star = false;
+
+  loop {
+    match lookahead(tokens) {
+      // End of parameters
+      Some(Token::TokRParen) => break,
+      _ if parameters.varkw.is_some() => return Err("SyntaxError: arguments cannot follow var-keyword argument".to_string()),
+
+      // End of positional-only parameters
+      Some(Token::TokDiv) => {
+        if slash {
+          return Err("SyntaxError: / may appear only once".to_string());
+        } else if star {
+          return Err("SyntaxError: / must be ahead of *".to_string());
+        } else if parameters.positional.is_empty() {
+          return Err("SyntaxError: at least one argument must precede /".to_string());
+        }
+        slash = true;
+        parameters.posonly = parameters.positional.len();
+        tokens = &tokens[1..];
+      },
+
+      // * or *args
+      Some(Token::TokMult) => {
+        if star {
+          return Err("SyntaxError: * argument may appear only once".to_string());
+        }
+        star = true;
+        tokens = &tokens[1..];
+        if let Some(Token::TokVar(name)) = lookahead(tokens) {
+          check_parameter(&parameters, name)?;
+          parameters.varargs = Some(name.clone());
+          tokens = &tokens[1..];
+        }
+      },
+
+      // **kwargs
+      Some(Token::TokDoubleStar) => match lookahead_at(tokens, 1) {
+        Some(Token::TokVar(name)) => {
+          check_parameter(&parameters, name)?;
+          parameters.varkw = Some(name.clone());
+          tokens = &tokens[2..];
+        },
+        _ => return Err("SyntaxError: incorrect syntax in function header".to_string())
+      },
+
+      // Named parameter with an optional default
+      Some(Token::TokVar(name)) => {
+        let name = name.clone();
+        check_parameter(&parameters, &name)?;
+        let default = match lookahead_at(tokens, 1) {
+          Some(Token::TokAssign) => {
+            let (tokens2, default) = parse_expr(&tokens[2..])?;
+            tokens = tokens2;
+            Some(default)
+          },
+          _ => {
+            tokens = &tokens[1..];
+            None
+          }
+        };
+
+        if star {
+          parameters.kwonly.push((name, default));
+        } else {
+          if default.is_none() && matches!(parameters.positional.last(), Some((_, Some(_)))) {
+            return Err("SyntaxError: non-default argument follows default argument".to_string());
+          }
+          parameters.positional.push((name, default));
+        }
+      },
+
+      _ => return Err("SyntaxError: incorrect syntax in function header".to_string())
+    }
+
+    match lookahead(tokens) {
+      Some(Token::TokRParen) => {},
+      Some(Token::TokComma) => tokens = match_token(tokens, &Token::TokComma).unwrap(),
+      _ => return Err("SyntaxError: incorrect syntax in function header".to_string())
+    }
+  }
+
+  if star && parameters.varargs.is_none() && parameters.kwonly.is_empty() {
+    return Err("SyntaxError: named arguments must follow bare *".to_string());
+  }
+  Ok((match_token(tokens, &Token::TokRParen).unwrap(), parameters))
+}
+
+fn check_parameter(parameters: &Parameters, name: &str) -> Result<(), String> {
+  if parameters.names().iter().any(|n| n == name) {
+    return Err(format!("SyntaxError: duplicate argument '{}' in function definition", name));
   }
+  Ok(())
 }
 
 // Returns expression of if statement condition and list of expressions in body
-fn parse_if(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut Vec<i32>, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
-  indent_stack.push(*prev_indent);
+fn parse_if(tokens: &[Token], source: &mut LineSource, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
   match parse_expr(tokens) {
     // Condition of if statement
     Ok((tokens2, condition)) => {
-      match match_token(&tokens2, &Token::TokColon) {
-        Ok(tokens3) if tokens3.is_empty() => {
-          // Read first line of body under if statement and tokenize
-          match read_body_line(prev_indent) {
-            Ok((body_tokens, indentation)) => {
-              *prev_indent = indentation;
-              match lookahead(&body_tokens) {
-                // Get indent for first line of body
-                Some(Token::TokIndent(n)) => {
-
-                  // Parsing body of if statement
-                  let mut body_contents = Vec::<PyType>::new();
-                  match parse_body(&match_token(&body_tokens, &Token::TokIndent(*n)).unwrap(), prev_indent, &mut body_contents, indent_stack, in_function) {
-                    Ok((tokens4, _)) => {
-
-                      // Check if there's an else statement to parse
-                      if lookahead(&tokens4) == Some(&Token::TokElse) {
-                        match parse_else(&match_token(&tokens4, &Token::TokElse).unwrap(), prev_indent, indent_stack, in_function) {
-                          Ok((tokens5, else_body)) => return Ok((tokens5, PyType::Stmt(Stmt::If(Box::from(condition), body_contents, Some(else_body))))),
-                          Err(e) => return Err(e)
-                        }
-                      }
-                      else {
-                        return Ok((tokens4, PyType::Stmt(Stmt::If(Box::from(condition), body_contents, None))))
-                      }
-                    },
-                    Err(e) => Err(e)
-                  }
-                },
-                _ => Err("Indentation Error: expected indent".to_string())
-              }
-            },
-            Err(e) => Err(e)
+      match parse_block(tokens2, source, "'if' statement", in_function) {
+        Ok((tokens3, body)) => {
+          // Check if there's an else statement to parse
+          if lookahead(&tokens3) == Some(&Token::TokElse) {
+            match parse_block(match_token(&tokens3, &Token::TokElse).unwrap(), source, "'else' statement", in_function) {
+              Ok((tokens4, else_body)) => Ok((tokens4, PyType::Stmt(Stmt::If(Box::from(condition), body, Some(else_body))))),
+              Err(e) => Err(e)
+            }
+          }
+          else {
+            Ok((tokens3, PyType::Stmt(Stmt::If(Box::from(condition), body, None))))
           }
         },
-        Err(e) => Err(e),
-        _ => Err("SyntaxError: expected new line after ':'".to_string()) // if more tokens after :
+        Err(e) => Err(e)
+      }
+    },
+    Err(e) => Err(e)
+  }
+}
+
+// Returns the condition of a while loop and the statements in its body
+fn parse_while(tokens: &[Token], source: &mut LineSource, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
+  match parse_expr(tokens) {
+    Ok((tokens2, condition)) => {
+      match parse_block(tokens2, source, "'while' statement", in_function) {
+        Ok((tokens3, body)) => Ok((tokens3, PyType::Stmt(Stmt::While(Box::from(condition), body)))),
+        Err(e) => Err(e)
       }
     },
     Err(e) => Err(e)
   }
 }
 
-fn parse_else(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut Vec<i32>, in_function: bool) -> Result<(Vec<Token>, Vec<PyType>), String> {
-  indent_stack.push(*prev_indent); // not sure if this is right
-  
+// Returns the loop variable and iterable of a for loop and the statements in its body
+fn parse_for(tokens: &[Token], source: &mut LineSource, in_function: bool) -> Result<(Vec<Token>, PyType), String> {
+  match (lookahead(tokens), lookahead_at(tokens, 1)) {
+    (Some(Token::TokVar(var)), Some(Token::TokIn)) => {
+      match parse_expr(&tokens[2..]) {
+        Ok((tokens2, iterable)) => {
+          match parse_block(tokens2, source, "'for' statement", in_function) {
+            Ok((tokens3, body)) => Ok((tokens3, PyType::Stmt(Stmt::For(var.to_string(), Box::from(iterable), body)))),
+            Err(e) => Err(e)
+          }
+        },
+        Err(e) => Err(e)
+      }
+    },
+    _ => Err("SyntaxError: expected 'for <name> in <expression>:'".to_string())
+  }
+}
+
+// Parses the ':' ending a statement header followed by the indented block under it, returning the
+// tokens left on the line that closed the block
+fn parse_block(tokens: &[Token], source: &mut LineSource, header: &str, in_function: bool) -> Result<(Vec<Token>, Vec<Statement>), String> {
+  let header_line = source.line();
+
   match match_token(tokens, &Token::TokColon) {
-    Ok(tokens2) if tokens2.is_empty() => {
+    Ok([]) => {
       // Read first line of body and tokenize
-      match read_body_line(prev_indent) {
-        Ok((body_tokens, indentation)) => {
-          *prev_indent = indentation;
+      match read_body_line(source) {
+        Ok(body_tokens) => {
           match lookahead(&body_tokens) {
-            Some(Token::TokIndent(n)) => {
-              // Parse rest of body
-              let mut body_contents = Vec::<PyType>::new();
-              match parse_body(&match_token(&body_tokens, &Token::TokIndent(*n)).unwrap(), prev_indent, &mut body_contents, indent_stack, in_function) {
-                Ok((tokens3, _)) => {
-                  Ok((tokens3, body_contents))
-                },
-                Err(e) => Err(e)
-              }
-            }
-            _ => Err("Indentation Error: expected indent".to_string())
+            Some(Token::TokIndent) => parse_body(&body_tokens[1..], source, in_function),
+            _ => Err(format!("IndentationError: expected an indented block after {} on line {}", header, header_line))
           }
         },
         Err(e) => Err(e)
@@ -205,112 +722,66 @@ fn parse_else(tokens: &Vec<Token>, prev_indent: &mut i32, indent_stack: &mut Vec
     },
     Err(e) => Err(e),
     _ => Err("SyntaxError: expected new line after ':'".to_string()) // if more tokens after :
-
   }
 }
 
+// Parses the statements of an indented block, up to the DEDENT that closes it
+fn parse_body(tokens: &[Token], source: &mut LineSource, in_function: bool) -> Result<(Vec<Token>, Vec<Statement>), String> {
+  let mut body = Vec::new();
+  let mut tokens = tokens.to_vec();
 
-// Parses a closure (determined by indents in Python), returning a list of the expressions in it
-fn parse_body(tokens: &Vec<Token>, prev_indent: &mut i32, body_contents: &mut Vec<PyType>, indent_stack: &mut Vec<i32>, in_function: bool) -> Result<(Vec<Token>, Vec<PyType>), String> {
-
-  let mut is_return = false;
-  let mut tokens = tokens.clone();
-
-  // Deal with return expression if found
-  if lookahead(&tokens) == Some(&Token::TokReturn) {
-    if !in_function {
-      return Err("SyntaxError: 'return' outside function".to_string());
+  loop {
+    tokens = parse_line(tokens, source, in_function, &mut body)?;
+    match lookahead(&tokens) {
+      Some(Token::TokDedent) => return Ok((tokens[1..].to_vec(), body)),
+      _ => {
+        tokens = read_body_line(source)?;
+        if tokens.is_empty() {
+          return Err("SyntaxError: unexpected EOF while parsing".to_string());
+        }
+      }
     }
-    is_return = true;
-    tokens = match_token(&tokens, &Token::TokReturn).unwrap();
-  } 
-
-  match parse(&tokens, prev_indent, indent_stack, in_function) {
-    Ok((tokens2, parsed_line)) => {
-      match lookahead(&tokens2) {
-        Some(Token::TokDedent(n)) => {
-          if is_return {
-            body_contents.push(PyType::Expr(Expr::Return(Box::from(parsed_line))));
-          } else {
-            body_contents.push(parsed_line);
-          }
-        
-          let peek = indent_stack.last().unwrap();
-          // Current line was unindented to return to the current scope
-          if *n == *peek {
-            indent_stack.pop();
-            // Check if outer-most scope was exited
-            if indent_stack.is_empty() {
-              return Ok((match_token(&tokens2, &Token::TokDedent(*n)).unwrap(), body_contents.to_vec()));
-            }
-            return parse_body(&match_token(&tokens2, &Token::TokDedent(*n)).unwrap(), prev_indent, body_contents, indent_stack, in_function);
-          } else if *n < *peek { 
-            // Current line is unindented past this current level, so return to get to outside scope
-            indent_stack.pop();
-            // Check if outer-most scope was exited
-            if *indent_stack.last().unwrap() == 0 { 
-              indent_stack.pop();
-              return Ok((match_token(&tokens2, &Token::TokDedent(*n)).unwrap(), body_contents.to_vec()));
-            }
-            return Ok((tokens2, body_contents.to_vec()));
-          } else {
-            return Err(format!("IndentationError: unindent of size {} does not match any outer indentation level", n));
-          }
-        },
-        Some(_) => Err(format!("SyntaxError: not all tokens from previous line were parsed")),
-        None => {
-          if is_return {
-            body_contents.push(PyType::Expr(Expr::Return(Box::from(parsed_line))));
-          } else {
-            body_contents.push(parsed_line);
-          }
+  }
+}
 
-          match read_body_line(prev_indent) {
-            Ok((next_line, indentation)) => {
-              *prev_indent = indentation;
-        
-              // need to match dedents here since recursive call causes tokens to be parsed
-              match lookahead(&next_line) {
-        
-                // Input is unindented, exit to outer scope
-                Some(Token::TokDedent(n)) => {
-                  let peek = indent_stack.last().unwrap();
-        
-                  if *n == *peek {
-                    if *peek == 0 { 
-                      indent_stack.pop();
-                      return Ok((match_token(&next_line, &Token::TokDedent(*n)).unwrap(), body_contents.to_vec()));
-                    }
-                    return Ok((next_line, body_contents.to_vec()));
-                  } else if *n < *peek {
-                    return Ok((next_line, body_contents.to_vec()));
+fn parse_expr(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match parse_or(tokens) {
+    Ok((tokens2, or_expr)) => {
+      match lookahead(tokens2) {
+        // OrExpr if OrExpr else Expr
+        Some(Token::TokIf) => {
+          match parse_or(match_token(tokens2, &Token::TokIf).unwrap()) {
+            Ok((tokens3, condition)) => {
+              match match_token(tokens3, &Token::TokElse) {
+                Ok(tokens4) => {
+                  match parse_expr(tokens4) {
+                    Ok((tokens5, else_expr)) => {
+                      Ok((tokens5, PyType::Expr(Expr::Ternary(Box::from(condition), Box::from(or_expr), Box::from(else_expr)))))
+                    },
+                    Err(e) => Err(e)
                   }
-                  return Err(format!("IndentationError: unindent of size {} does not match any outer indentation level", n));
-        
                 },
-                // Line on same level was entered, parse it and continue on this level
-                _ => parse_body(&next_line, prev_indent, body_contents, indent_stack, in_function)
-                // None => {
-                //   return Err(format!("Syntax Error: nothing entered"));
-                // }
-              } 
+                Err(_) => Err("SyntaxError: expected 'else' after 'if' expression".to_string())
+              }
             },
             Err(e) => Err(e)
           }
-        }
+        },
+        // OrExpr
+        _ => Ok((tokens2, or_expr))
       }
     },
     Err(e) => Err(e)
   }
 }
 
-fn parse_expr(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
+fn parse_or(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
   match parse_and(tokens) {
     Ok((tokens2, and_expr)) => {
-      match lookahead(&tokens2) {
+      match lookahead(tokens2) {
         // AndExpr or OrExpr
         Some(Token::TokOr) => {
-          match parse_expr(&match_token(&tokens2, &Token::TokOr).unwrap()) {
+          match parse_or(match_token(tokens2, &Token::TokOr).unwrap()) {
             Ok((tokens3, or_expr)) => {
               Ok((tokens3, PyType::Expr(Expr::Binop(Op::Or, Box::from(and_expr), Box::from(or_expr)))))
             },
@@ -325,184 +796,195 @@ fn parse_expr(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
   }
 }
 
-fn parse_and(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match parse_equality(tokens) {
-    Ok((tokens2, equality_expr)) => {
-      match lookahead(&tokens2) {
-        // EqualityExpr and AndExpr
+fn parse_and(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match parse_not(tokens) {
+    Ok((tokens2, not_expr)) => {
+      match lookahead(tokens2) {
+        // NotExpr and AndExpr
         Some(Token::TokAnd) => {
-          match parse_and(&match_token(&tokens2, &Token::TokAnd).unwrap()) {
+          match parse_and(match_token(tokens2, &Token::TokAnd).unwrap()) {
             Ok((tokens3, and_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::And, Box::from(equality_expr), Box::from(and_expr)))))
+              Ok((tokens3, PyType::Expr(Expr::Binop(Op::And, Box::from(not_expr), Box::from(and_expr)))))
             },
             Err(e) => Err(e)
           }
         },
-        // EqualityExpr
-        _ => Ok((tokens2, equality_expr))
+        // NotExpr
+        _ => Ok((tokens2, not_expr))
       }
     },
     Err(e) => Err(e)
   }
 }
 
-fn parse_equality(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match parse_relational(tokens) {
-    Ok((tokens2, relational_expr)) => {
-      match lookahead(&tokens2) {
-        // RelationalExpr == EqualityExpr
-        Some(Token::TokDoubleEqual) => {
-          match parse_equality(&match_token(&tokens2, &Token::TokDoubleEqual).unwrap()) {
-            Ok((tokens3, equality_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::Equal, Box::from(relational_expr), Box::from(equality_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
-        // RelationalExpr != EqualityExpr
-        Some(Token::TokNotEqual) => {
-          match parse_equality(&match_token(&tokens2, &Token::TokNotEqual).unwrap()) {
-            Ok((tokens3, equality_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::NotEqual, Box::from(relational_expr), Box::from(equality_expr)))))
-            },
-            Err(e) => Err(e)
-          }
+fn parse_not(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match lookahead(tokens) {
+    // TokNot NotExpr
+    Some(Token::TokNot) => {
+      match parse_not(match_token(tokens, &Token::TokNot).unwrap()) {
+        Ok((tokens2, not_expr)) => {
+          Ok((tokens2, PyType::Expr(Expr::Not(Box::from(not_expr)))))
         },
-        // RelationalExpr
-        _ => Ok((tokens2, relational_expr))
+        Err(e) => Err(e)
       }
     },
-    Err(e) => Err(e)
+
+    // ComparisonExpr
+    _ => parse_comparison(tokens)
   }
 }
 
-fn parse_relational(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match parse_additive(tokens) {
+fn parse_comparison(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match parse_bitor(tokens) {
     Ok((tokens2, additive_expr)) => {
-      match lookahead(&tokens2) {
-        // AdditiveExpr < RelationalExpr
-        Some(Token::TokLess) => {
-          match parse_relational(&match_token(&tokens2, &Token::TokLess).unwrap()) {
-            Ok((tokens3, relational_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::Less, Box::from(additive_expr), Box::from(relational_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
-        // AdditiveExpr > RelationalExpr
-        Some(Token::TokGreater) => {
-          match parse_relational(&match_token(&tokens2, &Token::TokGreater).unwrap()) {
-            Ok((tokens3, relational_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::Greater, Box::from(additive_expr), Box::from(relational_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
-        // AdditiveExpr <= RelationalExpr
-        Some(Token::TokLessEqual) => {
-          match parse_relational(&match_token(&tokens2, &Token::TokLessEqual).unwrap()) {
-            Ok((tokens3, relational_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::LessEqual, Box::from(additive_expr), Box::from(relational_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
-        // AdditiveExpr >= RelationalExpr
-        Some(Token::TokGreaterEqual) => {
-          match parse_relational(&match_token(&tokens2, &Token::TokGreaterEqual).unwrap()) {
-            Ok((tokens3, relational_expr)) => {
-              Ok((tokens3, PyType::Expr(Expr::Binop(Op::GreaterEqual, Box::from(additive_expr), Box::from(relational_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
-        // AdditiveExpr
-        _ => Ok((tokens2, additive_expr))
+      // Collect every (operator, operand) pair so a < b < c is evaluated as a < b and b < c
+      let mut tokens2 = tokens2;
+      let mut comparisons = Vec::<(Op, PyType)>::new();
+
+      while let Some((op, op_len)) = comparison_op(tokens2) {
+        match parse_bitor(&tokens2[op_len..]) {
+          Ok((tokens3, operand)) => {
+            comparisons.push((op, operand));
+            tokens2 = tokens3;
+          },
+          Err(e) => return Err(e)
+        }
+      }
+
+      if comparisons.is_empty() {
+        // BitOrExpr
+        Ok((tokens2, additive_expr))
+      } else {
+        // BitOrExpr (ComparisonOperator BitOrExpr)+
+        Ok((tokens2, PyType::Expr(Expr::Compare(Box::from(additive_expr), comparisons))))
       }
     },
     Err(e) => Err(e)
   }
 }
 
-fn parse_additive(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
+// Returns the comparison operator at the front of tokens and how many tokens it spans
+fn comparison_op(tokens: &[Token]) -> Option<(Op, usize)> {
+  match (lookahead(tokens), lookahead_at(tokens, 1)) {
+    (Some(Token::TokDoubleEqual), _) => Some((Op::Equal, 1)),
+    (Some(Token::TokNotEqual), _) => Some((Op::NotEqual, 1)),
+    (Some(Token::TokLess), _) => Some((Op::Less, 1)),
+    (Some(Token::TokGreater), _) => Some((Op::Greater, 1)),
+    (Some(Token::TokLessEqual), _) => Some((Op::LessEqual, 1)),
+    (Some(Token::TokGreaterEqual), _) => Some((Op::GreaterEqual, 1)),
+    (Some(Token::TokIn), _) => Some((Op::In, 1)),
+    (Some(Token::TokNot), Some(Token::TokIn)) => Some((Op::NotIn, 2)),
+    (Some(Token::TokIs), Some(Token::TokNot)) => Some((Op::IsNot, 2)),
+    (Some(Token::TokIs), _) => Some((Op::Is, 1)),
+    _ => None
+  }
+}
+
+// BitOrExpr -> BitOrExpr TokPipe BitXorExpr | BitXorExpr
+fn parse_bitor(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  parse_left_assoc(tokens, &[(Token::TokPipe, Op::BitOr)], parse_bitxor)
+}
+
+// BitXorExpr -> BitXorExpr ^ BitAndExpr | BitAndExpr
+fn parse_bitxor(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  parse_left_assoc(tokens, &[(Token::TokCaret, Op::BitXor)], parse_bitand)
+}
+
+// BitAndExpr -> BitAndExpr & ShiftExpr | ShiftExpr
+fn parse_bitand(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  parse_left_assoc(tokens, &[(Token::TokAmpersand, Op::BitAnd)], parse_shift)
+}
+
+// ShiftExpr -> ShiftExpr (<< | >>) AdditiveExpr | AdditiveExpr
+fn parse_shift(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  parse_left_assoc(tokens, &[(Token::TokLeftShift, Op::LShift), (Token::TokRightShift, Op::RShift)], parse_additive)
+}
+
+type ParseFn = fn(&[Token]) -> Result<(&[Token], PyType), String>;
+
+// Parses operands separated by any of the given operators, each of which applies to everything
+// parsed before it
+fn parse_left_assoc<'a>(tokens: &'a [Token], operators: &[(Token, Op)], parse_operand: ParseFn) -> Result<(&'a [Token], PyType), String> {
+  let (mut tokens, mut expr) = parse_operand(tokens)?;
+  loop {
+    let op = match operators.iter().find(|(token, _)| lookahead(tokens) == Some(token)) {
+      Some((_, op)) => *op,
+      None => return Ok((tokens, expr))
+    };
+    let (tokens2, operand) = parse_operand(&tokens[1..])?;
+    expr = PyType::Expr(Expr::Binop(op, Box::from(expr), Box::from(operand)));
+    tokens = tokens2;
+  }
+}
+
+fn parse_additive(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
   match parse_multiplicative(tokens) {
-    Ok((tokens2, mult_expr)) => {
-      match lookahead(&tokens2) {
-        // MultExpr + AddExpr
-        Some(Token::TokPlus) => {
-          match parse_additive(&match_token(&tokens2, &Token::TokPlus).unwrap()) {
-            Ok((tokens3, add_expr)) => {
-              return Ok((tokens3, PyType::Expr(Expr::Binop(Op::Add, Box::from(mult_expr), Box::from(add_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
+    Ok((mut tokens2, mut add_expr)) => {
+      // Operators are left-associative, so each one applies to everything parsed before it
+      loop {
+        let op = match lookahead(tokens2) {
+          // AddExpr + MultExpr
+          Some(Token::TokPlus) => Op::Add,
 
-        // MultExpr - AddExpr
-        Some(Token::TokMinus) => {
-          match parse_additive(&match_token(&tokens2, &Token::TokMinus).unwrap()) {
-            Ok((tokens3, add_expr)) => {
-              return Ok((tokens3, PyType::Expr(Expr::Binop(Op::Sub, Box::from(mult_expr), Box::from(add_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
+          // AddExpr - MultExpr
+          Some(Token::TokMinus) => Op::Sub,
 
-        // MultExpr TokUnaryMinus NumericalExpr
-        Some(Token::TokUnaryMinus) => {
-          match parse_primary(&match_token(&tokens2, &Token::TokUnaryMinus).unwrap()) {
-            Ok((tokens3, num_expr)) => {
-              return Ok((tokens3, PyType::Expr(Expr::Binop(Op::Sub, Box::from(mult_expr), Box::from(num_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
+          // MultExpr
+          _ => return Ok((tokens2, add_expr))
+        };
 
-        // MultExpr
-        _ => Ok((tokens2, mult_expr))
+        match parse_multiplicative(&tokens2[1..]) {
+          Ok((tokens3, mult_expr)) => {
+            add_expr = PyType::Expr(Expr::Binop(op, Box::from(add_expr), Box::from(mult_expr)));
+            tokens2 = tokens3;
+          },
+          Err(e) => return Err(e)
+        }
       }
-    }, 
+    },
     Err(e) => Err(e)
-}
+  }
 }
 
-fn parse_multiplicative(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
+fn parse_multiplicative(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
   match parse_unary(tokens) {
-    Ok((tokens2, unary_expr)) => {
-      match lookahead(&tokens2) {
-        // UnaryExpr * MultExpr
-        Some(Token::TokMult) => {
-          match parse_multiplicative(&match_token(&tokens2, &Token::TokMult).unwrap()) {
-            Ok((tokens3, mult_expr)) => {
-              return Ok((tokens3, PyType::Expr(Expr::Binop(Op::Mult, Box::from(unary_expr), Box::from(mult_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        },
+    Ok((mut tokens2, mut mult_expr)) => {
+      loop {
+        let op = match lookahead(tokens2) {
+          // MultExpr * UnaryExpr
+          Some(Token::TokMult) => Op::Mult,
 
-        // UnaryExpr / MultExpr
-        Some(Token::TokDiv) => {
-          match parse_multiplicative(&match_token(&tokens2, &Token::TokDiv).unwrap()) {
-            Ok((tokens3, mult_expr)) => {
-              return Ok((tokens3, PyType::Expr(Expr::Binop(Op::Div, Box::from(unary_expr), Box::from(mult_expr)))))
-            },
-            Err(e) => Err(e)
-          }
-        }
+          // MultExpr / UnaryExpr
+          Some(Token::TokDiv) => Op::Div,
+
+          // MultExpr // UnaryExpr
+          Some(Token::TokDoubleSlash) => Op::FloorDiv,
 
-        // UnaryExpr
-        _ => Ok((tokens2, unary_expr))
+          // MultExpr % UnaryExpr
+          Some(Token::TokPercent) => Op::Mod,
+
+          // UnaryExpr
+          _ => return Ok((tokens2, mult_expr))
+        };
+
+        match parse_unary(&tokens2[1..]) {
+          Ok((tokens3, unary_expr)) => {
+            mult_expr = PyType::Expr(Expr::Binop(op, Box::from(mult_expr), Box::from(unary_expr)));
+            tokens2 = tokens3;
+          },
+          Err(e) => return Err(e)
+        }
       }
     },
     Err(e) => Err(e)
   }
 }
 
-fn parse_unary(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match lookahead(&tokens) {
-    // TokUnaryMinus FunctionCallExpr
-    Some(Token::TokUnaryMinus) => {
-      match parse_function_call(&match_token(tokens, &Token::TokUnaryMinus).unwrap()) {
+fn parse_unary(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match lookahead(tokens) {
+    // TokMinus UnaryExpr
+    Some(Token::TokMinus) => {
+      match parse_unary(match_token(tokens, &Token::TokMinus).unwrap()) {
         Ok((tokens2, num_expr)) => {
           Ok((tokens2, PyType::Expr(Expr::Binop(Op::Mult, Box::from(PyType::Expr(Expr::Int(-1))), Box::from(num_expr)))))
         },
@@ -510,100 +992,272 @@ fn parse_unary(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
       }
     },
 
-    // TokNot FunctionCallExpr
-    Some(Token::TokNot) => {
-      match parse_function_call(&match_token(tokens, &Token::TokNot).unwrap()) {
-        Ok((tokens2, num_expr)) => {
-          Ok((tokens2, PyType::Expr(Expr::Not(Box::from(num_expr)))))
-        },
+    // PowerExpr
+    _ => parse_power(tokens)
+  }
+}
+
+// PowerExpr -> PostfixExpr ** UnaryExpr | PostfixExpr
+// ** binds tighter than a minus on its left, so -2 ** 2 is -(2 ** 2), and is right-associative
+fn parse_power(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match parse_postfix(tokens) {
+    Ok((tokens2, base)) => match lookahead(tokens2) {
+      Some(Token::TokDoubleStar) => match parse_unary(&tokens2[1..]) {
+        Ok((tokens3, exponent)) => Ok((tokens3, PyType::Expr(Expr::Binop(Op::Pow, Box::from(base), Box::from(exponent))))),
         Err(e) => Err(e)
-      }
+      },
+      _ => Ok((tokens2, base))
+    },
+    Err(e) => Err(e)
+  }
+}
+
+// PrimaryExpr followed by any number of calls, subscripts and attribute lookups, applied left to right
+fn parse_postfix(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  let (mut tokens, mut expr) = parse_primary(tokens)?;
+
+  loop {
+    match (lookahead(tokens), lookahead_at(tokens, 1)) {
+      // Call: ( Arguments )
+      (Some(Token::TokLParen), _) => {
+        let (tokens2, args, kwargs) = parse_arguments(match_token(tokens, &Token::TokLParen).unwrap())?;
+        tokens = tokens2;
+        expr = PyType::Expr(Expr::FunctionCall(Box::from(expr), args, kwargs));
+      },
+      // Subscript: [ Subscript ]
+      (Some(Token::TokLBracket), _) => {
+        let (tokens2, index) = parse_subscript(match_token(tokens, &Token::TokLBracket).unwrap())?;
+        tokens = tokens2;
+        expr = PyType::Expr(Expr::Subscript(Box::from(expr), Box::from(index)));
+      },
+      // Attribute: . TokVar
+      (Some(Token::TokDot), Some(Token::TokVar(name))) => {
+        let name = name.clone();
+        tokens = &tokens[2..];
+        expr = PyType::Expr(Expr::Attribute(Box::from(expr), name));
+      },
+      (Some(Token::TokDot), _) => return Err("SyntaxError: invalid syntax".to_string()),
+      _ => return Ok((tokens, expr))
     }
+  }
+}
+
+// Comma-separated indices or slices up to and including the closing bracket. Several of them
+// make a tuple, as in d[1, 2]
+fn parse_subscript(mut tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  let mut items = Vec::new();
+  let mut trailing_comma = false;
 
-    // FunctionCallExpr
-    _ => parse_function_call(tokens)
+  loop {
+    let (tokens2, item) = parse_slice_item(tokens)?;
+    items.push(item);
+    tokens = tokens2;
+
+    match lookahead(tokens) {
+      Some(Token::TokRBracket) => break,
+      Some(Token::TokComma) => {
+        tokens = match_token(tokens, &Token::TokComma).unwrap();
+        if let Some(Token::TokRBracket) = lookahead(tokens) {
+          trailing_comma = true;
+          break;
+        }
+      },
+      _ => return Err("SyntaxError: invalid syntax".to_string())
+    }
+  }
+
+  let tokens = match_token(tokens, &Token::TokRBracket).unwrap();
+  if items.len() == 1 && !trailing_comma {
+    Ok((tokens, items.pop().unwrap()))
+  } else {
+    Ok((tokens, PyType::Expr(Expr::Tuple(items))))
   }
 }
 
-fn parse_function_call(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match (lookahead(tokens), lookahead_at(tokens, 1)) {
-    // TokVar TokLParen (Expr TokComma)* TokRParen 
-    (Some(Token::TokVar(func_name)), Some(Token::TokLParen)) => {
-      let mut args = Vec::<PyType>::new();
-      let tokens2 = match_token(&match_token(&tokens, &Token::TokVar(func_name.to_string())).unwrap(), &Token::TokLParen).unwrap(); // match name and (
-      match parse_arguments(&tokens2, &mut args) {
-        Ok((tokens3, _)) => {
-          Ok((tokens3, PyType::Expr(Expr::FunctionCall(func_name.to_string(), args))))
-        },
-        Err(e) => Err(e)
+// Expr | Expr? : Expr? (: Expr?)?
+fn parse_slice_item(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  // A bound is left out when the next token ends it
+  fn parse_bound(tokens: &[Token]) -> Result<(&[Token], Option<Box<PyType>>), String> {
+    match lookahead(tokens) {
+      Some(Token::TokColon) | Some(Token::TokComma) | Some(Token::TokRBracket) => Ok((tokens, None)),
+      _ => {
+        let (tokens2, bound) = parse_expr(tokens)?;
+        Ok((tokens2, Some(Box::from(bound))))
       }
-    },
-    // PrimaryExpr
-    _ => parse_primary(tokens)
+    }
   }
+
+  let (tokens, start) = parse_bound(tokens)?;
+  let tokens = match lookahead(tokens) {
+    Some(Token::TokColon) => match_token(tokens, &Token::TokColon).unwrap(),
+    // Plain index
+    _ => return match start {
+      Some(index) => Ok((tokens, *index)),
+      None => Err("SyntaxError: invalid syntax".to_string())
+    }
+  };
+
+  let (tokens, stop) = parse_bound(tokens)?;
+  let (tokens, step) = match lookahead(tokens) {
+    Some(Token::TokColon) => parse_bound(match_token(tokens, &Token::TokColon).unwrap())?,
+    _ => (tokens, None)
+  };
+  Ok((tokens, PyType::Expr(Expr::Slice(start, stop, step))))
 }
 
-fn parse_arguments(tokens: &Vec<Token>, arguments: &mut Vec<PyType>) -> Result<(Vec<Token>, Vec<PyType>), String> {
-  match lookahead(tokens) {
-    // End of arguments
-    Some(Token::TokRParen) => Ok((match_token(tokens, &Token::TokRParen).unwrap(), arguments.to_vec())),
-    // Continue parsing
-    _ => {
-      match parse_expr(tokens) {
-        Ok((tokens2, curr_arg)) => {
-          arguments.push(curr_arg);
-          match lookahead(&tokens2) {
-            // End of arguments
-            Some(Token::TokRParen) => Ok((match_token(&tokens2, &Token::TokRParen).unwrap(), arguments.to_vec())),
-            Some(Token::TokComma) => parse_arguments(&match_token(&tokens2, &Token::TokComma).unwrap(), arguments),
-            _ => Err("SyntaxError".to_string())
+type KeywordArguments = Vec<(Option<String>, PyType)>;
+
+// Comma-separated arguments up to and including the closing parenthesis. Keyword arguments
+// (TokVar = Expr) and **mapping must come after the positional ones, and *iterable may appear
+// anywhere before a **mapping
+fn parse_arguments(mut tokens: &[Token]) -> Result<(&[Token], Vec<PyType>, KeywordArguments), String> {
+  let mut args = Vec::new();
+  let mut kwargs: KeywordArguments = Vec::new();
+
+  loop {
+    if let Some(Token::TokRParen) = lookahead(tokens) {
+      return Ok((match_token(tokens, &Token::TokRParen).unwrap(), args, kwargs));
+    }
+    let unpacked_mapping = kwargs.iter().any(|(name, _)| name.is_none());
+
+    match (lookahead(tokens), lookahead_at(tokens, 1)) {
+      // Keyword argument
+      (Some(Token::TokVar(name)), Some(Token::TokAssign)) => {
+        let name = name.clone();
+        if kwargs.iter().any(|(n, _)| n.as_ref() == Some(&name)) {
+          return Err(format!("SyntaxError: keyword argument repeated: {}", name));
+        }
+        let (tokens2, arg) = parse_expr(&tokens[2..])?;
+        kwargs.push((Some(name), arg));
+        tokens = tokens2;
+      },
+      // **mapping
+      (Some(Token::TokDoubleStar), _) => {
+        let (tokens2, arg) = parse_expr(&tokens[1..])?;
+        kwargs.push((None, arg));
+        tokens = tokens2;
+      },
+      // *iterable
+      (Some(Token::TokMult), _) => {
+        let (tokens2, arg) = parse_expr(&tokens[1..])?;
+        if unpacked_mapping {
+          return Err("SyntaxError: iterable argument unpacking follows keyword argument unpacking".to_string());
+        }
+        args.push(PyType::Expr(Expr::Starred(Box::new(arg))));
+        tokens = tokens2;
+      },
+      // Positional argument
+      _ => {
+        let (tokens2, arg) = parse_expr(tokens)?;
+        // Only a name can be given a keyword argument
+        if lookahead(tokens2) == Some(&Token::TokAssign) {
+          return Err("SyntaxError: expression cannot contain assignment, perhaps you meant \"==\"?".to_string());
+        }
+        // A generator expression that is the only argument needs no parentheses of its own
+        if lookahead(tokens2) == Some(&Token::TokFor) {
+          let (tokens3, arg) = parse_comprehension(tokens2, Comprehension::Generator, arg)?;
+          if !args.is_empty() || !kwargs.is_empty() || lookahead(tokens3) != Some(&Token::TokRParen) {
+            return Err("SyntaxError: Generator expression must be parenthesized".to_string());
           }
-        },
-        Err(e) => Err(e)
+          args.push(arg);
+          tokens = tokens3;
+          continue;
+        }
+        if unpacked_mapping {
+          return Err("SyntaxError: positional argument follows keyword argument unpacking".to_string());
+        } else if !kwargs.is_empty() {
+          return Err("SyntaxError: positional argument follows keyword argument".to_string());
+        }
+        args.push(arg);
+        tokens = tokens2;
       }
     }
+
+    match lookahead(tokens) {
+      Some(Token::TokRParen) => {},
+      Some(Token::TokComma) => tokens = match_token(tokens, &Token::TokComma).unwrap(),
+      _ => return Err("SyntaxError: invalid syntax".to_string())
+    }
   }
 }
 
-fn parse_primary(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
-  match lookahead(&tokens) {
+fn parse_primary(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  match lookahead(tokens) {
     // Int
     Some(Token::TokInt(n)) => {
-      Ok((match_token(&tokens, &Token::TokInt(*n)).unwrap(), PyType::Expr(Expr::Int(*n))))
+      Ok((match_token(tokens, &Token::TokInt(*n)).unwrap(), PyType::Expr(Expr::Int(*n))))
     },
 
     // Float
     Some(Token::TokFloat(d)) => {
-      Ok((match_token(&tokens, &Token::TokFloat(*d)).unwrap(), PyType::Expr(Expr::Float(*d))))
+      Ok((match_token(tokens, &Token::TokFloat(*d)).unwrap(), PyType::Expr(Expr::Float(*d))))
     },
 
-    // String
-    Some(Token::TokString(s)) => {
-      Ok((match_token(&tokens, &Token::TokString(s.clone())).unwrap(), PyType::Expr(Expr::String(s.clone()))))
-    },
+    // String or bytes, joining adjacent literals
+    Some(Token::TokString(_) | Token::TokBytes(_) | Token::TokFString(_)) => parse_string_literals(tokens),
 
     // Bool
     Some(Token::TokBool(b)) => {
-      Ok((match_token(&tokens, &Token::TokBool(*b)).unwrap(), PyType::Expr(Expr::Bool(*b))))
+      Ok((match_token(tokens, &Token::TokBool(*b)).unwrap(), PyType::Expr(Expr::Bool(*b))))
+    },
+
+    // None
+    Some(Token::TokNone) => {
+      Ok((match_token(tokens, &Token::TokNone).unwrap(), PyType::Expr(Expr::None)))
     },
 
     // Var
     Some(Token::TokVar(v)) => {
-      Ok((match_token(&tokens, &Token::TokVar(v.clone())).unwrap(), PyType::Expr(Expr::Var(v.clone()))))
+      Ok((match_token(tokens, &Token::TokVar(v.clone())).unwrap(), PyType::Expr(Expr::Var(v.clone()))))
+    },
+
+    // List or list comprehension
+    Some(Token::TokLBracket) => {
+      let tokens2 = match_token(tokens, &Token::TokLBracket).unwrap();
+      if lookahead(tokens2) == Some(&Token::TokRBracket) {
+        return Ok((match_token(tokens2, &Token::TokRBracket).unwrap(), PyType::Expr(Expr::List(Vec::new()))));
+      }
+      let (tokens3, first) = parse_expr(tokens2)?;
+      if lookahead(tokens3) == Some(&Token::TokFor) {
+        let (tokens4, comprehension) = parse_comprehension(tokens3, Comprehension::List, first)?;
+        return Ok((match_token(tokens4, &Token::TokRBracket)?, comprehension));
+      }
+      match parse_more_elements(first, tokens3, &Token::TokRBracket) {
+        Ok((tokens4, items, _)) => Ok((tokens4, PyType::Expr(Expr::List(items)))),
+        Err(e) => Err(e)
+      }
     },
 
-    // (OrExpr) or error
+    // Dict or Set
+    Some(Token::TokLBrace) => parse_braces(match_token(tokens, &Token::TokLBrace).unwrap()),
+
+    // (Expr), Tuple or error
     _ => {
       // Match opening parenthesis
-      match match_token(&tokens, &Token::TokLParen) {
+      match match_token(tokens, &Token::TokLParen) {
+        // (YieldExpr)
+        Ok(tokens2) if lookahead(tokens2) == Some(&Token::TokYield) => {
+          let (tokens3, e) = parse_yield(match_token(tokens2, &Token::TokYield).unwrap())?;
+          Ok((match_token(tokens3, &Token::TokRParen)?, e))
+        }
+        Ok(tokens2) if lookahead(tokens2) == Some(&Token::TokRParen) => {
+          Ok((match_token(tokens2, &Token::TokRParen).unwrap(), PyType::Expr(Expr::Tuple(Vec::new()))))
+        }
         Ok(tokens2) => {
-          // Parse expression inside parentheses
-          match parse_expr(&tokens2) {
-            Ok((tokens3, expr)) => {
-              // Match closing parenthesis
-              match match_token(&tokens3, &Token::TokRParen) {
-                Ok(tokens4) => Ok((tokens4, expr)),
-                Err(e) => Err(e)
+          let (tokens3, first) = parse_expr(tokens2)?;
+          // (Expr CompClause)
+          if lookahead(tokens3) == Some(&Token::TokFor) {
+            let (tokens4, comprehension) = parse_comprehension(tokens3, Comprehension::Generator, first)?;
+            return Ok((match_token(tokens4, &Token::TokRParen)?, comprehension));
+          }
+
+          // Parse expressions inside parentheses, a comma makes it a tuple
+          match parse_more_elements(first, tokens3, &Token::TokRParen) {
+            Ok((tokens4, mut items, has_comma)) => {
+              if items.len() == 1 && !has_comma {
+                Ok((tokens4, items.remove(0)))
+              } else {
+                Ok((tokens4, PyType::Expr(Expr::Tuple(items))))
               }
             },
             Err(e) => Err(e)
@@ -613,4 +1267,227 @@ fn parse_primary(tokens: &Vec<Token>) -> Result<(Vec<Token>, PyType), String> {
       }
     }
   }
+}
+
+// Adjacent string literals are joined into one, as are adjacent bytes literals. Joining an f-string
+// with plain strings gives an f-string
+fn parse_string_literals(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  let mut parts = Vec::new();
+  let mut bytes = Vec::new();
+  let (mut has_string, mut has_bytes, mut has_fstring) = (false, false, false);
+  let mut count = 0;
+
+  for token in tokens {
+    match token {
+      Token::TokString(s) => {
+        push_literal(&mut parts, s);
+        has_string = true;
+      },
+      Token::TokFString(fstring) => {
+        for part in parse_fstring(fstring)? {
+          match part {
+            FStringPart::Literal(s) => push_literal(&mut parts, &s),
+            field => parts.push(field),
+          }
+        }
+        has_string = true;
+        has_fstring = true;
+      },
+      Token::TokBytes(b) => {
+        bytes.extend_from_slice(b);
+        has_bytes = true;
+      },
+      _ => break
+    }
+    count += 1;
+  }
+
+  match (has_string, has_bytes) {
+    (true, true) => Err("SyntaxError: cannot mix bytes and nonbytes literals".to_string()),
+    (false, _) => Ok((&tokens[count..], PyType::Expr(Expr::Bytes(bytes)))),
+    (true, _) if has_fstring => {
+      parts.retain(|part| !matches!(part, FStringPart::Literal(s) if s.is_empty()));
+      Ok((&tokens[count..], PyType::Expr(Expr::FString(parts))))
+    },
+    (true, _) => {
+      let string = match parts.pop() {
+        Some(FStringPart::Literal(s)) => s,
+        _ => String::new(),
+      };
+      Ok((&tokens[count..], PyType::Expr(Expr::String(string))))
+    }
+  }
+}
+
+// Appends literal text, merging it into a literal part that comes right before
+fn push_literal(parts: &mut Vec<FStringPart>, s: &str) {
+  match parts.last_mut() {
+    Some(FStringPart::Literal(literal)) => literal.push_str(s),
+    _ => parts.push(FStringPart::Literal(s.to_string())),
+  }
+}
+
+// Parses the expressions in the replacement fields of an f-string, and in their format specs
+fn parse_fstring(fstring: &[FStringToken]) -> Result<Vec<FStringPart>, String> {
+  let mut parts = Vec::new();
+  for token in fstring {
+    match token {
+      FStringToken::Literal(s) => parts.push(FStringPart::Literal(s.clone())),
+      FStringToken::Field(tokens, conversion, spec) => {
+        match parse_expr(tokens) {
+          Ok(([], expr)) => {
+            parts.push(FStringPart::Field(Box::new(expr), *conversion, parse_fstring(spec)?));
+          },
+          Ok(_) => return Err("SyntaxError: f-string: invalid syntax".to_string()),
+          Err(e) => return Err(e)
+        }
+      }
+    }
+  }
+  Ok(parts)
+}
+
+// Parses comma-separated expressions up to and including the closing token, allowing a trailing comma.
+// Also returns whether any comma was seen, which tells (x) apart from the tuple (x,)
+fn parse_elements<'a>(mut tokens: &'a [Token], closing: &Token) -> Result<(&'a [Token], Vec<PyType>, bool), String> {
+  let mut items = Vec::<PyType>::new();
+  let mut has_comma = false;
+
+  loop {
+    if lookahead(tokens) == Some(closing) {
+      return Ok((match_token(tokens, closing).unwrap(), items, has_comma));
+    }
+
+    match parse_expr(tokens) {
+      Ok((tokens2, item)) => {
+        items.push(item);
+        match lookahead(tokens2) {
+          Some(Token::TokComma) => {
+            has_comma = true;
+            tokens = match_token(tokens2, &Token::TokComma).unwrap();
+          },
+          _ => return match match_token(tokens2, closing) {
+            Ok(tokens3) => Ok((tokens3, items, has_comma)),
+            Err(e) => Err(e)
+          }
+        }
+      },
+      Err(e) => return Err(e)
+    }
+  }
+}
+
+// Parses the rest of a display after its first item, which is followed by a comma or the closing token
+fn parse_more_elements<'a>(first: PyType, tokens: &'a [Token], closing: &Token) -> Result<(&'a [Token], Vec<PyType>, bool), String> {
+  match lookahead(tokens) {
+    Some(Token::TokComma) => match parse_elements(match_token(tokens, &Token::TokComma).unwrap(), closing) {
+      Ok((tokens2, mut items, _)) => {
+        items.insert(0, first);
+        Ok((tokens2, items, true))
+      },
+      Err(e) => Err(e)
+    },
+    _ => match match_token(tokens, closing) {
+      Ok(tokens2) => Ok((tokens2, vec![first], false)),
+      Err(e) => Err(e)
+    }
+  }
+}
+
+// CompClause -> for TokVar in OrExpr (for TokVar in OrExpr | if OrExpr)*
+// Parses the clauses after the element of a comprehension, leaving the closing bracket
+fn parse_comprehension(mut tokens: &[Token], kind: Comprehension, element: PyType) -> Result<(&[Token], PyType), String> {
+  let mut clauses = Vec::new();
+
+  loop {
+    match (lookahead(tokens), lookahead_at(tokens, 1), lookahead_at(tokens, 2)) {
+      (Some(Token::TokFor), Some(Token::TokVar(var)), Some(Token::TokIn)) => {
+        let var = var.clone();
+        let (tokens2, iterable) = parse_or(&tokens[3..])?;
+        clauses.push(Clause::For(var, iterable));
+        tokens = tokens2;
+      },
+      (Some(Token::TokFor), _, _) => return Err("SyntaxError: expected 'for <name> in <expression>'".to_string()),
+      (Some(Token::TokIf), _, _) => {
+        let (tokens2, condition) = parse_or(&tokens[1..])?;
+        clauses.push(Clause::If(condition));
+        tokens = tokens2;
+      },
+      _ => break
+    }
+  }
+
+  // Only the first iterable is evaluated in the enclosing scope, which a yield can suspend
+  if comprehension_scope(&kind, &element, &clauses).iter().any(|node| node.contains_yield()) {
+    let what = match kind {
+      Comprehension::List => "list comprehension",
+      Comprehension::Set => "set comprehension",
+      Comprehension::Dict(_) => "dict comprehension",
+      Comprehension::Generator => "generator expression",
+    };
+    return Err(format!("SyntaxError: 'yield' inside {}", what));
+  }
+
+  Ok((tokens, PyType::Expr(Expr::Comprehension(kind, Box::new(element), clauses))))
+}
+
+// Parses the contents of {...}: empty braces and key: value pairs make a dict, anything else a set.
+// Either can be a comprehension
+fn parse_braces(tokens: &[Token]) -> Result<(&[Token], PyType), String> {
+  if lookahead(tokens) == Some(&Token::TokRBrace) {
+    return Ok((match_token(tokens, &Token::TokRBrace).unwrap(), PyType::Expr(Expr::Dict(Vec::new()))));
+  }
+
+  match parse_expr(tokens) {
+    Ok((tokens2, first)) => {
+      if lookahead(tokens2) == Some(&Token::TokFor) {
+        let (tokens3, comprehension) = parse_comprehension(tokens2, Comprehension::Set, first)?;
+        return Ok((match_token(tokens3, &Token::TokRBrace)?, comprehension));
+      }
+      if lookahead(tokens2) != Some(&Token::TokColon) {
+        // Set
+        return match parse_more_elements(first, tokens2, &Token::TokRBrace) {
+          Ok((tokens3, items, _)) => Ok((tokens3, PyType::Expr(Expr::Set(items)))),
+          Err(e) => Err(e)
+        };
+      }
+
+      // Dict
+      let mut tokens = tokens2;
+      let mut key = first;
+      let mut pairs = Vec::<(PyType, PyType)>::new();
+      loop {
+        match parse_expr(match_token(tokens, &Token::TokColon)?) {
+          Ok((tokens3, value)) if pairs.is_empty() && lookahead(tokens3) == Some(&Token::TokFor) => {
+            let (tokens4, comprehension) = parse_comprehension(tokens3, Comprehension::Dict(Box::new(value)), key)?;
+            return Ok((match_token(tokens4, &Token::TokRBrace)?, comprehension));
+          },
+          Ok((tokens3, value)) => {
+            pairs.push((key, value));
+            match lookahead(tokens3) {
+              Some(Token::TokComma) => tokens = match_token(tokens3, &Token::TokComma).unwrap(),
+              _ => return match match_token(tokens3, &Token::TokRBrace) {
+                Ok(tokens4) => Ok((tokens4, PyType::Expr(Expr::Dict(pairs)))),
+                Err(e) => Err(e)
+              }
+            }
+          },
+          Err(e) => return Err(e)
+        }
+
+        // Trailing comma
+        if lookahead(tokens) == Some(&Token::TokRBrace) {
+          return Ok((match_token(tokens, &Token::TokRBrace).unwrap(), PyType::Expr(Expr::Dict(pairs))));
+        }
+        match parse_expr(tokens) {
+          Ok((tokens3, next_key)) => {
+            key = next_key;
+            tokens = tokens3;
+          },
+          Err(e) => return Err(e)
+        }
+      }
+    },
+    Err(e) => Err(e)
+  }
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+
+// Streams used by print(), input() and dis(). They are the process's standard streams unless
+// replaced, which lets an embedder capture what a program prints or feed it input
+
+thread_local! {
+  static OUTPUT: RefCell<Box<dyn Write>> = RefCell::new(Box::new(io::stdout()));
+  static ERRORS: RefCell<Box<dyn Write>> = RefCell::new(Box::new(io::stderr()));
+  // None reads standard input through the buffer it shares with the REPL
+  static INPUT: RefCell<Option<Box<dyn BufRead>>> = const { RefCell::new(None) };
+}
+
+// The output streams a program can name, as sys.stdout and sys.stderr
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+  Stdout,
+  Stderr,
+}
+
+impl Stream {
+  pub fn name(self) -> &'static str {
+    match self {
+      Stream::Stdout => "<stdout>",
+      Stream::Stderr => "<stderr>",
+    }
+  }
+
+  fn with<T>(self, f: impl FnOnce(&mut Box<dyn Write>) -> T) -> T {
+    let sink = match self {
+      Stream::Stdout => &OUTPUT,
+      Stream::Stderr => &ERRORS,
+    };
+    sink.with(|sink| f(&mut sink.borrow_mut()))
+  }
+}
+
+// Replaces the sink of an output stream, returning the previous one
+pub fn set_output(stream: Stream, sink: Box<dyn Write>) -> Box<dyn Write> {
+  stream.with(|current| std::mem::replace(current, sink))
+}
+
+// Replaces the input source, returning the previous one
+pub fn set_input(source: Option<Box<dyn BufRead>>) -> Option<Box<dyn BufRead>> {
+  INPUT.with(|input| input.replace(source))
+}
+
+pub fn write(stream: Stream, text: &str) -> Result<(), String> {
+  stream.with(|sink| sink.write_all(text.as_bytes())).map_err(|e| format!("OSError: {}", e))
+}
+
+pub fn flush(stream: Stream) -> Result<(), String> {
+  stream.with(|sink| sink.flush()).map_err(|e| format!("OSError: {}", e))
+}
+
+// Reads a line without its line ending, or None at the end of the input
+pub fn read_line() -> Result<Option<String>, String> {
+  let mut line = String::new();
+  let read = INPUT.with(|input| match input.borrow_mut().as_mut() {
+    Some(source) => source.read_line(&mut line),
+    None => io::stdin().read_line(&mut line),
+  });
+  match read {
+    Ok(0) => Ok(None),
+    Ok(_) => {
+      if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+          line.pop();
+        }
+      }
+      Ok(Some(line))
+    }
+    Err(e) => Err(format!("OSError: {}", e)),
+  }
+}
@@ -1,21 +1,36 @@
+use crate::value::repr_bytes;
 use std::fmt;
 
-#[derive(Debug, PartialEq, Clone)]
-
 // TODO: create enum for Error codes/messages
 
+#[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Token {
-  TokInt(i32),
-  TokFloat(f32),
+  TokInt(i64),
+  TokFloat(f64),
   TokBool(bool),
   TokString(String),
-  TokUnaryMinus,
+  TokBytes(Vec<u8>),
+  TokFString(Vec<FStringToken>),
+  TokNone,
   TokPlus,
   TokMinus,
   TokMult,
+  TokDoubleStar,
   TokDiv,
+  TokDoubleSlash,
+  TokPercent,
+  TokAmpersand,
+  TokPipe,
+  TokCaret,
+  TokLeftShift,
+  TokRightShift,
   TokLParen,
   TokRParen,
+  TokLBracket,
+  TokRBracket,
+  TokLBrace,
+  TokRBrace,
   TokOr,
   TokAnd,
   TokDoubleEqual,
@@ -26,16 +41,32 @@ pub enum Token {
   TokGreaterEqual,
   TokVar(String),
   TokAssign,
+  TokAugAssign(Op), // the operator of +=, -= and so on
   TokIf,
   TokElif,
   TokElse,
   TokColon,
-  TokIndent(i32),
-  TokDedent(i32),
+  TokIndent,
+  TokDedent,
   TokNot,
+  TokIn,
+  TokIs,
   TokDef,
   TokReturn,
+  TokYield,
+  TokFrom,
+  TokWhile,
+  TokFor,
+  TokBreak,
+  TokContinue,
+  TokDel,
+  TokPass,
+  TokAssert,
+  TokGlobal,
+  TokNonlocal,
+  TokImport,
   TokComma,
+  TokDot,
 }
 
 impl fmt::Display for Token {
@@ -45,13 +76,27 @@ impl fmt::Display for Token {
       Token::TokFloat(d) => write!(f, "TokFloat({})", d),
       Token::TokBool(b) => write!(f, "TokBool({})", b),
       Token::TokString(s) => write!(f, "TokString(\"{}\")", s),
-      Token::TokUnaryMinus => write!(f, "TokUnaryMinus"),
+      Token::TokBytes(b) => write!(f, "TokBytes({})", repr_bytes(b)),
+      Token::TokFString(parts) => write!(f, "TokFString({:?})", parts),
+      Token::TokNone => write!(f, "TokNone"),
       Token::TokPlus => write!(f, "TokPlus"),
       Token::TokMinus => write!(f, "TokMinus"),
       Token::TokMult => write!(f, "TokMult"),
+      Token::TokDoubleStar => write!(f, "TokDoubleStar"),
       Token::TokDiv => write!(f, "TokDiv"),
+      Token::TokDoubleSlash => write!(f, "TokDoubleSlash"),
+      Token::TokPercent => write!(f, "TokPercent"),
+      Token::TokAmpersand => write!(f, "TokAmpersand"),
+      Token::TokPipe => write!(f, "TokPipe"),
+      Token::TokCaret => write!(f, "TokCaret"),
+      Token::TokLeftShift => write!(f, "TokLeftShift"),
+      Token::TokRightShift => write!(f, "TokRightShift"),
       Token::TokLParen => write!(f, "TokLParen"),
       Token::TokRParen => write!(f, "TokRParen"),
+      Token::TokLBracket => write!(f, "TokLBracket"),
+      Token::TokRBracket => write!(f, "TokRBracket"),
+      Token::TokLBrace => write!(f, "TokLBrace"),
+      Token::TokRBrace => write!(f, "TokRBrace"),
       Token::TokOr => write!(f, "TokOr"),
       Token::TokAnd => write!(f, "TokAnd"),
       Token::TokDoubleEqual => write!(f, "TokDoubleEqual"),
@@ -62,20 +107,43 @@ impl fmt::Display for Token {
       Token::TokGreaterEqual => write!(f, "TokGreaterEqual"),
       Token::TokVar(s) => write!(f, "TokVar({})", s),
       Token::TokAssign => write!(f, "TokAssign"),
+      Token::TokAugAssign(op) => write!(f, "TokAugAssign({}=)", op),
       Token::TokIf => write!(f, "TokIf"),
       Token::TokElif => write!(f, "TokElif"),
       Token::TokElse => write!(f, "TokElse"),
       Token::TokColon => write!(f, "TokColon"),
-      Token::TokIndent(n) => write!(f, "TokIndent({})", n),
-      Token::TokDedent(n) => write!(f, "TokDedent({})", n),
+      Token::TokIndent => write!(f, "TokIndent"),
+      Token::TokDedent => write!(f, "TokDedent"),
       Token::TokNot => write!(f, "TokNot"),
+      Token::TokIn => write!(f, "TokIn"),
+      Token::TokIs => write!(f, "TokIs"),
       Token::TokDef => write!(f, "TokDef"),
       Token::TokReturn => write!(f, "TokReturn"),
+      Token::TokYield => write!(f, "TokYield"),
+      Token::TokFrom => write!(f, "TokFrom"),
+      Token::TokWhile => write!(f, "TokWhile"),
+      Token::TokFor => write!(f, "TokFor"),
+      Token::TokBreak => write!(f, "TokBreak"),
+      Token::TokContinue => write!(f, "TokContinue"),
+      Token::TokDel => write!(f, "TokDel"),
+      Token::TokPass => write!(f, "TokPass"),
+      Token::TokAssert => write!(f, "TokAssert"),
+      Token::TokGlobal => write!(f, "TokGlobal"),
+      Token::TokNonlocal => write!(f, "TokNonlocal"),
+      Token::TokImport => write!(f, "TokImport"),
       Token::TokComma => write!(f, "TokComma"),
+      Token::TokDot => write!(f, "TokDot"),
     }
   }
 }
 
+// Piece of an f-string as split up by the lexer
+#[derive(Debug, PartialEq, Clone)]
+pub enum FStringToken {
+  Literal(String),
+  Field(Vec<Token>, Option<char>, Vec<FStringToken>), // expression tokens, conversion, format spec
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum PyType {
   Stmt(Stmt),
@@ -91,33 +159,331 @@ impl fmt::Display for PyType {
   }
 }
 
+impl PyType {
+  // Whether running the node can yield, which makes the function it is in a generator. The body
+  // of a nested function doesn't count, but its defaults do
+  pub fn contains_yield(&self) -> bool {
+    match self {
+      PyType::Expr(Expr::Yield(_) | Expr::YieldFrom(_)) => true,
+      PyType::Stmt(Stmt::Function(_, parameters, _)) => parameters.defaults().any(PyType::contains_yield),
+      // The rest of a comprehension runs in a scope of its own, where a yield isn't allowed
+      PyType::Expr(Expr::Comprehension(_, _, clauses)) => clauses[0].node().contains_yield(),
+      _ => self.children().into_iter().any(PyType::contains_yield),
+    }
+  }
+
+  // The nodes directly inside this one, including the statements of a body
+  pub fn children(&self) -> Vec<&PyType> {
+    let mut children = Vec::new();
+    match self {
+      PyType::Expr(expr) => match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::String(_) | Expr::Bytes(_) | Expr::None | Expr::Var(_) => {}
+        Expr::FString(parts) => fstring_children(parts, &mut children),
+        Expr::List(items) | Expr::Tuple(items) | Expr::Set(items) => children.extend(items),
+        Expr::Dict(pairs) => {
+          for (key, value) in pairs {
+            children.push(key);
+            children.push(value);
+          }
+        }
+        Expr::Binop(_, left, right) | Expr::Subscript(left, right) => {
+          children.push(&**left);
+          children.push(&**right);
+        }
+        Expr::Compare(first, comparisons) => {
+          children.push(&**first);
+          children.extend(comparisons.iter().map(|(_, operand)| operand));
+        }
+        Expr::Not(e) | Expr::Attribute(e, _) | Expr::Starred(e) | Expr::YieldFrom(e) => children.push(&**e),
+        Expr::Yield(value) => children.extend(value.as_deref()),
+        Expr::Ternary(condition, if_true, if_false) => children.extend([&**condition, &**if_true, &**if_false]),
+        Expr::Slice(start, stop, step) => children.extend([start, stop, step].into_iter().flatten().map(|bound| &**bound)),
+        Expr::FunctionCall(func, args, kwargs) => {
+          children.push(&**func);
+          children.extend(args);
+          children.extend(kwargs.iter().map(|(_, arg)| arg));
+        }
+        Expr::Comprehension(kind, element, clauses) => {
+          children.extend(clauses.iter().map(Clause::node));
+          children.push(&**element);
+          if let Comprehension::Dict(value) = kind {
+            children.push(&**value);
+          }
+        }
+      },
+      PyType::Stmt(stmt) => match stmt {
+        Stmt::Return(e) => children.push(&**e),
+        Stmt::Assign(targets, e) => {
+          children.extend(targets);
+          children.push(&**e);
+        }
+        Stmt::Del(targets) => children.extend(targets),
+        Stmt::AugAssign(target, _, e) => {
+          children.push(&**target);
+          children.push(&**e);
+        }
+        Stmt::If(condition, body, else_body) => {
+          children.push(&**condition);
+          children.extend(body.iter().chain(else_body.iter().flatten()).map(|statement| &statement.node));
+        }
+        Stmt::While(condition, body) | Stmt::For(_, condition, body) => {
+          children.push(&**condition);
+          children.extend(body.iter().map(|statement| &statement.node));
+        }
+        Stmt::Function(_, parameters, body) => {
+          children.extend(parameters.defaults());
+          children.extend(body.iter().map(|statement| &statement.node));
+        }
+        Stmt::Assert(condition, message) => {
+          children.push(&**condition);
+          children.extend(message.as_deref());
+        }
+        Stmt::Break | Stmt::Continue | Stmt::Pass | Stmt::Global(_) | Stmt::Nonlocal(_) => {}
+      },
+    }
+    children
+  }
+}
+
+fn fstring_children<'a>(parts: &'a [FStringPart], children: &mut Vec<&'a PyType>) {
+  for part in parts {
+    if let FStringPart::Field(expr, _, spec) = part {
+      children.push(&**expr);
+      fstring_children(spec, children);
+    }
+  }
+}
+
+impl Clause {
+  // The iterable of a for clause, or the condition of an if clause
+  pub fn node(&self) -> &PyType {
+    match self {
+      Clause::For(_, node) | Clause::If(node) => node,
+    }
+  }
+}
+
+// The nodes of a comprehension that run in its own scope, which is all of them but the first iterable
+pub fn comprehension_scope<'a>(kind: &'a Comprehension, element: &'a PyType, clauses: &'a [Clause]) -> Vec<&'a PyType> {
+  let mut nodes = vec![element];
+  if let Comprehension::Dict(value) = kind {
+    nodes.push(&**value);
+  }
+  nodes.extend(clauses[1..].iter().map(Clause::node));
+  nodes
+}
+
+pub fn body_contains_yield(body: &[Statement]) -> bool {
+  body.iter().any(|statement| statement.node.contains_yield())
+}
+
+pub fn push_unique(names: &mut Vec<String>, name: &str) {
+  if !names.iter().any(|n| n == name) {
+    names.push(name.to_string());
+  }
+}
+
+// Names assigned anywhere in a function body, which makes them local to the function unless they
+// are declared global or nonlocal
+pub fn bound_names(body: &[Statement], names: &mut Vec<String>) {
+  for statement in body {
+    match &statement.node {
+      PyType::Stmt(Stmt::Function(v, _, _)) => push_unique(names, v),
+      PyType::Stmt(Stmt::Assign(targets, _)) | PyType::Stmt(Stmt::Del(targets)) => {
+        for target in targets {
+          if let PyType::Expr(Expr::Var(v)) = target {
+            push_unique(names, v);
+          }
+        }
+      }
+      PyType::Stmt(Stmt::AugAssign(target, _, _)) => {
+        if let PyType::Expr(Expr::Var(v)) = &**target {
+          push_unique(names, v);
+        }
+      }
+      PyType::Stmt(Stmt::For(v, _, for_body)) => {
+        push_unique(names, v);
+        bound_names(for_body, names);
+      }
+      PyType::Stmt(Stmt::While(_, while_body)) => bound_names(while_body, names),
+      PyType::Stmt(Stmt::If(_, if_body, else_body)) => {
+        bound_names(if_body, names);
+        if let Some(else_body_list) = else_body {
+          bound_names(else_body_list, names);
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+// Names a function body declares global or nonlocal. A declaration covers the whole body, wherever
+// it is, but not the functions nested in it
+#[derive(Debug, Default)]
+pub struct Declarations {
+  pub globals: Vec<String>,
+  pub nonlocals: Vec<String>,
+}
+
+impl Declarations {
+  pub fn of(body: &[Statement]) -> Declarations {
+    let mut declarations = Declarations::default();
+    declarations.add(body);
+    declarations
+  }
+
+  fn add(&mut self, body: &[Statement]) {
+    for statement in body {
+      match &statement.node {
+        PyType::Stmt(Stmt::Global(names)) => names.iter().for_each(|name| push_unique(&mut self.globals, name)),
+        PyType::Stmt(Stmt::Nonlocal(names)) => names.iter().for_each(|name| push_unique(&mut self.nonlocals, name)),
+        PyType::Stmt(Stmt::For(_, _, body)) | PyType::Stmt(Stmt::While(_, body)) => self.add(body),
+        PyType::Stmt(Stmt::If(_, body, else_body)) => {
+          self.add(body);
+          else_body.iter().for_each(|else_body| self.add(else_body));
+        }
+        _ => {}
+      }
+    }
+  }
+
+  pub fn is_global(&self, name: &str) -> bool {
+    self.globals.iter().any(|n| n == name)
+  }
+
+  pub fn is_nonlocal(&self, name: &str) -> bool {
+    self.nonlocals.iter().any(|n| n == name)
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.is_global(name) || self.is_nonlocal(name)
+  }
+}
+
+// The names bound in a function's own scope: its parameters and the names assigned in its body,
+// apart from those declared global or nonlocal
+pub fn local_names(parameters: &Parameters, body: &[Statement]) -> Vec<String> {
+  let mut names = parameters.names();
+  bound_names(body, &mut names);
+  let declarations = Declarations::of(body);
+  names.retain(|name| !declarations.contains(name));
+  names
+}
+
+// A statement along with the number of the source line it starts on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+  pub line: u32,
+  pub node: PyType,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
-  Int(i32),
-  Float(f32),
+  Int(i64),
+  Float(f64),
   Bool(bool),
   String(String),
+  Bytes(Vec<u8>),
+  FString(Vec<FStringPart>),
+  None,
   Var(String),
+  List(Vec<PyType>),
+  Tuple(Vec<PyType>),
+  Dict(Vec<(PyType, PyType)>), // (key, value) pairs
+  Set(Vec<PyType>),
   Binop(Op, Box<PyType>, Box<PyType>),
+  Compare(Box<PyType>, Vec<(Op, PyType)>), // first operand, (comparison operator, operand) for each link of the chain
   Not(Box<PyType>),
-  Return(Box<PyType>),
-  FunctionCall(String, Vec<PyType>), // function name, arguments supplied
+  Ternary(Box<PyType>, Box<PyType>, Box<PyType>), // condition, value if true, value if false
+  Attribute(Box<PyType>, String), // object, attribute name
+  Subscript(Box<PyType>, Box<PyType>), // container, index or slice
+  Slice(Option<Box<PyType>>, Option<Box<PyType>>, Option<Box<PyType>>), // start, stop, step
+  FunctionCall(Box<PyType>, Vec<PyType>, Vec<(Option<String>, PyType)>), // function, positional arguments, keyword arguments (None for **mapping)
+  Starred(Box<PyType>), // *iterable among the positional arguments of a call
+  Yield(Option<Box<PyType>>), // value to yield, None for a bare yield
+  YieldFrom(Box<PyType>), // iterable to delegate to
+  Comprehension(Comprehension, Box<PyType>, Vec<Clause>), // kind, element (the key for a dict), clauses in order
+}
+
+// Kinds of comprehension, by the brackets around them
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comprehension {
+  List,
+  Set,
+  Dict(Box<PyType>), // the value, with the key as the element
+  Generator,
+}
+
+// A clause of a comprehension, the first of which is always a for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Clause {
+  For(String, PyType), // loop variable, iterable
+  If(PyType),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FStringPart {
+  Literal(String),
+  Field(Box<PyType>, Option<char>, Vec<FStringPart>), // expression, conversion, format spec
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Stmt {
-  If(Box<PyType>, Vec<PyType>, Option<Vec<PyType>>), // condition, body if true, else body
-  VarAssign(String, Box<PyType>),
-  Function(String, Vec<String>, Vec<PyType>), // function name, parameter names, body
-  None,
+  If(Box<PyType>, Vec<Statement>, Option<Vec<Statement>>), // condition, body if true, else body
+  While(Box<PyType>, Vec<Statement>), // condition, body
+  For(String, Box<PyType>, Vec<Statement>), // loop variable, iterable, body
+  Assign(Vec<PyType>, Box<PyType>), // targets (names, subscripts or attributes) from left to right, value
+  AugAssign(Box<PyType>, Op, Box<PyType>), // target, operator, value
+  Del(Vec<PyType>), // targets
+  Function(String, Parameters, Vec<Statement>), // function name, parameters, body
+  Return(Box<PyType>),
+  Break,
+  Continue,
+  Pass,
+  Assert(Box<PyType>, Option<Box<PyType>>), // condition, message
+  Global(Vec<String>),
+  Nonlocal(Vec<String>),
 }
 
+// Parameters of a function definition, in the order they are declared
 #[derive(Clone, Debug, PartialEq)]
+pub struct Parameters {
+  pub positional: Vec<(String, Option<PyType>)>, // name, default value
+  pub posonly: usize,                            // number of positional parameters before the /
+  pub varargs: Option<String>,                   // *args
+  pub kwonly: Vec<(String, Option<PyType>)>,     // keyword-only parameters, after * or *args
+  pub varkw: Option<String>,                     // **kwargs
+}
+
+impl Parameters {
+  // Names in the order of the local slots the arguments are bound to: positional, keyword-only,
+  // *args then **kwargs
+  pub fn names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self.positional.iter().chain(&self.kwonly).map(|(name, _)| name.clone()).collect();
+    names.extend(self.varargs.iter().chain(&self.varkw).cloned());
+    names
+  }
+
+  // Default value expressions, positional then keyword-only
+  pub fn defaults(&self) -> impl Iterator<Item = &PyType> {
+    self.positional.iter().chain(&self.kwonly).filter_map(|(_, default)| default.as_ref())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Op {
   Add,
   Sub,
   Mult,
   Div,
+  FloorDiv,
+  Mod,
+  Pow,
+  BitAnd,
+  BitOr,
+  BitXor,
+  LShift,
+  RShift,
   Or,
   And,
   Equal,
@@ -126,37 +492,27 @@ pub enum Op {
   Greater,
   LessEqual,
   GreaterEqual,
-}
-
-impl PyType {
-  pub fn to_bool(&self) -> Result<bool, String> {
-    match self {
-      PyType::Expr(Expr::Bool(b)) => Ok(*b),
-      PyType::Expr(Expr::Int(n)) => {
-        if *n == 0 {
-          Ok(false)
-        } else {
-          Ok(true)
-        }
-      }
-      PyType::Expr(Expr::Float(n)) => {
-        if *n == 0.0 {
-          Ok(false)
-        } else {
-          Ok(true)
-        }
-      }
-      _ => Err(format!("TypeError: Cannot convert type to bool")),
-    }
-  }
+  In,
+  NotIn,
+  Is,
+  IsNot,
 }
 
 impl fmt::Display for Expr {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       Expr::Int(n) => write!(f, "{}", n),
-      Expr::Float(d) => write!(f, "{}", d),
+      Expr::Float(d) => write!(f, "{}", float_repr(*d)),
       Expr::String(s) => write!(f, "\"{}\"", s),
+      Expr::Bytes(b) => write!(f, "{}", repr_bytes(b)),
+      Expr::FString(parts) => {
+        write!(f, "f\"")?;
+
+        for part in parts {
+          write!(f, "{}", part)?;
+        }
+        write!(f, "\"")
+      }
       Expr::Bool(b) => {
         if *b {
           write!(f, "True")
@@ -164,60 +520,220 @@ impl fmt::Display for Expr {
           write!(f, "False")
         }
       }
+      Expr::None => write!(f, "None"),
       Expr::Var(v) => write!(f, "{}", v),
+      Expr::List(items) => {
+        write!(f, "List([")?;
+
+        for item in items {
+          write!(f, "{}, ", item)?;
+        }
+        write!(f, "])")
+      }
+      Expr::Tuple(items) => {
+        write!(f, "Tuple([")?;
+
+        for item in items {
+          write!(f, "{}, ", item)?;
+        }
+        write!(f, "])")
+      }
+      Expr::Dict(pairs) => {
+        write!(f, "Dict([")?;
+
+        for (key, value) in pairs {
+          write!(f, "{}: {}, ", key, value)?;
+        }
+        write!(f, "])")
+      }
+      Expr::Set(items) => {
+        write!(f, "Set([")?;
+
+        for item in items {
+          write!(f, "{}, ", item)?;
+        }
+        write!(f, "])")
+      }
       Expr::Binop(op, left, right) => {
         write!(f, "{} {} {}", left, op, right)
       }
+      Expr::Compare(first, comparisons) => {
+        write!(f, "{}", first)?;
+
+        for (op, operand) in comparisons {
+          write!(f, " {} {}", op, operand)?;
+        }
+        Ok(())
+      }
       Expr::Not(e) => write!(f, "Not({})", e),
-      Expr::Return(e) => write!(f, "Return({})", e),
-      Expr::FunctionCall(n, args) => {
-        write!(f, "FunctionCall({}, [", n);
+      Expr::Ternary(condition, if_true, if_false) => write!(f, "{} if {} else {}", if_true, condition, if_false),
+      Expr::Attribute(e, name) => write!(f, "{}.{}", e, name),
+      Expr::Subscript(e, index) => write!(f, "{}[{}]", e, index),
+      Expr::Slice(start, stop, step) => {
+        if let Some(start) = start {
+          write!(f, "{}", start)?;
+        }
+        write!(f, ":")?;
+        if let Some(stop) = stop {
+          write!(f, "{}", stop)?;
+        }
+        if let Some(step) = step {
+          write!(f, ":{}", step)?;
+        }
+        Ok(())
+      }
+      Expr::FunctionCall(func, args, kwargs) => {
+        write!(f, "FunctionCall({}, [", func)?;
 
         for arg in args {
-          write!(f, "{}, ", arg);
+          write!(f, "{}, ", arg)?;
+        }
+        for (name, arg) in kwargs {
+          match name {
+            Some(name) => write!(f, "{}={}, ", name, arg)?,
+            None => write!(f, "**{}, ", arg)?,
+          }
         }
 
         write!(f, "])")
       }
+      Expr::Starred(e) => write!(f, "*{}", e),
+      Expr::Yield(Some(e)) => write!(f, "Yield({})", e),
+      Expr::Yield(None) => write!(f, "Yield()"),
+      Expr::YieldFrom(e) => write!(f, "YieldFrom({})", e),
+      Expr::Comprehension(kind, element, clauses) => {
+        match kind {
+          Comprehension::List => write!(f, "ListComp({}", element)?,
+          Comprehension::Set => write!(f, "SetComp({}", element)?,
+          Comprehension::Dict(value) => write!(f, "DictComp({}: {}", element, value)?,
+          Comprehension::Generator => write!(f, "GeneratorExp({}", element)?,
+        }
+
+        for clause in clauses {
+          match clause {
+            Clause::For(var, iterable) => write!(f, " for {} in {}", var, iterable)?,
+            Clause::If(condition) => write!(f, " if {}", condition)?,
+          }
+        }
+        write!(f, ")")
+      }
     }
   }
 }
 
+impl fmt::Display for FStringPart {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FStringPart::Literal(s) => write!(f, "{}", s.replace('{', "{{").replace('}', "}}")),
+      FStringPart::Field(expr, conversion, spec) => {
+        write!(f, "{{{}", expr)?;
+        if let Some(c) = conversion {
+          write!(f, "!{}", c)?;
+        }
+        if !spec.is_empty() {
+          write!(f, ":")?;
+          for part in spec {
+            write!(f, "{}", part)?;
+          }
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+impl fmt::Display for Statement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.node)
+  }
+}
+
+impl fmt::Display for Parameters {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let param = |(name, default): &(String, Option<PyType>)| match default {
+      Some(default) => format!("{}={}", name, default),
+      None => name.clone(),
+    };
+    let mut parts: Vec<String> = self.positional.iter().map(param).collect();
+    if self.posonly > 0 {
+      parts.insert(self.posonly, "/".to_string());
+    }
+    match &self.varargs {
+      Some(name) => parts.push(format!("*{}", name)),
+      None if !self.kwonly.is_empty() => parts.push("*".to_string()),
+      None => {}
+    }
+    parts.extend(self.kwonly.iter().map(param));
+    if let Some(name) = &self.varkw {
+      parts.push(format!("**{}", name));
+    }
+    write!(f, "{}", parts.join(", "))
+  }
+}
+
 impl fmt::Display for Stmt {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Stmt::VarAssign(v, e) => write!(f, "{} = {}", v, e),
+      Stmt::Assign(targets, e) => {
+        for target in targets {
+          write!(f, "{} = ", target)?;
+        }
+        write!(f, "{}", e)
+      }
+      Stmt::AugAssign(target, op, e) => write!(f, "{} {}= {}", target, op, e),
       Stmt::If(condition, body, else_body) => {
-        write!(f, "If({}, [", condition);
+        write!(f, "If({}, [", condition)?;
 
         for expr in body {
-          write!(f, "{}, ", expr);
+          write!(f, "{}, ", expr)?;
         }
-        write!(f, "])");
+        write!(f, "])")?;
         if let Some(else_body_list) = else_body {
-          write!(f, " Else [");
+          write!(f, " Else [")?;
           for expr in else_body_list {
-            write!(f, "{}, ", expr);
+            write!(f, "{}, ", expr)?;
           }
           write!(f, "]")
         } else {
           write!(f, "")
         }
       }
-      Stmt::Function(name, parameters, body) => {
-        write!(f, "Def({}, [", name);
+      Stmt::While(condition, body) => {
+        write!(f, "While({}, [", condition)?;
+
+        for expr in body {
+          write!(f, "{}, ", expr)?;
+        }
+        write!(f, "])")
+      }
+      Stmt::For(var, iterable, body) => {
+        write!(f, "For({}, {}, [", var, iterable)?;
 
-        for p in parameters {
-          write!(f, "{}, ", p);
+        for expr in body {
+          write!(f, "{}, ", expr)?;
         }
-        write!(f, "], [");
+        write!(f, "])")
+      }
+      Stmt::Function(name, parameters, body) => {
+        write!(f, "Def({}, [{}], [", name, parameters)?;
 
         for line in body {
-          write!(f, "{}, ", line);
+          write!(f, "{}, ", line)?;
         }
         write!(f, "])")
       }
-      Stmt::None => write!(f, ""),
+      Stmt::Return(e) => write!(f, "Return({})", e),
+      Stmt::Del(targets) => {
+        let targets: Vec<String> = targets.iter().map(|target| target.to_string()).collect();
+        write!(f, "Del({})", targets.join(", "))
+      }
+      Stmt::Break => write!(f, "Break"),
+      Stmt::Continue => write!(f, "Continue"),
+      Stmt::Pass => write!(f, "Pass"),
+      Stmt::Assert(condition, Some(message)) => write!(f, "Assert({}, {})", condition, message),
+      Stmt::Assert(condition, None) => write!(f, "Assert({})", condition),
+      Stmt::Global(names) => write!(f, "Global({})", names.join(", ")),
+      Stmt::Nonlocal(names) => write!(f, "Nonlocal({})", names.join(", ")),
     }
   }
 }
@@ -229,6 +745,14 @@ impl fmt::Display for Op {
       Op::Sub => write!(f, "-"),
       Op::Mult => write!(f, "*"),
       Op::Div => write!(f, "/"),
+      Op::FloorDiv => write!(f, "//"),
+      Op::Mod => write!(f, "%"),
+      Op::Pow => write!(f, "**"),
+      Op::BitAnd => write!(f, "&"),
+      Op::BitOr => write!(f, "|"),
+      Op::BitXor => write!(f, "^"),
+      Op::LShift => write!(f, "<<"),
+      Op::RShift => write!(f, ">>"),
       Op::Or => write!(f, "or"),
       Op::And => write!(f, "and"),
       Op::Equal => write!(f, "=="),
@@ -237,16 +761,35 @@ impl fmt::Display for Op {
       Op::Greater => write!(f, ">"),
       Op::LessEqual => write!(f, "<="),
       Op::GreaterEqual => write!(f, ">="),
+      Op::In => write!(f, "in"),
+      Op::NotIn => write!(f, "not in"),
+      Op::Is => write!(f, "is"),
+      Op::IsNot => write!(f, "is not"),
     }
   }
 }
 
-pub type Environment = Vec<(String, PyType)>;
+// Python's repr() of a float: the shortest digits that round-trip, in positional notation for
+// exponents from -4 to 15 (always with a fractional part) and scientific notation otherwise
+pub fn float_repr(d: f64) -> String {
+  if d.is_nan() {
+    return "nan".to_string();
+  } else if d.is_infinite() {
+    return if d > 0.0 { "inf".to_string() } else { "-inf".to_string() };
+  }
 
-pub fn print_env(env: &Environment) {
-  print!("Env: [");
-  for (v, e) in env {
-    print!("{} = {}, ", v, e);
+  let scientific = format!("{:e}", d);
+  let (mantissa, exponent) = scientific.split_once('e').unwrap();
+  let exponent = exponent.parse::<i32>().unwrap();
+
+  if (-4..16).contains(&exponent) {
+    let positional = format!("{}", d);
+    if positional.contains('.') {
+      positional
+    } else {
+      positional + ".0"
+    }
+  } else {
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
   }
-  println!("]");
 }
@@ -0,0 +1,222 @@
+// Character classes and case mappings that follow Python's str methods where Rust's char
+// methods differ
+
+use unicode_general_category::{get_general_category, GeneralCategory};
+
+// First code point of each run of ten decimal digits (category Nd)
+const DECIMAL_ZEROS: &[u32] = &[
+  0x30, 0x660, 0x6F0, 0x7C0, 0x966, 0x9E6, 0xA66, 0xAE6, 0xB66, 0xBE6, 0xC66, 0xCE6, 0xD66, 0xDE6,
+  0xE50, 0xED0, 0xF20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80, 0x1A90, 0x1B50,
+  0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0, 0xFF10, 0x104A0,
+  0x10D30, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450, 0x114D0, 0x11650, 0x116C0,
+  0x11730, 0x118E0, 0x11950, 0x11C50, 0x11D50, 0x11DA0, 0x16A60, 0x16AC0, 0x16B50, 0x1D7CE,
+  0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E140, 0x1E2F0, 0x1E950, 0x1FBF0,
+];
+
+// Digits that are not decimal, such as superscripts and circled digits
+const OTHER_DIGITS: &[(u32, u32)] = &[
+  (0xB2, 0xB3), (0xB9, 0xB9), (0x1369, 0x1371), (0x19DA, 0x19DA), (0x2070, 0x2070),
+  (0x2074, 0x2079), (0x2080, 0x2089), (0x2460, 0x2468), (0x2474, 0x247C), (0x2488, 0x2490),
+  (0x24EA, 0x24EA), (0x24F5, 0x24FD), (0x24FF, 0x24FF), (0x2776, 0x277E), (0x2780, 0x2788),
+  (0x278A, 0x2792), (0x10A40, 0x10A43), (0x10E60, 0x10E68), (0x11052, 0x1105A), (0x1F100, 0x1F10A),
+];
+
+// Ideographs with a numeric value, such as the CJK numerals, whose category is Lo rather than a
+// number category but whose Numeric_Type is Numeric
+const NUMERIC_IDEOGRAPHS: &[(u32, u32)] = &[
+  (0x3405, 0x3405), (0x3483, 0x3483), (0x382A, 0x382A), (0x3B4D, 0x3B4D), (0x4E00, 0x4E00), (0x4E03, 0x4E03),
+  (0x4E07, 0x4E07), (0x4E09, 0x4E09), (0x4E5D, 0x4E5D), (0x4E8C, 0x4E8C), (0x4E94, 0x4E94), (0x4E96, 0x4E96),
+  (0x4EBF, 0x4EC0), (0x4EDF, 0x4EDF), (0x4EE8, 0x4EE8), (0x4F0D, 0x4F0D), (0x4F70, 0x4F70), (0x5104, 0x5104),
+  (0x5146, 0x5146), (0x5169, 0x5169), (0x516B, 0x516B), (0x516D, 0x516D), (0x5341, 0x5341), (0x5343, 0x5345),
+  (0x534C, 0x534C), (0x53C1, 0x53C4), (0x56DB, 0x56DB), (0x58F1, 0x58F1), (0x58F9, 0x58F9), (0x5E7A, 0x5E7A),
+  (0x5EFE, 0x5EFF), (0x5F0C, 0x5F0E), (0x5F10, 0x5F10), (0x62FE, 0x62FE), (0x634C, 0x634C), (0x67D2, 0x67D2),
+  (0x6F06, 0x6F06), (0x7396, 0x7396), (0x767E, 0x767E), (0x8086, 0x8086), (0x842C, 0x842C), (0x8CAE, 0x8CAE),
+  (0x8CB3, 0x8CB3), (0x8D30, 0x8D30), (0x9621, 0x9621), (0x9646, 0x9646), (0x964C, 0x964C), (0x9678, 0x9678),
+  (0x96F6, 0x96F6), (0xF96B, 0xF96B), (0xF973, 0xF973), (0xF978, 0xF978), (0xF9B2, 0xF9B2), (0xF9D1, 0xF9D1),
+  (0xF9D3, 0xF9D3), (0xF9FD, 0xF9FD), (0x20001, 0x20001), (0x20064, 0x20064), (0x200E2, 0x200E2),
+  (0x20121, 0x20121), (0x2092A, 0x2092A), (0x20983, 0x20983), (0x2098C, 0x2098C), (0x2099C, 0x2099C),
+  (0x20AEA, 0x20AEA), (0x20AFD, 0x20AFD), (0x20B19, 0x20B19), (0x22390, 0x22390), (0x22998, 0x22998),
+  (0x23B1B, 0x23B1B), (0x2626D, 0x2626D), (0x2F890, 0x2F890),
+];
+
+fn in_ranges(c: char, ranges: &[(u32, u32)]) -> bool {
+  ranges.iter().any(|&(low, high)| (low..=high).contains(&(c as u32)))
+}
+
+// The value of a decimal digit from any script
+pub fn decimal_value(c: char) -> Option<u32> {
+  let code = c as u32;
+  let i = DECIMAL_ZEROS.partition_point(|&zero| zero <= code);
+  match i {
+    0 => None,
+    _ if code - DECIMAL_ZEROS[i - 1] < 10 => Some(code - DECIMAL_ZEROS[i - 1]),
+    _ => None,
+  }
+}
+
+pub fn is_decimal(c: char) -> bool {
+  decimal_value(c).is_some()
+}
+
+pub fn is_digit(c: char) -> bool {
+  is_decimal(c) || in_ranges(c, OTHER_DIGITS)
+}
+
+// Categories Nd, Nl and No, plus the ideographs that only have a numeric value
+pub fn is_numeric(c: char) -> bool {
+  use GeneralCategory::*;
+  matches!(get_general_category(c), DecimalNumber | LetterNumber | OtherNumber) || in_ranges(c, NUMERIC_IDEOGRAPHS)
+}
+
+// Letters only (categories Lu, Ll, Lt, Lm and Lo), where Rust also counts letter numbers, vowel
+// signs and circled letters as alphabetic
+pub fn is_alpha(c: char) -> bool {
+  use GeneralCategory::*;
+  matches!(get_general_category(c), UppercaseLetter | LowercaseLetter | TitlecaseLetter | ModifierLetter | OtherLetter)
+}
+
+// Python also treats the file, group, record and unit separators as whitespace
+pub fn is_space(c: char) -> bool {
+  c.is_whitespace() || ('\x1c'..='\x1f').contains(&c)
+}
+
+// Line boundaries recognised by str.splitlines()
+pub fn is_linebreak(c: char) -> bool {
+  matches!(c, '\n' | '\r' | '\x0b' | '\x0c' | '\x1c' | '\x1d' | '\x1e' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+// Everything but the space is unprintable if it is a separator or in one of the C categories,
+// which includes unassigned code points
+pub fn is_printable(c: char) -> bool {
+  use GeneralCategory::*;
+  let hidden = matches!(
+    get_general_category(c),
+    Control | Format | Surrogate | PrivateUse | Unassigned | LineSeparator | ParagraphSeparator | SpaceSeparator
+  );
+  c == ' ' || !hidden
+}
+
+// Digraphs such as ǅ and Greek capitals with prosgegrammeni, which are neither upper nor lower case
+pub fn is_titlecase(c: char) -> bool {
+  matches!(c as u32, 0x1C5 | 0x1C8 | 0x1CB | 0x1F2 | 0x1F88..=0x1F8F | 0x1F98..=0x1F9F | 0x1FA8..=0x1FAF | 0x1FBC | 0x1FCC | 0x1FFC)
+}
+
+pub fn is_cased(c: char) -> bool {
+  c.is_uppercase() || c.is_lowercase() || is_titlecase(c)
+}
+
+// The form of a character that starts a word in str.title() and str.capitalize()
+pub fn to_titlecase(c: char) -> String {
+  let code = c as u32;
+  let title = match code {
+    0x1C4..=0x1C6 => 0x1C5,
+    0x1C7..=0x1C9 => 0x1C8,
+    0x1CA..=0x1CC => 0x1CB,
+    0x1F1..=0x1F3 => 0x1F2,
+    // Georgian letters have no title case, their upper case is a separate alphabet
+    0x10D0..=0x10FF => code,
+    0x1F80..=0x1FAF => code | 0x8,
+    0x1FB3 | 0x1FC3 | 0x1FF3 => code + 9,
+    0x1FBC | 0x1FCC | 0x1FFC => code,
+    _ => {
+      // Characters that upper case to several, such as ß to SS, keep only the first letter in upper
+      // case. A capital iota standing for an iota subscript goes back to being a subscript
+      let mut seen_cased = false;
+      return c
+        .to_uppercase()
+        .flat_map(|u| {
+          let keep = !seen_cased;
+          seen_cased |= is_cased(u);
+          match u {
+            _ if keep => vec![u],
+            '\u{399}' => vec!['\u{345}'],
+            _ => u.to_lowercase().collect(),
+          }
+        })
+        .collect();
+    }
+  };
+  char::from_u32(title).unwrap_or(c).to_string()
+}
+
+// Full case folding for caseless comparison, so ß folds to ss like SS does
+pub fn casefold(c: char) -> String {
+  match c as u32 {
+    // Dotless i has no case-insensitive partner, and Cherokee folds to its older upper case letters
+    0x131 | 0x13A0..=0x13F5 => c.to_string(),
+    0x13F8..=0x13FD | 0xAB70..=0xABBF => c.to_uppercase().collect(),
+    0x1E9E => "ss".to_string(),
+    _ => c.to_uppercase().flat_map(char::to_lowercase).collect(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn all(s: &str, class: fn(char) -> bool) -> bool {
+    s.chars().all(class)
+  }
+
+  #[test]
+  fn marks_and_symbols_are_not_alpha() {
+    // Devanagari vowel signs and the virama are marks (Mn, Mc), not letters
+    assert!(!all("हिंदी", is_alpha));
+    assert!(!all("का", is_alpha));
+    assert!(is_alpha('क'));
+    // Circled letters are symbols (So)
+    assert!(!is_alpha('ⓐ'));
+    assert!(all("aÅǅʰ中", is_alpha));
+    assert!(!is_alpha('Ⅷ'));
+  }
+
+  #[test]
+  fn numeric_includes_numeric_type() {
+    assert!(all("一二三", is_numeric));
+    assert!(all("零百千万億", is_numeric));
+    assert!(all("12½²Ⅷ٣", is_numeric));
+    assert!(!is_numeric('中'));
+    assert!(!is_numeric('a'));
+  }
+
+  #[test]
+  fn digits_and_decimals() {
+    assert_eq!(decimal_value('٣'), Some(3));
+    assert_eq!(decimal_value('7'), Some(7));
+    assert_eq!(decimal_value('²'), None);
+    assert!(is_digit('²') && !is_decimal('²'));
+    assert!(!is_digit('½'));
+  }
+
+  #[test]
+  fn unassigned_is_not_printable() {
+    assert!(!is_printable('\u{378}'));
+    assert!(!is_printable('\u{E000}'));
+    assert!(!is_printable('\u{AD}'));
+    assert!(!is_printable('\n'));
+    assert!(!is_printable('\u{A0}'));
+    assert!(is_printable(' '));
+    assert!(all("abcहिंदीⓐ一", is_printable));
+  }
+
+  #[test]
+  fn whitespace_and_line_breaks() {
+    let spaces: Vec<u32> = (0..0x3000).filter(|&c| char::from_u32(c).is_some_and(is_space)).collect();
+    let expected = [
+      0x9, 0xA, 0xB, 0xC, 0xD, 0x1C, 0x1D, 0x1E, 0x1F, 0x20, 0x85, 0xA0, 0x1680, 0x2000, 0x2001, 0x2002, 0x2003,
+      0x2004, 0x2005, 0x2006, 0x2007, 0x2008, 0x2009, 0x200A, 0x2028, 0x2029, 0x202F, 0x205F,
+    ];
+    assert_eq!(spaces, expected);
+    assert!(all("\n\r\x0b\x0c\x1c\x1d\x1e\u{85}\u{2028}\u{2029}", is_linebreak));
+    assert!(!is_linebreak('\x1f') && !is_linebreak(' ') && !is_linebreak('\t'));
+  }
+
+  #[test]
+  fn case_mappings() {
+    assert_eq!(to_titlecase('ǆ'), "ǅ");
+    assert_eq!(to_titlecase('ß'), "Ss");
+    assert_eq!(casefold('ß'), "ss");
+    assert_eq!(casefold('ẞ'), "ss");
+    assert!(is_titlecase('ǅ') && is_cased('ǅ'));
+  }
+}
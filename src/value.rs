@@ -0,0 +1,1139 @@
+use crate::bytecode::CodeObject;
+use crate::generator::Step;
+use crate::interpreter::{self, StaticScope};
+use crate::stdio::Stream;
+use crate::types::{float_repr, Parameters, Statement};
+use crate::unicode;
+use crate::vm;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Python's default recursion limit. It bounds the calls in both engines and how deeply repr() and ==
+// go into nested containers
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+// Runtime values produced by the interpreter. Immutable scalars are stored inline, everything
+// else is a reference-counted heap object so that assignment and argument passing share the
+// object (as in Python) instead of copying it
+#[derive(Clone)]
+pub enum Value {
+  None,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Str(Rc<str>),
+  Bytes(Rc<[u8]>),
+  List(Rc<RefCell<Vec<Value>>>),
+  Tuple(Rc<Vec<Value>>),
+  Dict(Rc<RefCell<Dict>>),
+  Set(Rc<RefCell<Dict>>), // keys of the table are the members, values are unused
+  Function(Rc<Function>),
+  Builtin(Builtin),
+  Method(Rc<Method>),
+  Iterator(Rc<RefCell<Iter>>),
+  Slice(Rc<[Value; 3]>), // start, stop, step, each None when left out
+  Range(i64, i64, i64),  // start, stop, step
+  Type(&'static str),    // a built-in type, called to convert a value or create an instance
+  Exception(Rc<Exception>),
+  Module(&'static str), // a built-in module, given by import
+  Stream(Stream),       // sys.stdout or sys.stderr
+}
+
+// Dropping a list nested a million deep one level at a time would overflow the stack, so the
+// items of a container that is about to be freed are moved out and dropped in a loop instead
+impl Drop for Value {
+  fn drop(&mut self) {
+    let mut pending = Vec::new();
+    take_items(self, &mut pending);
+    while let Some(mut value) = pending.pop() {
+      take_items(&mut value, &mut pending);
+    }
+  }
+}
+
+fn take_items(value: &mut Value, pending: &mut Vec<Value>) {
+  match value {
+    Value::List(items) => {
+      if let Some(items) = Rc::get_mut(items) {
+        pending.append(items.get_mut());
+      }
+    }
+    Value::Tuple(items) => {
+      if let Some(items) = Rc::get_mut(items) {
+        pending.append(items);
+      }
+    }
+    Value::Dict(table) | Value::Set(table) => {
+      if let Some(table) = Rc::get_mut(table) {
+        let table = table.get_mut();
+        table.index.clear();
+        for (key, value) in table.entries.drain(..) {
+          pending.push(key);
+          pending.push(value);
+        }
+      }
+    }
+    _ => {}
+  }
+}
+
+// A function implemented in Rust, called with its positional and keyword arguments
+#[derive(Clone, Copy)]
+pub struct Builtin {
+  pub name: &'static str,
+  pub func: fn(&[Value], &Keywords, &mut dyn Caller) -> Result<Value, String>,
+}
+
+// Makes calls on behalf of builtins that take a function, such as map() or sorted(key=...). Each
+// engine implements it with its own way of running a user-defined function
+pub trait Caller {
+  fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, String>;
+
+  // Runs a suspended generator frame until its next yield, or until it returns. The value sent
+  // back is the result of the yield it was suspended at, and is None when the generator starts
+  fn resume(&mut self, frame: &mut Option<GeneratorFrame>, sent: Option<Value>) -> Result<Resumed, String>;
+}
+
+// Keyword arguments of a call, in the order they were given
+pub type Keywords = [(String, Value)];
+
+// A built-in method bound to the object it was looked up on, which is passed as the first argument
+pub struct Method {
+  pub receiver: Value,
+  pub builtin: Builtin,
+}
+
+// An instance of a built-in exception type. There is no try statement, so an exception value is
+// only ever raised by generator.throw()
+pub struct Exception {
+  pub kind: &'static str,
+  pub args: Vec<Value>,
+}
+
+impl Exception {
+  // What str() gives, which is the text after the type name in an error message
+  pub fn message(&self) -> Result<String, String> {
+    match &self.args[..] {
+      [] => Ok(String::new()),
+      [arg] if self.kind == "KeyError" => arg.repr(),
+      [arg] => arg.str(),
+      _ => Value::Tuple(Rc::new(self.args.clone())).repr(),
+    }
+  }
+
+  // The error the exception is raised as
+  pub fn error(&self) -> String {
+    match self.message() {
+      Ok(message) if message.is_empty() => self.kind.to_string(),
+      Ok(message) => format!("{}: {}", self.kind, message),
+      Err(error) => error,
+    }
+  }
+}
+
+// A user-defined function
+pub struct Function {
+  pub name: String,
+  pub signature: Signature,
+  pub defaults: Vec<Value>,             // values of the last positional parameters when not given
+  pub kwdefaults: Vec<(String, Value)>, // values of keyword-only parameters when not given
+  pub body: FunctionBody,
+  pub attributes: RefCell<HashMap<String, Value>>, // set by assigning to function.name
+}
+
+// The parameters of a function, as needed to bind the arguments of a call
+#[derive(Clone)]
+pub struct Signature {
+  pub names: Vec<String>, // positional, keyword-only, *args then **kwargs, which is the order of the local slots
+  pub argcount: usize,    // positional parameters, including the positional-only ones
+  pub posonly: usize,
+  pub kwonly: usize,
+  pub varargs: bool,
+  pub varkw: bool,
+}
+
+impl Signature {
+  pub fn new(parameters: &Parameters) -> Signature {
+    Signature {
+      names: parameters.names(),
+      argcount: parameters.positional.len(),
+      posonly: parameters.posonly,
+      kwonly: parameters.kwonly.len(),
+      varargs: parameters.varargs.is_some(),
+      varkw: parameters.varkw.is_some(),
+    }
+  }
+}
+
+impl Function {
+  // Matches the arguments of a call to the parameters, giving the value of each local slot in the
+  // order of signature.names. Errors are checked in the same order as CPython
+  pub fn bind_arguments(&self, args: Vec<Value>, kwargs: Vec<(String, Value)>) -> Result<Vec<Value>, String> {
+    let sig = &self.signature;
+    let given = args.len();
+    let mut bound: Vec<Option<Value>> = vec![None; sig.argcount + sig.kwonly];
+    let mut extra = Vec::new();
+    for (i, arg) in args.into_iter().enumerate() {
+      if i < sig.argcount {
+        bound[i] = Some(arg);
+      } else {
+        extra.push(arg);
+      }
+    }
+
+    // Positional-only parameters can't be given by keyword, so such a keyword goes to **kwargs
+    let mut extra_kwargs = Dict::new();
+    for (name, value) in &kwargs {
+      match sig.names[sig.posonly..bound.len()].iter().position(|param| param == name) {
+        Some(i) if bound[sig.posonly + i].is_some() => {
+          return Err(format!("TypeError: {}() got multiple values for argument '{}'", self.name, name));
+        }
+        Some(i) => bound[sig.posonly + i] = Some(value.clone()),
+        None if sig.varkw => extra_kwargs.insert(Value::Str(Rc::from(name.as_str())), value.clone())?,
+        None => {
+          let posonly: Vec<&str> =
+            kwargs.iter().map(|(name, _)| name.as_str()).filter(|name| sig.names[..sig.posonly].iter().any(|param| param == name)).collect();
+          if posonly.is_empty() {
+            return Err(format!("TypeError: {}() got an unexpected keyword argument '{}'", self.name, name));
+          }
+          return Err(format!(
+            "TypeError: {}() got some positional-only arguments passed as keyword arguments: '{}'",
+            self.name,
+            posonly.join(", ")
+          ));
+        }
+      }
+    }
+
+    if !extra.is_empty() && !sig.varargs {
+      let takes = match self.defaults.len() {
+        0 if sig.argcount == 1 => "1 positional argument".to_string(),
+        0 => format!("{} positional arguments", sig.argcount),
+        n => format!("from {} to {} positional arguments", sig.argcount - n, sig.argcount),
+      };
+      let kwonly_given = bound[sig.argcount..].iter().filter(|value| value.is_some()).count();
+      let given = match kwonly_given {
+        0 if given == 1 => "1 was".to_string(),
+        0 => format!("{} were", given),
+        n => format!(
+          "{} positional argument{} (and {} keyword-only argument{}) were",
+          given,
+          if given == 1 { "" } else { "s" },
+          n,
+          if n == 1 { "" } else { "s" }
+        ),
+      };
+      return Err(format!("TypeError: {}() takes {} but {} given", self.name, takes, given));
+    }
+
+    let required = sig.argcount - self.defaults.len();
+    let mut missing = Vec::new();
+    for (i, value) in bound[..sig.argcount].iter_mut().enumerate() {
+      if value.is_none() {
+        if i >= required {
+          *value = Some(self.defaults[i - required].clone());
+        } else {
+          missing.push(format!("'{}'", sig.names[i]));
+        }
+      }
+    }
+    if !missing.is_empty() {
+      return Err(self.missing_arguments("positional", &missing));
+    }
+
+    for (name, value) in sig.names[sig.argcount..].iter().zip(&mut bound[sig.argcount..]) {
+      if value.is_none() {
+        match self.kwdefaults.iter().find(|(kwonly, _)| kwonly == name) {
+          Some((_, default)) => *value = Some(default.clone()),
+          None => missing.push(format!("'{}'", name)),
+        }
+      }
+    }
+    if !missing.is_empty() {
+      return Err(self.missing_arguments("keyword-only", &missing));
+    }
+
+    let mut values: Vec<Value> = bound.into_iter().map(Option::unwrap).collect();
+    if sig.varargs {
+      values.push(Value::Tuple(Rc::new(extra)));
+    }
+    if sig.varkw {
+      values.push(Value::Dict(Rc::new(RefCell::new(extra_kwargs))));
+    }
+    Ok(values)
+  }
+
+  fn missing_arguments(&self, kind: &str, missing: &[String]) -> String {
+    let names = match missing.len() {
+      1 => missing[0].clone(),
+      2 => format!("{} and {}", missing[0], missing[1]),
+      n => format!("{}, and {}", missing[..n - 1].join(", "), missing[n - 1]),
+    };
+    let plural = if missing.len() == 1 { "argument" } else { "arguments" };
+    format!("TypeError: {}() missing {} required {} {}: {}", self.name, missing.len(), kind, plural, names)
+  }
+}
+
+// How a callable is named in errors about unpacking the arguments of a call
+fn call_description(function: &Value) -> String {
+  match function {
+    Value::Function(func) => format!("__main__.{}()", func.name),
+    Value::Builtin(builtin) => format!("{}()", builtin.name),
+    Value::Method(method) => format!("{}.{}()", method.receiver.type_name(), method.builtin.name),
+    Value::Type(name) => format!("{}()", name),
+    _ => format!("{} object", function.type_name()),
+  }
+}
+
+// The items of the iterable after * in a call, which are passed as positional arguments
+pub fn unpack_arguments(function: &Value, iterable: &Value, caller: &mut dyn Caller) -> Result<Vec<Value>, String> {
+  if iterable.to_iterator().is_err() {
+    return Err(format!(
+      "TypeError: {} argument after * must be an iterable, not {}",
+      call_description(function),
+      iterable.type_name()
+    ));
+  }
+  iterable.items(caller)
+}
+
+// Adds the entries of the mapping after ** in a call to the keyword arguments collected so far
+pub fn merge_keywords(function: &Value, kwargs: &mut Dict, mapping: &Value) -> Result<(), String> {
+  let entries = match mapping {
+    Value::Dict(table) => table.borrow().entries().to_vec(),
+    _ => {
+      return Err(format!(
+        "TypeError: {} argument after ** must be a mapping, not {}",
+        call_description(function),
+        mapping.type_name()
+      ))
+    }
+  };
+  for (key, value) in entries {
+    match &key {
+      Value::Str(name) if kwargs.contains(&key)? => {
+        return Err(format!("TypeError: {} got multiple values for keyword argument '{}'", call_description(function), name));
+      }
+      Value::Str(_) => kwargs.insert(key, value)?,
+      _ => return Err("TypeError: keywords must be strings".to_string()),
+    }
+  }
+  Ok(())
+}
+
+// Keyword arguments collected by merge_keywords, whose keys are all strings
+pub fn keywords(kwargs: &Dict) -> Vec<(String, Value)> {
+  kwargs.entries().iter().map(|(key, value)| (key.to_string(), value.clone())).collect()
+}
+
+// What a function runs, which depends on the engine that defined it
+pub enum FunctionBody {
+  // Tree-walker: the statements of the body, the local scopes of the enclosing function calls
+  // (innermost last) and the names the body binds or declares global or nonlocal
+  Ast(Rc<Vec<Statement>>, Vec<Scope>, Rc<StaticScope>),
+  // Bytecode VM: the compiled body and the cells of its free variables
+  Code(Rc<CodeObject>, Vec<Cell>),
+  // Tree-walker generator function: the body lowered to steps it can be suspended between, the
+  // local scopes of the enclosing function calls and the body's names
+  Steps(Rc<Vec<Step>>, Vec<Scope>, Rc<StaticScope>),
+}
+
+pub type Scope = Rc<RefCell<HashMap<String, Value>>>;
+
+// Variable shared between a function and the functions nested in it. None until it is assigned
+pub type Cell = Rc<RefCell<Option<Value>>>;
+
+// Hashable projection of a value, used to index dicts and sets. Numbers that compare equal
+// (True, 1 and 1.0) share a key, as Python requires of hash()
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+  None,
+  Int(i64),
+  Float(u64),
+  Str(Rc<str>),
+  Bytes(Rc<[u8]>),
+  Tuple(Vec<HashKey>),
+  Range(i64, i64, i64), // length, then start and step when they affect the items
+  Type(&'static str),
+  Id(usize),
+}
+
+// Insertion-ordered hash table backing dict and set
+#[derive(Default)]
+pub struct Dict {
+  entries: Vec<(Value, Value)>,
+  index: HashMap<HashKey, usize>,
+}
+
+impl Dict {
+  pub fn new() -> Dict {
+    Dict::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn get(&self, key: &Value) -> Result<Option<Value>, String> {
+    let hash_key = key.hash_key()?;
+    Ok(self.index.get(&hash_key).map(|i| self.entries[*i].1.clone()))
+  }
+
+  pub fn contains(&self, key: &Value) -> Result<bool, String> {
+    Ok(self.index.contains_key(&key.hash_key()?))
+  }
+
+  // Overwriting an existing entry keeps the original key object, like Python
+  pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+    let hash_key = key.hash_key()?;
+    match self.index.get(&hash_key) {
+      Some(i) => self.entries[*i].1 = value,
+      None => {
+        self.index.insert(hash_key, self.entries.len());
+        self.entries.push((key, value));
+      }
+    }
+    Ok(())
+  }
+
+  // Removes an entry, returning its value. Later entries keep their order
+  pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, String> {
+    let i = match self.index.remove(&key.hash_key()?) {
+      Some(i) => i,
+      None => return Ok(None),
+    };
+    for position in self.index.values_mut() {
+      if *position > i {
+        *position -= 1;
+      }
+    }
+    Ok(Some(self.entries.remove(i).1))
+  }
+
+  pub fn entries(&self) -> &[(Value, Value)] {
+    &self.entries
+  }
+
+  pub fn key_at(&self, i: usize) -> Option<Value> {
+    self.entries.get(i).map(|(key, _)| key.clone())
+  }
+}
+
+// Iterator over the items of a list, tuple, string, bytes (as ints), dict (keys) or set. Lists are
+// read live so that appending during a for loop is seen by the loop, as in Python
+pub struct SeqIter {
+  seq: Value,
+  pos: usize, // item index, or byte offset for strings
+}
+
+impl SeqIter {
+  pub fn type_name(&self) -> &'static str {
+    match self.seq {
+      Value::List(_) => "list_iterator",
+      Value::Tuple(_) => "tuple_iterator",
+      Value::Str(_) => "str_iterator",
+      Value::Bytes(_) => "bytes_iterator",
+      Value::Dict(_) => "dict_keyiterator",
+      _ => "set_iterator",
+    }
+  }
+}
+
+impl Iterator for SeqIter {
+  type Item = Value;
+
+  fn next(&mut self) -> Option<Value> {
+    match &self.seq {
+      Value::List(items) => {
+        let item = items.borrow().get(self.pos).cloned();
+        self.pos += 1;
+        item
+      }
+      Value::Tuple(items) => {
+        let item = items.get(self.pos).cloned();
+        self.pos += 1;
+        item
+      }
+      Value::Str(s) => {
+        let c = s[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(Value::Str(Rc::from(c.to_string())))
+      }
+      Value::Bytes(b) => {
+        let byte = b.get(self.pos)?;
+        self.pos += 1;
+        Some(Value::Int(*byte as i64))
+      }
+      Value::Dict(table) | Value::Set(table) => {
+        let key = table.borrow().key_at(self.pos);
+        self.pos += 1;
+        key
+      }
+      _ => None,
+    }
+  }
+}
+
+// State of an iterator. map() and filter() call a function for each item, so advancing an
+// iterator takes a Caller
+pub enum Iter {
+  Seq(SeqIter),
+  Range { next: i64, step: i64, remaining: u64 },
+  Reversed(&'static str, Vec<Value>), // type name, items still to come in reverse order
+  Enumerate(Value, i64),              // iterator, count given to its next item
+  Zip(Vec<Value>, bool),              // iterators, whether they must all have the same length
+  Map(Value, Vec<Value>),             // function, iterators of its arguments
+  Filter(Value, Value),               // function (None keeps truthy items), iterator
+  Callable(Value, Option<Value>),     // function, sentinel that ends the iteration (None once it has)
+  Generator(Generator),
+}
+
+impl Iter {
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Iter::Seq(seq) => seq.type_name(),
+      Iter::Range { .. } => "range_iterator",
+      Iter::Reversed(name, _) => name,
+      Iter::Enumerate(..) => "enumerate",
+      Iter::Zip(..) => "zip",
+      Iter::Map(..) => "map",
+      Iter::Filter(..) => "filter",
+      Iter::Callable(..) => "callable_iterator",
+      Iter::Generator(_) => "generator",
+    }
+  }
+
+  pub fn next(&mut self, caller: &mut dyn Caller) -> Result<Option<Value>, String> {
+    match self {
+      Iter::Seq(seq) => Ok(seq.next()),
+      Iter::Range { next, step, remaining } => {
+        if *remaining == 0 {
+          return Ok(None);
+        }
+        let item = *next;
+        *next = next.wrapping_add(*step);
+        *remaining -= 1;
+        Ok(Some(Value::Int(item)))
+      }
+      Iter::Reversed(_, items) => Ok(items.pop()),
+      Iter::Enumerate(iterator, count) => match next_item(iterator, caller)? {
+        Some(item) => {
+          let index = Value::Int(*count);
+          *count += 1;
+          Ok(Some(Value::Tuple(Rc::new(vec![index, item]))))
+        }
+        None => Ok(None),
+      },
+      // zip() of no iterables is empty
+      Iter::Zip(iterators, _) if iterators.is_empty() => Ok(None),
+      Iter::Zip(iterators, strict) => {
+        let mut items = Vec::with_capacity(iterators.len());
+        for (i, iterator) in iterators.iter().enumerate() {
+          match next_item(iterator, caller)? {
+            Some(item) => items.push(item),
+            None if *strict => return zip_mismatch(iterators, i, caller),
+            None => return Ok(None),
+          }
+        }
+        Ok(Some(Value::Tuple(Rc::new(items))))
+      }
+      Iter::Map(function, iterators) => {
+        let mut args = Vec::with_capacity(iterators.len());
+        for iterator in iterators.iter() {
+          match next_item(iterator, caller)? {
+            Some(item) => args.push(item),
+            None => return Ok(None),
+          }
+        }
+        caller.call(function, args).map(Some)
+      }
+      Iter::Filter(function, iterator) => {
+        while let Some(item) = next_item(iterator, caller)? {
+          let keep = match function {
+            Value::None => item.is_truthy(),
+            _ => caller.call(function, vec![item.clone()])?.is_truthy(),
+          };
+          if keep {
+            return Ok(Some(item));
+          }
+        }
+        Ok(None)
+      }
+      Iter::Callable(function, sentinel) => {
+        let end = match sentinel {
+          Some(end) => end.clone(),
+          None => return Ok(None),
+        };
+        let item = caller.call(function, Vec::new())?;
+        if item.equals(&end)? {
+          *sentinel = None;
+          return Ok(None);
+        }
+        Ok(Some(item))
+      }
+      // The value a generator returns is dropped when it is iterated over
+      Iter::Generator(generator) => match generator.resume(Value::None, caller)? {
+        Resumed::Yield(item) => Ok(Some(item)),
+        Resumed::Return(_) => Ok(None),
+      },
+    }
+  }
+}
+
+// Checks that the other iterators of zip(strict=True) are exhausted once iterator i is
+fn zip_mismatch(iterators: &[Value], i: usize, caller: &mut dyn Caller) -> Result<Option<Value>, String> {
+  let before = |i: usize| if i == 1 { "argument 1".to_string() } else { format!("arguments 1-{}", i) };
+  if i > 0 {
+    return Err(format!("ValueError: zip() argument {} is shorter than {}", i + 1, before(i)));
+  }
+  for (j, iterator) in iterators.iter().enumerate().skip(1) {
+    if next_item(iterator, caller)?.is_some() {
+      return Err(format!("ValueError: zip() argument {} is longer than {}", j + 1, before(j)));
+    }
+  }
+  Ok(None)
+}
+
+// Advances an iterator by one item. An iterator can't be advanced again while it is calling a
+// function for its current item, as when a map() function calls next() on its own map
+pub fn next_item(iterator: &Value, caller: &mut dyn Caller) -> Result<Option<Value>, String> {
+  match iterator {
+    Value::Iterator(it) => match it.try_borrow_mut() {
+      Ok(mut it) => it.next(caller),
+      Err(_) => Err("ValueError: generator already executing".to_string()),
+    },
+    _ => Err(format!("TypeError: '{}' object is not an iterator", iterator.type_name())),
+  }
+}
+
+// The state of a call to a generator function
+pub struct Generator {
+  pub name: String,
+  pub frame: Option<GeneratorFrame>, // None once the generator has returned or raised an error
+  pub started: bool,
+}
+
+// A suspended generator, in the form of the engine that created it
+pub enum GeneratorFrame {
+  Code(Box<vm::Frame>),
+  Ast(Box<interpreter::GeneratorFrame>),
+}
+
+impl GeneratorFrame {
+  // The iterator the generator is suspended in a `yield from` of, if any
+  fn delegate(&self) -> Option<Value> {
+    match self {
+      GeneratorFrame::Code(frame) => frame.delegate(),
+      GeneratorFrame::Ast(frame) => frame.delegate(),
+    }
+  }
+}
+
+// How a generator stopped running
+pub enum Resumed {
+  Yield(Value),
+  Return(Value),
+}
+
+impl Generator {
+  // Runs the generator up to its next yield, sending it a value
+  pub fn resume(&mut self, sent: Value, caller: &mut dyn Caller) -> Result<Resumed, String> {
+    if self.frame.is_none() {
+      return Ok(Resumed::Return(Value::None));
+    }
+    // There is no yield to give the value of before the generator has started
+    let sent = if self.started {
+      Some(sent)
+    } else if matches!(sent, Value::None) {
+      None
+    } else {
+      return Err("TypeError: can't send non-None value to a just-started generator".to_string());
+    };
+    self.started = true;
+
+    match caller.resume(&mut self.frame, sent) {
+      Ok(Resumed::Yield(value)) => Ok(Resumed::Yield(value)),
+      Ok(Resumed::Return(value)) => {
+        self.frame = None;
+        Ok(Resumed::Return(value))
+      }
+      // A StopIteration escaping the body would otherwise look like the generator ending
+      Err(e) => {
+        self.frame = None;
+        if e == "StopIteration" || e.starts_with("StopIteration:") {
+          Err("RuntimeError: generator raised StopIteration".to_string())
+        } else {
+          Err(e)
+        }
+      }
+    }
+  }
+
+  // Finishes the generator without running any more of it, along with the generator it delegates to
+  pub fn close(&mut self) {
+    if let Some(frame) = self.frame.take() {
+      if let Some(Value::Iterator(delegate)) = &frame.delegate() {
+        if let Ok(mut delegate) = delegate.try_borrow_mut() {
+          if let Iter::Generator(generator) = &mut *delegate {
+            generator.close();
+          }
+        }
+      }
+    }
+  }
+}
+
+// Resumes the iterator of a `yield from` with the value sent to the delegating generator. Only a
+// generator can take a value, any other iterator just gives its next item
+pub fn send_value(iterator: &Value, sent: Value, caller: &mut dyn Caller) -> Result<Resumed, String> {
+  match iterator {
+    Value::Iterator(it) => match it.try_borrow_mut() {
+      Ok(mut it) => match &mut *it {
+        Iter::Generator(generator) => generator.resume(sent, caller),
+        it if matches!(sent, Value::None) => match it.next(caller)? {
+          Some(item) => Ok(Resumed::Yield(item)),
+          None => Ok(Resumed::Return(Value::None)),
+        },
+        it => Err(format!("AttributeError: '{}' object has no attribute 'send'", it.type_name())),
+      },
+      Err(_) => Err("ValueError: generator already executing".to_string()),
+    },
+    _ => Err(format!("TypeError: '{}' object is not an iterator", iterator.type_name())),
+  }
+}
+
+// The error that ends a generator.send() or next() once the generator has returned
+pub fn stop_iteration(value: &Value) -> String {
+  match value {
+    Value::None => "StopIteration".to_string(),
+    _ => format!("StopIteration: {}", value),
+  }
+}
+
+// The error a failed assert raises, with the message given after its condition
+pub fn assertion_error(message: Option<Value>) -> String {
+  Exception { kind: "AssertionError", args: message.into_iter().collect() }.error()
+}
+
+// Number of items in range(start, stop, step)
+pub fn range_len(start: i64, stop: i64, step: i64) -> u64 {
+  let (start, stop, step) = (start as i128, stop as i128, step as i128);
+  let len = match step {
+    _ if step > 0 && start < stop => (stop - start - 1) / step + 1,
+    _ if step < 0 && stop < start => (start - stop - 1) / -step + 1,
+    _ => 0,
+  };
+  len as u64
+}
+
+// Number of code points in a string. Strings are usually ASCII, with one code point per byte
+pub fn char_count(s: &str) -> usize {
+  if s.is_ascii() {
+    s.len()
+  } else {
+    s.chars().count()
+  }
+}
+
+impl Value {
+  pub fn new_str(s: &str) -> Value {
+    Value::Str(Rc::from(s))
+  }
+
+  pub fn new_list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+  }
+
+  // A generator that hasn't started running its frame
+  pub fn new_generator(name: String, frame: GeneratorFrame) -> Value {
+    let generator = Generator { name, frame: Some(frame), started: false };
+    Value::Iterator(Rc::new(RefCell::new(Iter::Generator(generator))))
+  }
+
+  // Name of the value's type, as shown in error messages
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::None => "NoneType",
+      Value::Bool(_) => "bool",
+      Value::Int(_) => "int",
+      Value::Float(_) => "float",
+      Value::Str(_) => "str",
+      Value::Bytes(_) => "bytes",
+      Value::List(_) => "list",
+      Value::Tuple(_) => "tuple",
+      Value::Dict(_) => "dict",
+      Value::Set(_) => "set",
+      Value::Function(_) => "function",
+      Value::Builtin(_) | Value::Method(_) => "builtin_function_or_method",
+      // An iterator is mutably borrowed while it calls a function for its current item
+      Value::Iterator(it) => it.try_borrow().map_or("iterator", |it| it.type_name()),
+      Value::Slice(_) => "slice",
+      Value::Range(..) => "range",
+      Value::Type(_) => "type",
+      Value::Exception(e) => e.kind,
+      Value::Module(_) => "module",
+      Value::Stream(_) => "_io.TextIOWrapper",
+    }
+  }
+
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Value::None => false,
+      Value::Bool(b) => *b,
+      Value::Int(n) => *n != 0,
+      Value::Float(d) => *d != 0.0,
+      Value::Str(s) => !s.is_empty(),
+      Value::Bytes(b) => !b.is_empty(),
+      Value::List(items) => !items.borrow().is_empty(),
+      Value::Tuple(items) => !items.is_empty(),
+      Value::Dict(table) | Value::Set(table) => !table.borrow().is_empty(),
+      Value::Range(start, stop, step) => range_len(*start, *stop, *step) != 0,
+      Value::Function(_)
+      | Value::Builtin(_)
+      | Value::Method(_)
+      | Value::Iterator(_)
+      | Value::Slice(_)
+      | Value::Type(_)
+      | Value::Exception(_)
+      | Value::Module(_)
+      | Value::Stream(_) => true,
+    }
+  }
+
+  // The `is` operator. Heap objects are identical only when they are the same allocation,
+  // scalars when they have the same type and value (like CPython's cached small ints)
+  pub fn is_identical(&self, other: &Value) -> bool {
+    match (self, other) {
+      (Value::None, Value::None) => true,
+      (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+      (Value::Int(n1), Value::Int(n2)) => n1 == n2,
+      (Value::Float(d1), Value::Float(d2)) => d1.to_bits() == d2.to_bits(),
+      (Value::Str(s1), Value::Str(s2)) => Rc::ptr_eq(s1, s2),
+      (Value::Bytes(b1), Value::Bytes(b2)) => Rc::ptr_eq(b1, b2),
+      (Value::List(l1), Value::List(l2)) => Rc::ptr_eq(l1, l2),
+      (Value::Tuple(t1), Value::Tuple(t2)) => Rc::ptr_eq(t1, t2),
+      (Value::Dict(d1), Value::Dict(d2)) => Rc::ptr_eq(d1, d2),
+      (Value::Set(s1), Value::Set(s2)) => Rc::ptr_eq(s1, s2),
+      (Value::Function(f1), Value::Function(f2)) => Rc::ptr_eq(f1, f2),
+      (Value::Builtin(b1), Value::Builtin(b2)) => b1.name == b2.name,
+      (Value::Method(m1), Value::Method(m2)) => Rc::ptr_eq(m1, m2),
+      (Value::Iterator(i1), Value::Iterator(i2)) => Rc::ptr_eq(i1, i2),
+      (Value::Slice(s1), Value::Slice(s2)) => Rc::ptr_eq(s1, s2),
+      (Value::Range(..), Value::Range(..)) => self.hash_key() == other.hash_key(),
+      (Value::Type(t1), Value::Type(t2)) => t1 == t2,
+      (Value::Exception(e1), Value::Exception(e2)) => Rc::ptr_eq(e1, e2),
+      (Value::Module(m1), Value::Module(m2)) => m1 == m2,
+      (Value::Stream(s1), Value::Stream(s2)) => s1 == s2,
+      _ => false,
+    }
+  }
+
+  // The `==` operator
+  pub fn equals(&self, other: &Value) -> Result<bool, String> {
+    self.equals_nested(other, 0)
+  }
+
+  // Items of containers are compared one level deeper, up to the recursion limit
+  fn equals_nested(&self, other: &Value, depth: usize) -> Result<bool, String> {
+    if depth >= MAX_CALL_DEPTH {
+      return Err("RecursionError: maximum recursion depth exceeded in comparison".to_string());
+    }
+    match (self, other) {
+      (Value::None, Value::None) => Ok(true),
+      (Value::Str(s1), Value::Str(s2)) => Ok(s1 == s2),
+      (Value::Bytes(b1), Value::Bytes(b2)) => Ok(b1 == b2),
+      (Value::List(l1), Value::List(l2)) => Ok(Rc::ptr_eq(l1, l2) || seq_equals(&l1.borrow(), &l2.borrow(), depth)?),
+      (Value::Tuple(t1), Value::Tuple(t2)) => seq_equals(t1, t2, depth),
+      (Value::Dict(d1), Value::Dict(d2)) => {
+        let (d1, d2) = (d1.borrow(), d2.borrow());
+        if d1.len() != d2.len() {
+          return Ok(false);
+        }
+        for (key, value) in d1.entries() {
+          match d2.get(key) {
+            Ok(Some(other_value)) if value.equals_nested(&other_value, depth + 1)? => {}
+            _ => return Ok(false),
+          }
+        }
+        Ok(true)
+      }
+      (Value::Set(s1), Value::Set(s2)) => {
+        let (s1, s2) = (s1.borrow(), s2.borrow());
+        Ok(s1.len() == s2.len() && s1.entries().iter().all(|(key, _)| s2.contains(key) == Ok(true)))
+      }
+      (Value::Function(f1), Value::Function(f2)) => Ok(Rc::ptr_eq(f1, f2)),
+      (Value::Builtin(b1), Value::Builtin(b2)) => Ok(b1.name == b2.name),
+      (Value::Method(m1), Value::Method(m2)) => Ok(m1.builtin.name == m2.builtin.name && m1.receiver.is_identical(&m2.receiver)),
+      (Value::Iterator(i1), Value::Iterator(i2)) => Ok(Rc::ptr_eq(i1, i2)),
+      (Value::Slice(s1), Value::Slice(s2)) => seq_equals(&s1[..], &s2[..], depth),
+      // Ranges are equal when they give the same items
+      (Value::Range(..), Value::Range(..)) => Ok(self.hash_key() == other.hash_key()),
+      (Value::Type(t1), Value::Type(t2)) => Ok(t1 == t2),
+      (Value::Exception(e1), Value::Exception(e2)) => Ok(Rc::ptr_eq(e1, e2)),
+      (Value::Module(m1), Value::Module(m2)) => Ok(m1 == m2),
+      (Value::Stream(s1), Value::Stream(s2)) => Ok(s1 == s2),
+      _ => match (self.as_number(), other.as_number()) {
+        (Some(Number::Int(n1)), Some(Number::Int(n2))) => Ok(n1 == n2),
+        (Some(n1), Some(n2)) => Ok(n1.as_f64() == n2.as_f64()),
+        _ => Ok(false),
+      },
+    }
+  }
+
+  // Numeric view of the value, with bools acting as the ints 0 and 1
+  pub fn as_number(&self) -> Option<Number> {
+    match self {
+      Value::Bool(b) => Some(Number::Int(*b as i64)),
+      Value::Int(n) => Some(Number::Int(*n)),
+      Value::Float(d) => Some(Number::Float(*d)),
+      _ => None,
+    }
+  }
+
+  pub fn hash_key(&self) -> Result<HashKey, String> {
+    match self {
+      Value::None => Ok(HashKey::None),
+      Value::Bool(b) => Ok(HashKey::Int(*b as i64)),
+      Value::Int(n) => Ok(HashKey::Int(*n)),
+      Value::Float(d) => {
+        if d.fract() == 0.0 && d.abs() < 9.2e18 {
+          Ok(HashKey::Int(*d as i64))
+        } else {
+          Ok(HashKey::Float(d.to_bits()))
+        }
+      }
+      Value::Str(s) => Ok(HashKey::Str(s.clone())),
+      Value::Bytes(b) => Ok(HashKey::Bytes(b.clone())),
+      Value::Tuple(items) => Ok(HashKey::Tuple(items.iter().map(|item| item.hash_key()).collect::<Result<Vec<_>, _>>()?)),
+      Value::Function(f) => Ok(HashKey::Id(Rc::as_ptr(f) as usize)),
+      Value::Builtin(b) => Ok(HashKey::Id(b.name.as_ptr() as usize)),
+      Value::Method(m) => Ok(HashKey::Id(Rc::as_ptr(m) as usize)),
+      Value::Iterator(it) => Ok(HashKey::Id(Rc::as_ptr(it) as usize)),
+      Value::Exception(e) => Ok(HashKey::Id(Rc::as_ptr(e) as usize)),
+      Value::Module(name) => Ok(HashKey::Id(name.as_ptr() as usize)),
+      Value::Stream(stream) => Ok(HashKey::Id(stream.name().as_ptr() as usize)),
+      Value::Range(start, stop, step) => {
+        let len = range_len(*start, *stop, *step);
+        let start = if len > 0 { *start } else { 0 };
+        let step = if len > 1 { *step } else { 0 };
+        Ok(HashKey::Range(len as i64, start, step))
+      }
+      Value::Type(name) => Ok(HashKey::Type(name)),
+      Value::List(_) | Value::Dict(_) | Value::Set(_) | Value::Slice(_) => Err(format!("TypeError: unhashable type: '{}'", self.type_name())),
+    }
+  }
+
+  pub fn iter(&self) -> Result<Iter, String> {
+    match self {
+      Value::List(_) | Value::Tuple(_) | Value::Str(_) | Value::Bytes(_) | Value::Dict(_) | Value::Set(_) => {
+        Ok(Iter::Seq(SeqIter { seq: self.clone(), pos: 0 }))
+      }
+      Value::Range(start, stop, step) => Ok(Iter::Range { next: *start, step: *step, remaining: range_len(*start, *stop, *step) }),
+      _ => Err(format!("TypeError: '{}' object is not iterable", self.type_name())),
+    }
+  }
+
+  // Python's iter(): a new iterator over a container, or the iterator itself
+  pub fn to_iterator(&self) -> Result<Value, String> {
+    match self {
+      Value::Iterator(_) => Ok(self.clone()),
+      _ => Ok(Value::Iterator(Rc::new(RefCell::new(self.iter()?)))),
+    }
+  }
+
+  // The items of an iterable, in iteration order
+  pub fn items(&self, caller: &mut dyn Caller) -> Result<Vec<Value>, String> {
+    match self {
+      Value::List(items) => Ok(items.borrow().clone()),
+      Value::Tuple(items) => Ok(items.to_vec()),
+      _ => {
+        let iterator = self.to_iterator()?;
+        let mut items = Vec::new();
+        while let Some(item) = next_item(&iterator, caller)? {
+          items.push(item);
+        }
+        Ok(items)
+      }
+    }
+  }
+
+  // Python's str(), which is the repr() for everything but strings and a few scalar types
+  pub fn str(&self) -> Result<String, String> {
+    match self {
+      Value::List(_) | Value::Tuple(_) | Value::Dict(_) | Value::Set(_) | Value::Slice(_) => self.repr(),
+      Value::Exception(e) => e.message(),
+      _ => Ok(self.to_string()),
+    }
+  }
+
+  // Python's repr()
+  pub fn repr(&self) -> Result<String, String> {
+    self.repr_nested(&mut Vec::new())
+  }
+
+  // Containers already being printed are tracked so that a list containing itself prints as [...],
+  // and the values being printed are counted to stop at the recursion limit
+  fn repr_nested(&self, active: &mut Vec<usize>) -> Result<String, String> {
+    let id = match self {
+      Value::List(items) => Rc::as_ptr(items) as usize,
+      Value::Dict(table) | Value::Set(table) => Rc::as_ptr(table) as usize,
+      _ => 0,
+    };
+    if id != 0 && active.contains(&id) {
+      return match self {
+        Value::List(_) => Ok("[...]".to_string()),
+        _ => Ok("{...}".to_string()),
+      };
+    }
+    if active.len() >= MAX_CALL_DEPTH {
+      return Err("RecursionError: maximum recursion depth exceeded while getting the repr of an object".to_string());
+    }
+    active.push(id);
+
+    let result = match self {
+      Value::Str(s) => Ok(repr_str(s)),
+      Value::Bytes(b) => Ok(repr_bytes(b)),
+      Value::List(items) => repr_items(&items.borrow(), active).map(|items| format!("[{}]", items)),
+      Value::Tuple(items) if items.len() == 1 => items[0].repr_nested(active).map(|item| format!("({},)", item)),
+      Value::Tuple(items) => repr_items(items, active).map(|items| format!("({})", items)),
+      Value::Dict(table) => table
+        .borrow()
+        .entries()
+        .iter()
+        .map(|(key, value)| Ok(format!("{}: {}", key.repr_nested(active)?, value.repr_nested(active)?)))
+        .collect::<Result<Vec<_>, String>>()
+        .map(|pairs| format!("{{{}}}", pairs.join(", "))),
+      Value::Set(table) if table.borrow().is_empty() => Ok("set()".to_string()),
+      Value::Set(table) => {
+        let keys = table.borrow().entries().iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        repr_items(&keys, active).map(|keys| format!("{{{}}}", keys))
+      }
+      Value::Slice(bounds) => repr_items(&bounds[..], active).map(|bounds| format!("slice({})", bounds)),
+      Value::Range(start, stop, 1) => Ok(format!("range({}, {})", start, stop)),
+      Value::Range(start, stop, step) => Ok(format!("range({}, {}, {})", start, stop, step)),
+      Value::Exception(e) => repr_items(&e.args, active).map(|args| format!("{}({})", e.kind, args)),
+      _ => Ok(self.to_string()),
+    };
+
+    active.pop();
+    result
+  }
+}
+
+// Number value used for arithmetic
+pub enum Number {
+  Int(i64),
+  Float(f64),
+}
+
+impl Number {
+  pub fn as_f64(&self) -> f64 {
+    match self {
+      Number::Int(n) => *n as f64,
+      Number::Float(d) => *d,
+    }
+  }
+}
+
+fn seq_equals(items1: &[Value], items2: &[Value], depth: usize) -> Result<bool, String> {
+  if items1.len() != items2.len() {
+    return Ok(false);
+  }
+  for (item1, item2) in items1.iter().zip(items2) {
+    if !item1.equals_nested(item2, depth + 1)? {
+      return Ok(false);
+    }
+  }
+  Ok(true)
+}
+
+fn repr_items(items: &[Value], active: &mut Vec<usize>) -> Result<String, String> {
+  Ok(items.iter().map(|item| item.repr_nested(active)).collect::<Result<Vec<_>, String>>()?.join(", "))
+}
+
+// Quotes a string the way Python's repr() does, preferring single quotes
+pub fn repr_str(s: &str) -> String {
+  let quote = if s.contains('\'') && !s.contains('"') { '"' } else { '\'' };
+  let mut result = String::new();
+  result.push(quote);
+
+  for c in s.chars() {
+    match c {
+      '\\' => result.push_str("\\\\"),
+      '\n' => result.push_str("\\n"),
+      '\r' => result.push_str("\\r"),
+      '\t' => result.push_str("\\t"),
+      c if c == quote => {
+        result.push('\\');
+        result.push(c);
+      }
+      c if !unicode::is_printable(c) => match c as u32 {
+        n @ 0..=0xff => result.push_str(&format!("\\x{:02x}", n)),
+        n @ 0x100..=0xffff => result.push_str(&format!("\\u{:04x}", n)),
+        n => result.push_str(&format!("\\U{:08x}", n)),
+      },
+      c => result.push(c),
+    }
+  }
+
+  result.push(quote);
+  result
+}
+
+// Python's repr() of bytes: printable ASCII as is, other bytes as escapes
+pub fn repr_bytes(b: &[u8]) -> String {
+  let quote = if b.contains(&b'\'') && !b.contains(&b'"') { '"' } else { '\'' };
+  let mut result = String::from("b");
+  result.push(quote);
+
+  for byte in b {
+    match *byte {
+      b'\\' => result.push_str("\\\\"),
+      b'\n' => result.push_str("\\n"),
+      b'\r' => result.push_str("\\r"),
+      b'\t' => result.push_str("\\t"),
+      byte if byte as char == quote => {
+        result.push('\\');
+        result.push(quote);
+      }
+      0x20..=0x7e => result.push(*byte as char),
+      byte => result.push_str(&format!("\\x{:02x}", byte)),
+    }
+  }
+
+  result.push(quote);
+  result
+}
+
+// Python's str()
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::None => write!(f, "None"),
+      Value::Bool(b) => write!(f, "{}", if *b { "True" } else { "False" }),
+      Value::Int(n) => write!(f, "{}", n),
+      Value::Float(d) => write!(f, "{}", float_repr(*d)),
+      Value::Str(s) => write!(f, "{}", s),
+      Value::Function(func) => write!(f, "<function {} at {:#x}>", func.name, Rc::as_ptr(func) as usize),
+      Value::Builtin(b) => write!(f, "<built-in function {}>", b.name),
+      Value::Method(m) => write!(f, "<built-in method {} of {} object at {:#x}>", m.builtin.name, m.receiver.type_name(), Rc::as_ptr(m) as usize),
+      Value::Iterator(it) => match it.try_borrow().as_deref() {
+        Ok(Iter::Generator(generator)) => write!(f, "<generator object {} at {:#x}>", generator.name, Rc::as_ptr(it) as usize),
+        _ => write!(f, "<{} object at {:#x}>", self.type_name(), Rc::as_ptr(it) as usize),
+      },
+      Value::Type(name) => write!(f, "<class '{}'>", name),
+      Value::Module(name) => write!(f, "<module '{}' (built-in)>", name),
+      Value::Stream(stream) => write!(f, "<_io.TextIOWrapper name='{}' mode='w' encoding='utf-8'>", stream.name()),
+      // Values that can fail to print go through str() or repr() where the error can be raised, and
+      // show the error in its place here
+      Value::Exception(e) => write!(f, "{}", e.message().unwrap_or_else(|error| error)),
+      _ => write!(f, "{}", self.repr().unwrap_or_else(|error| error)),
+    }
+  }
+}
@@ -0,0 +1,589 @@
+use crate::bytecode::{
+  CodeObject, Constant, Instr, CALL_WITH_KEYWORDS, CODE_DEFAULTS, CODE_GENERATOR, CODE_KWDEFAULTS, FORMAT_ASCII, FORMAT_REPR,
+  FORMAT_STR, FORMAT_WITH_SPEC,
+};
+use crate::builtins;
+use crate::compiler;
+use crate::format::{convert, format_value};
+use crate::methods::{get_attribute, set_attribute};
+use crate::operators::{del_item, eval_binop, eval_inplace, get_item, set_item};
+use crate::types::{PyType, Statement};
+use crate::value::{
+  assertion_error, keywords, merge_keywords, next_item, send_value, unpack_arguments, Caller, Cell, Dict, Function, FunctionBody,
+  GeneratorFrame, Resumed, Scope, Value, MAX_CALL_DEPTH,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Activation record of a running piece of code, or of a suspended generator
+pub struct Frame {
+  code: Rc<CodeObject>,
+  ip: usize,
+  stack: Vec<Value>,
+  locals: Vec<Option<Value>>, // None until the slot is assigned
+  cells: Vec<Cell>,           // cellvars then freevars
+}
+
+impl Frame {
+  fn pop(&mut self) -> Value {
+    self.stack.pop().expect("VM stack underflow")
+  }
+
+  fn top(&self) -> &Value {
+    self.stack.last().expect("VM stack underflow")
+  }
+
+  // Removes the top n values, returning them in the order they were pushed
+  fn pop_n(&mut self, n: u32) -> Vec<Value> {
+    let start = self.stack.len() - n as usize;
+    self.stack.split_off(start)
+  }
+
+  // A generator suspended in a `yield from` resumes at the YieldFrom, with the iterator on top of the stack
+  pub fn delegate(&self) -> Option<Value> {
+    match self.code.instructions.get(self.ip) {
+      Some(Instr::YieldFrom) => self.stack.last().cloned(),
+      _ => None,
+    }
+  }
+}
+
+// How a frame stopped running
+enum Exit {
+  Return(Value),
+  Yield(Value, Frame), // the generator frame, suspended
+}
+
+// Stack-based virtual machine running compiled code. Module globals persist between runs so the
+// REPL can build on earlier lines
+pub struct Vm {
+  globals: Scope,
+  frames: Vec<Frame>,
+  resumes: usize, // generators being resumed inside one another, each in a nested execute loop on the Rust stack
+  optimize: bool, // compile REPL lines without their assert statements (-O)
+}
+
+impl Vm {
+  pub fn new(optimize: bool) -> Vm {
+    Vm { globals: Rc::new(RefCell::new(HashMap::new())), frames: Vec::new(), resumes: 0, optimize }
+  }
+
+  // Compiles and runs one statement entered in the REPL, returning the value of an expression so it can be echoed
+  pub fn run(&mut self, statement: &Statement) -> Result<Option<Value>, String> {
+    let code = Rc::new(compiler::compile_interactive(statement, self.optimize)?);
+    let value = self.run_module(code)?;
+
+    match statement.node {
+      PyType::Expr(_) => Ok(Some(value)),
+      PyType::Stmt(_) => Ok(None),
+    }
+  }
+
+  // Runs compiled module code in the global scope
+  pub fn run_module(&mut self, code: Rc<CodeObject>) -> Result<Value, String> {
+    let frame = Frame { code, ip: 0, stack: Vec::new(), locals: Vec::new(), cells: Vec::new() };
+
+    match self.execute(frame) {
+      Ok(value) => Ok(value),
+      Err(e) => {
+        self.frames.clear();
+        Err(e)
+      }
+    }
+  }
+
+  // Runs a frame until it returns, along with any frames it calls
+  fn execute(&mut self, frame: Frame) -> Result<Value, String> {
+    match self.run_frame(frame)? {
+      Exit::Return(value) => Ok(value),
+      Exit::Yield(..) => unreachable!("only generator frames yield, and they are run by resume"),
+    }
+  }
+
+  // Runs a frame until it returns or, for a generator, until it yields. Calling a generator
+  // function doesn't run it, so a yield only ever happens in the frame being run here
+  fn run_frame(&mut self, frame: Frame) -> Result<Exit, String> {
+    let base = self.frames.len();
+    self.frames.push(frame);
+
+    loop {
+      let frame = self.frames.last_mut().unwrap();
+      let instr = frame.code.instructions[frame.ip];
+      frame.ip += 1;
+
+      match instr {
+        Instr::LoadConst(i) => {
+          let value = frame.code.constants[i as usize].to_value();
+          frame.stack.push(value);
+        }
+        Instr::LoadFast(i) => match &frame.locals[i as usize] {
+          Some(value) => frame.stack.push(value.clone()),
+          None => {
+            return Err(format!("UnboundLocalError: cannot access local variable '{}' where it is not associated with a value", frame.code.varnames[i as usize]))
+          }
+        },
+        Instr::StoreFast(i) => {
+          let value = frame.pop();
+          frame.locals[i as usize] = Some(value);
+        }
+        Instr::DeleteFast(i) => {
+          if frame.locals[i as usize].take().is_none() {
+            return Err(format!("UnboundLocalError: cannot access local variable '{}' where it is not associated with a value", frame.code.varnames[i as usize]));
+          }
+        }
+        Instr::LoadDeref(i) => {
+          let value = frame.cells[i as usize].borrow().clone();
+          match value {
+            Some(value) => frame.stack.push(value),
+            None => return Err(unbound_cell(&frame.code, i)),
+          }
+        }
+        Instr::StoreDeref(i) => {
+          let value = frame.pop();
+          *frame.cells[i as usize].borrow_mut() = Some(value);
+        }
+        Instr::DeleteDeref(i) => {
+          if frame.cells[i as usize].borrow_mut().take().is_none() {
+            return Err(unbound_cell(&frame.code, i));
+          }
+        }
+        Instr::LoadGlobal(i) => {
+          let name = &frame.code.names[i as usize];
+          // Globals shadow builtins
+          let value = self.globals.borrow().get(name).cloned().or_else(|| builtins::lookup(name));
+          match value {
+            Some(value) => frame.stack.push(value),
+            None => return Err(format!("NameError: name '{}' is not defined", name)),
+          }
+        }
+        Instr::StoreGlobal(i) => {
+          let value = frame.pop();
+          self.globals.borrow_mut().insert(frame.code.names[i as usize].clone(), value);
+        }
+        Instr::DeleteGlobal(i) => {
+          let name = &frame.code.names[i as usize];
+          if self.globals.borrow_mut().remove(name).is_none() {
+            return Err(format!("NameError: name '{}' is not defined", name));
+          }
+        }
+        Instr::PopTop => {
+          frame.pop();
+        }
+        Instr::DupTop => {
+          let value = frame.top().clone();
+          frame.stack.push(value);
+        }
+        Instr::DupTopTwo => {
+          let len = frame.stack.len();
+          frame.stack.extend_from_within(len - 2..);
+        }
+        Instr::RotTwo => {
+          let len = frame.stack.len();
+          frame.stack.swap(len - 1, len - 2);
+        }
+        Instr::RotThree => {
+          let top = frame.pop();
+          let len = frame.stack.len();
+          frame.stack.insert(len - 2, top);
+        }
+        Instr::UnaryNot => {
+          let value = frame.pop();
+          frame.stack.push(Value::Bool(!value.is_truthy()));
+        }
+        Instr::BinaryOp(op) | Instr::CompareOp(op) => {
+          let right = frame.pop();
+          let left = frame.pop();
+          frame.stack.push(eval_binop(&op, &left, &right)?);
+        }
+        Instr::InplaceOp(op) => {
+          let right = frame.pop();
+          let left = frame.pop();
+          let result = eval_inplace(&op, &left, &right, self)?;
+          self.frames.last_mut().unwrap().stack.push(result);
+        }
+        Instr::Jump(target) => frame.ip = target as usize,
+        Instr::PopJumpIfFalse(target) => {
+          if !frame.pop().is_truthy() {
+            frame.ip = target as usize;
+          }
+        }
+        Instr::JumpIfFalseOrPop(target) => {
+          if frame.top().is_truthy() {
+            frame.pop();
+          } else {
+            frame.ip = target as usize;
+          }
+        }
+        Instr::JumpIfTrueOrPop(target) => {
+          if frame.top().is_truthy() {
+            frame.ip = target as usize;
+          } else {
+            frame.pop();
+          }
+        }
+        Instr::GetIter => {
+          let iterable = frame.pop();
+          frame.stack.push(iterable.to_iterator()?);
+        }
+        Instr::ForIter(target) => {
+          let iterator = frame.top().clone();
+          let item = next_item(&iterator, self)?;
+          let frame = self.frames.last_mut().unwrap();
+          match item {
+            Some(item) => frame.stack.push(item),
+            None => {
+              frame.pop();
+              frame.ip = target as usize;
+            }
+          }
+        }
+        Instr::BuildList(n) => {
+          let items = frame.pop_n(n);
+          frame.stack.push(Value::new_list(items));
+        }
+        Instr::BuildTuple(n) => {
+          let items = frame.pop_n(n);
+          frame.stack.push(Value::Tuple(Rc::new(items)));
+        }
+        Instr::BuildDict(n) => {
+          let items = frame.pop_n(2 * n);
+          let mut table = Dict::new();
+          for pair in items.chunks(2) {
+            table.insert(pair[0].clone(), pair[1].clone())?;
+          }
+          frame.stack.push(Value::Dict(Rc::new(RefCell::new(table))));
+        }
+        Instr::BuildSet(n) => {
+          let items = frame.pop_n(n);
+          let mut table = Dict::new();
+          for item in items {
+            table.insert(item, Value::None)?;
+          }
+          frame.stack.push(Value::Set(Rc::new(RefCell::new(table))));
+        }
+        Instr::MakeFunction(i) => {
+          let code = match &frame.code.constants[i as usize] {
+            Constant::Code(code) => code.clone(),
+            _ => unreachable!("MakeFunction expects a code constant"),
+          };
+          // Free variables share the cells of the same names in the defining code
+          let closure = code
+            .freevars
+            .iter()
+            .map(|name| frame.cells[cell_position(&frame.code, name)].clone())
+            .collect();
+          let kwdefaults = if code.flags & CODE_KWDEFAULTS != 0 {
+            match &frame.pop() {
+              Value::Dict(table) => keywords(&table.borrow()),
+              _ => unreachable!("MakeFunction expects a dict of keyword-only defaults"),
+            }
+          } else {
+            Vec::new()
+          };
+          let defaults = if code.flags & CODE_DEFAULTS != 0 {
+            match &frame.pop() {
+              Value::Tuple(items) => items.to_vec(),
+              _ => unreachable!("MakeFunction expects a tuple of defaults"),
+            }
+          } else {
+            Vec::new()
+          };
+          let function = Function {
+            name: code.name.clone(),
+            signature: code.signature(),
+            defaults,
+            kwdefaults,
+            body: FunctionBody::Code(code, closure),
+            attributes: RefCell::default(),
+          };
+          frame.stack.push(Value::Function(Rc::new(function)));
+        }
+        Instr::CallFunction(n) => {
+          let args = frame.pop_n(n);
+          let function = frame.pop();
+          self.call(function, args, Vec::new())?;
+        }
+        Instr::CallFunctionKw(n) => {
+          let names = match &frame.pop() {
+            Value::Tuple(names) => names.clone(),
+            _ => unreachable!("CallFunctionKw expects a tuple of keyword names"),
+          };
+          let mut args = frame.pop_n(n);
+          let values = args.split_off(args.len() - names.len());
+          let kwargs = names.iter().map(|name| name.to_string()).zip(values).collect();
+          let function = frame.pop();
+          self.call(function, args, kwargs)?;
+        }
+        Instr::ListAppend(i) => {
+          let value = frame.pop();
+          match &frame.stack[frame.stack.len() - i as usize] {
+            Value::List(items) => items.borrow_mut().push(value),
+            _ => unreachable!("ListAppend expects a list"),
+          }
+        }
+        Instr::SetAdd(i) => {
+          let value = frame.pop();
+          match &frame.stack[frame.stack.len() - i as usize] {
+            Value::Set(table) => table.borrow_mut().insert(value, Value::None)?,
+            _ => unreachable!("SetAdd expects a set"),
+          }
+        }
+        Instr::MapAdd(i) => {
+          let value = frame.pop();
+          let key = frame.pop();
+          match &frame.stack[frame.stack.len() - i as usize] {
+            Value::Dict(table) => table.borrow_mut().insert(key, value)?,
+            _ => unreachable!("MapAdd expects a dict"),
+          }
+        }
+        Instr::ListExtend => {
+          let iterable = frame.pop();
+          let function = frame.stack[frame.stack.len() - 2].clone();
+          let items = unpack_arguments(&function, &iterable, self)?;
+          match self.frames.last_mut().unwrap().top() {
+            Value::List(args) => args.borrow_mut().extend(items),
+            _ => unreachable!("ListExtend expects an argument list"),
+          }
+        }
+        Instr::DictMerge => {
+          let mapping = frame.pop();
+          let function = &frame.stack[frame.stack.len() - 3];
+          match frame.top() {
+            Value::Dict(kwargs) => merge_keywords(function, &mut kwargs.borrow_mut(), &mapping)?,
+            _ => unreachable!("DictMerge expects a keyword argument dict"),
+          }
+        }
+        Instr::CallFunctionEx(flags) => {
+          let kwargs = if flags & CALL_WITH_KEYWORDS != 0 {
+            match &frame.pop() {
+              Value::Dict(kwargs) => keywords(&kwargs.borrow()),
+              _ => unreachable!("CallFunctionEx expects a keyword argument dict"),
+            }
+          } else {
+            Vec::new()
+          };
+          let args = match &frame.pop() {
+            Value::List(args) => args.take(),
+            _ => unreachable!("CallFunctionEx expects an argument list"),
+          };
+          let function = frame.pop();
+          self.call(function, args, kwargs)?;
+        }
+        Instr::LoadAttr(i) => {
+          let value = frame.pop();
+          frame.stack.push(get_attribute(&value, &frame.code.names[i as usize])?);
+        }
+        Instr::StoreAttr(i) => {
+          let object = frame.pop();
+          let value = frame.pop();
+          set_attribute(&object, &frame.code.names[i as usize], Some(value))?;
+        }
+        Instr::DeleteAttr(i) => {
+          let object = frame.pop();
+          set_attribute(&object, &frame.code.names[i as usize], None)?;
+        }
+        Instr::RaiseAssertion(n) => {
+          let message = if n == 1 { Some(frame.pop()) } else { None };
+          return Err(assertion_error(message));
+        }
+        Instr::FormatValue(flags) => {
+          let spec = if flags & FORMAT_WITH_SPEC != 0 { frame.pop().to_string() } else { String::new() };
+          let mut value = frame.pop();
+          let conversion = match flags & 3 {
+            FORMAT_STR => Some('s'),
+            FORMAT_REPR => Some('r'),
+            FORMAT_ASCII => Some('a'),
+            _ => None,
+          };
+          if let Some(c) = conversion {
+            value = Value::new_str(&convert(&value, c)?);
+          }
+          frame.stack.push(Value::new_str(&format_value(&value, &spec)?));
+        }
+        Instr::BuildString(n) => {
+          let parts = frame.pop_n(n);
+          let joined: String = parts.iter().map(|part| part.to_string()).collect();
+          frame.stack.push(Value::new_str(&joined));
+        }
+        Instr::BuildSlice(n) => {
+          let step = if n == 3 { frame.pop() } else { Value::None };
+          let stop = frame.pop();
+          let start = frame.pop();
+          frame.stack.push(Value::Slice(Rc::new([start, stop, step])));
+        }
+        Instr::BinarySubscr => {
+          let key = frame.pop();
+          let container = frame.pop();
+          frame.stack.push(get_item(&container, &key)?);
+        }
+        Instr::StoreSubscr => {
+          let key = frame.pop();
+          let container = frame.pop();
+          let value = frame.pop();
+          set_item(&container, &key, value, self)?;
+        }
+        Instr::DeleteSubscr => {
+          let key = frame.pop();
+          let container = frame.pop();
+          del_item(&container, &key)?;
+        }
+        Instr::ReturnValue => {
+          let value = frame.pop();
+          self.frames.pop();
+          if self.frames.len() == base {
+            return Ok(Exit::Return(value));
+          }
+          self.frames.last_mut().unwrap().stack.push(value);
+        }
+        Instr::YieldValue => {
+          let value = frame.pop();
+          let frame = self.frames.pop().unwrap();
+          return Ok(Exit::Yield(value, frame));
+        }
+        Instr::YieldFrom => {
+          let sent = frame.pop();
+          let iterator = frame.top().clone();
+          let resumed = send_value(&iterator, sent, self)?;
+          let frame = self.frames.last_mut().unwrap();
+          match resumed {
+            // Resuming runs the YieldFrom again, with the value sent then
+            Resumed::Yield(value) => {
+              frame.ip -= 1;
+              let frame = self.frames.pop().unwrap();
+              return Ok(Exit::Yield(value, frame));
+            }
+            Resumed::Return(value) => {
+              frame.pop();
+              frame.stack.push(value);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  // Calls a function: builtins run straight away and push their result, while a user-defined
+  // function gets a new frame that the main loop continues in
+  fn call(&mut self, function: Value, args: Vec<Value>, kwargs: Vec<(String, Value)>) -> Result<(), String> {
+    match function {
+      Value::Function(_) => {
+        let callee = self.new_frame(&function, args, kwargs)?;
+        if callee.code.flags & CODE_GENERATOR != 0 {
+          let generator = Value::new_generator(callee.code.name.clone(), GeneratorFrame::Code(Box::new(callee)));
+          self.frames.last_mut().unwrap().stack.push(generator);
+        } else {
+          self.frames.push(callee);
+        }
+      }
+      _ => {
+        let result = builtins::call(&function, args, &kwargs, self)?;
+        self.frames.last_mut().unwrap().stack.push(result);
+      }
+    }
+    Ok(())
+  }
+
+  // Sets up the frame for a call, binding the arguments to the first local slots
+  fn new_frame(&self, function: &Value, args: Vec<Value>, kwargs: Vec<(String, Value)>) -> Result<Frame, String> {
+    match function {
+      Value::Function(func) => {
+        let (code, closure) = match &func.body {
+          FunctionBody::Code(code, closure) => (code, closure),
+          FunctionBody::Ast(..) | FunctionBody::Steps(..) => unreachable!("tree-walker functions only exist in the interpreter"),
+        };
+        let args = func.bind_arguments(args, kwargs)?;
+        if self.frames.len() > MAX_CALL_DEPTH {
+          return Err("RecursionError: maximum recursion depth exceeded".to_string());
+        }
+
+        let mut locals: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        locals.resize(code.varnames.len(), None);
+
+        // Captured parameters start out in their cells
+        let mut cells: Vec<Cell> = code
+          .cellvars
+          .iter()
+          .map(|name| {
+            let param = code.varnames[..code.param_count()].iter().position(|p| p == name);
+            Rc::new(RefCell::new(param.and_then(|i| locals[i].clone())))
+          })
+          .collect();
+        cells.extend(closure.iter().cloned());
+
+        Ok(Frame { code: code.clone(), ip: 0, stack: Vec::new(), locals, cells })
+      }
+      _ => Err(format!("TypeError: '{}' object is not callable", function.type_name())),
+    }
+  }
+}
+
+// Functions called by builtins, such as the key function of sorted(), run in a nested execute loop
+// that returns with the function's result
+impl Caller for Vm {
+  fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, String> {
+    match function {
+      Value::Function(_) => {
+        let frame = self.new_frame(function, args, Vec::new())?;
+        if frame.code.flags & CODE_GENERATOR != 0 {
+          return Ok(Value::new_generator(frame.code.name.clone(), GeneratorFrame::Code(Box::new(frame))));
+        }
+        self.execute(frame)
+      }
+      _ => builtins::call(function, args, &[], self),
+    }
+  }
+
+  // The generator's frame is taken out while it runs on the frame stack, and put back when it yields.
+  // A chain of yield from resumes each generator from inside the one delegating to it, so the depth
+  // of that nesting is limited as well as the number of frames
+  fn resume(&mut self, frame: &mut Option<GeneratorFrame>, sent: Option<Value>) -> Result<Resumed, String> {
+    if self.frames.len() > MAX_CALL_DEPTH || self.resumes >= MAX_CALL_DEPTH {
+      return Err("RecursionError: maximum recursion depth exceeded".to_string());
+    }
+    let mut suspended = match frame.take() {
+      Some(GeneratorFrame::Code(suspended)) => *suspended,
+      _ => unreachable!("the VM only resumes its own generators"),
+    };
+    if let Some(sent) = sent {
+      suspended.stack.push(sent);
+    }
+
+    self.resumes += 1;
+    let exit = self.run_frame(suspended);
+    self.resumes -= 1;
+    match exit? {
+      Exit::Yield(value, suspended) => {
+        *frame = Some(GeneratorFrame::Code(Box::new(suspended)));
+        Ok(Resumed::Yield(value))
+      }
+      Exit::Return(value) => Ok(Resumed::Return(value)),
+    }
+  }
+}
+
+fn cell_position(code: &CodeObject, name: &str) -> usize {
+  match code.cellvars.iter().position(|n| n == name) {
+    Some(i) => i,
+    None => code.cellvars.len() + code.freevars.iter().position(|n| n == name).expect("free variable missing from enclosing code"),
+  }
+}
+
+// The error for reading or deleting an empty cell, which is a local variable of the function if it
+// is one of its cellvars
+fn unbound_cell(code: &CodeObject, i: u32) -> String {
+  if (i as usize) < code.cellvars.len() {
+    format!("UnboundLocalError: cannot access local variable '{}' where it is not associated with a value", cell_name(code, i))
+  } else {
+    format!("NameError: cannot access free variable '{}' where it is not associated with a value in enclosing scope", cell_name(code, i))
+  }
+}
+
+fn cell_name(code: &CodeObject, i: u32) -> &str {
+  let i = i as usize;
+  if i < code.cellvars.len() {
+    &code.cellvars[i]
+  } else {
+    &code.freevars[i - code.cellvars.len()]
+  }
+}
@@ -0,0 +1,70 @@
+// Runs every script in tests/scripts on the bytecode VM and on the tree-walker and checks that
+// both print what the .out file next to the script expects, which is what CPython prints apart
+// from the traceback lines before an error. A script whose first line is a comment such as
+// "# flags: -O" is run with those flags, as CPython is when making its .out file
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Output of one run: stdout, then stderr, which ends with the error message if the script failed
+fn run(script: &Path, flags: &[&str]) -> String {
+  let output = Command::new(env!("CARGO_BIN_EXE_tompython")).args(flags).arg(script).output().expect("failed to run tompython");
+  let mut printed = String::from_utf8_lossy(&output.stdout).into_owned();
+  printed.push_str(&String::from_utf8_lossy(&output.stderr));
+  printed.split('\n').map(without_location).collect::<Vec<_>>().join("\n")
+}
+
+// CPython shows where a syntax error is in the traceback lines that the .out files leave out, while
+// tompython adds it to the message as " (line 2, column 7)", so that part is not compared
+fn without_location(line: &str) -> &str {
+  match line.rfind(" (line ") {
+    Some(at) if line.starts_with("SyntaxError: ") && line.ends_with(')') => &line[..at],
+    _ => line,
+  }
+}
+
+// Flags the first line of a script asks for
+fn script_flags(source: &str) -> Vec<&str> {
+  match source.lines().next().and_then(|line| line.strip_prefix("# flags:")) {
+    Some(flags) => flags.split_whitespace().collect(),
+    None => Vec::new(),
+  }
+}
+
+fn scripts() -> Vec<PathBuf> {
+  let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("scripts");
+  let mut scripts: Vec<PathBuf> = fs::read_dir(dir)
+    .expect("tests/scripts is missing")
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|extension| extension == "py"))
+    .collect();
+  scripts.sort();
+  scripts
+}
+
+#[test]
+fn engines_agree() {
+  // The VM caches bytecode next to the script, so the scripts are run from a copy
+  let dir = std::env::temp_dir().join(format!("tompython-differential-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+
+  let mut failures = Vec::new();
+  for script in scripts() {
+    let copy = dir.join(script.file_name().unwrap());
+    fs::copy(&script, &copy).unwrap();
+    let expected = fs::read_to_string(script.with_extension("out")).unwrap_or_default();
+    let source = fs::read_to_string(&script).unwrap();
+
+    for engine in [None, Some("--tree-walk")] {
+      let mut flags = script_flags(&source);
+      flags.extend(engine);
+      let printed = run(&copy, &flags);
+      if printed != expected {
+        failures.push(format!("{} {:?}\n--- expected\n{}--- printed\n{}", script.display(), flags, expected, printed));
+      }
+    }
+  }
+  fs::remove_dir_all(&dir).unwrap();
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
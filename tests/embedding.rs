@@ -0,0 +1,55 @@
+// Runs programs through the library with the output, error and input streams of the embedder
+
+use std::cell::RefCell;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+// An output sink whose bytes stay readable after it is handed to the interpreter
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+  fn text(&self) -> String {
+    String::from_utf8(self.0.borrow().clone()).unwrap()
+  }
+}
+
+impl Write for Capture {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.borrow_mut().write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+// Runs source with the given input, returning its result and what it wrote to output and errors
+fn run(source: &str, input: &str) -> (Result<(), String>, String, String) {
+  let (output, errors) = (Capture::default(), Capture::default());
+  let input = Box::new(Cursor::new(input.as_bytes().to_vec()));
+  let result = tompython::run(source, Box::new(output.clone()), Box::new(errors.clone()), input);
+  (result, output.text(), errors.text())
+}
+
+#[test]
+fn print_goes_to_the_embedders_streams() {
+  let source = "import sys\nprint(1, 'two', sep=', ', end='!\\n')\nprint('oops', file=sys.stderr)\nsys.stdout.write('done\\n')\n";
+  assert_eq!(run(source, ""), (Ok(()), "1, two!\ndone\n".to_string(), "oops\n".to_string()));
+}
+
+#[test]
+fn input_reads_from_the_embedders_input() {
+  let source = "name = input('name? ')\nprint('hello', name)\n";
+  assert_eq!(run(source, "world\n"), (Ok(()), "name? hello world\n".to_string(), String::new()));
+}
+
+#[test]
+fn errors_are_returned_after_what_was_printed() {
+  let (result, output, _) = run("print('before')\nprint(1 / 0)\n", "");
+  assert_eq!((result, output.as_str()), (Err("ZeroDivisionError: division by zero".to_string()), "before\n"));
+
+  let (result, output, _) = run("print(\n", "");
+  assert!(result.unwrap_err().starts_with("SyntaxError"));
+  assert_eq!(output, "");
+}
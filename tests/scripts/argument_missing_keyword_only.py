@@ -0,0 +1,13 @@
+def f(a, b, c=3, *, d, e=5):
+    return (a, b, c, d, e)
+
+
+def g(a, b, /, c, *args, **kwargs):
+    return (a, b, c, args, kwargs)
+
+
+def h(x, /, y=2):
+    return (x, y)
+
+print("before")
+print(f(1, 2))
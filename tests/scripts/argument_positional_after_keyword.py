@@ -0,0 +1,2 @@
+print("never printed")
+print(sep="", 1)
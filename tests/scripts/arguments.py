@@ -0,0 +1,16 @@
+def f(a, b, c=3, *, d, e=5):
+    return (a, b, c, d, e)
+
+
+def g(a, b, /, c, *args, **kwargs):
+    return (a, b, c, args, kwargs)
+
+
+def h(x, /, y=2):
+    return (x, y)
+
+
+print(f(1, 2, d=4), f(1, 2, 3, d=4, e=6), f(b=2, a=1, d=0))
+print(f(1, *[2, 3], d=4), f(*[1], **{"b": 2, "d": 4}), f(*(1, 2), **{"e": 0, "d": 9}))
+print(g(1, 2, 3), g(1, 2, 3, 4, 5, x=6), g(1, 2, c=3), g(1, 2, 3, a=4, b=5))
+print(h(1), h(1, y=3), h(*[1, 2]), h(1, **{"y": 5}))
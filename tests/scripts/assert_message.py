@@ -0,0 +1,10 @@
+def side_effect(label):
+    print("evaluated", label)
+    return label
+
+
+assert True, side_effect("never")
+assert side_effect("condition"), side_effect("message")
+assert [1], "a non-empty list is true"
+print("passed")
+assert [], [1, "a"]
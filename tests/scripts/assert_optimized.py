@@ -0,0 +1,19 @@
+# flags: -O
+def side_effect():
+    print("evaluated")
+    return False
+
+
+assert False
+assert side_effect(), side_effect()
+assert 1 / 0
+print("asserts are left out with -O")
+
+
+def check(n):
+    assert n > 0, "n must be positive"
+    return n * 2
+
+
+print(check(-1))
+print(1 / 0)
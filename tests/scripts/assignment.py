@@ -0,0 +1,19 @@
+a = b = [1, 2, 3]
+a[0] = 10
+b[1:] = [20, 30, 40]
+print(a, b)
+d = {}
+d["k"] = 1
+d["k"] += 5
+print(d)
+n = 7
+n //= 2
+n **= 3
+n <<= 1
+print(n, 7 & 3, 7 | 8, 7 ^ 2)
+del a[0]
+print(a)
+assert len(a) == 3, "length"
+s = "héllo wörld"
+print(s.title(), s.upper(), s[::-1], len(s))
+print(f"{3.14159:.2f} {'x':>4} {255:#x}")
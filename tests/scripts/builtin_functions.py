@@ -0,0 +1,23 @@
+def negate(n):
+    return -n
+
+
+def second(pair):
+    return pair[1]
+
+
+words = ["pear", "Apple", "fig", "banana"]
+print(sorted([3, 1, 2]), sorted("hello"), sorted((2.5, 1, True)))
+print(sorted(words), sorted(words, key=len), sorted(words, key=len, reverse=True))
+print(sorted([(1, "b"), (0, "c"), (1, "a")]), sorted({3: "x", 1: "y"}))
+print(sorted([1, 3, 2], key=negate), sorted([], reverse=True))
+print(sorted([("a", 2), ("b", 1), ("c", 2)], key=second))
+print(min(3, 1, 2), max(3, 1, 2), min([4, 5]), max("hello"), min(words, key=len))
+print(max(words, key=len), min([], default="empty"), max((), default=None))
+print(min(1, 1.0), max(1, True), min([[2], [1, 5]]), max(range(10), key=negate))
+print(list(zip([1, 2, 3], "ab")), list(zip()), list(zip(range(3), (4, 5, 6), "xyz")))
+print(list(zip([1], [2], strict=True)), dict(zip("abc", range(3))))
+print(round(2.5), round(3.5), round(-0.5), round(2.675, 2), round(1234, -2), round(5, 1))
+print(round(0.125, 2), round(7.0), round(-1.5), round(15, -1), round(25, -1), round(1e15), round(2.5, None))
+print(int("ff", 16), int("0x1F", 16), int("0b101", 0), int("0o17", 0), int("z", 36), int("1_000"))
+print(int(" -42 "), int("+7", 8), int("10", base=2), int(3.99), int(-3.99), int(True), int("0", 0))
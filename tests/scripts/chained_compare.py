@@ -0,0 +1,18 @@
+def show(label, value):
+    print("evaluated", label)
+    return value
+
+
+print(1 < show("middle", 2) < 3)
+print(1 < show("middle", 5) < 3)
+print(show("first", 5) < show("second", 3) < show("third", 4))
+print(1 < 2 < 3 < 4, 1 < 2 > 3 < 4)
+print(1 == 1.0 == True, 1 != 2 != 1)
+print(1 < 2 <= 2 >= 1 > 0)
+print("a" < "b" < "c", "b" in "abc" in "xabcx")
+print(None is None is not False)
+print([1] == [1] != [2])
+x = 5
+print(0 <= x < 10, 0 <= x < 3)
+print(not 1 < 2 < 3)
+print(1 < "a" < 3)
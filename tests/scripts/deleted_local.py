@@ -0,0 +1,6 @@
+def d():
+    v = 1
+    del v
+    return v
+
+d()
@@ -0,0 +1,2 @@
+print("never printed")
+print("\N{LATIN SMALL LETTER A")
@@ -0,0 +1,3 @@
+# A syntax error stops the script before any of it runs
+print("never printed")
+print("café \x4g")
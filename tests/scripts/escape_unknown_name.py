@@ -0,0 +1,3 @@
+# The position counts each non-ASCII character before the escape as ten bytes
+print("never printed")
+print("中 \N{NO SUCH CHARACTER NAME}")
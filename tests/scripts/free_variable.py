@@ -0,0 +1,9 @@
+q = 7
+
+def m():
+    def k():
+        return q
+    print(k())
+    q = 1
+
+m()
@@ -0,0 +1,20 @@
+name = "café"
+values = [1, "two", 3.5]
+width = 10
+precision = 3
+x = 3.14159
+
+print(f"{name}", f"{name!s}", f"{name!r}", f"{name!a}")
+print(f"{values!r}", f"{values!a}", f"{'中'!a}")
+print(f"{x=}", f"{name=}", f"{x = }", f"{x=:.2f}", f"{name=!s}", f"{name = !a}")
+print(f"{x:{width}.{precision}f}|", f"{name:>{width}}|", f"{name:*^{width}}|")
+print(f"{x:{'>'}{width}}|", f"{42:{'0'}{width}d}")
+print(f"{{}}", f"{{x}}", f"{{{x}}}", f"a{{b}}c")
+print(f"{1 + 2}", f"{values[1]}", f"{'nested' + ' quotes'}", f'{"double"}')
+print(f"{x:.1%}", f"{255:#x}", f"{255:08b}", f"{1234567:,}", f"{-5:+d}")
+print(f"{x!r:>12}|", f"{name!r:^10}|")
+print(rf"\n{name}", fr"{x}\t")
+print(f"""multi
+{name}""")
+print(f"{'a' if x > 3 else 'b'}", f"{[v for v in range(3)]}")
+print(f"{x:{width}}|", f"{True}", f"{None!r}")
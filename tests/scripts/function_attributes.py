@@ -0,0 +1,35 @@
+# Functions take attributes by assignment, augmented assignment and setattr(), and lose them with del
+def counter():
+    counter.calls += 1
+    return counter.calls
+
+counter.calls = 0
+counter()
+counter()
+print(counter.calls, counter())
+
+setattr(counter, "label", "count")
+print(getattr(counter, "label"), hasattr(counter, "label"), hasattr(counter, "other"), getattr(counter, "other", None))
+
+def make():
+    def inner():
+        return inner.value * 2
+    return inner
+
+a = make()
+b = make()
+a.value = 1
+b.value = 10
+a.value += 2
+print(a(), b())
+
+a.items = []
+a.items += [1]
+a.items[0] = 5
+print(a.items)
+
+del counter.label
+print(hasattr(counter, "label"))
+s = "text"
+print(s.upper())
+del counter.label
@@ -0,0 +1,21 @@
+def countdown(n):
+    while n > 0:
+        yield n
+        n -= 1
+
+def chain(a, b):
+    yield from a
+    result = yield from b
+    return result
+
+print(list(countdown(3)))
+print(list(chain(countdown(2), [7, 8])))
+
+def echo():
+    received = yield 1
+    while received is not None:
+        received = yield received * 2
+
+g = echo()
+print(next(g), g.send(5), g.send(10))
+print(sorted({n % 3 for n in range(10)}), {k: k * k for k in range(3)})
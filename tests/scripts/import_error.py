@@ -0,0 +1,3 @@
+import sys
+print(sys.stderr)
+import nosuchmodule
@@ -0,0 +1,2 @@
+print(int("z", 36), int("-0b11", 0))
+print(int("12", 37))
@@ -0,0 +1,2 @@
+print(int("077", 8))
+print(int("089", 8))
@@ -0,0 +1,12 @@
+print(8 - 2 - 1)
+print(8 / 2 / 2)
+print(2 * 3 % 4)
+print(17 % 5 * 2)
+print(100 // 10 // 3)
+print(2 ** 3 ** 2)
+print(1 - 2 + 3)
+print(12 / 3 * 2)
+print(-2 - -3 - 4)
+print(10 - 2 * 3 - 1)
+print(32 >> 2 >> 1, 1 << 2 << 3)
+print("a" + "b" + "c")
@@ -0,0 +1,2 @@
+print(max([5, 2]))
+print(max([], key=len))
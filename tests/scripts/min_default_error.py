@@ -0,0 +1,2 @@
+print(min([], default=0), max([1, 2]))
+print(min(1, 2, default=0))
@@ -0,0 +1,13 @@
+# Comparing containers nested deeper than the recursion limit is a RecursionError, and freeing them
+# doesn't overflow the stack
+def nest(n):
+    x = []
+    for i in range(n):
+        x = [x]
+    return x
+
+print(nest(50) == nest(50), nest(50) == nest(49))
+print(nest(50) != nest(50), (nest(30),) == (nest(30),))
+x = nest(100000)
+x = None
+print(nest(100000) == nest(100000))
@@ -0,0 +1,10 @@
+# Printing containers nested deeper than the recursion limit is a RecursionError
+def nest(n):
+    x = []
+    for i in range(n):
+        x = [x]
+    return x
+
+print(nest(3), (nest(2),), {1: nest(2)})
+print(len(repr(nest(50))), len(str(nest(50))), len(f"{nest(50)}"))
+print(repr(nest(100000)))
@@ -0,0 +1,21 @@
+import sys
+
+print("to stdout", file=sys.stdout)
+print("to stderr", file=sys.stderr)
+print("a", "b", sep="-", end="!\n", file=sys.stderr, flush=True)
+print(sys.stdout.write("written\n"))
+sys.stderr.write("also to stderr\n")
+sys.stdout.flush()
+print(sys)
+print(sys.stdout)
+print(sys.stderr is sys.stderr, sys.stdout == sys.stderr)
+
+
+def show():
+    import sys
+    return sys.stdout
+
+
+print(show())
+print(sys.stdout.write("é\n"))
+sys.stdout.write(3)
@@ -0,0 +1,4 @@
+# The attributes of the other built-in types can't be assigned
+s = "text"
+print(hasattr(s, "upper"))
+s.upper = None
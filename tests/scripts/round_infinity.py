@@ -0,0 +1,2 @@
+print(round(2.5), round(-2.5, 0))
+print(round(float("inf")))
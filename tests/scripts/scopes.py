@@ -0,0 +1,46 @@
+x = 9
+
+def outer():
+    def inner():
+        return w
+    w = 4
+    return inner()
+
+print(outer())
+
+def counter():
+    c = 0
+    def inc():
+        nonlocal c
+        c += 1
+        return c
+    inc()
+    return inc()
+
+print(counter())
+
+y = 5
+
+def read_global():
+    global y
+    def inner():
+        return y
+    return inner()
+
+print(read_global())
+
+def totals():
+    total = 0
+    for i in [1, 2, 3]:
+        total += i
+    return ([i * total for i in range(3)], sum(i for i in range(total)))
+
+print(totals())
+print([y for y in range(2)], y)
+
+def shadow(x):
+    def inner():
+        return x * 2
+    return inner()
+
+print(shadow(21), x)
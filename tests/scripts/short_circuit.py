@@ -0,0 +1,21 @@
+def show(label, value):
+    print("evaluated", label)
+    return value
+
+
+print(show("left", 0) and show("right", 1))
+print(show("left", 2) and show("right", 3))
+print(show("left", 0) or show("right", 4))
+print(show("left", 5) or show("right", 6))
+print([] or {} or "last")
+print([1] and (2,) and "last")
+print("" and 1 / 0)
+print(1 or 1 / 0)
+print(None or 0 or [] or "" or None)
+print(1 and 2 or 3, 0 and 2 or 3, 0 or 2 and 3)
+print(not 0 and 1, not (0 and 1))
+x = 0
+print(x != 0 and 10 / x > 1)
+print(x == 0 or 10 / x > 1)
+print(show("a", 1) and show("b", 0) and show("c", 1))
+print(0 or 1 / 0)
@@ -0,0 +1,2 @@
+print(sorted([3, 1], reverse=True))
+print(sorted([3, 1, 2], reverse=None))
@@ -0,0 +1,13 @@
+# Strings are indexed and sliced by code point, whether or not they are ASCII
+for s in ["hello, world", "héllo, wörld", "", "日本語テキスト"]:
+    print(len(s), s[0:1], s[-1:], s[2:5], s[::2], s[::-1], s[5:2:-1], s[100:], s[-100:3])
+    if s:
+        print(s[0], s[-1], s[len(s) // 2])
+
+s = "x" * 100000 + "y"
+total = 0
+for i in range(0, len(s), 1000):
+    if s[i] == "x":
+        total += 1
+print(total, s[-1], len(s[1:-1]), s[99999:])
+print("abc"[3])
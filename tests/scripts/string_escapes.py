@@ -0,0 +1,20 @@
+print("tab:\tend", "quote:\"", 'apostrophe:\'', "backslash:\\")
+print(repr("\a\b\f\n\r\t\v\0"))
+print("\x41\x62\x7e", "\101\142\176", "\1234", "\0101")
+print("é中\U0001F600", len("\U0001F600"))
+print("\N{LATIN SMALL LETTER E WITH ACUTE}\N{GREEK SMALL LETTER ALPHA}\N{SNOWMAN}")
+print("\N{latin small letter a}", "\N{EM DASH}" == "—")
+print("line one \
+continued")
+print(r"\n\t\x41\N{SNOWMAN}", len(r"\n"), r"\"", r'\'')
+print(R"raw \d+", rb"\x00", Rb"\n", bR"\t", BR"\\")
+print(b"\x00\x7f\xff", b"\101\n", list(b"\x80\t"))
+print(b"\N{SNOWMAN}", b"\u00e9", len(b"\u00e9"))
+print(b"bytes" + b" joined", b"a\
+b")
+print("""triple "quoted"
+across 'lines'""")
+print('''single \'''' + "'''")
+print("unknown \q \d escapes", len("\q"))
+print("adjacent" " literals" ' joined', b"ad" b"jacent")
+print(repr("\x00\x1f\x7f\xa0\xad\u200b\u0378\U000e0001 é中😀"), repr(b"\x00'\""))
@@ -0,0 +1,8 @@
+print("a" != "b", "a" != "a")
+print("" != "", "" != " ")
+print("abc" != "abd", "abc" != "ab")
+s = "x" * 3
+print(s != "xxx", s != "xx")
+print("1" != 1, 1 != "1")
+if "left" != "right":
+    print("different")
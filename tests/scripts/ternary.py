@@ -0,0 +1,19 @@
+def show(label, value):
+    print("evaluated", label)
+    return value
+
+
+print(show("yes", 1) if show("condition", True) else show("no", 2))
+print(show("yes", 1) if show("condition", []) else show("no", 2))
+x = 7
+print("odd" if x % 2 else "even")
+print("big" if x > 100 else "medium" if x > 5 else "small")
+print(("a" if x else "b") + "c")
+print([n if n % 2 else -n for n in range(5)])
+print(1 if 0 else 2 if 0 else 3)
+y = 0 if x else 1 / 0
+print(y)
+z = x if x < 0 else None
+print(z)
+print(1 / 0 if False else "safe")
+print(1 / 0 if True else "safe")
@@ -0,0 +1,9 @@
+z = 3
+
+def g():
+    if False:
+        z = 1
+    return z
+
+print(z)
+g()
@@ -0,0 +1,7 @@
+y = 5
+
+def f():
+    print(y)
+    y = 1
+
+f()
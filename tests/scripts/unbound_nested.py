@@ -0,0 +1,9 @@
+x = 9
+
+def outer():
+    def inner():
+        print(x)
+        x = 2
+    inner()
+
+outer()
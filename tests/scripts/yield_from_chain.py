@@ -0,0 +1,13 @@
+# Each link of a chain of yield from resumes the next one, so a chain longer than the recursion
+# limit is a RecursionError in CPython as well
+def wrap(g):
+    yield from g
+
+def build(n):
+    g = iter([1, 2])
+    for i in range(n):
+        g = wrap(g)
+    return g
+
+print(list(build(900)))
+print(list(build(5000)))
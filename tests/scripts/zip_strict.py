@@ -0,0 +1,2 @@
+print(list(zip([1, 2], "ab", strict=True)))
+print(list(zip([1, 2], "ab", [4, 5, 6], strict=True)))